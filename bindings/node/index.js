@@ -0,0 +1,5 @@
+// Node.js bindings for patch-rs. Run `npm run build` in this directory to compile the addon.
+
+"use strict";
+
+module.exports = require("./patch_rs.node");
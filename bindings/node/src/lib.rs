@@ -0,0 +1,82 @@
+//! Node.js bindings for patch-rs, built into an N-API addon with napi-rs so they load in any
+//! Node.js or Electron release without rebuilding. Buffers are copied in and out, and patches are
+//! released when their JavaScript objects are collected.
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use patch_rs::prelude::*;
+
+fn error(err: patch_rs::Error) -> Error {
+    Error::from_reason(err.to_string())
+}
+
+/// An IPS, UPS, or BPS patch.
+#[napi(js_name = "Patch")]
+pub struct JsPatch {
+    patch: Box<dyn Patch>,
+}
+
+#[napi]
+impl JsPatch {
+    /// Loads a patch, detecting its format from its contents.
+    #[napi(factory)]
+    pub fn load(data: Buffer) -> Result<Self> {
+        let patch = patch_rs::load(&data).map_err(error)?;
+        Ok(Self { patch })
+    }
+
+    /// Creates a patch that turns `original` into `modified` in the given format, "ips" (the
+    /// default), "ups", or "bps". Throws if the format can't hold the changes, such as IPS with
+    /// changes past the first 4 GiB.
+    #[napi(factory)]
+    pub fn create(original: Buffer, modified: Buffer, format: Option<String>) -> Result<Self> {
+        let (src, dst, options) = (&original[..], &modified[..], CreateOptions::default());
+        let patch: Box<dyn Patch> = match format.as_deref().unwrap_or("ips") {
+            "ips" => {
+                Box::new(IpsPatch::create_observed(src, dst, &options, &mut ()).map_err(error)?)
+            }
+            "ups" => {
+                Box::new(UpsPatch::create_observed(src, dst, &options, &mut ()).map_err(error)?)
+            }
+            "bps" => {
+                Box::new(BpsPatch::create_observed(src, dst, &options, &mut ()).map_err(error)?)
+            }
+            format => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("unsupported format: {format:?}"),
+                ))
+            }
+        };
+        Ok(Self { patch })
+    }
+
+    /// The name of the patch format, e.g. "BPS".
+    #[napi(getter)]
+    pub fn format(&self) -> &'static str {
+        self.patch.format()
+    }
+
+    /// Throws if `rom` isn't the source the patch expects. Returns false if the patch doesn't
+    /// record a source checksum (IPS), true otherwise.
+    #[napi]
+    pub fn verify(&self, rom: Buffer) -> Result<bool> {
+        match self.patch.validate(&rom) {
+            Some(Ok(())) => Ok(true),
+            Some(Err(err)) => Err(error(err)),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the patch to `rom`, returning the output.
+    #[napi]
+    pub fn apply(&self, rom: Buffer) -> Result<Buffer> {
+        self.patch.apply(&rom).map(Buffer::from).map_err(error)
+    }
+
+    /// Serializes the patch.
+    #[napi]
+    pub fn export(&self) -> Result<Buffer> {
+        self.patch.export(None).map(Buffer::from).map_err(error)
+    }
+}
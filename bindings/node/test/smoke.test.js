@@ -0,0 +1,30 @@
+"use strict";
+
+const assert = require("node:assert");
+const test = require("node:test");
+const { Patch } = require("..");
+
+test("load and apply", () => {
+  const patch = Patch.load(Buffer.from("PATCH\0\0\x01\0\x02abEOF", "latin1"));
+  assert.strictEqual(patch.format, "IPS");
+  assert.strictEqual(patch.verify(Buffer.from("0123")), false);
+  assert.deepStrictEqual(patch.apply(Buffer.from("0123")), Buffer.from("0ab3"));
+});
+
+test("create and verify", () => {
+  for (const format of ["ips", "ups", "bps"]) {
+    const patch = Patch.create(Buffer.from("abc"), Buffer.from("abd"), format);
+    const loaded = Patch.load(patch.export());
+    assert.deepStrictEqual(loaded.apply(Buffer.from("abc")), Buffer.from("abd"));
+  }
+
+  const patch = Patch.create(Buffer.from("abc"), Buffer.from("abd"), "ups");
+  assert.strictEqual(patch.verify(Buffer.from("abc")), true);
+  assert.throws(() => patch.verify(Buffer.from("xyz")));
+  assert.throws(() => patch.apply(Buffer.from("xyz")));
+});
+
+test("errors", () => {
+  assert.throws(() => Patch.load(Buffer.from("junk")), { message: /./ });
+  assert.throws(() => Patch.create(Buffer.from("a"), Buffer.from("b"), "xdelta"), /unsupported/);
+});
@@ -0,0 +1,36 @@
+"""Python bindings for patch-rs."""
+
+class PatchError(Exception):
+    """Raised when the library reports a failure."""
+
+class Patch:
+    """An IPS, UPS, or BPS patch."""
+
+    @staticmethod
+    def load(data: bytes) -> Patch:
+        """Loads a patch, detecting its format from its contents."""
+
+    @staticmethod
+    def create(original: bytes, modified: bytes, format: str = "ips") -> Patch:
+        """Creates a patch that turns ``original`` into ``modified`` in the given format, "ips",
+        "ups", or "bps".
+
+        Raises PatchError if the format can't hold the changes, such as IPS with changes past the
+        first 4 GiB.
+        """
+
+    @property
+    def format(self) -> str:
+        """The name of the patch format, e.g. "BPS"."""
+
+    def verify(self, rom: bytes) -> bool:
+        """Checks that ``rom`` is the source the patch expects, raising PatchError if it isn't.
+
+        Returns False if the patch doesn't record a source checksum (IPS), True otherwise.
+        """
+
+    def apply(self, rom: bytes) -> bytes:
+        """Applies the patch to ``rom`` and returns the output."""
+
+    def export(self) -> bytes:
+        """Serializes the patch."""
@@ -0,0 +1,99 @@
+//! Python bindings for patch-rs, built into the `patch_rs` extension module with maturin. Patches
+//! are owned by their Python objects, and byte strings are copied in and out, so nothing on the
+//! Python side refers to memory owned by the library.
+
+use patch_rs::prelude::*;
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+create_exception!(
+    patch_rs,
+    PatchError,
+    PyException,
+    "Raised when the library reports a failure."
+);
+
+fn error(err: patch_rs::Error) -> PyErr {
+    PatchError::new_err(err.to_string())
+}
+
+/// An IPS, UPS, or BPS patch.
+#[pyclass(name = "Patch", module = "patch_rs", frozen)]
+struct PyPatch(Box<dyn Patch>);
+
+#[pymethods]
+impl PyPatch {
+    /// Loads a patch, detecting its format from its contents.
+    #[staticmethod]
+    fn load(data: &[u8]) -> PyResult<Self> {
+        patch_rs::load(data).map(Self).map_err(error)
+    }
+
+    /// Creates a patch that turns `original` into `modified` in the given format, "ips", "ups",
+    /// or "bps". Raises `PatchError` if the format can't hold the changes, such as IPS with
+    /// changes past the first 4 GiB.
+    #[staticmethod]
+    #[pyo3(signature = (original, modified, format = "ips"))]
+    fn create(py: Python<'_>, original: &[u8], modified: &[u8], format: &str) -> PyResult<Self> {
+        if !matches!(format, "ips" | "ups" | "bps") {
+            return Err(PyValueError::new_err(format!(
+                "unsupported format: {format:?}"
+            )));
+        }
+
+        let (src, dst, options) = (original, modified, CreateOptions::default());
+        py.allow_threads(|| -> patch_rs::Result<Box<dyn Patch>> {
+            Ok(match format {
+                "ips" => Box::new(IpsPatch::create_observed(src, dst, &options, &mut ())?),
+                "ups" => Box::new(UpsPatch::create_observed(src, dst, &options, &mut ())?),
+                _ => Box::new(BpsPatch::create_observed(src, dst, &options, &mut ())?),
+            })
+        })
+        .map(Self)
+        .map_err(error)
+    }
+
+    /// The name of the patch format, e.g. "BPS".
+    #[getter]
+    fn format(&self) -> &'static str {
+        self.0.format()
+    }
+
+    /// Checks that `rom` is the source the patch expects, raising `PatchError` if it isn't.
+    /// Returns False if the patch doesn't record a source checksum (IPS), True otherwise.
+    fn verify(&self, rom: &[u8]) -> PyResult<bool> {
+        match self.0.validate(rom) {
+            Some(Ok(())) => Ok(true),
+            Some(Err(err)) => Err(error(err)),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the patch to `rom` and returns the output.
+    fn apply<'py>(&self, py: Python<'py>, rom: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let output = py.allow_threads(|| self.0.apply(rom)).map_err(error)?;
+        Ok(PyBytes::new(py, &output))
+    }
+
+    /// Serializes the patch.
+    fn export<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.0.export(None).map_err(error)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<patch_rs.Patch {}>", self.0.format())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "patch_rs")]
+fn patch_rs_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPatch>()?;
+    m.add("PatchError", m.py().get_type::<PatchError>())?;
+    Ok(())
+}
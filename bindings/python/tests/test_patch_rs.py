@@ -0,0 +1,37 @@
+"""Tests for the extension module, run with ``python -m unittest discover tests`` after
+``maturin develop``."""
+
+import unittest
+
+import patch_rs
+
+
+class PatchTest(unittest.TestCase):
+    def test_load_and_apply(self):
+        patch = patch_rs.Patch.load(b"PATCH\0\0\x01\0\x02abEOF")
+        self.assertEqual(patch.format, "IPS")
+        self.assertFalse(patch.verify(b"0123"))
+        self.assertEqual(patch.apply(b"0123"), b"0ab3")
+
+    def test_create_and_verify(self):
+        for format in ["ips", "ups", "bps"]:
+            patch = patch_rs.Patch.create(b"abc", b"abd", format=format)
+            self.assertEqual(patch_rs.Patch.load(patch.export()).apply(b"abc"), b"abd")
+
+        patch = patch_rs.Patch.create(b"abc", b"abd", format="ups")
+        self.assertTrue(patch.verify(b"abc"))
+        with self.assertRaises(patch_rs.PatchError):
+            patch.verify(b"xyz")
+        with self.assertRaises(patch_rs.PatchError):
+            patch.apply(b"xyz")
+
+    def test_errors(self):
+        with self.assertRaises(patch_rs.PatchError) as cm:
+            patch_rs.Patch.load(b"junk")
+        self.assertTrue(str(cm.exception))
+        with self.assertRaises(ValueError):
+            patch_rs.Patch.create(b"a", b"b", format="xdelta")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,125 @@
+/*
+ * C interface to patch-rs, available when the crate is built with the `ffi` feature:
+ *
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ */
+
+#ifndef PATCH_RS_H
+#define PATCH_RS_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit it by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define PATCH_RS_OK 0
+
+#define PATCH_RS_ERROR -1
+
+#define PATCH_RS_BUFFER_TOO_SMALL -2
+
+/**
+ * Returned by `patch_rs_validate` for patches that don't record a source checksum.
+ */
+#define PATCH_RS_UNVERIFIABLE -3
+
+#define PATCH_RS_FORMAT_IPS 0
+
+#define PATCH_RS_FORMAT_UPS 1
+
+#define PATCH_RS_FORMAT_BPS 2
+
+/**
+ * An opaque handle to a loaded patch.
+ */
+typedef struct PatchRsPatch PatchRsPatch;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Loads an IPS, UPS, or BPS patch, detecting the format from its contents. Returns null on
+ * failure. The patch must be released with `patch_rs_free`.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes.
+ */
+PatchRsPatch *patch_rs_load(const uint8_t *data, size_t len);
+
+/**
+ * Creates a patch in the given `PATCH_RS_FORMAT_*` that turns `original` into `modified`.
+ * Returns null on failure, such as an IPS patch with changes past the first 4 GiB. The patch
+ * must be released with `patch_rs_free`.
+ *
+ * # Safety
+ *
+ * `original` and `modified` must point to `original_len` and `modified_len` readable bytes.
+ */
+PatchRsPatch *patch_rs_create(int format,
+                              const uint8_t *original,
+                              size_t original_len,
+                              const uint8_t *modified,
+                              size_t modified_len);
+
+/**
+ * Checks that `rom` is the source `patch` expects. Returns `PATCH_RS_ERROR` with the reason
+ * available from `patch_rs_last_error` if it isn't, or `PATCH_RS_UNVERIFIABLE` if the patch
+ * doesn't record a source checksum.
+ *
+ * # Safety
+ *
+ * `patch` must come from `patch_rs_load` or `patch_rs_create`, and `rom` must point to
+ * `rom_len` readable bytes.
+ */
+int patch_rs_validate(const PatchRsPatch *patch, const uint8_t *rom, size_t rom_len);
+
+/**
+ * Serializes `patch` into `out`, following the same buffer convention as `patch_rs_apply`.
+ *
+ * # Safety
+ *
+ * `patch` must come from `patch_rs_load` or `patch_rs_create`, and `out` must point to
+ * `*out_len` writable bytes.
+ */
+int patch_rs_export(const PatchRsPatch *patch, uint8_t *out, size_t *out_len);
+
+/**
+ * Applies `patch` to `rom`, writing the output into `out`.
+ *
+ * On entry `*out_len` is the capacity of `out`, and on return it's the size of the output. If
+ * the output doesn't fit, nothing is written and `PATCH_RS_BUFFER_TOO_SMALL` is returned, so
+ * callers can pass a null `out` to query the size first.
+ *
+ * # Safety
+ *
+ * `patch` must come from `patch_rs_load` or `patch_rs_create`, `rom` must point to `rom_len`
+ * readable bytes, and `out` must point to `*out_len` writable bytes.
+ */
+int patch_rs_apply(const PatchRsPatch *patch,
+                   const uint8_t *rom,
+                   size_t rom_len,
+                   uint8_t *out,
+                   size_t *out_len);
+
+/**
+ * Releases a patch returned by `patch_rs_load` or `patch_rs_create`. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `patch` must come from `patch_rs_load` or `patch_rs_create` and not have been freed already.
+ */
+void patch_rs_free(PatchRsPatch *patch);
+
+/**
+ * Returns the message for the most recent failure on the calling thread, or null if nothing has
+ * failed. The string stays valid until the next failing call on the same thread.
+ */
+const char *patch_rs_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PATCH_RS_H */
@@ -0,0 +1,160 @@
+//! Asynchronous loading and applying, so services can stream ROMs and patches from remote storage
+//! without blocking a worker thread on I/O.
+//!
+//! Readers and writers are the standard [`futures_io::AsyncRead`] and [`futures_io::AsyncWrite`]
+//! traits, so the functions work on any runtime. Tokio's types implement them through
+//! `tokio_util::compat`:
+//!
+//! ```ignore
+//! use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+//!
+//! let rom = tokio::fs::File::open("game.sfc").await?.compat();
+//! let out = tokio::fs::File::create("hack.sfc").await?.compat_write();
+//! stream_async(patch.writer(Vec::new()), rom, out).await?;
+//! ```
+//!
+//! IPS and UPS patches stream: [`stream_async`] patches the ROM a chunk at a time as it's read,
+//! yielding to the executor between chunks. BPS copies from anywhere in the source and the
+//! output, so [`apply_async`] reads the whole ROM before applying, and does the applying on a
+//! blocking thread pool so the executor isn't held up by a large patch.
+
+use std::{
+    future::Future,
+    io::{self, Write},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_util::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{stream::PatchWriter, Patch, Result};
+
+/// How much is read or written at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// Reads a patch of any supported format from `reader`, detecting the format from its contents.
+/// The patch is parsed on a blocking thread.
+pub async fn load_async(reader: impl AsyncRead + Unpin) -> Result<Box<dyn Patch>> {
+    let data = read_chunked(reader).await?;
+    blocking::unblock(move || crate::load(&data)).await
+}
+
+/// Reads a ROM from `rom`, applies `patch` to it on a blocking thread, and writes the output to
+/// `out`. Works for any format, but buffers the ROM and the output; prefer [`stream_async`] for
+/// IPS and UPS patches.
+pub async fn apply_async(
+    patch: Arc<dyn Patch>,
+    rom: impl AsyncRead + Unpin,
+    mut out: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let rom = read_chunked(rom).await?;
+    let output = blocking::unblock(move || patch.apply(&rom)).await?;
+    for chunk in output.chunks(CHUNK) {
+        out.write_all(chunk).await?;
+        YieldNow(false).await;
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+/// Streams `rom` through `writer`, from [`IpsPatch::writer`](crate::ips::IpsPatch::writer) or
+/// [`UpsPatch::writer`](crate::ups::UpsPatch::writer), and writes the output to `out` as each
+/// chunk is patched. Like [`PatchWriter`], UPS checksums are only checked once the whole ROM has
+/// been read, so a mismatch is reported after the output has been written.
+pub async fn stream_async(
+    mut writer: PatchWriter<'_, Vec<u8>>,
+    mut rom: impl AsyncRead + Unpin,
+    mut out: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut buf = vec![0; CHUNK];
+    loop {
+        let len = rom.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+
+        writer.write_all(&buf[..len])?;
+        out.write_all(writer.get_mut()).await?;
+        writer.get_mut().clear();
+        YieldNow(false).await;
+    }
+
+    out.write_all(&writer.finish()?).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+async fn read_chunked(mut reader: impl AsyncRead + Unpin) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = vec![0; CHUNK];
+    loop {
+        match reader.read(&mut buf).await? {
+            0 => return Ok(data),
+            len => data.extend_from_slice(&buf[..len]),
+        }
+        YieldNow(false).await;
+    }
+}
+
+/// Returns to the executor once, so a reader that's always ready can't starve other tasks.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if std::mem::replace(&mut self.0, true) {
+            return Poll::Ready(());
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use std::{pin::pin, task::Waker};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn load_and_apply() {
+        let patch = block_on(load_async(&b"PATCH\0\0\x01\0\x01!EOF"[..])).unwrap();
+        let mut out = Vec::new();
+        block_on(apply_async(Arc::from(patch), &b"abc"[..], &mut out)).unwrap();
+        assert_eq!(out, b"a!c");
+    }
+
+    #[test]
+    fn stream() {
+        let src: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let mut dst = src.clone();
+        dst[100_000] = 0xFF;
+        dst.extend_from_slice(b"tail");
+
+        let ips = IpsPatch::create(&src, &dst);
+        let mut out = Vec::new();
+        block_on(stream_async(ips.writer(Vec::new()), &src[..], &mut out)).unwrap();
+        assert_eq!(out, dst);
+
+        let ups = UpsPatch::create(&src, &dst);
+        let mut out = Vec::new();
+        block_on(stream_async(ups.writer(Vec::new()), &src[..], &mut out)).unwrap();
+        assert_eq!(out, dst);
+
+        let mut out = Vec::new();
+        assert!(block_on(stream_async(ups.writer(Vec::new()), &dst[..], &mut out)).is_err());
+    }
+}
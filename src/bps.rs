@@ -1,11 +1,21 @@
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
 
-use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use byteorder::{ReadBytesExt, LE};
 use num_enum::TryFromPrimitive;
 
 use crate::{
-    bps_ups::{self, ReadVarExt, WriteVarExt},
-    Error, Patch, ReadExt, Result,
+    bps_ups::{self, HashingWriter},
+    encoding::{self, ReadVarExt, WriteVarExt},
+    equiv::{Model, Size, Sym},
+    hash::{self, Crc32Hasher},
+    json::{self, Object, Value},
+    lint::{self, Lint},
+    manifest::Manifest,
+    progress::{self, Event, Observer, Ticker},
+    source::{self, Source},
+    trace::Span,
+    vectored::Chunks,
+    CreateOptions, Error, Patch, ReadExt, RecordKind, Region, RegionKind, Result,
 };
 
 #[repr(u8)]
@@ -28,24 +38,83 @@ impl From<&Record> for Action {
     }
 }
 
+#[derive(Clone, Copy)]
 enum Record {
     SourceRead,
-    TargetRead(Vec<u8>),
+    /// Bytes stored in the patch, starting at this offset in [`BpsPatch::literals`].
+    TargetRead(usize),
     SourceCopy(isize),
     TargetCopy(isize),
 }
 
+/// A record and its length as stored: the action word as encoded in the patch, then the
+/// record's offset. This takes 16 bytes where `(usize, Record)` takes 24.
+#[derive(Clone, Copy)]
+struct Packed {
+    action: u64,
+    value: u64,
+}
+
+impl Packed {
+    /// Packs a record of `length` bytes, which must not be 0.
+    fn new(length: usize, record: Record) -> Self {
+        let value = match record {
+            Record::SourceRead => 0,
+            Record::TargetRead(start) => start as u64,
+            Record::SourceCopy(offset) | Record::TargetCopy(offset) => offset as i64 as u64,
+        };
+        Self {
+            action: ((length as u64 - 1) << 2) | Action::from(&record) as u64,
+            value,
+        }
+    }
+
+    fn unpack(self) -> (usize, Record) {
+        let length = (self.action >> 2) as usize + 1;
+        let record = match Action::try_from((self.action & 0b11) as u8) {
+            Ok(Action::SourceRead) => Record::SourceRead,
+            Ok(Action::TargetRead) => Record::TargetRead(self.value as usize),
+            Ok(Action::SourceCopy) => Record::SourceCopy(self.value as i64 as isize),
+            Ok(Action::TargetCopy) | Err(_) => Record::TargetCopy(self.value as i64 as isize),
+        };
+        (length, record)
+    }
+}
+
+#[derive(Clone)]
 pub struct BpsPatch {
     src_data: bps_ups::Validation,
     out_data: bps_ups::Validation,
-    metadata: Option<Vec<u8>>,
-    records: Vec<(usize, Record)>,
+    metadata: Option<Box<[u8]>>,
+    records: Vec<Packed>,
+    /// The data of every TargetRead record back to back, so patches with many of them don't need
+    /// an allocation for each.
+    literals: Box<[u8]>,
 }
 
 impl BpsPatch {
     const MAGIC: &[u8; 4] = b"BPS1";
 
-    pub fn load(mut data: &[u8]) -> Result<Self> {
+    pub fn load(data: &[u8]) -> Result<Self> {
+        let span = Span::load("BPS");
+        let result = Self::parse(data).and_then(Self::strict);
+        span.finish_with(&result, |patch| patch.records.len());
+        result
+    }
+
+    /// Like [`load`](Self::load), but returns the ways the records disagree with the declared
+    /// sizes as warnings instead of failing. See [`crate::load_lenient`].
+    pub fn load_lenient(data: &[u8]) -> Result<(Self, Vec<Error>)> {
+        let span = Span::load("BPS");
+        let result = Self::parse(data);
+        span.finish_with(&result, |patch| patch.records.len());
+        let patch = result?;
+        let warnings = patch.size_problems()?;
+        Ok((patch, warnings))
+    }
+
+    fn parse(patch: &[u8]) -> Result<Self> {
+        let mut data = patch;
         if data.read_arr()? != *Self::MAGIC {
             return Err(Error::Magic(unsafe {
                 std::str::from_utf8_unchecked(Self::MAGIC)
@@ -56,135 +125,1189 @@ impl BpsPatch {
         let out_size = data.read_var_int()?;
         let metadata = match data.read_var_int()? {
             0 => None,
-            len => Some(data.read_vec(len)?),
+            len => Some(data.read_vec(len)?.into_boxed_slice()),
         };
 
         let mut records = Vec::new();
+        let mut literals = Vec::new();
         while data.len() > 12 {
             let action = data.read_var_int()?;
             let length = (action >> 2) + 1;
-            records.push(match Action::try_from((action & 0b11) as u8) {
-                Ok(Action::SourceRead) => (length, Record::SourceRead),
-                Ok(Action::TargetRead) => (length, Record::TargetRead(data.read_vec(length)?)),
-                Ok(Action::SourceCopy) => {
-                    (length, Record::SourceCopy(Self::read_copy_size(&mut data)?))
-                }
-                Ok(Action::TargetCopy) => {
-                    (length, Record::TargetCopy(Self::read_copy_size(&mut data)?))
+            let record = match Action::try_from((action & 0b11) as u8) {
+                Ok(Action::SourceRead) => Record::SourceRead,
+                Ok(Action::TargetRead) => {
+                    let bytes = data
+                        .get(..length)
+                        .ok_or(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+                    let start = literals.len();
+                    literals.extend_from_slice(bytes);
+                    data = &data[length..];
+                    Record::TargetRead(start)
                 }
+                Ok(Action::SourceCopy) => Record::SourceCopy(data.read_signed_var_int()?),
+                Ok(Action::TargetCopy) => Record::TargetCopy(data.read_signed_var_int()?),
                 Err(_) => {
                     return Err(Error::InvalidPatch);
                 }
-            });
+            };
+            records.push(Packed::new(length, record));
         }
 
         let this = Self {
             src_data: bps_ups::Validation {
                 size: src_size,
                 crc: data.read_u32::<LE>()?,
+                ..Default::default()
             },
             out_data: bps_ups::Validation {
                 size: out_size,
                 crc: data.read_u32::<LE>()?,
+                ..Default::default()
             },
             metadata,
             records,
+            literals: literals.into_boxed_slice(),
         };
 
-        this.export(Some(data.read_u32::<LE>()?))?;
+        // hashed as written rather than re-encoded, so a non-canonical encoding still loads and
+        // can be linted
+        let crc = data.read_u32::<LE>()?;
+        let hash = hash::crc32(&patch[..patch.len() - 4]);
+        if hash != crc {
+            return Err(Error::CorruptPatch(hash, crc));
+        }
         Ok(this)
     }
 
-    #[inline(always)]
-    fn read_copy_size(data: &mut impl std::io::Read) -> Result<isize> {
-        let num = data.read_var_int()?;
-        Ok(if num & 0b1 != 0 { -1 } else { 1 } * (num >> 1) as isize)
+    /// Creates a patch from runs of unchanged and changed bytes. The output isn't as small as a
+    /// patch that searches for copies, but it's fast to create.
+    pub fn create(src: &[u8], dst: &[u8]) -> Self {
+        Self::create_with(src, dst, &CreateOptions::default())
     }
-}
 
-impl Patch for BpsPatch {
-    fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
-        self.validate(rom).unwrap()?;
+    pub fn create_with(src: &[u8], dst: &[u8], options: &CreateOptions) -> Self {
+        Self::create_observed(src, dst, options, &mut ())
+            .expect("creation can't be cancelled without an observer")
+    }
+
+    /// Like [`create_with`](Self::create_with), reporting progress through the modified file to
+    /// `observer`. Fails with [`Error::Cancelled`] if the observer cancels.
+    pub fn create_observed(
+        src: &[u8],
+        dst: &[u8],
+        options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<Self> {
+        /// Runs of one byte at least this long are copied from the output instead of stored.
+        const MIN_RUN: usize = 16;
+
+        let span = Span::create("BPS");
+        let mut records = Vec::new();
+        let mut literals = Vec::new();
+        let mut target_read = |records: &mut Vec<_>, data: &[u8]| {
+            literals.extend_from_slice(data);
+            records.push(Packed::new(
+                data.len(),
+                Record::TargetRead(literals.len() - data.len()),
+            ));
+        };
+        // where the last target copy ended, which the next one's offset is relative to
+        let mut copy_end = 0;
+        let mut i = 0;
+        let mut ticker = Ticker::new(observer, dst.len());
+        while i < dst.len() {
+            let start = i;
+            let same = src.get(i) == Some(&dst[i]);
+            while i < dst.len() && (src.get(i) == Some(&dst[i])) == same {
+                i += 1;
+                ticker.tick(i)?;
+            }
+
+            if same {
+                records.push(Packed::new(i - start, Record::SourceRead));
+                continue;
+            }
+
+            // store the first byte of a long run, then copy it over the rest, so new data with
+            // nothing to read from the source (e.g. a file created from scratch) stays small
+            let mut literal = start;
+            let mut j = start;
+            while j < i {
+                let run = dst[j..i].iter().take_while(|&&b| b == dst[j]).count();
+                if run >= MIN_RUN {
+                    target_read(&mut records, &dst[literal..=j]);
+                    records.push(Packed::new(
+                        run - 1,
+                        Record::TargetCopy(j as isize - copy_end as isize),
+                    ));
+                    copy_end = j + run - 1;
+                    literal = j + run;
+                }
+                j += run;
+            }
+            if literal < i {
+                target_read(&mut records, &dst[literal..i]);
+            }
+        }
+        ticker.finish();
+
+        span.finish(Some(records.len()), None);
+        Ok(Self {
+            src_data: bps_ups::Validation::new(src),
+            out_data: bps_ups::Validation::new(dst),
+            metadata: options.metadata().map(Vec::into_boxed_slice),
+            records,
+            literals: literals.into_boxed_slice(),
+        })
+    }
+
+    /// The XML manifest in the patch's metadata, if it has one.
+    pub fn manifest(&self) -> Option<Manifest> {
+        Manifest::parse(self.metadata.as_deref()?)
+    }
+
+    /// Drops the metadata and re-encodes the records in their smallest equivalent form, as
+    /// [`canonicalize`](Self::canonicalize) does. The output is unchanged.
+    pub fn minify(&mut self) {
+        self.metadata = None;
+        self.canonicalize();
+    }
+
+    /// Re-encodes the records in their canonical form: source copies to the same offset become
+    /// source reads, and touching records of the same kind are merged. Patches that perform the
+    /// same actions, however they were split up, then export to identical bytes. The output and
+    /// the metadata are unchanged.
+    pub fn canonicalize(&mut self) {
+        /// A record with its copy offset made absolute, so records can be merged and removed
+        /// without disturbing the relative offsets of the ones after them.
+        enum Absolute {
+            SourceRead,
+            TargetRead,
+            SourceCopy(isize),
+            TargetCopy(isize),
+        }
+
+        // literals are copied in record order, so touching TargetReads stay contiguous
+        let mut literals = Vec::with_capacity(self.literals.len());
+        let mut records: Vec<(usize, Absolute)> = Vec::new();
+        let (mut out_offset, mut src_offset, mut dst_offset) = (0isize, 0isize, 0isize);
+        for (length, record) in self.iter() {
+            let record = match record {
+                Record::SourceRead => Absolute::SourceRead,
+                Record::TargetRead(start) => {
+                    literals.extend_from_slice(self.literal(start, length));
+                    Absolute::TargetRead
+                }
+                Record::SourceCopy(delta) => {
+                    src_offset += delta;
+                    let start = src_offset;
+                    src_offset += length as isize;
+                    if start == out_offset {
+                        Absolute::SourceRead
+                    } else {
+                        Absolute::SourceCopy(start)
+                    }
+                }
+                Record::TargetCopy(delta) => {
+                    dst_offset += delta;
+                    let start = dst_offset;
+                    dst_offset += length as isize;
+                    Absolute::TargetCopy(start)
+                }
+            };
+            out_offset += length as isize;
+
+            match (records.last_mut(), record) {
+                (Some((len, Absolute::SourceRead)), Absolute::SourceRead) => *len += length,
+                (Some((len, Absolute::TargetRead)), Absolute::TargetRead) => *len += length,
+                (Some((len, Absolute::SourceCopy(a))), Absolute::SourceCopy(b))
+                | (Some((len, Absolute::TargetCopy(a))), Absolute::TargetCopy(b))
+                    if *a + *len as isize == b =>
+                {
+                    *len += length
+                }
+                (_, record) => records.push((length, record)),
+            }
+        }
+
+        let (mut src_offset, mut dst_offset, mut literal) = (0isize, 0isize, 0);
+        self.records = records
+            .into_iter()
+            .map(|(length, record)| {
+                let record = match record {
+                    Absolute::SourceRead => Record::SourceRead,
+                    Absolute::TargetRead => {
+                        literal += length;
+                        Record::TargetRead(literal - length)
+                    }
+                    Absolute::SourceCopy(start) => {
+                        let delta = start - src_offset;
+                        src_offset = start + length as isize;
+                        Record::SourceCopy(delta)
+                    }
+                    Absolute::TargetCopy(start) => {
+                        let delta = start - dst_offset;
+                        dst_offset = start + length as isize;
+                        Record::TargetCopy(delta)
+                    }
+                };
+                Packed::new(length, record)
+            })
+            .collect();
+        self.literals = literals.into_boxed_slice();
+    }
+
+    /// Every way the records disagree with the declared sizes: reading past the end of the
+    /// source, running past the end of the output, or not producing all of it. Only the first
+    /// record past the end of the output is reported. A copy starting outside the source, or
+    /// outside the output written before it, is malformed rather than sized wrong, and fails
+    /// instead.
+    fn size_problems(&self) -> Result<Vec<Error>> {
+        let (src_size, out_size) = (self.src_data.size, self.out_data.size);
+        let mut problems = Vec::new();
+        let mut src_offset: usize = 0;
+        let mut dst_offset: usize = 0;
+        let mut end: usize = 0;
+        for (index, (length, record)) in self.iter().enumerate() {
+            let read = match record {
+                Record::SourceRead => Some(end.saturating_add(length)),
+                Record::SourceCopy(offset) => {
+                    src_offset = src_offset
+                        .checked_add_signed(offset)
+                        .filter(|&start| start <= src_size)
+                        .ok_or(Error::SourceOutOfRange {
+                            index,
+                            size: src_size,
+                        })?
+                        .saturating_add(length);
+                    Some(src_offset)
+                }
+                Record::TargetCopy(offset) => {
+                    dst_offset = dst_offset
+                        .checked_add_signed(offset)
+                        .filter(|&start| start < end)
+                        .ok_or(Error::TargetOutOfRange { index })?
+                        .saturating_add(length);
+                    None
+                }
+                Record::TargetRead(_) => None,
+            };
+            if let Some(read) = read.filter(|&read| read > src_size) {
+                problems.push(Error::SourceOverrun {
+                    index,
+                    end: read,
+                    size: src_size,
+                });
+            }
+
+            let start = end;
+            end = end.saturating_add(length);
+            if end > out_size && start <= out_size {
+                problems.push(Error::RecordOverrun {
+                    index,
+                    end,
+                    size: out_size,
+                });
+            }
+        }
+
+        if end < out_size {
+            problems.push(Error::IncompleteOutput(end, out_size));
+        }
+        Ok(problems)
+    }
+
+    /// Fails with the first of the [size problems](Self::size_problems), so a bad patch is
+    /// reported at the record that causes it instead of as a checksum mismatch after applying it.
+    fn strict(self) -> Result<Self> {
+        match self.size_problems()?.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(self),
+        }
+    }
+
+    /// See [`lint::lint`]. The loaded records say what each one is, and the file is walked
+    /// alongside them for how they're encoded.
+    pub(crate) fn lint(data: &[u8]) -> Result<Vec<Lint>> {
+        let patch = Self::load(data)?;
+        let mut lints = Vec::new();
+        lint::check_size(&mut lints, "source", patch.src_data.size);
+        lint::check_size(&mut lints, "target", patch.out_data.size);
+
+        let mut raw = &data[Self::MAGIC.len()..];
+        raw.read_var_int()?;
+        raw.read_var_int()?;
+        let metadata = raw.read_var_int()?;
+        raw = &raw[metadata..];
 
-        let mut buf = Vec::with_capacity(self.out_data.size);
         let mut src_offset: usize = 0;
         let mut out_offset: usize = 0;
-        for (length, record) in self.records.iter() {
-            let length = *length;
+        for (record, (length, kind)) in patch.iter().enumerate() {
+            raw.read_var_int()?;
+            match kind {
+                Record::SourceRead => {}
+                Record::TargetRead(_) => raw = &raw[length..],
+                Record::SourceCopy(_) | Record::TargetCopy(_) => {
+                    let offset = data.len() - raw.len();
+                    // the sign bit set on a magnitude of zero
+                    if raw.read_var_int()? == 1 {
+                        lints.push(Lint::NonCanonicalVarInt { offset });
+                    }
+                }
+            }
+
+            if let Record::SourceCopy(offset) = kind {
+                src_offset = src_offset.saturating_add_signed(offset);
+                if src_offset == out_offset {
+                    lints.push(Lint::IdenticalRewrite { record });
+                }
+                src_offset += length;
+            }
+            out_offset += length;
+        }
+        Ok(lints)
+    }
+
+    /// The records with their lengths, in patch order.
+    fn iter(&self) -> impl Iterator<Item = (usize, Record)> + '_ {
+        self.records.iter().map(|record| record.unpack())
+    }
+
+    /// The data of a TargetRead record.
+    fn literal(&self, start: usize, length: usize) -> &[u8] {
+        &self.literals[start..start + length]
+    }
+
+    /// Fails if the records address offsets that don't fit in memory, which only a hostile patch
+    /// does.
+    pub(crate) fn model(&self) -> Result<Model> {
+        let mut output = Vec::new();
+        output
+            .try_reserve_exact(self.out_data.size)
+            .map_err(|_| Error::Io(std::io::ErrorKind::OutOfMemory.into()))?;
+        let mut src_offset: usize = 0;
+        let mut out_offset: usize = 0;
+        for (length, record) in self.iter() {
+            let range = |start: usize| {
+                let end = start.checked_add(length).ok_or(Error::InvalidPatch)?;
+                Ok::<_, Error>(start..end)
+            };
             match record {
                 Record::SourceRead => {
-                    buf.write_all(&rom[buf.len()..][..length])?;
+                    output.extend(range(output.len())?.map(|i| Sym::Source(i, 0)));
                 }
-                Record::TargetRead(data) => {
-                    buf.write_all(data)?;
+                Record::TargetRead(start) => {
+                    output.extend(self.literal(start, length).iter().map(|&b| Sym::Literal(b)))
+                }
+                Record::SourceCopy(delta) => {
+                    let start = src_offset
+                        .checked_add_signed(delta)
+                        .ok_or(Error::InvalidPatch)?;
+                    let range = range(start)?;
+                    src_offset = range.end;
+                    output.extend(range.map(|i| Sym::Source(i, 0)));
                 }
-                &Record::SourceCopy(offset) => {
+                Record::TargetCopy(delta) => {
+                    let start = out_offset
+                        .checked_add_signed(delta)
+                        .ok_or(Error::InvalidPatch)?;
+                    let range = range(start)?;
+                    out_offset = range.end;
+                    for i in range {
+                        let sym = output.get(i).copied();
+                        output.push(sym.unwrap_or(Sym::Literal(0)));
+                    }
+                }
+            }
+        }
+
+        Ok(Model {
+            source: Some(self.src_data.size),
+            size: Size::Fixed(self.out_data.size),
+            output,
+        })
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self> {
+        let mut records = Vec::new();
+        let mut literals = Vec::new();
+        let mut src_offset = 0;
+        let mut out_offset = 0;
+        for action in value.get("actions")?.as_array()? {
+            let record = match action.get("action")?.as_str()? {
+                "source-read" => (action.get("length")?.as_usize()?, Record::SourceRead),
+                "target-read" => {
+                    let data = action.get("data")?.as_bytes()?;
+                    literals.extend_from_slice(&data);
+                    (data.len(), Record::TargetRead(literals.len() - data.len()))
+                }
+                kind @ ("source-copy" | "target-copy") => {
+                    let length = action.get("length")?.as_usize()?;
+                    let from = action.get("from")?.as_isize()?;
+                    let offset = if kind == "source-copy" {
+                        &mut src_offset
+                    } else {
+                        &mut out_offset
+                    };
+                    let out_of_range = || Error::InvalidJson(format!("BPS {kind} out of range"));
+                    let delta = from.checked_sub(*offset).ok_or_else(out_of_range)?;
+                    *offset = isize::try_from(length)
+                        .ok()
+                        .and_then(|length| from.checked_add(length))
+                        .ok_or_else(out_of_range)?;
+                    if kind == "source-copy" {
+                        (length, Record::SourceCopy(delta))
+                    } else {
+                        (length, Record::TargetCopy(delta))
+                    }
+                }
+                action => {
+                    return Err(Error::InvalidJson(format!("unknown BPS action '{action}'")));
+                }
+            };
+
+            if record.0 == 0 {
+                return Err(Error::InvalidJson("BPS actions can't be empty".into()));
+            }
+            records.push(Packed::new(record.0, record.1));
+        }
+
+        let patch = Self {
+            src_data: json::parse_validation(value.get("source")?)?,
+            out_data: json::parse_validation(value.get("target")?)?,
+            metadata: value
+                .get_opt("metadata")
+                .map(|metadata| metadata.as_bytes().map(Vec::into_boxed_slice))
+                .transpose()?,
+            records,
+            literals: literals.into_boxed_slice(),
+        };
+
+        patch.strict()
+    }
+}
+
+impl BpsPatch {
+    fn apply_records(
+        &self,
+        rom: &[u8],
+        mut buf: HashingWriter,
+        observer: &mut dyn Observer,
+    ) -> Result<HashingWriter> {
+        let mut src_offset: usize = 0;
+        let mut out_offset: usize = 0;
+        for (i, (length, record)) in self.iter().enumerate() {
+            match record {
+                Record::SourceRead => {
+                    buf.write_all(
+                        rom.get(buf.len()..buf.len() + length)
+                            .ok_or(Error::InvalidPatch)?,
+                    )?;
+                }
+                Record::TargetRead(start) => {
+                    buf.write_all(self.literal(start, length))?;
+                }
+                Record::SourceCopy(offset) => {
                     src_offset = src_offset
                         .checked_add_signed(offset)
                         .ok_or(Error::InvalidPatch)?;
-                    buf.write_all(&rom[src_offset..][..length])?;
+                    buf.write_all(
+                        rom.get(src_offset..src_offset + length)
+                            .ok_or(Error::InvalidPatch)?,
+                    )?;
                     src_offset += length;
                 }
-                &Record::TargetCopy(offset) => {
+                Record::TargetCopy(offset) => {
                     out_offset = out_offset
                         .checked_add_signed(offset)
                         .ok_or(Error::InvalidPatch)?;
                     // we cant use copy_from_slice or extend because we have to be able to read from
                     // the data as we write it
                     for _ in 0..length {
-                        buf.push(buf[out_offset]);
+                        buf.push(buf.get(out_offset).ok_or(Error::InvalidPatch)?);
                         out_offset += 1;
                     }
                 }
             }
+
+            observer.event(Event::RecordApplied(i));
+            progress::checkpoint(observer)?;
+            observer.event(Event::Progress {
+                done: buf.len(),
+                total: self.out_data.size,
+            });
         }
 
-        self.out_data.validate(&buf)?;
         Ok(buf)
     }
+}
+
+impl TryFrom<&[u8]> for BpsPatch {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::load(data)
+    }
+}
+
+impl Patch for BpsPatch {
+    fn apply_observed(&self, rom: &[u8], observer: &mut dyn Observer) -> Result<Vec<u8>> {
+        let span = Span::apply("BPS");
+        let result = (|| {
+            self.validate(rom).unwrap()?;
+            observer.event(Event::SourceValidated);
+            let buf = self
+                .apply_records(rom, HashingWriter::new(self.out_data.size), observer)?
+                .validate(&self.out_data)?;
+            observer.event(Event::OutputValidated);
+            Ok(buf)
+        })();
+        span.finish_with(&result, |_| self.records.len());
+        result
+    }
+
+    fn apply_unchecked(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        let buf = HashingWriter::unhashed(self.out_data.size);
+        Ok(self.apply_records(rom, buf, &mut ())?.into_inner())
+    }
+
+    fn apply_source(&self, source: &mut dyn Source) -> Result<Vec<u8>> {
+        source::validate(source, &self.src_data)?;
+        let mut buf = HashingWriter::new(self.out_data.size);
+        let mut src_offset: usize = 0;
+        let mut out_offset: usize = 0;
+        let mut read = |buf: &mut HashingWriter, offset: usize, length: usize| -> Result<()> {
+            buf.append_with(length, |dst| {
+                source.read_at(offset, dst).map_err(|err| match err.kind() {
+                    std::io::ErrorKind::UnexpectedEof => Error::InvalidPatch,
+                    _ => err.into(),
+                })
+            })
+        };
+        for (length, record) in self.iter() {
+            match record {
+                Record::SourceRead => {
+                    let offset = buf.len();
+                    read(&mut buf, offset, length)?
+                }
+                Record::TargetRead(start) => buf.write_all(self.literal(start, length))?,
+                Record::SourceCopy(offset) => {
+                    src_offset = src_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    read(&mut buf, src_offset, length)?;
+                    src_offset += length;
+                }
+                Record::TargetCopy(offset) => {
+                    out_offset = out_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    for _ in 0..length {
+                        buf.push(buf.get(out_offset).ok_or(Error::InvalidPatch)?);
+                        out_offset += 1;
+                    }
+                }
+            }
+        }
+
+        buf.validate(&self.out_data)
+    }
+
+    fn apply_into(&self, rom: &[u8], out: &mut [u8]) -> Result<()> {
+        self.validate(rom).unwrap()?;
+        if out.len() != self.out_data.size {
+            return Err(Error::InvalidSize(out.len(), self.out_data.size));
+        }
+
+        let mut hasher = Crc32Hasher::new();
+        let mut pos: usize = 0;
+        let mut src_offset: usize = 0;
+        let mut out_offset: usize = 0;
+        for (length, record) in self.iter() {
+            let end = pos + length;
+            let dst = out.get_mut(pos..end).ok_or(Error::InvalidPatch)?;
+            match record {
+                Record::SourceRead => {
+                    dst.copy_from_slice(rom.get(pos..end).ok_or(Error::InvalidPatch)?);
+                }
+                Record::TargetRead(start) => dst.copy_from_slice(self.literal(start, length)),
+                Record::SourceCopy(offset) => {
+                    src_offset = src_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    dst.copy_from_slice(
+                        rom.get(src_offset..src_offset + length)
+                            .ok_or(Error::InvalidPatch)?,
+                    );
+                    src_offset += length;
+                }
+                Record::TargetCopy(offset) => {
+                    out_offset = out_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    // the copy can read bytes it has just written, but nothing past them
+                    for i in pos..end {
+                        if out_offset >= i {
+                            return Err(Error::InvalidPatch);
+                        }
+                        out[i] = out[out_offset];
+                        out_offset += 1;
+                    }
+                }
+            }
+
+            hasher.update(&out[pos..end]);
+            pos = end;
+        }
+
+        if pos != out.len() {
+            let err = Error::InvalidSize(pos, out.len());
+            return Err(Error::OutputMismatch(Box::new(err)));
+        }
+        self.out_data
+            .validate_crc(out, hasher.finalize())
+            .map_err(|err| Error::OutputMismatch(Box::new(err)))
+    }
+
+    fn apply_vectored<'a>(&'a self, rom: &'a [u8]) -> Result<Vec<Cow<'a, [u8]>>> {
+        self.validate(rom).unwrap()?;
+        let mut out = Chunks::new();
+        let mut src_offset: usize = 0;
+        let mut out_offset: usize = 0;
+        for (length, record) in self.iter() {
+            match record {
+                Record::SourceRead => out.push(Cow::Borrowed(
+                    rom.get(out.len()..out.len() + length)
+                        .ok_or(Error::InvalidPatch)?,
+                )),
+                Record::TargetRead(start) => out.push(Cow::Borrowed(self.literal(start, length))),
+                Record::SourceCopy(offset) => {
+                    src_offset = src_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    out.push(Cow::Borrowed(
+                        rom.get(src_offset..src_offset + length)
+                            .ok_or(Error::InvalidPatch)?,
+                    ));
+                    src_offset += length;
+                }
+                Record::TargetCopy(offset) => {
+                    out_offset = out_offset
+                        .checked_add_signed(offset)
+                        .ok_or(Error::InvalidPatch)?;
+                    // the copy can overlap the bytes it produces, so read those from the copy
+                    let mut bytes = Vec::with_capacity(length);
+                    for i in out_offset..out_offset + length {
+                        let byte = match i.checked_sub(out.len()) {
+                            Some(i) => bytes.get(i).copied(),
+                            None => out.get(i),
+                        };
+                        bytes.push(byte.ok_or(Error::InvalidPatch)?);
+                    }
+                    out_offset += length;
+                    out.push(Cow::Owned(bytes));
+                }
+            }
+        }
+
+        let chunks = out.into_vec();
+        self.out_data
+            .validate_chunks(&chunks)
+            .map_err(|err| Error::OutputMismatch(Box::new(err)))?;
+        Ok(chunks)
+    }
 
     fn validate(&self, rom: &[u8]) -> Option<Result<()>> {
         Some(self.src_data.validate(rom))
     }
 
-    fn export(&self, crc: Option<u32>) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
+    fn source(&self) -> Option<bps_ups::Validation> {
+        Some(self.src_data)
+    }
+
+    fn target(&self) -> Option<bps_ups::Validation> {
+        Some(self.out_data)
+    }
+
+    fn estimated_peak_memory(&self, _rom_len: usize) -> usize {
+        std::mem::size_of_val(&self.records[..])
+            + self.literals.len()
+            + self.metadata.as_ref().map_or(0, |metadata| metadata.len())
+            + self.out_data.size
+    }
+
+    fn format(&self) -> &'static str {
+        "BPS"
+    }
+
+    fn metadata(&self) -> Option<&[u8]> {
+        self.metadata.as_deref()
+    }
 
-        buf.write_all(Self::MAGIC)?;
-        buf.write_var_int(self.src_data.size)?;
-        buf.write_var_int(self.out_data.size)?;
+    fn regions(&self) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let mut src_offset: isize = 0;
+        let mut out_offset: isize = 0;
+        let mut offset = 0;
+        for (length, record) in self.iter() {
+            let kind = match record {
+                Record::SourceRead => None,
+                Record::TargetRead(_) => Some(RegionKind::Literal),
+                Record::SourceCopy(delta) => {
+                    src_offset += delta;
+                    let start = src_offset as usize;
+                    src_offset += length as isize;
+                    // copying from the same offset leaves the output unchanged
+                    (start != offset).then_some(RegionKind::SourceCopy(start))
+                }
+                Record::TargetCopy(delta) => {
+                    out_offset += delta;
+                    let kind = RegionKind::TargetCopy(out_offset as usize);
+                    out_offset += length as isize;
+                    Some(kind)
+                }
+            };
 
+            if let Some(kind) = kind {
+                regions.push(Region {
+                    offset,
+                    len: length,
+                    kind,
+                });
+            }
+            offset += length;
+        }
+
+        regions
+    }
+
+    fn records(&self) -> Vec<crate::Record<'_>> {
+        let mut src_offset: isize = 0;
+        let mut out_offset: isize = 0;
+        let mut offset = 0;
+        let mut records = Vec::with_capacity(self.records.len());
+        for (length, record) in self.iter() {
+            let kind = match record {
+                Record::SourceRead => RecordKind::SourceRead,
+                Record::TargetRead(start) => RecordKind::Literal(self.literal(start, length)),
+                Record::SourceCopy(delta) => {
+                    src_offset += delta;
+                    let kind = RecordKind::SourceCopy(src_offset as usize);
+                    src_offset += length as isize;
+                    kind
+                }
+                Record::TargetCopy(delta) => {
+                    out_offset += delta;
+                    let kind = RecordKind::TargetCopy(out_offset as usize);
+                    out_offset += length as isize;
+                    kind
+                }
+            };
+
+            records.push(crate::Record {
+                offset,
+                len: length,
+                kind,
+            });
+            offset += length;
+        }
+
+        records
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = Object::new()
+            .str("format", "bps")
+            .raw("source", &json::validation(&self.src_data))
+            .raw("target", &json::validation(&self.out_data));
         if let Some(metadata) = &self.metadata {
-            buf.write_var_int(metadata.len())?;
-            buf.write_all(metadata)?;
-        } else {
-            buf.write_var_int(0)?;
+            json = json.bytes("metadata", metadata);
         }
 
-        for (length, record) in self.records.iter() {
-            buf.write_var_int(((*length - 1) << 2) + Action::from(record) as usize)?;
+        let mut src_offset: isize = 0;
+        let mut out_offset: isize = 0;
+        let actions = self.iter().map(|(length, record)| {
+            let action = Object::new();
             match record {
-                Record::SourceRead => {}
-                Record::TargetRead(data) => buf.write_all(data)?,
-                Record::SourceCopy(offset) | Record::TargetCopy(offset) => {
-                    buf.write_var_int(
-                        (offset.unsigned_abs() << 1) | (offset.is_negative() as usize),
-                    )?;
+                Record::SourceRead => action.str("action", "source-read").num("length", length),
+                Record::TargetRead(start) => action
+                    .str("action", "target-read")
+                    .bytes("data", self.literal(start, length)),
+                Record::SourceCopy(delta) => {
+                    src_offset += delta;
+                    let action = action
+                        .str("action", "source-copy")
+                        .num("length", length)
+                        .num("from", src_offset);
+                    src_offset += length as isize;
+                    action
+                }
+                Record::TargetCopy(delta) => {
+                    out_offset += delta;
+                    let action = action
+                        .str("action", "target-copy")
+                        .num("length", length)
+                        .num("from", out_offset);
+                    out_offset += length as isize;
+                    action
                 }
             }
-        }
+            .finish()
+        });
+        json.array("actions", actions).finish()
+    }
 
-        buf.write_u32::<LE>(self.src_data.crc)?;
-        buf.write_u32::<LE>(self.out_data.crc)?;
+    fn export(&self, crc: Option<u32>) -> Result<Vec<u8>> {
+        let span = Span::export("BPS");
+        let result = (|| {
+            let mut buf = Vec::new();
 
-        let hash = crc32fast::hash(&buf);
-        if let Some(crc) = crc {
-            if hash != crc {
-                return Err(Error::InvalidCRC(hash, crc));
+            buf.write_all(Self::MAGIC)?;
+            buf.write_var_int(self.src_data.size)?;
+            buf.write_var_int(self.out_data.size)?;
+
+            if let Some(metadata) = &self.metadata {
+                buf.write_var_int(metadata.len())?;
+                buf.write_all(metadata)?;
+            } else {
+                buf.write_var_int(0)?;
             }
+
+            for (length, record) in self.iter() {
+                buf.write_var_int(((length - 1) << 2) + Action::from(&record) as usize)?;
+                match record {
+                    Record::SourceRead => {}
+                    Record::TargetRead(start) => buf.write_all(self.literal(start, length))?,
+                    Record::SourceCopy(offset) | Record::TargetCopy(offset) => {
+                        buf.write_signed_var_int(offset)?;
+                    }
+                }
+            }
+
+            let hash = encoding::write_footer(&mut buf, self.src_data.crc, self.out_data.crc);
+            if let Some(crc) = crc {
+                if hash != crc {
+                    return Err(Error::CorruptPatch(hash, crc));
+                }
+            }
+
+            Ok(buf)
+        })();
+        span.finish_with(&result, |_| self.records.len());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "abcd" -> "abxyabxy": source read, target read, source copy, target copy
+    fn sample() -> BpsPatch {
+        let mut data = b"BPS1\x84\x88\x80\x84\x85xy\x86\x80\x87\x84".to_vec();
+        data.extend_from_slice(&crc32fast::hash(b"abcd").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(b"abxyabxy").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+
+        BpsPatch::load(&data).unwrap()
+    }
+
+    #[test]
+    fn coverage() {
+        let data = |src_size: u8, out_size: u8| {
+            let mut data = b"BPS1".to_vec();
+            data.extend_from_slice(&[0x80 | src_size, 0x80 | out_size]);
+            data.extend_from_slice(b"\x80\x84\x85xy\x86\x80\x87\x84");
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+            data
+        };
+        let load = |out_size: u8| BpsPatch::load(&data(4, out_size));
+
+        assert!(load(8).is_ok());
+        assert!(matches!(
+            load(5),
+            Err(Error::RecordOverrun {
+                index: 2,
+                end: 6,
+                size: 5
+            })
+        ));
+        assert!(matches!(load(9), Err(Error::IncompleteOutput(8, 9))));
+
+        assert!(matches!(
+            BpsPatch::load(&data(1, 8)),
+            Err(Error::SourceOverrun {
+                index: 0,
+                end: 2,
+                size: 1
+            })
+        ));
+        let (patch, warnings) = BpsPatch::load_lenient(&data(1, 9)).unwrap();
+        assert_eq!(patch.records.len(), 4);
+        assert!(matches!(
+            warnings[..],
+            [
+                Error::SourceOverrun { index: 0, .. },
+                Error::SourceOverrun { index: 2, .. },
+                Error::IncompleteOutput(8, 9)
+            ]
+        ));
+    }
+
+    #[test]
+    fn copy_outside_input() {
+        let data = |records: &[u8]| {
+            let mut data = b"BPS1\x84\x82\x80".to_vec();
+            data.extend_from_slice(records);
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+            data
+        };
+        // a source copy from -1, then one from 5, past the end of the source
+        for records in [&b"\x86\x83"[..], b"\x80\x82\x8A"] {
+            let data = data(records);
+            assert!(matches!(
+                BpsPatch::load(&data),
+                Err(Error::SourceOutOfRange { size: 4, .. })
+            ));
+            assert!(BpsPatch::load_lenient(&data).is_err());
         }
+        assert!(BpsPatch::load(&data(b"\x86\x84")).is_ok());
 
-        buf.write_u32::<LE>(hash)?;
-        Ok(buf)
+        // a target copy from the byte being written, then one from -1
+        for records in [&b"\x81x\x83\x82"[..], b"\x81x\x83\x83"] {
+            assert!(matches!(
+                BpsPatch::load(&data(records)),
+                Err(Error::TargetOutOfRange { index: 1 })
+            ));
+        }
+        assert!(BpsPatch::load(&data(b"\x81x\x83\x80")).is_ok());
+    }
+
+    #[test]
+    fn model_overflow() {
+        // copies from far enough ahead that the output offset overflows
+        let patch = BpsPatch {
+            src_data: Default::default(),
+            out_data: bps_ups::Validation {
+                size: 3,
+                ..Default::default()
+            },
+            metadata: None,
+            records: vec![
+                Packed::new(1, Record::TargetRead(0)),
+                Packed::new(1, Record::TargetCopy(isize::MAX)),
+                Packed::new(1, Record::TargetCopy(isize::MAX)),
+            ],
+            literals: Box::new(*b"x"),
+        };
+        assert!(matches!(patch.model(), Err(Error::InvalidPatch)));
+        assert!(sample().model().is_ok());
+    }
+
+    #[test]
+    fn regions() {
+        let patch = sample();
+        assert_eq!(patch.apply(b"abcd").unwrap(), b"abxyabxy");
+        assert_eq!(
+            patch.regions(),
+            [
+                Region {
+                    offset: 2,
+                    len: 2,
+                    kind: RegionKind::Literal
+                },
+                Region {
+                    offset: 4,
+                    len: 2,
+                    kind: RegionKind::SourceCopy(0)
+                },
+                Region {
+                    offset: 6,
+                    len: 2,
+                    kind: RegionKind::TargetCopy(2)
+                },
+            ]
+        );
+
+        // "abcd" -> "cdab", copying backwards to the start of the source
+        let mut data = b"BPS1\x84\x84\x80\x86\x84\x86\x89".to_vec();
+        data.extend_from_slice(&crc32fast::hash(b"abcd").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(b"cdab").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+        let patch = BpsPatch::load(&data).unwrap();
+        assert_eq!(patch.apply(b"abcd").unwrap(), b"cdab");
+        assert_eq!(
+            patch.regions(),
+            [
+                Region {
+                    offset: 0,
+                    len: 2,
+                    kind: RegionKind::SourceCopy(2)
+                },
+                Region {
+                    offset: 2,
+                    len: 2,
+                    kind: RegionKind::SourceCopy(0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn records() {
+        let patch = sample();
+        let kinds: Vec<_> = patch.records().iter().map(|r| (r.offset, r.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (0, RecordKind::SourceRead),
+                (2, RecordKind::Literal(b"xy")),
+                (4, RecordKind::SourceCopy(0)),
+                (6, RecordKind::TargetCopy(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let patch = sample();
+        let json = patch.to_json();
+        assert!(json.contains(r#"{"action":"target-copy","length":2,"from":2}"#));
+
+        let parsed = crate::json::from_json(&json).unwrap();
+        assert_eq!(parsed.export(None).unwrap(), patch.export(None).unwrap());
+    }
+
+    #[test]
+    fn create() {
+        let patch = BpsPatch::create(b"abcdef", b"abXYefgh");
+        let patch = BpsPatch::load(&patch.export(None).unwrap()).unwrap();
+        assert_eq!(patch.apply(b"abcdef").unwrap(), b"abXYefgh");
+    }
+
+    #[test]
+    fn create_from_empty() {
+        let mut data = b"header".to_vec();
+        data.extend([0; 100]);
+        data.extend(b"middle");
+        data.extend([0xFF; 50]);
+        let patch = BpsPatch::create(b"", &data);
+        assert!(patch.creates_file());
+        let kinds: Vec<_> = patch.records().iter().map(|r| r.kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [
+                RecordKind::Literal(b"header\0"),
+                RecordKind::TargetCopy(6),
+                RecordKind::Literal(b"middle\xFF"),
+                RecordKind::TargetCopy(112),
+            ]
+        ));
+
+        let export = patch.export(None).unwrap();
+        assert!(export.len() < data.len());
+        let patch = BpsPatch::load(&export).unwrap();
+        assert_eq!(patch.apply(b"").unwrap(), data);
+        assert!(patch.apply(b"x").is_err());
+        assert!(!BpsPatch::create(b"abcd", b"abXd").creates_file());
+    }
+
+    #[test]
+    fn create_with_manifest() {
+        let options = CreateOptions {
+            provenance: true,
+            target_name: Some("hack.sfc".into()),
+            manifest: Manifest {
+                title: Some("My Hack".into()),
+                author: Some("Someone".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let patch = BpsPatch::create_with(b"abcd", b"abXd", &options);
+        let patch = BpsPatch::load(&patch.export(None).unwrap()).unwrap();
+        let manifest = patch.manifest().unwrap();
+        assert_eq!(manifest.title.as_deref(), Some("My Hack"));
+        assert_eq!(manifest.author.as_deref(), Some("Someone"));
+        assert_eq!(manifest.target.as_deref(), Some("hack.sfc"));
+        assert!(manifest.tool.is_some());
+
+        let options = CreateOptions {
+            metadata: Some(b"notes".to_vec()),
+            ..options
+        };
+        let patch = BpsPatch::create_with(b"abcd", b"abXd", &options);
+        assert_eq!(patch.metadata(), Some(&b"notes"[..]));
+        assert!(BpsPatch::create(b"abcd", b"abXd").metadata().is_none());
+    }
+
+    #[test]
+    fn shared_literals() {
+        // every other byte changes, so there's a TargetRead per changed byte
+        let src = vec![0u8; 1000];
+        let dst: Vec<u8> = (0..1000).map(|i| (i % 2 * (i % 255 + 1)) as u8).collect();
+        let patch = BpsPatch::create(&src, &dst);
+        assert_eq!(patch.records.len(), 1000);
+        assert_eq!(patch.literals.len(), 500);
+        assert_eq!(patch.apply(&src).unwrap(), dst);
+
+        let data = patch.export(None).unwrap();
+        let loaded = BpsPatch::load(&data).unwrap();
+        assert_eq!(loaded.literals, patch.literals);
+        assert_eq!(loaded.export(None).unwrap(), data);
+    }
+
+    #[test]
+    fn minify() {
+        // metadata, split target reads, a source copy to the same offset, and split copies
+        let mut data = b"BPS1\x84\x86\x84note".to_vec();
+        data.extend_from_slice(b"\x81x\x81y\x86\x84\x83\x80\x83\x80");
+        data.extend_from_slice(&crc32fast::hash(b"abcd").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(b"xycdxy").to_le_bytes());
+        data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+        let mut patch = BpsPatch::load(&data).unwrap();
+        assert_eq!(patch.apply(b"abcd").unwrap(), b"xycdxy");
+
+        patch.minify();
+        assert!(patch.metadata().is_none());
+        let kinds: Vec<_> = patch.records().iter().map(|r| (r.offset, r.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (0, RecordKind::Literal(b"xy")),
+                (2, RecordKind::SourceRead),
+                (4, RecordKind::TargetCopy(0)),
+            ]
+        );
+        let minified = patch.export(None).unwrap();
+        assert!(minified.len() < data.len());
+        assert_eq!(
+            BpsPatch::load(&minified).unwrap().apply(b"abcd").unwrap(),
+            b"xycdxy"
+        );
+    }
+
+    #[test]
+    fn canonicalize() {
+        let data = |records: &[u8]| {
+            let mut data = b"BPS1\x84\x86\x84note".to_vec();
+            data.extend_from_slice(records);
+            data.extend_from_slice(&crc32fast::hash(b"abcd").to_le_bytes());
+            data.extend_from_slice(&crc32fast::hash(b"xyabxy").to_le_bytes());
+            data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+            data
+        };
+        // split target reads, a source copy from offset -0, and split target copies
+        let split = data(b"\x81x\x81y\x86\x81\x83\x80\x83\x80");
+        let merged = data(b"\x85xy\x86\x80\x87\x80");
+
+        let mut patch = BpsPatch::load(&split).unwrap();
+        patch.canonicalize();
+        assert_eq!(patch.metadata(), Some(&b"note"[..]));
+        assert_eq!(patch.export(None).unwrap(), merged);
+        assert_eq!(patch.apply(b"abcd").unwrap(), b"xyabxy");
+        assert_eq!(crate::canonicalize(&merged).unwrap(), merged);
     }
 }
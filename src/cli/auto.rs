@@ -0,0 +1,87 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use patch_rs::{prelude::*, softpatch};
+
+use super::log::status;
+use crate::{load_patch, write_atomic};
+
+#[derive(Args)]
+pub struct AutoArgs {
+    /// The ROM file to patch
+    rom: PathBuf,
+    /// The output file. Defaults to the ROM's name with '.patched' before its extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Finds the patches for `rom`: the soft-patches RetroArch would apply, or failing that, any patch
+/// in the same directory whose expected source matches the ROM.
+fn find_patches(rom: &Path, data: &[u8]) -> Result<Vec<(PathBuf, Box<dyn Patch>)>> {
+    let found = softpatch::softpatches(rom);
+    if !found.is_empty() {
+        return found
+            .into_iter()
+            .map(|path| load_patch(&path).map(|patch| (path, patch)))
+            .collect();
+    }
+
+    let dir = rom
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let source = Validation::new(data);
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|ext| {
+                    softpatch::EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+                })
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .find_map(|path| {
+            load_patch(&path)
+                .ok()
+                .filter(|patch| patch.source() == Some(source))
+                .map(|patch| (path, patch))
+        })
+        .into_iter()
+        .collect())
+}
+
+pub fn auto(args: AutoArgs) -> Result<()> {
+    let mut data = fs::read(&args.rom)?;
+    let patches = find_patches(&args.rom, &data)?;
+    if patches.is_empty() {
+        bail!("No patch found for '{}'.", args.rom.display());
+    }
+
+    let dest = args.output.unwrap_or_else(|| {
+        let mut name = args.rom.file_stem().unwrap_or_default().to_owned();
+        name.push(".patched");
+        if let Some(ext) = args.rom.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        args.rom.with_file_name(name)
+    });
+
+    for (path, patch) in patches.iter() {
+        data = patch.apply(&data)?;
+        status!("{} + {}", args.rom.display(), path.display());
+    }
+
+    write_atomic(&dest, &data)?;
+    status!("-> {}", dest.display());
+    Ok(())
+}
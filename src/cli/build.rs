@@ -0,0 +1,42 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use patch_rs::{script::Script, Patch};
+
+use super::create::Format;
+use crate::write_atomic;
+
+#[derive(Args)]
+pub struct BuildArgs {
+    /// The patch script (*.toml)
+    script: PathBuf,
+    /// The output file. Defaults to the script with the format's extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// The patch format
+    #[arg(short, long, default_value = "ips")]
+    format: Format,
+    /// The ROM the script is written against. Required for UPS and BPS, which record its checksum
+    #[arg(short, long)]
+    base: Option<PathBuf>,
+}
+
+pub fn build(args: BuildArgs) -> Result<()> {
+    let script = Script::parse(&fs::read_to_string(&args.script)?)?;
+    let (format, patch) = match (args.format, &args.base) {
+        (Format::Ips, _) => (Format::Ips, script.to_ips()?.export(None)?),
+        (format, Some(base)) => {
+            let base = fs::read(base)?;
+            format.create(&base, &script.apply(&base), &Default::default(), &mut ())?
+        }
+        (_, None) => bail!("A base ROM is required to build UPS and BPS patches"),
+    };
+
+    write_atomic(
+        &args
+            .output
+            .unwrap_or_else(|| args.script.with_extension(format.extension())),
+        &patch,
+    )
+}
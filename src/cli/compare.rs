@@ -0,0 +1,40 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use patch_rs::equiv::{self, Comparison};
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// The first patch
+    a: PathBuf,
+    /// The second patch
+    b: PathBuf,
+    /// The ROM to apply both patches to. Without it, the patches are compared by what they write
+    base: Option<PathBuf>,
+}
+
+pub fn compare(args: CompareArgs) -> Result<()> {
+    let a = crate::load_patch(&args.a)?;
+    let b = crate::load_patch(&args.b)?;
+    let base = args.base.map(fs::read).transpose()?;
+
+    match equiv::compare(&*a, &*b, base.as_deref()) {
+        Comparison::Equivalent => {
+            println!("The patches are equivalent.");
+            Ok(())
+        }
+        Comparison::Different(ranges) => {
+            for range in ranges.iter() {
+                println!(
+                    "{:#08X}-{:#08X} {:>8} bytes",
+                    range.start,
+                    range.end - 1,
+                    range.len()
+                );
+            }
+            bail!("The patches differ in {} range(s).", ranges.len())
+        }
+        Comparison::Incomparable(reason) => bail!("{reason}"),
+    }
+}
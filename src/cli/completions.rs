@@ -0,0 +1,49 @@
+use std::io;
+
+use anyhow::Result;
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+use crate::Arguments;
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for
+    shell: Shell,
+}
+
+pub fn completions(args: CompletionsArgs) -> Result<()> {
+    let mut cmd = Arguments::command();
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(args.shell, &mut cmd, name, &mut io::stdout());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn every_shell() {
+        for &shell in Shell::value_variants() {
+            let mut cmd = Arguments::command();
+            let mut out = Vec::new();
+            clap_complete::generate(shell, &mut cmd, "patch-rs", &mut out);
+            let script = String::from_utf8(out).unwrap();
+            assert!(script.contains("apply"), "{shell}");
+            assert!(script.contains("output-size"), "{shell}");
+        }
+    }
+
+    #[test]
+    fn value_enums() {
+        let mut cmd = Arguments::command();
+        let mut out = Vec::new();
+        clap_complete::generate(Shell::Bash, &mut cmd, "patch-rs", &mut out);
+        let script = String::from_utf8(out).unwrap();
+        // the shells are offered as words rather than falling back to file names
+        assert!(script.contains("bash elvish fish powershell zsh"));
+    }
+}
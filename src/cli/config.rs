@@ -0,0 +1,91 @@
+use std::{env, fs, io, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use patch_rs::header::HeaderPolicy;
+use toml_edit::{Document, Item};
+
+use super::create::Format;
+
+/// Defaults read from `~/.config/patch-rs/config.toml`. Every setting can be overridden by the
+/// corresponding command line flag.
+#[derive(Default)]
+pub struct Config {
+    /// The directory to write patched files to
+    pub dest_dir: Option<PathBuf>,
+    /// The format used by `create`
+    pub format: Option<Format>,
+    /// How `apply` handles copier headers
+    pub header: HeaderPolicy,
+    /// Whether `apply` and `create` show their progress. Defaults to whether stderr is a terminal
+    pub progress: Option<bool>,
+    /// The directory of patches `serve` offers
+    pub patch_dir: Option<PathBuf>,
+    /// The directory of ROMs `serve` can apply patches to
+    pub rom_dir: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("patch-rs").join("config.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Self::parse(&text).with_context(|| format!("Invalid config file '{}'", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let doc: Document = text.parse()?;
+        let mut config = Self::default();
+        for (key, item) in doc.iter() {
+            match key {
+                "dest_dir" => config.dest_dir = Some(str_value(key, item)?.into()),
+                "patch_dir" => config.patch_dir = Some(str_value(key, item)?.into()),
+                "rom_dir" => config.rom_dir = Some(str_value(key, item)?.into()),
+                "progress" => {
+                    config.progress = Some(
+                        item.as_bool()
+                            .with_context(|| format!("Setting '{key}' must be true or false."))?,
+                    )
+                }
+                "format" => {
+                    config.format = Some(
+                        Format::from_str(str_value(key, item)?, true)
+                            .map_err(anyhow::Error::msg)?,
+                    )
+                }
+                "header" => {
+                    config.header = match str_value(key, item)? {
+                        "keep" => HeaderPolicy::Keep,
+                        "skip" => HeaderPolicy::Skip,
+                        "add" => HeaderPolicy::Add,
+                        "auto" => HeaderPolicy::Auto,
+                        value => bail!("Unknown header policy '{value}'."),
+                    }
+                }
+                _ => bail!("Unknown setting '{key}'."),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn str_value<'a>(key: &str, item: &'a Item) -> Result<&'a str> {
+    item.as_str()
+        .with_context(|| format!("Setting '{key}' must be a string."))
+}
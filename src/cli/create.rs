@@ -0,0 +1,220 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use patch_rs::{manifest::Manifest, prelude::*};
+
+use super::{
+    config::Config,
+    log::{self, status, Level},
+};
+use crate::{write_atomic, Failure};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Ips,
+    Ups,
+    Bps,
+    /// Whichever of IPS, UPS, and BPS is smallest
+    Auto,
+}
+
+impl Format {
+    /// The formats `Auto` chooses from, in order of preference when sizes tie.
+    const CONCRETE: [Format; 3] = [Format::Ips, Format::Ups, Format::Bps];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Ips => "ips",
+            Format::Ups => "ups",
+            Format::Bps => "bps",
+            Format::Auto => unreachable!("`auto` is replaced by the format it chose"),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Ips => "IPS",
+            Format::Ups => "UPS",
+            Format::Bps => "BPS",
+            Format::Auto => "auto",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_str(path.extension()?.to_str()?, true).ok()
+    }
+
+    /// Creates the patch, returning it with the format it's in. `Auto` creates the patch in every
+    /// format that can represent the change and keeps the smallest. Progress through the modified
+    /// file is reported to `observer`, once per format for `Auto`.
+    pub fn create(
+        self,
+        original: &[u8],
+        modified: &[u8],
+        options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<(Format, Vec<u8>)> {
+        if self != Format::Auto {
+            return Ok((self, self.encode(original, modified, options, observer)?));
+        }
+
+        Ok(smallest(Self::candidates(
+            original, modified, options, observer,
+        )?))
+    }
+
+    /// The patch in every format that can represent the change.
+    pub fn candidates(
+        original: &[u8],
+        modified: &[u8],
+        options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<(Format, Vec<u8>)>> {
+        Self::CONCRETE
+            .into_iter()
+            .filter_map(
+                |format| match format.encode(original, modified, options, observer) {
+                    Err(err) if ips_cant_represent(&err) => None,
+                    result => Some(result.map(|patch| (format, patch))),
+                },
+            )
+            .collect()
+    }
+
+    fn encode(
+        self,
+        original: &[u8],
+        modified: &[u8],
+        options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<u8>> {
+        Ok(match self {
+            Format::Ips => {
+                IpsPatch::create_observed(original, modified, options, observer)?.export(None)?
+            }
+            Format::Ups => {
+                UpsPatch::create_observed(original, modified, options, observer)?.export(None)?
+            }
+            Format::Bps => {
+                BpsPatch::create_observed(original, modified, options, observer)?.export(None)?
+            }
+            Format::Auto => unreachable!("`auto` is resolved before encoding"),
+        })
+    }
+}
+
+fn smallest(candidates: Vec<(Format, Vec<u8>)>) -> (Format, Vec<u8>) {
+    candidates
+        .into_iter()
+        .min_by_key(|(_, patch)| patch.len())
+        .expect("UPS and BPS can represent any change")
+}
+
+/// Whether `err` means the change needs offsets or a truncation size past IPS's 24 bits.
+fn ips_cant_represent(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref(),
+        Some(patch_rs::Error::OutOfRange(..) | patch_rs::Error::EofOffset)
+    )
+}
+
+#[derive(Args)]
+pub struct CreateArgs {
+    /// The original file
+    original: PathBuf,
+    /// The modified file
+    modified: PathBuf,
+    /// The output file. Defaults to the modified file with the format's extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// The patch format. `auto` keeps the smallest and reports the choice [default: ups]
+    #[arg(short, long)]
+    format: Option<Format>,
+    /// Record this tool, the time, and the file names in the patch's metadata (BPS only)
+    #[arg(long)]
+    provenance: bool,
+    /// The title to record in the patch's metadata (BPS only)
+    #[arg(long)]
+    title: Option<String>,
+    /// The author to record in the patch's metadata (BPS only)
+    #[arg(long)]
+    author: Option<String>,
+    /// The version to record in the patch's metadata (BPS only)
+    #[arg(long)]
+    version: Option<String>,
+    /// The description to record in the patch's metadata (BPS only)
+    #[arg(long)]
+    description: Option<String>,
+    /// Store this file as the patch's metadata instead of generating a manifest (BPS only)
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["provenance", "title", "author", "version", "description"]
+    )]
+    metadata_file: Option<PathBuf>,
+}
+
+pub fn create(args: CreateArgs, config: &Config) -> Result<()> {
+    let start = Instant::now();
+    let format = args.format.or(config.format).unwrap_or(Format::Ups);
+    let original = fs::read(&args.original)?;
+    let modified = fs::read(&args.modified)?;
+
+    let name = |path: &Path| Some(path.file_name()?.to_string_lossy().into_owned());
+    let options = CreateOptions {
+        provenance: args.provenance,
+        source_name: name(&args.original),
+        target_name: name(&args.modified),
+        manifest: Manifest {
+            title: args.title,
+            author: args.author,
+            version: args.version,
+            description: args.description,
+            ..Default::default()
+        },
+        metadata: args.metadata_file.as_ref().map(fs::read).transpose()?,
+    };
+    if (options.manifest != Manifest::default() || options.metadata.is_some())
+        && format != Format::Bps
+    {
+        return Err(Failure::Usage(
+            "Only BPS patches can store metadata; use --format bps.".into(),
+        )
+        .into());
+    }
+    let mut progress = log::Progress::new("Creating");
+    let (format, patch) = if format == Format::Auto {
+        let candidates = Format::candidates(&original, &modified, &options, &mut progress)?;
+        let sizes: Vec<_> = candidates
+            .iter()
+            .map(|(format, patch)| format!("{} {} bytes", format.name(), patch.len()))
+            .collect();
+        let chosen = smallest(candidates);
+        status!("Chose {} ({})", chosen.0.name(), sizes.join(", "));
+        chosen
+    } else {
+        format.create(&original, &modified, &options, &mut progress)?
+    };
+    let output = args
+        .output
+        .unwrap_or_else(|| args.modified.with_extension(format.extension()));
+    write_atomic(&output, &patch)?;
+    log::fields(
+        Level::Verbose,
+        "create",
+        &[
+            ("patch", &output.display()),
+            ("format", &format.name()),
+            ("source_crc", &log::crc(&original)),
+            ("target_crc", &log::crc(&modified)),
+            ("size", &patch.len()),
+            ("elapsed", &log::elapsed(start)),
+        ],
+    );
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use clap::Args;
+
+const WIDTH: usize = 16;
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// The original file
+    original: PathBuf,
+    /// The modified file
+    patched: PathBuf,
+    /// The number of unchanged lines to show around each change
+    #[arg(short = 'C', long, default_value_t = 1)]
+    context: usize,
+}
+
+enum Side {
+    Original,
+    Patched,
+    Both,
+}
+
+struct Printer<W> {
+    out: W,
+    color: bool,
+}
+
+impl<W: Write> Printer<W> {
+    fn line(&mut self, offset: usize, side: Side, row: &[u8], other: &[u8]) -> io::Result<()> {
+        let (marker, color) = match side {
+            Side::Original => ('-', "\x1b[31m"),
+            Side::Patched => ('+', "\x1b[32m"),
+            Side::Both => (' ', ""),
+        };
+
+        write!(self.out, "{offset:08X} {marker} ")?;
+        for i in 0..WIDTH {
+            match row.get(i) {
+                Some(byte) if self.color && other.get(i) != Some(byte) => {
+                    write!(self.out, "{color}{byte:02X}\x1b[0m ")?
+                }
+                Some(byte) => write!(self.out, "{byte:02X} ")?,
+                None => write!(self.out, "   ")?,
+            }
+        }
+
+        let ascii: String = row
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(self.out, " |{ascii}|")
+    }
+}
+
+fn row(data: &[u8], row: usize) -> &[u8] {
+    data.get(row * WIDTH..)
+        .map_or(&[], |s| &s[..s.len().min(WIDTH)])
+}
+
+pub fn diff(args: DiffArgs) -> Result<()> {
+    let original = fs::read(&args.original)?;
+    let patched = fs::read(&args.patched)?;
+
+    let rows = original.len().max(patched.len()).div_ceil(WIDTH);
+    let changed: Vec<usize> = (0..rows)
+        .filter(|&i| row(&original, i) != row(&patched, i))
+        .collect();
+
+    let stdout = io::stdout();
+    let mut printer = Printer {
+        color: stdout.is_terminal(),
+        out: stdout.lock(),
+    };
+
+    if changed.is_empty() {
+        writeln!(printer.out, "Files are identical.")?;
+        return Ok(());
+    }
+
+    let mut next = 0;
+    let mut changes = changed.iter().peekable();
+    while let Some(&first) = changes.next() {
+        let mut last = first;
+        while let Some(&&i) = changes.peek() {
+            if i > last + 2 * args.context + 1 {
+                break;
+            }
+            last = i;
+            changes.next();
+        }
+
+        let start = first.saturating_sub(args.context).max(next);
+        let end = (last + args.context + 1).min(rows);
+        if start > next {
+            writeln!(printer.out, "* ({} identical lines)", start - next)?;
+        }
+
+        for i in start..end {
+            let (a, b) = (row(&original, i), row(&patched, i));
+            if a == b {
+                printer.line(i * WIDTH, Side::Both, a, b)?;
+            } else {
+                if !a.is_empty() {
+                    printer.line(i * WIDTH, Side::Original, a, b)?;
+                }
+                if !b.is_empty() {
+                    printer.line(i * WIDTH, Side::Patched, b, a)?;
+                }
+            }
+        }
+
+        next = end;
+    }
+
+    if next < rows {
+        writeln!(printer.out, "* ({} identical lines)", rows - next)?;
+    }
+
+    writeln!(
+        printer.out,
+        "{} of {rows} lines differ ({:#X} -> {:#X} bytes).",
+        changed.len(),
+        original.len(),
+        patched.len()
+    )?;
+    Ok(())
+}
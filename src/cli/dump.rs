@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::{Record, RecordKind};
+
+/// The number of bytes of literal data shown per record.
+const PREVIEW: usize = 16;
+
+#[derive(Args)]
+pub struct DumpArgs {
+    /// The patch to dump
+    patch: PathBuf,
+}
+
+fn preview(data: &[u8]) -> String {
+    let shown = &data[..data.len().min(PREVIEW)];
+    let hex: Vec<_> = shown.iter().map(|b| format!("{b:02X}")).collect();
+    let ascii: String = shown
+        .iter()
+        .map(|&b| match b {
+            0x20..=0x7e => b as char,
+            _ => '.',
+        })
+        .collect();
+    let more = if data.len() > PREVIEW { " ..." } else { "" };
+    format!("{}{more} |{ascii}|", hex.join(" "))
+}
+
+/// Names the record's action, with a short description of what it writes.
+pub fn describe(record: &Record) -> (&'static str, String) {
+    match record.kind {
+        RecordKind::Literal(data) => ("literal", preview(data)),
+        RecordKind::Fill(byte) => ("fill", format!("{byte:02X}")),
+        RecordKind::Xor(data) => ("xor", preview(data)),
+        RecordKind::SourceRead => ("source-read", String::new()),
+        RecordKind::SourceCopy(from) => ("source-copy", format!("from {from:#08X}")),
+        RecordKind::TargetCopy(from) => ("target-copy", format!("from {from:#08X}")),
+    }
+}
+
+pub fn dump(args: DumpArgs) -> Result<()> {
+    let patch = crate::load_patch(&args.patch)?;
+    if let Some(source) = patch.source() {
+        println!("source: {:#X} bytes, CRC32 {:08X}", source.size, source.crc);
+    }
+
+    let records = patch.records();
+    let width = records.len().to_string().len();
+    for (i, record) in records.iter().enumerate() {
+        let (action, detail) = describe(record);
+        let line = format!(
+            "#{i:<width$} {action:<11} {:#08X} {:>8} bytes  {detail}",
+            record.offset, record.len
+        );
+        println!("{}", line.trim_end());
+    }
+
+    println!("{} records.", records.len());
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use patch_rs::prelude::*;
+
+use super::log::notice;
+use crate::{parse_int, write_atomic};
+
+#[derive(Args)]
+pub struct EditArgs {
+    /// The IPS patch to edit
+    patch: PathBuf,
+    /// Delete the record at the given index, counting from 0 in file order
+    #[arg(long, value_parser = parse_int)]
+    delete_record: Vec<usize>,
+    /// Move the record starting at OFFSET to TARGET. A TARGET prefixed with '+' or '-' is
+    /// relative to OFFSET
+    #[arg(long, value_name = "OFFSET:TARGET", value_parser = parse_move)]
+    r#move: Vec<(usize, Target)>,
+    /// Rewrite the records so none overlap, keeping the bytes that win when the patch is applied
+    #[arg(long)]
+    normalize: bool,
+    /// The output file. Defaults to overwriting the patch
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Target {
+    Absolute(usize),
+    Forward(usize),
+    Backward(usize),
+}
+
+fn parse_move(s: &str) -> Result<(usize, Target), String> {
+    let (offset, target) = s
+        .split_once(':')
+        .ok_or_else(|| "expected OFFSET:TARGET".to_string())?;
+    let target = if let Some(delta) = target.strip_prefix('+') {
+        Target::Forward(parse_int(delta)?)
+    } else if let Some(delta) = target.strip_prefix('-') {
+        Target::Backward(parse_int(delta)?)
+    } else {
+        Target::Absolute(parse_int(target)?)
+    };
+
+    Ok((parse_int(offset)?, target))
+}
+
+pub fn edit(args: EditArgs) -> Result<()> {
+    let mut patch =
+        IpsPatch::load(&fs::read(&args.patch)?).context("Only IPS patches can be edited")?;
+
+    let mut deleted = args.delete_record.clone();
+    deleted.sort_unstable();
+    deleted.dedup();
+    // remove from the back so the indices keep referring to the original file order
+    for &index in deleted.iter().rev() {
+        patch.remove_record(index)?;
+    }
+
+    for &(offset, target) in args.r#move.iter() {
+        let new_offset = match target {
+            Target::Absolute(target) => Some(target),
+            Target::Forward(delta) => offset.checked_add(delta),
+            Target::Backward(delta) => offset.checked_sub(delta),
+        }
+        .with_context(|| format!("Can't move the record at {offset:#X} out of bounds"))?;
+        patch.move_record(offset, new_offset)?;
+    }
+
+    if args.normalize {
+        for conflict in patch.conflicts() {
+            match conflict {
+                IpsConflict::Overlap(a, b) => {
+                    notice!("note: records {a} and {b} overlap, record {b} wins")
+                }
+                IpsConflict::Overwritten(i) => {
+                    notice!("note: record {i} is completely overwritten by later records")
+                }
+            }
+        }
+        patch.normalize();
+    }
+
+    write_atomic(
+        args.output.as_ref().unwrap_or(&args.patch),
+        &patch.export(None)?,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::header::RomChecksums;
+
+#[derive(Args)]
+pub struct HashArgs {
+    /// The files to hash
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+pub fn hash(args: HashArgs) -> Result<()> {
+    for path in args.files.iter() {
+        let checksums = RomChecksums::new(&fs::read(path)?);
+        println!(
+            "{}: size {:#X}, CRC32 {:08X}",
+            path.display(),
+            checksums.full.size,
+            checksums.full.crc
+        );
+        if let Some((kind, headerless)) = checksums.headerless {
+            println!(
+                "  without {kind} header: size {:#X}, CRC32 {:08X}",
+                headerless.size, headerless.crc
+            );
+        }
+    }
+
+    Ok(())
+}
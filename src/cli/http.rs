@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use patch_rs::hash;
+use ureq::Agent;
+
+use super::log::notice;
+
+/// The largest patch we're willing to download.
+const MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long a download may take in total before it's abandoned.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Downloads `url`, refusing anything but HTTPS (including redirects) and anything larger than
+/// [`MAX_SIZE`].
+pub fn download(url: &str) -> Result<Vec<u8>> {
+    let agent: Agent = Agent::config_builder()
+        .https_only(true)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let data = agent
+        .get(url)
+        .call()
+        .and_then(|mut response| {
+            response
+                .body_mut()
+                .with_config()
+                .limit(MAX_SIZE as u64)
+                .read_to_vec()
+        })
+        .with_context(|| format!("Couldn't download '{url}'"))?;
+
+    notice!(
+        "Downloaded {url} ({} bytes, CRC32 {:08X})",
+        data.len(),
+        hash::crc32(&data)
+    );
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn https_only() {
+        // refused before connecting, so this doesn't need the network
+        let err = download("http://127.0.0.1:9/patch.ips").unwrap_err();
+        assert!(format!("{err:#}").contains("http://127.0.0.1:9/patch.ips"));
+        assert!(matches!(
+            err.downcast_ref::<ureq::Error>(),
+            Some(ureq::Error::RequireHttpsOnly(_))
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::{manifest::Manifest, RecordKind, Validation};
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// The patch to describe
+    patch: PathBuf,
+}
+
+fn validation(name: &str, validation: Option<Validation>) {
+    if let Some(v) = validation {
+        println!("{name:<8} {:#X} bytes, CRC32 {:08X}", v.size, v.crc);
+    }
+}
+
+pub fn info(args: InfoArgs) -> Result<()> {
+    let patch = crate::load_patch(&args.patch)?;
+    let records = patch.records();
+    let size = patch.export(None)?.len();
+
+    println!("format   {}", patch.format());
+    println!("size     {size:#X} bytes");
+    validation("source", patch.source());
+    validation("target", patch.target());
+    if patch.creates_file() {
+        println!("         creates a new file; apply it to an empty one");
+    }
+    if let Some(manifest) = patch.metadata().and_then(Manifest::parse) {
+        for (name, value) in [
+            ("title", manifest.title),
+            ("author", manifest.author),
+            ("version", manifest.version),
+            ("about", manifest.description),
+            ("tool", manifest.tool),
+            ("created", manifest.created),
+            ("from", manifest.source),
+            ("to", manifest.target),
+        ] {
+            if let Some(value) = value {
+                println!("{name:<8} {}", value.replace('\n', "\n         "));
+            }
+        }
+    } else if let Some(metadata) = patch.metadata() {
+        let text = String::from_utf8_lossy(metadata);
+        let first = text.lines().map(str::trim).find(|l| !l.is_empty());
+        println!(
+            "metadata {:#X} bytes{}",
+            metadata.len(),
+            first.map_or(String::new(), |line| format!(", starting \"{line}\""))
+        );
+    }
+
+    println!("records  {}", records.len());
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    // record sizes in power-of-two buckets: 1, 2-3, 4-7, ...
+    let mut buckets = [0usize; usize::BITS as usize];
+    for record in records.iter().filter(|r| r.len != 0) {
+        buckets[record.len.ilog2() as usize] += 1;
+    }
+    let last = buckets.iter().rposition(|&n| n != 0).unwrap_or(0);
+    let most = *buckets.iter().max().unwrap();
+    println!("\nrecord sizes");
+    for (bucket, &count) in buckets[..=last].iter().enumerate() {
+        let (lo, hi) = (1usize << bucket, (1usize << bucket << 1) - 1);
+        let range = if lo == hi {
+            lo.to_string()
+        } else {
+            format!("{lo}-{hi}")
+        };
+        let bar = "#".repeat((count * 40).div_ceil(most));
+        println!("{}", format!("  {range:>13} {count:>6} {bar}").trim_end());
+    }
+
+    let (mut literal, mut fill, mut source, mut copy) = (0, 0, 0, 0);
+    for record in records.iter() {
+        match record.kind {
+            RecordKind::Literal(_) | RecordKind::Xor(_) => literal += record.len,
+            RecordKind::Fill(_) => fill += record.len,
+            RecordKind::SourceRead => source += record.len,
+            RecordKind::SourceCopy(_) | RecordKind::TargetCopy(_) => copy += record.len,
+        }
+    }
+
+    println!("\noutput bytes");
+    for (name, count) in [
+        ("stored", literal),
+        ("filled", fill),
+        ("unchanged", source),
+        ("copied", copy),
+    ] {
+        if count != 0 {
+            println!("  {name:<9} {count:>10}");
+        }
+    }
+
+    // a naive patch stores every byte of the output
+    let output = patch
+        .target()
+        .map(|target| target.size)
+        .unwrap_or_else(|| records.iter().map(|r| r.offset + r.len).max().unwrap_or(0));
+    if output != 0 {
+        println!(
+            "\npatch is {:.1}% of the {output:#X} byte output",
+            size as f64 * 100.0 / output as f64
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::{Record, RecordKind};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use super::dump::describe;
+
+/// The number of bytes per hexdump line.
+const ROW: usize = 16;
+
+const HELP: &str = "↑/↓ PgUp/PgDn Home/End: records  ←/→: scroll data  /: go to offset  q: quit";
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// The patch to inspect
+    patch: PathBuf,
+}
+
+/// Finds the record that writes `offset`, or else the nearest one after it. IPS records can be in
+/// any order, so this doesn't assume they're sorted.
+fn find(records: &[Record], offset: usize) -> Option<usize> {
+    records
+        .iter()
+        .position(|r| (r.offset..r.offset + r.len.max(1)).contains(&offset))
+        .or_else(|| {
+            (0..records.len())
+                .filter(|&i| records[i].offset > offset)
+                .min_by_key(|&i| records[i].offset)
+        })
+}
+
+fn hexdump_line(offset: usize, row: &[u8]) -> Line<'static> {
+    let hex: Vec<_> = row.iter().map(|b| format!("{b:02X}")).collect();
+    let ascii: String = row
+        .iter()
+        .map(|&b| match b {
+            0x20..=0x7e => b as char,
+            _ => '.',
+        })
+        .collect();
+    Line::from(format!(
+        "{offset:08X}  {:<w$}  |{ascii}|",
+        hex.join(" "),
+        w = ROW * 3 - 1
+    ))
+}
+
+struct Inspector<'a> {
+    title: String,
+    records: Vec<Record<'a>>,
+    selected: usize,
+    /// The first record shown in the list.
+    top: usize,
+    /// The first hexdump line shown for the selected record.
+    scroll: usize,
+    /// The number of records and hexdump lines that fit, as of the last draw.
+    list_height: usize,
+    dump_height: usize,
+    /// The offset being typed, while searching.
+    search: Option<String>,
+    status: String,
+}
+
+impl<'a> Inspector<'a> {
+    fn new(title: String, records: Vec<Record<'a>>) -> Self {
+        Self {
+            title,
+            records,
+            selected: 0,
+            top: 0,
+            scroll: 0,
+            list_height: 1,
+            dump_height: 1,
+            search: None,
+            status: HELP.into(),
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.records.len().saturating_sub(1));
+        self.scroll = 0;
+    }
+
+    /// The number of hexdump lines the selected record has.
+    fn dump_lines(&self) -> usize {
+        match self.records.get(self.selected).map(|r| r.kind) {
+            Some(RecordKind::Literal(data) | RecordKind::Xor(data)) => data.len().div_ceil(ROW),
+            _ => 1,
+        }
+    }
+
+    /// Handles a key press, returning false to quit.
+    fn handle(&mut self, key: KeyCode) -> bool {
+        if let Some(search) = &mut self.search {
+            match key {
+                KeyCode::Char(c) if c.is_ascii_hexdigit() || c == 'x' => search.push(c),
+                KeyCode::Backspace => _ = search.pop(),
+                KeyCode::Enter => {
+                    let text = self.search.take().unwrap();
+                    let digits = text.trim_start_matches("0x");
+                    match usize::from_str_radix(digits, 16) {
+                        Ok(offset) => match find(&self.records, offset) {
+                            Some(index) => {
+                                self.select(index);
+                                self.status = HELP.into();
+                            }
+                            None => self.status = format!("No record at or after {offset:#X}."),
+                        },
+                        Err(_) => self.status = format!("'{text}' isn't a hex offset."),
+                    }
+                }
+                KeyCode::Esc => self.search = None,
+                _ => {}
+            }
+            return true;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.select(self.selected.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(self.selected + 1),
+            KeyCode::PageUp => self.select(self.selected.saturating_sub(self.list_height)),
+            KeyCode::PageDown => self.select(self.selected + self.list_height),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(usize::MAX),
+            KeyCode::Left => self.scroll = self.scroll.saturating_sub(self.dump_height),
+            KeyCode::Right => {
+                self.scroll = (self.scroll + self.dump_height).min(self.dump_lines() - 1);
+            }
+            KeyCode::Char('/') => self.search = Some(String::new()),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let width = self.records.len().to_string().len();
+        let list_width = (width + 33) as u16;
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, dump_area] =
+            Layout::horizontal([Constraint::Length(list_width), Constraint::Min(0)]).areas(main);
+        self.list_height = (list_area.height.saturating_sub(2) as usize).max(1);
+        self.dump_height = (dump_area.height.saturating_sub(2) as usize).max(1);
+
+        // keep the selection in view
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + self.list_height {
+            self.top = self.selected + 1 - self.list_height;
+        }
+
+        let items: Vec<_> = self
+            .records
+            .iter()
+            .enumerate()
+            .skip(self.top)
+            .take(self.list_height)
+            .map(|(i, record)| {
+                let (action, _) = describe(record);
+                ListItem::new(format!(
+                    "#{i:<width$} {action:<11} {:#08X} {:>8}",
+                    record.offset, record.len
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" {} ", self.title)))
+            .highlight_style(Style::new().reversed());
+        let selected = (!self.records.is_empty()).then(|| self.selected - self.top);
+        let mut state = ListState::default().with_selected(selected);
+        frame.render_stateful_widget(list, list_area, &mut state);
+
+        let (title, lines) = match self.records.get(self.selected) {
+            Some(record) => {
+                let (action, detail) = describe(record);
+                let title = format!(
+                    " #{} {action} {:#X}..{:#X} ",
+                    self.selected,
+                    record.offset,
+                    record.offset + record.len
+                );
+                let lines = match record.kind {
+                    RecordKind::Literal(data) | RecordKind::Xor(data) => data
+                        .chunks(ROW)
+                        .enumerate()
+                        .skip(self.scroll)
+                        .take(self.dump_height)
+                        .map(|(i, row)| hexdump_line(record.offset + i * ROW, row))
+                        .collect(),
+                    _ => vec![Line::from(
+                        format!("{action} {detail}").trim_end().to_owned(),
+                    )],
+                };
+                (title, lines)
+            }
+            None => (String::new(), vec![Line::from("The patch has no records.")]),
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            dump_area,
+        );
+
+        let status_line = match &self.search {
+            Some(search) => format!("Go to offset: {search}_"),
+            None => self.status.clone(),
+        };
+        frame.render_widget(Line::from(status_line), status);
+    }
+}
+
+pub fn inspect(args: InspectArgs) -> Result<()> {
+    let patch = crate::load_patch(&args.patch)?;
+    let title = format!(
+        "{} ({})",
+        args.patch.file_name().unwrap_or_default().to_string_lossy(),
+        patch.format()
+    );
+    let mut inspector = Inspector::new(title, patch.records());
+
+    let mut terminal = ratatui::init();
+    let result = inspector.run(&mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct JsonArgs {
+    /// The patch to convert. JSON files (*.json) are compiled back to their binary format
+    patch: PathBuf,
+    /// Write the output to a file instead of printing it. Required when compiling
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn json(args: JsonArgs) -> Result<()> {
+    let patch = crate::load_patch(&args.patch)?;
+    let compile = args
+        .patch
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    match (compile, args.output) {
+        (true, Some(output)) => crate::write_atomic(&output, &patch.export(None)?),
+        (true, None) => anyhow::bail!("An output file is required to compile a JSON patch"),
+        (false, Some(output)) => {
+            crate::write_atomic(&output, format!("{}\n", patch.to_json()).as_bytes())
+        }
+        (false, None) => {
+            println!("{}", patch.to_json());
+            Ok(())
+        }
+    }
+}
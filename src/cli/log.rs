@@ -0,0 +1,146 @@
+//! Output verbosity, set once from the global `-q` and `-v` flags.
+//!
+//! Status lines (what was written where, notes, and warnings) are printed with [`status!`] and
+//! [`notice!`], and hidden by `-q`. Errors and the output a command exists to print, such as
+//! `info` or `dump`, are always printed. `-v` adds a line of `key=value` fields to stderr for each
+//! file processed, and `-vv` one for each step, e.g.
+//!
+//! ```text
+//! event=apply rom=game.sfc dest=hack.sfc format=BPS source_crc=0x12345678 output_crc=0x9abcdef0 size=524288 elapsed=0.004s
+//! ```
+
+use std::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Instant,
+};
+
+use patch_rs::{
+    hash,
+    progress::{Event, Observer},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_level(quiet: bool, verbose: u8) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::Quiet,
+        (false, 0) => Level::Normal,
+        (false, 1) => Level::Verbose,
+        (false, _) => Level::Debug,
+    };
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Sets whether [`Progress`] draws anything, from `--progress`, `--no-progress`, or the config.
+pub fn set_progress(show: bool) {
+    PROGRESS.store(show, Ordering::Relaxed);
+}
+
+/// An observer that draws the percentage of an operation on stderr as it goes, and clears it
+/// once it's done, unless progress is turned off or `-q` was given.
+pub struct Progress<'a> {
+    label: &'a str,
+    shown: Option<usize>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(label: &'a str) -> Self {
+        Self { label, shown: None }
+    }
+}
+
+impl Observer for Progress<'_> {
+    fn event(&mut self, event: Event) {
+        let Event::Progress { done, total } = event else {
+            return;
+        };
+        if !PROGRESS.load(Ordering::Relaxed) || !enabled(Level::Normal) {
+            return;
+        }
+
+        if done >= total {
+            if self.shown.take().is_some() {
+                eprint!("\r{:width$}\r", "", width = self.label.len() + 6);
+            }
+            return;
+        }
+
+        let percent = (done as u128 * 100 / total as u128) as usize;
+        if self.shown.replace(percent) != Some(percent) {
+            eprint!("\r{}: {percent:>3}%", self.label);
+        }
+    }
+}
+
+/// Prints `event` and `fields` as one line of `key=value` pairs to stderr, if the verbosity is at
+/// least `level`. Values containing whitespace, quotes, or `=` are quoted.
+pub fn fields(level: Level, event: &str, fields: &[(&str, &dyn Display)]) {
+    if !enabled(level) {
+        return;
+    }
+
+    let mut line = format!("event={event}");
+    for (key, value) in fields {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=')
+        {
+            line += &format!(" {key}={value:?}");
+        } else {
+            line += &format!(" {key}={value}");
+        }
+    }
+    eprintln!("{line}");
+}
+
+/// Formats a CRC32 the way the structured fields print it. The data is only hashed when the
+/// value is displayed, so fields below the verbosity cost nothing.
+pub fn crc(data: &[u8]) -> impl Display + '_ {
+    struct Crc<'a>(&'a [u8]);
+
+    impl Display for Crc<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:#010x}", hash::crc32(self.0))
+        }
+    }
+
+    Crc(data)
+}
+
+/// Formats the time since `start` the way the structured fields print it.
+pub fn elapsed(start: Instant) -> String {
+    format!("{:.3}s", start.elapsed().as_secs_f64())
+}
+
+/// Prints a status line to stdout unless `-q` was given.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::cli::log::enabled($crate::cli::log::Level::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints a note or warning to stderr unless `-q` was given.
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        if $crate::cli::log::enabled($crate::cli::log::Level::Normal) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {notice, status};
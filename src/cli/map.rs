@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::{Region, RegionKind};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct MapArgs {
+    /// The patch to map
+    patch: PathBuf,
+    /// Print the regions as a JSON array
+    #[arg(long)]
+    json: bool,
+}
+
+/// A region as printed by `--json`.
+#[derive(Serialize)]
+struct JsonRegion {
+    offset: usize,
+    length: usize,
+    #[serde(flatten)]
+    kind: JsonKind,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum JsonKind {
+    Literal,
+    Fill { value: u8 },
+    Xor,
+    SourceCopy { from: usize },
+    TargetCopy { from: usize },
+}
+
+pub fn map(args: MapArgs) -> Result<()> {
+    let regions = crate::load_patch(&args.patch)?.regions();
+    if args.json {
+        println!("{}", to_json(&regions)?);
+        return Ok(());
+    }
+
+    for region in regions.iter() {
+        println!("{}", describe(region));
+    }
+
+    Ok(())
+}
+
+fn describe(region: &Region) -> String {
+    let source = match region.kind {
+        RegionKind::Literal => "literal".to_string(),
+        RegionKind::Fill(byte) => format!("fill {byte:#04X}"),
+        RegionKind::Xor => "xor".to_string(),
+        RegionKind::SourceCopy(from) => format!("copy from source {from:#08X}"),
+        RegionKind::TargetCopy(from) => format!("copy from output {from:#08X}"),
+    };
+    // an empty IPS record covers no bytes, so it has no last byte to show
+    let range = match region.len {
+        0 => format!("{:#08X}", region.offset),
+        len => format!("{:#08X}-{:#08X}", region.offset, region.offset + len - 1),
+    };
+    format!("{range:<17} {:>8} bytes  {source}", region.len)
+}
+
+fn to_json(regions: &[Region]) -> Result<String> {
+    let regions: Vec<_> = regions
+        .iter()
+        .map(|region| JsonRegion {
+            offset: region.offset,
+            length: region.len,
+            kind: match region.kind {
+                RegionKind::Literal => JsonKind::Literal,
+                RegionKind::Fill(value) => JsonKind::Fill { value },
+                RegionKind::Xor => JsonKind::Xor,
+                RegionKind::SourceCopy(from) => JsonKind::SourceCopy { from },
+                RegionKind::TargetCopy(from) => JsonKind::TargetCopy { from },
+            },
+        })
+        .collect();
+    Ok(serde_json::to_string(&regions)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_region() {
+        let regions = [
+            Region {
+                offset: 0x10,
+                len: 0,
+                kind: RegionKind::Literal,
+            },
+            Region {
+                offset: 0x20,
+                len: 4,
+                kind: RegionKind::Fill(0xFF),
+            },
+            Region {
+                offset: 0x30,
+                len: 2,
+                kind: RegionKind::SourceCopy(8),
+            },
+        ];
+        assert_eq!(
+            describe(&regions[0]),
+            "0x000010                 0 bytes  literal"
+        );
+        assert_eq!(
+            describe(&regions[1]),
+            "0x000020-0x000023        4 bytes  fill 0xFF"
+        );
+        assert_eq!(
+            to_json(&regions).unwrap(),
+            r#"[{"offset":16,"length":0,"kind":"literal"},{"offset":32,"length":4,"kind":"fill","value":255},{"offset":48,"length":2,"kind":"source-copy","from":8}]"#
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::Args;
+use patch_rs::prelude::*;
+
+use super::log::status;
+use crate::write_atomic;
+
+#[derive(Args)]
+pub struct MinifyArgs {
+    /// The patch to minify
+    patch: PathBuf,
+    /// The output file. Defaults to overwriting the patch
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn minify(args: MinifyArgs) -> Result<()> {
+    let data = fs::read(&args.patch)?;
+    let minified = match patch_rs::detect(&data)? {
+        patch_rs::Format::Bps => {
+            let mut patch = BpsPatch::load(&data)?;
+            patch.minify();
+            patch.export(None)?
+        }
+        patch_rs::Format::Ips => {
+            let mut patch = IpsPatch::load(&data)?;
+            patch.normalize();
+            patch.export(None)?
+        }
+        patch_rs::Format::Ups => {
+            let mut patch = UpsPatch::load(&data)?;
+            patch.canonicalize();
+            patch.export(None)?
+        }
+    };
+
+    // normalizing can split overlapping IPS records into more than they started as
+    let original = data.len();
+    let minified = if minified.len() < original {
+        minified
+    } else {
+        data
+    };
+    let output = args.output.as_ref().unwrap_or(&args.patch);
+    write_atomic(output, &minified)?;
+    status!(
+        "Wrote {} ({original} -> {} bytes)",
+        output.display(),
+        minified.len()
+    );
+    Ok(())
+}
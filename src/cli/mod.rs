@@ -0,0 +1,24 @@
+pub mod auto;
+pub mod build;
+pub mod compare;
+pub mod completions;
+pub mod config;
+pub mod create;
+pub mod diff;
+pub mod dump;
+pub mod edit;
+pub mod hash;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod info;
+#[cfg(feature = "tui")]
+pub mod inspect;
+pub mod json;
+pub mod log;
+pub mod map;
+pub mod minify;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "dat")]
+pub mod verify;
+pub mod watch;
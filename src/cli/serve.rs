@@ -0,0 +1,600 @@
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use patch_rs::{header::HeaderKind, softpatch, Error};
+
+use super::{
+    config::Config,
+    log::{notice, status},
+};
+use crate::load_patch;
+
+/// The largest ROM accepted in an upload.
+const MAX_ROM: usize = 256 * 1024 * 1024;
+/// The largest request line and headers accepted.
+const MAX_HEAD: u64 = 16 * 1024;
+/// How long a connection may wait on a read or write before it's dropped.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const INDEX: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>patch-rs</title></head>
+<body>
+<h1>patch-rs</h1>
+<form id="form">
+<p><label>Patch <select id="patch">{patches}</select></label></p>
+<p><label>ROM <input type="file" id="file"></label>{roms}</p>
+<p><button>Patch</button> <span id="status"></span></p>
+</form>
+<script>
+document.getElementById("form").onsubmit = async (e) => {
+  e.preventDefault();
+  const patch = encodeURIComponent(document.getElementById("patch").value);
+  const file = document.getElementById("file").files[0];
+  const local = document.getElementById("rom");
+  const status = document.getElementById("status");
+  let url = "/apply?patch=" + patch, init = {};
+  if (file) {
+    url += "&name=" + encodeURIComponent(file.name);
+    init = { method: "POST", body: file };
+  } else if (local && local.value) {
+    url += "&rom=" + encodeURIComponent(local.value);
+  } else {
+    status.textContent = "Choose a ROM.";
+    return;
+  }
+  status.textContent = "Patching...";
+  const res = await fetch(url, init);
+  if (!res.ok) {
+    status.textContent = await res.text();
+    return;
+  }
+  const name = decodeURIComponent(/filename\*=UTF-8''(.*)/.exec(res.headers.get("Content-Disposition"))[1]);
+  const a = document.createElement("a");
+  a.href = URL.createObjectURL(await res.blob());
+  a.download = name;
+  a.click();
+  status.textContent = "Done.";
+};
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// The directory of patches to offer. Defaults to `patch_dir` from the config file
+    #[arg(long)]
+    patches: Option<PathBuf>,
+    /// A directory of ROMs that requests can name instead of uploading one. Defaults to `rom_dir`
+    /// from the config file
+    #[arg(long)]
+    roms: Option<PathBuf>,
+    /// The address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    /// The most requests handled at once. Each can hold an uploaded ROM and its output in memory
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: u16,
+}
+
+/// An error response: the status and a message for the user.
+#[derive(Debug)]
+struct HttpError(&'static str, String);
+
+impl HttpError {
+    fn new(status: &'static str, message: impl Into<String>) -> Self {
+        Self(status, message.into())
+    }
+
+    fn internal(err: impl Display) -> Self {
+        Self::new("500 Internal Server Error", err.to_string())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn require(&self, key: &str) -> Result<&str, HttpError> {
+        self.param(key)
+            .ok_or_else(|| HttpError::new("400 Bad Request", format!("Missing '{key}'.")))
+    }
+}
+
+/// Decodes a percent-encoded query string component.
+fn decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+    while let Some(b) = iter.next() {
+        bytes.push(match b {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b => b,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Reads a request from `reader`, writing the interim response to `stream` if the client waits
+/// for one before sending the body.
+fn read_request(reader: &mut impl BufRead, stream: &mut impl Write) -> Result<Request, HttpError> {
+    let bad = |msg: &str| HttpError::new("400 Bad Request", msg);
+    let mut head = (&mut *reader).take(MAX_HEAD);
+    let mut line = String::new();
+    let mut read_line = |line: &mut String| {
+        line.clear();
+        match head.read_line(line) {
+            Ok(_) if line.ends_with('\n') => Ok(line.trim_end().to_owned()),
+            _ => Err(bad("Malformed request.")),
+        }
+    };
+
+    let request_line = read_line(&mut line)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad("Malformed request."));
+    };
+    if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+        return Err(bad("Malformed request."));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((decode(k)?, decode(v)?))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| bad("Malformed query string."))?;
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut length = 0;
+    let mut expect_continue = false;
+    loop {
+        let header = read_line(&mut line)?;
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad("Malformed header."));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse().map_err(|_| bad("Invalid Content-Length."))?;
+        } else if name.eq_ignore_ascii_case("expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+    }
+
+    if length > MAX_ROM {
+        return Err(HttpError::new(
+            "413 Content Too Large",
+            format!("ROMs larger than {MAX_ROM} bytes aren't accepted."),
+        ));
+    }
+    if expect_continue && length > 0 {
+        let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+    // the body grows as it arrives, so a large Content-Length alone doesn't reserve memory
+    let mut body = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(|_| bad("The request body was cut short."))?;
+    if body.len() != length {
+        return Err(bad("The request body was cut short."));
+    }
+
+    Ok(Request {
+        method,
+        path,
+        query,
+        body,
+    })
+}
+
+fn send(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[Cow<[u8]>],
+) -> io::Result<()> {
+    let length: usize = body.iter().map(|chunk| chunk.len()).sum();
+    let mut head =
+        format!("HTTP/1.1 {status}\r\nContent-Length: {length}\r\nConnection: close\r\n");
+    for (name, value) in headers {
+        head += &format!("{name}: {value}\r\n");
+    }
+    head += "\r\n";
+
+    stream.write_all(head.as_bytes())?;
+    for chunk in body {
+        stream.write_all(chunk)?;
+    }
+    stream.flush()
+}
+
+/// A `Content-Disposition` header offering `filename`. Control characters are dropped so the name
+/// can't end the header, and the name is given percent-encoded as well as in plain ASCII.
+fn disposition(filename: &str) -> String {
+    let filename: String = filename.chars().filter(|c| !c.is_control()).collect();
+    let ascii: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect();
+    format!(r#"attachment; filename="{ascii}"; filename*=UTF-8''{encoded}"#)
+}
+
+/// Lists the files in `dir` with one of `extensions`, or every file if it's empty, by name.
+fn list(dir: &Path, extensions: &[&str]) -> io::Result<Vec<String>> {
+    let mut names: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| {
+            extensions.is_empty()
+                || name
+                    .rsplit_once('.')
+                    .is_some_and(|(_, ext)| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+struct Server {
+    patches: PathBuf,
+    roms: Option<PathBuf>,
+}
+
+impl Server {
+    /// Resolves a file name from a request to a file listed in `dir`, so requests can't reach
+    /// anything outside it.
+    fn resolve(dir: &Path, extensions: &[&str], name: &str) -> Result<PathBuf, HttpError> {
+        let names = list(dir, extensions).map_err(HttpError::internal)?;
+        if !names.iter().any(|n| n == name) {
+            return Err(HttpError::new(
+                "404 Not Found",
+                format!("There's no '{name}'."),
+            ));
+        }
+        Ok(dir.join(name))
+    }
+
+    fn index(&self) -> Result<String, HttpError> {
+        let options = |names: Vec<String>| {
+            names
+                .iter()
+                .map(|name| format!("<option>{}</option>", escape(name)))
+                .collect::<String>()
+        };
+
+        let patches =
+            options(list(&self.patches, &softpatch::EXTENSIONS).map_err(HttpError::internal)?);
+        let roms = match &self.roms {
+            Some(dir) => format!(
+                r#" or <select id="rom"><option value="">(on the server)</option>{}</select>"#,
+                options(list(dir, &[]).map_err(HttpError::internal)?)
+            ),
+            None => String::new(),
+        };
+        Ok(INDEX
+            .replace("{patches}", &patches)
+            .replace("{roms}", &roms))
+    }
+
+    fn apply(&self, req: &Request, stream: &mut TcpStream) -> Result<(), HttpError> {
+        let patch_name = req.require("patch")?;
+        let path = Self::resolve(&self.patches, &softpatch::EXTENSIONS, patch_name)?;
+        let patch = load_patch(&path).map_err(|err| HttpError::internal(format!("{err:#}")))?;
+
+        let (rom, rom_name) = match req.method.as_str() {
+            "POST" => (Cow::Borrowed(&req.body[..]), req.param("name")),
+            _ => {
+                let Some(dir) = &self.roms else {
+                    return Err(HttpError::new(
+                        "400 Bad Request",
+                        "This server has no ROM directory, so the ROM must be uploaded.",
+                    ));
+                };
+                let name = req.require("rom")?;
+                let data = fs::read(Self::resolve(dir, &[], name)?).map_err(HttpError::internal)?;
+                (Cow::Owned(data), Some(name))
+            }
+        };
+
+        let wrong_rom = |err: Error| match err {
+            Error::InvalidSize(..)
+            | Error::InvalidCRC(..)
+            | Error::InvalidHash(..)
+            | Error::HeaderMismatch { .. } => HttpError::new(
+                "422 Unprocessable Content",
+                format!("This is the wrong base ROM for '{patch_name}': {err}"),
+            ),
+            err => HttpError::internal(err),
+        };
+        // patches made for headerless ROMs are retried without a detected copier header
+        let output = match patch.apply_vectored(&rom) {
+            Ok(output) => output,
+            Err(err) => match HeaderKind::detect(&rom).filter(|kind| rom.len() >= kind.size()) {
+                Some(kind) => {
+                    let (header, body) = rom.split_at(kind.size());
+                    let mut output = vec![Cow::Borrowed(header)];
+                    output.extend(patch.apply_vectored(body).map_err(|_| wrong_rom(err))?);
+                    output
+                }
+                None => return Err(wrong_rom(err)),
+            },
+        };
+
+        let filename = match rom_name.map(Path::new) {
+            Some(name) => {
+                let mut patched = name.file_stem().unwrap_or_default().to_owned();
+                patched.push(".patched");
+                if let Some(ext) = name.extension() {
+                    patched.push(".");
+                    patched.push(ext);
+                }
+                patched.to_string_lossy().into_owned()
+            }
+            None => format!(
+                "{}.bin",
+                path.file_stem().unwrap_or_default().to_string_lossy()
+            ),
+        };
+        let disposition = disposition(&filename);
+        send(
+            stream,
+            "200 OK",
+            &[
+                ("Content-Type", "application/octet-stream"),
+                ("Content-Disposition", &disposition),
+            ],
+            &output,
+        )
+        .map_err(HttpError::internal)
+    }
+
+    fn route(&self, req: &Request, stream: &mut TcpStream) -> Result<(), HttpError> {
+        let html = [("Content-Type", "text/html; charset=utf-8")];
+        let text = [("Content-Type", "text/plain; charset=utf-8")];
+        let sent = |result: io::Result<()>| result.map_err(HttpError::internal);
+        match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/") => {
+                let page = self.index()?;
+                sent(send(stream, "200 OK", &html, &[page.as_bytes().into()]))
+            }
+            ("GET", "/patches") => {
+                let names =
+                    list(&self.patches, &softpatch::EXTENSIONS).map_err(HttpError::internal)?;
+                let body: String = names.iter().map(|name| format!("{name}\n")).collect();
+                sent(send(stream, "200 OK", &text, &[body.as_bytes().into()]))
+            }
+            ("GET" | "POST", "/apply") => self.apply(req, stream),
+            (_, "/" | "/patches" | "/apply") => Err(HttpError::new(
+                "405 Method Not Allowed",
+                format!("{} isn't allowed here.", req.method),
+            )),
+            _ => Err(HttpError::new("404 Not Found", "Not found.")),
+        }
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let read = stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+            .and_then(|_| stream.try_clone())
+            .map_err(|err| HttpError::new("400 Bad Request", err.to_string()))
+            .and_then(|clone| read_request(&mut BufReader::new(clone), &mut stream));
+        let (request, result) = match read {
+            Ok(req) => {
+                let result = self.route(&req, &mut stream);
+                (format!("{} {}", req.method, req.path), result)
+            }
+            Err(err) => ("-".into(), Err(err)),
+        };
+
+        let code = match result {
+            Ok(()) => "200 OK",
+            Err(HttpError(code, message)) => {
+                let text = [("Content-Type", "text/plain; charset=utf-8")];
+                let _ = send(&mut stream, code, &text, &[message.as_bytes().into()]);
+                code
+            }
+        };
+        status!("{request} -> {code}");
+    }
+}
+
+pub fn serve(args: ServeArgs, config: &Config) -> Result<()> {
+    let patches = args
+        .patches
+        .or_else(|| config.patch_dir.clone())
+        .context("No patch directory given. Pass --patches or set patch_dir in the config file.")?;
+    let roms = args.roms.or_else(|| config.rom_dir.clone());
+    let count = list(&patches, &softpatch::EXTENSIONS)
+        .with_context(|| format!("Couldn't read '{}'", patches.display()))?
+        .len();
+
+    let listener = TcpListener::bind(&args.bind)
+        .with_context(|| format!("Couldn't listen on {}", args.bind))?;
+    status!(
+        "Serving {count} patches from {} on http://{} (Ctrl+C to stop)...",
+        patches.display(),
+        listener.local_addr()?
+    );
+    if roms.is_none() {
+        notice!("note: no ROM directory is set, so ROMs must be uploaded");
+    }
+
+    // a fixed set of workers accept connections, so at most that many are handled at once and
+    // the rest wait in the listen backlog
+    let server = Arc::new(Server { patches, roms });
+    let workers = (0..args.workers)
+        .map(|_| {
+            let listener = listener.try_clone()?;
+            let server = server.clone();
+            Ok(thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    server.handle(stream);
+                }
+            }))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn parse(request: &[u8]) -> Result<Request, HttpError> {
+        read_request(&mut &request[..], &mut Vec::new())
+    }
+
+    fn status(result: Result<Request, HttpError>) -> &'static str {
+        result.err().map_or("200 OK", |HttpError(status, _)| status)
+    }
+
+    #[test]
+    fn request_line() {
+        let req = parse(b"GET /apply?patch=a%20b.bps&rom=x+y.sfc HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("GET", "/apply"));
+        assert_eq!(req.param("patch"), Some("a b.bps"));
+        assert_eq!(req.param("rom"), Some("x y.sfc"));
+        assert!(req.body.is_empty());
+
+        for bad in [
+            &b"GET /\r\n\r\n"[..],
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET relative HTTP/1.1\r\n\r\n",
+            b"GET / SMTP\r\n\r\n",
+            b"GET /?patch=%zz HTTP/1.1\r\n\r\n",
+            b"GET /?patch=%FF HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1",
+        ] {
+            assert_eq!(status(parse(bad)), "400 Bad Request", "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn headers_and_body() {
+        let req = parse(b"POST /apply HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcdef").unwrap();
+        assert_eq!(req.body, b"abc");
+
+        let mut interim = Vec::new();
+        let request = b"POST / HTTP/1.1\r\nExpect: 100-continue\r\ncontent-length: 1\r\n\r\nx";
+        read_request(&mut &request[..], &mut interim).unwrap();
+        assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        // the declared length is checked before anything is read or allocated
+        let huge = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_ROM + 1);
+        assert_eq!(status(parse(huge.as_bytes())), "413 Content Too Large");
+        let cut = b"POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\nabc";
+        assert_eq!(status(parse(cut)), "400 Bad Request");
+
+        let long = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD as usize)
+        );
+        for bad in [
+            long.as_bytes(),
+            b"GET / HTTP/1.1\r\nno colon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: 1\r\n",
+        ] {
+            assert_eq!(status(parse(bad)), "400 Bad Request");
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = env::temp_dir().join(format!("patch-rs-serve-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("hack.bps"), b"").unwrap();
+        fs::write(dir.join("readme.txt"), b"").unwrap();
+
+        let found = Server::resolve(&dir, &["bps"], "hack.bps");
+        assert_eq!(found.unwrap(), dir.join("hack.bps"));
+        for name in [
+            "readme.txt",
+            "sub",
+            "../hack.bps",
+            "./hack.bps",
+            "/etc/passwd",
+            "",
+        ] {
+            let err = Server::resolve(&dir, &["bps"], name).unwrap_err();
+            assert_eq!(err.0, "404 Not Found", "{name}");
+        }
+        assert_eq!(list(&dir, &[]).unwrap(), ["hack.bps", "readme.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn content_disposition() {
+        assert_eq!(
+            disposition("hack.patched.sfc"),
+            r#"attachment; filename="hack.patched.sfc"; filename*=UTF-8''hack.patched.sfc"#
+        );
+        let header = disposition("evil\r\nSet-Cookie: x=\"1\".sfc");
+        assert!(!header.chars().any(|c| c.is_control()));
+        assert!(header.starts_with(r#"attachment; filename="evilSet-Cookie: x=_1_.sfc";"#));
+        assert!(
+            disposition("ゲーム.sfc").ends_with("filename*=UTF-8''%E3%82%B2%E3%83%BC%E3%83%A0.sfc")
+        );
+    }
+}
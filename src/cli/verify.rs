@@ -0,0 +1,54 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use patch_rs::dat::Dat;
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// The DAT file to verify against
+    #[arg(long)]
+    dat: PathBuf,
+    /// The ROM files to verify
+    #[arg(required = true)]
+    roms: Vec<PathBuf>,
+}
+
+pub fn load_dat(path: &PathBuf) -> Result<Dat> {
+    Dat::parse(&fs::read_to_string(path)?)
+        .with_context(|| format!("Couldn't load DAT '{}'", path.display()))
+}
+
+/// Prints the DAT entry matching `data`, returning whether one was found.
+pub fn report(dat: &Dat, name: &str, data: &[u8]) -> bool {
+    match dat.find(data) {
+        Some(found) => {
+            println!(
+                "{name}: {} [{}]",
+                found.game.name,
+                found.rom.status.as_deref().unwrap_or("good")
+            );
+            true
+        }
+        None => {
+            println!("{name}: not found in DAT");
+            false
+        }
+    }
+}
+
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let dat = load_dat(&args.dat)?;
+    let mut missing = 0;
+    for rom in args.roms.iter() {
+        if !report(&dat, &rom.display().to_string(), &fs::read(rom)?) {
+            missing += 1;
+        }
+    }
+
+    if missing != 0 {
+        bail!("{missing} ROM(s) were not found in the DAT.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+
+use super::{config::Config, create::Format, log::status};
+use crate::write_atomic;
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// The modified file to watch
+    modified: PathBuf,
+    /// The original file
+    original: PathBuf,
+    /// The patch file to keep up to date
+    #[arg(short, long)]
+    output: PathBuf,
+    /// The patch format. Defaults to the format matching the output file's extension
+    #[arg(short, long)]
+    format: Option<Format>,
+    /// How often to check the modified file for changes, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    interval: u64,
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+pub fn watch(args: WatchArgs, config: &Config) -> Result<()> {
+    let format = args
+        .format
+        .or_else(|| Format::from_path(&args.output))
+        .or(config.format)
+        .unwrap_or(Format::Ups);
+    let original = fs::read(&args.original)?;
+    let interval = Duration::from_millis(args.interval);
+
+    status!(
+        "Watching {} for changes (Ctrl+C to stop)...",
+        args.modified.display()
+    );
+
+    let mut last = None;
+    loop {
+        let current = stamp(&args.modified);
+        if current.is_some() && current != last {
+            // wait for the file to stop changing so we don't diff a partially written ROM
+            thread::sleep(interval);
+            if stamp(&args.modified) != current {
+                continue;
+            }
+
+            last = current;
+            let result = fs::read(&args.modified)
+                .with_context(|| format!("Couldn't read {}", args.modified.display()))
+                .and_then(|modified| {
+                    format.create(&original, &modified, &Default::default(), &mut ())
+                })
+                .map(|(_, patch)| patch)
+                .and_then(|patch| write_atomic(&args.output, &patch).map(|_| patch.len()));
+            match result {
+                Ok(len) => status!("Wrote {} ({len} bytes)", args.output.display()),
+                Err(err) => eprintln!("Error: {err:?}"),
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
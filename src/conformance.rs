@@ -0,0 +1,147 @@
+//! Canonical test vectors: tiny known-good IPS, UPS, and BPS patches with the output they
+//! produce, covering each kind of record. Integrators and new format modules can check they're
+//! byte-for-byte compatible with [`check`] and [`verify_roundtrip`].
+
+use crate::{json, load, Error, Result};
+
+/// A patch, the source it applies to, and the output it must produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector {
+    pub name: &'static str,
+    /// The name of the patch format, as returned by [`Patch::format`](crate::Patch::format).
+    pub format: &'static str,
+    pub source: &'static [u8],
+    pub patch: &'static [u8],
+    pub output: &'static [u8],
+}
+
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        name: "ips-literal",
+        format: "IPS",
+        source: b"Hello, world!",
+        patch: b"PATCH\x00\x00\x07\x00\x05Rust!EOF",
+        output: b"Hello, Rust!!",
+    },
+    Vector {
+        name: "ips-rle",
+        format: "IPS",
+        source: b"Hello, world!",
+        patch: b"PATCH\x00\x00\x00\x00\x00\x00\x05-\x00\x00\x07\x00\x01WEOF",
+        output: b"-----, World!",
+    },
+    Vector {
+        name: "ips-extend",
+        format: "IPS",
+        source: b"Hello, world!",
+        patch: b"PATCH\x00\x00\x0D\x00\x05 Bye.\x00\x00\x10\x00\x00\x00\x04!EOF",
+        output: b"Hello, world! By!!!!",
+    },
+    Vector {
+        name: "ips-truncate",
+        format: "IPS",
+        source: b"Hello, world!",
+        patch: b"PATCH\x00\x00\x00\x00\x01JEOF\x00\x00\x05",
+        output: b"Jello",
+    },
+    Vector {
+        name: "ups-same-size",
+        format: "UPS",
+        source: b"Hello, world!",
+        patch: b"UPS1\x8D\x8D\x87%\x1A\x01\x18E\x00\xE6\xC6\xE6\xEB\xAD\xF3P\xB1a\x04\x1D\xA4",
+        output: b"Hello, Rust!!",
+    },
+    Vector {
+        name: "ups-grow",
+        format: "UPS",
+        source: b"Hello, world!",
+        patch: b"UPS1\x8D\x97\x80\x02\x00\x8B And more.\x00\xE6\xC6\xE6\xEB\x07\xD3k\x11\x99q\x00\x91",
+        output: b"Jello, world! And more.",
+    },
+    Vector {
+        name: "ups-shrink",
+        format: "UPS",
+        source: b"Hello, world!",
+        patch: b"UPS1\x8D\x85\xE6\xC6\xE6\xEB\x82\x89\xD1\xF7\x8Fu\x84\x81",
+        output: b"Hello",
+    },
+    Vector {
+        name: "bps-actions",
+        format: "BPS",
+        source: b"abcd",
+        patch: b"BPS1\x84\x88\x80\x84\x85xy\x86\x80\x87\x84\x11\xCD\x82\xED\xDC\x9A\x0Fp\x15kP\xD1",
+        output: b"abxyabxy",
+    },
+    Vector {
+        name: "bps-overlap",
+        format: "BPS",
+        source: b"abcd",
+        patch: b"BPS1\x84\x89\x80\x84\x97\x80\x81!\x11\xCD\x82\xED\x96\x16\xE80M\x16>`",
+        output: b"abababab!",
+    },
+    Vector {
+        name: "bps-metadata",
+        format: "BPS",
+        source: b"abcd",
+        patch: b"BPS1\x84\x84\x95<note>reversed</note>\x82\x86\x82\x85\x82\x85\x82\x85\x11\xCD\x82\xED\xDA\x92\xEF\xB2\xF2Y\x89N",
+        output: b"dcba",
+    },
+];
+
+/// Checks that `patch` loads and exports back to exactly the same bytes, and survives a trip
+/// through its JSON form unchanged.
+pub fn verify_roundtrip(patch: &[u8]) -> Result<()> {
+    let loaded = load(patch)?;
+    if loaded.export(None)? != patch {
+        return Err(Error::Conformance(
+            "exporting doesn't reproduce the patch".into(),
+        ));
+    }
+
+    let compiled = json::from_json(&loaded.to_json())?;
+    if compiled.export(None)? != patch {
+        return Err(Error::Conformance(
+            "the JSON form doesn't compile back to the patch".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the vector's patch loads as its format, applies to its source to produce exactly
+/// its output, and roundtrips as in [`verify_roundtrip`].
+pub fn check(vector: &Vector) -> Result<()> {
+    let fail = |what: &str| Err(Error::Conformance(format!("{}: {what}", vector.name)));
+    let patch = load(vector.patch)?;
+    if patch.format() != vector.format {
+        return fail(&format!("loaded as {}", patch.format()));
+    }
+    if patch.apply(vector.source)? != vector.output {
+        return fail("the output differs");
+    }
+    verify_roundtrip(vector.patch).or_else(|err| fail(&err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors() {
+        for vector in VECTORS {
+            check(vector).unwrap();
+        }
+    }
+
+    #[test]
+    fn mismatch() {
+        let vector = Vector {
+            output: b"Hello, world!",
+            ..VECTORS[0]
+        };
+        assert!(matches!(check(&vector), Err(Error::Conformance(_))));
+
+        let mut patch = VECTORS[0].patch.to_vec();
+        patch.extend_from_slice(b"junk");
+        assert!(verify_roundtrip(&patch).is_err());
+    }
+}
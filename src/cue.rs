@@ -0,0 +1,188 @@
+//! Multi-track `.bin`/`.cue` disc images. Disc patches address the whole disc as one image, so the
+//! track files are concatenated before patching and the output is split back at the same track
+//! boundaries.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+pub struct CueSheet {
+    lines: Vec<String>,
+    /// The line index and file name of each `FILE` command.
+    files: Vec<(usize, String)>,
+}
+
+impl CueSheet {
+    pub fn parse(text: &str) -> Result<Self> {
+        let lines: Vec<_> = text.lines().map(String::from).collect();
+        let files: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let rest = line.trim_start().strip_prefix("FILE ")?.trim();
+                let name = match rest.strip_prefix('"') {
+                    Some(rest) => rest.split_once('"')?.0,
+                    None => rest.rsplit_once(' ')?.0,
+                };
+                Some((i, name.to_string()))
+            })
+            .collect();
+
+        if files.is_empty() {
+            return Err(Error::InvalidCue);
+        }
+
+        Ok(Self { lines, files })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(_, name)| name.as_str())
+    }
+
+    /// Returns the cue sheet with its `FILE` commands pointing at `names` instead.
+    pub fn rename(&self, names: &[String]) -> String {
+        let mut lines = self.lines.clone();
+        for ((i, old), new) in self.files.iter().zip(names) {
+            lines[*i] = lines[*i].replacen(old.as_str(), new, 1);
+        }
+
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+pub struct Disc {
+    pub cue: CueSheet,
+    pub path: PathBuf,
+    pub tracks: Vec<PathBuf>,
+    pub sizes: Vec<usize>,
+}
+
+impl Disc {
+    /// Reads the cue sheet at `path` and the track files it references, returning the disc and
+    /// its tracks concatenated into a single image.
+    pub fn open(path: &Path) -> Result<(Self, Vec<u8>)> {
+        let cue = CueSheet::parse(&fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut image = Vec::new();
+        let mut tracks = Vec::new();
+        let mut sizes = Vec::new();
+        for name in cue.files() {
+            let track = dir.join(name);
+            let data = fs::read(&track)?;
+            sizes.push(data.len());
+            image.extend(data);
+            tracks.push(track);
+        }
+
+        let disc = Self {
+            cue,
+            path: path.to_owned(),
+            tracks,
+            sizes,
+        };
+        Ok((disc, image))
+    }
+
+    /// Resolves an absolute disc offset to the index of the track containing it and the offset
+    /// within that track.
+    pub fn locate(&self, mut offset: usize) -> Option<(usize, usize)> {
+        for (i, &size) in self.sizes.iter().enumerate() {
+            if offset < size {
+                return Some((i, offset));
+            }
+            offset -= size;
+        }
+
+        None
+    }
+
+    /// Splits a patched disc image back into tracks. If the image changed size, the last track
+    /// absorbs the difference.
+    pub fn split<'a>(&self, mut image: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut tracks = Vec::with_capacity(self.sizes.len());
+        for &size in self.sizes[..self.sizes.len() - 1].iter() {
+            let (track, rest) = image.split_at(size.min(image.len()));
+            tracks.push(track);
+            image = rest;
+        }
+
+        tracks.push(image);
+        tracks
+    }
+
+    /// Returns the files to write for a patched image saved as the cue sheet `dest`, including
+    /// the cue sheet itself. Writing over the original cue sheet keeps the original track names,
+    /// otherwise tracks are named after `dest`.
+    pub fn outputs(&self, image: &[u8], dest: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let dir = dest.parent().unwrap_or(Path::new(""));
+        let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+        let names: Vec<_> = if dest == self.path {
+            self.cue.files().map(String::from).collect()
+        } else {
+            self.tracks
+                .iter()
+                .enumerate()
+                .map(|(i, track)| {
+                    let ext = track.extension().unwrap_or_default().to_string_lossy();
+                    match self.tracks.len() {
+                        1 => format!("{stem}.{ext}"),
+                        _ => format!("{stem} (Track {:02}).{ext}", i + 1),
+                    }
+                })
+                .collect()
+        };
+
+        let mut outputs: Vec<_> = names
+            .iter()
+            .zip(self.split(image))
+            .map(|(name, track)| (dir.join(name), track.to_vec()))
+            .collect();
+        outputs.push((dest.to_owned(), self.cue.rename(&names).into_bytes()));
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUE: &str = r#"FILE "Game (Track 01).bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE "Game (Track 02).bin" BINARY
+  TRACK 02 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+"#;
+
+    #[test]
+    fn split_tracks() {
+        let disc = Disc {
+            cue: CueSheet::parse(CUE).unwrap(),
+            path: PathBuf::from("Game.cue"),
+            tracks: vec![
+                PathBuf::from("Game (Track 01).bin"),
+                PathBuf::from("Game (Track 02).bin"),
+            ],
+            sizes: vec![4, 2],
+        };
+
+        assert_eq!(disc.locate(3), Some((0, 3)));
+        assert_eq!(disc.locate(5), Some((1, 1)));
+        assert_eq!(disc.locate(6), None);
+        assert_eq!(
+            disc.split(&[0, 1, 2, 3, 4, 5, 6]),
+            [&[0, 1, 2, 3][..], &[4, 5, 6]]
+        );
+
+        let outputs = disc.outputs(&[0; 6], Path::new("Hack.cue"));
+        assert_eq!(outputs[1].0, Path::new("Hack (Track 02).bin"));
+        assert_eq!(outputs[1].1, [0, 0]);
+        assert!(String::from_utf8_lossy(&outputs[2].1)
+            .starts_with("FILE \"Hack (Track 01).bin\" BINARY\n"));
+    }
+}
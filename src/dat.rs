@@ -0,0 +1,192 @@
+//! Parsing of No-Intro/clrmamepro (Logiqx XML) DAT files, used to identify ROMs and patched
+//! outputs.
+
+use crate::{hash, xml::unescape, Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dat {
+    pub name: Option<String>,
+    pub games: Vec<Game>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub name: String,
+    pub roms: Vec<RomEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomEntry {
+    pub name: String,
+    pub size: usize,
+    pub crc: Option<u32>,
+    /// The SHA-1 as a lowercase hexadecimal string.
+    pub sha1: Option<String>,
+    /// The dump status, such as `verified` or `baddump`. Absent for good dumps in most DATs.
+    pub status: Option<String>,
+}
+
+impl RomEntry {
+    fn matches(&self, rom: &[u8], crc: u32, sha1: &mut Option<String>) -> bool {
+        if self.size != rom.len() || self.crc.is_some_and(|c| c != crc) {
+            return false;
+        }
+
+        match &self.sha1 {
+            Some(expected) => {
+                expected == sha1.get_or_insert_with(|| hash::to_hex(&hash::sha1(rom)))
+            }
+            None => self.crc.is_some(),
+        }
+    }
+}
+
+/// A DAT entry matching a ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatMatch<'a> {
+    pub game: &'a Game,
+    pub rom: &'a RomEntry,
+}
+
+impl Dat {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut dat = Dat {
+            name: None,
+            games: Vec::new(),
+        };
+
+        let mut header = false;
+        let mut text_tag = None;
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            let text = &rest[..start];
+            rest = &rest[start..];
+
+            if let (Some("name"), true) = (text_tag, header) {
+                dat.name = Some(unescape(text.trim()));
+            }
+
+            if let Some(after) = rest.strip_prefix("<!--") {
+                rest = after
+                    .find("-->")
+                    .map(|end| &after[end + 3..])
+                    .ok_or(Error::InvalidDat)?;
+                continue;
+            }
+
+            let end = rest.find('>').ok_or(Error::InvalidDat)?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+
+            if let Some(name) = tag.strip_prefix('/') {
+                if name.trim() == "header" {
+                    header = false;
+                }
+                text_tag = None;
+                continue;
+            }
+
+            let (name, attrs) = tag
+                .trim_end_matches('/')
+                .split_once(char::is_whitespace)
+                .unwrap_or((tag.trim_end_matches('/'), ""));
+            let attrs = parse_attrs(attrs)?;
+            let attr = |key: &str| {
+                attrs
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.clone())
+            };
+
+            text_tag = None;
+            match name {
+                "header" => header = true,
+                "name" => text_tag = Some("name"),
+                "game" | "machine" => dat.games.push(Game {
+                    name: attr("name").ok_or(Error::InvalidDat)?,
+                    roms: Vec::new(),
+                }),
+                "rom" => {
+                    let game = dat.games.last_mut().ok_or(Error::InvalidDat)?;
+                    game.roms.push(RomEntry {
+                        name: attr("name").unwrap_or_default(),
+                        size: attr("size")
+                            .and_then(|s| s.parse().ok())
+                            .ok_or(Error::InvalidDat)?,
+                        crc: attr("crc")
+                            .map(|s| u32::from_str_radix(&s, 16).map_err(|_| Error::InvalidDat))
+                            .transpose()?,
+                        sha1: attr("sha1").map(|s| s.to_ascii_lowercase()),
+                        status: attr("status"),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(dat)
+    }
+
+    /// Finds the entry matching `rom` by size, CRC32, and SHA-1 when the DAT provides one.
+    pub fn find(&self, rom: &[u8]) -> Option<DatMatch<'_>> {
+        let crc = hash::crc32(rom);
+        let mut sha1 = None;
+        self.games.iter().find_map(|game| {
+            game.roms
+                .iter()
+                .find(|entry| entry.matches(rom, crc, &mut sha1))
+                .map(|rom| DatMatch { game, rom })
+        })
+    }
+}
+
+fn parse_attrs(mut s: &str) -> Result<Vec<(&str, String)>> {
+    let mut attrs = Vec::new();
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            return Ok(attrs);
+        }
+
+        let (key, rest) = s.split_once('=').ok_or(Error::InvalidDat)?;
+        let rest = rest.trim_start();
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'');
+        let quote = quote.ok_or(Error::InvalidDat)?;
+        let end = rest[1..].find(quote).ok_or(Error::InvalidDat)?;
+        attrs.push((key.trim(), unescape(&rest[1..end + 1])));
+        s = &rest[end + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_find() {
+        let xml = r#"<?xml version="1.0"?>
+<!DOCTYPE datafile SYSTEM "http://www.logiqx.com/Dats/datafile.dtd">
+<datafile>
+    <header>
+        <name>Test - System</name>
+    </header>
+    <!-- a comment with <tags> -->
+    <game name="Hello &amp; Goodbye (World)">
+        <description>Hello &amp; Goodbye (World)</description>
+        <rom name="hello.bin" size="5" crc="3610A686" sha1="AAF4C61DDCC5E8A2DABEDE0F3B482CD9AEA9434D" status="verified"/>
+    </game>
+</datafile>"#;
+
+        let dat = Dat::parse(xml).unwrap();
+        assert_eq!(dat.name.as_deref(), Some("Test - System"));
+
+        let found = dat.find(b"hello").unwrap();
+        assert_eq!(found.game.name, "Hello & Goodbye (World)");
+        assert_eq!(found.rom.status.as_deref(), Some("verified"));
+        assert!(dat.find(b"jello").is_none());
+    }
+}
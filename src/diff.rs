@@ -0,0 +1,154 @@
+//! A format-neutral description of how a file changes, for building patches programmatically or
+//! converting between formats without going through their records.
+//!
+//! A [`DiffSet`] is a list of [`Hunk`]s, each replacing the bytes at an offset, plus the size of
+//! the output. The output is the source resized to that size, with bytes past its end set to 0,
+//! and then every hunk written in order, so a later hunk wins where two overlap.
+
+use std::io::Write;
+
+use crate::{prelude::*, stream::DiffWriter, Result};
+
+/// Bytes written to the output at an offset.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hunk {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl Hunk {
+    /// The offset just past the last byte the hunk writes.
+    pub fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DiffSet {
+    pub hunks: Vec<Hunk>,
+    /// The size of the output.
+    pub size: usize,
+}
+
+impl DiffSet {
+    /// An empty diff producing output of the given size.
+    pub fn new(size: usize) -> Self {
+        Self {
+            hunks: Vec::new(),
+            size,
+        }
+    }
+
+    /// Adds a hunk writing `data` at `offset`, after the existing hunks.
+    pub fn push(&mut self, offset: usize, data: impl Into<Vec<u8>>) {
+        self.hunks.push(Hunk {
+            offset,
+            data: data.into(),
+        });
+    }
+
+    /// The differences between `src` and `dst`, as the fewest sorted, non-overlapping hunks.
+    /// Bytes past the end of `src` are always included.
+    pub fn between(src: &[u8], dst: &[u8]) -> Self {
+        let mut writer = DiffWriter::new(src);
+        writer
+            .write_all(dst)
+            .expect("writing to a DiffWriter can't fail");
+        writer.into_diff()
+    }
+
+    /// The changes `patch` makes to `source`, in any format. The source and output are validated
+    /// as in [`Patch::apply`].
+    pub fn from_patch(patch: &dyn Patch, source: &[u8]) -> Result<Self> {
+        Ok(Self::between(source, &patch.apply(source)?))
+    }
+
+    /// Applies the hunks to `source`.
+    pub fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let mut buf = source.to_vec();
+        buf.resize(self.size, 0);
+        for hunk in self.hunks.iter() {
+            if let Some(dst) = buf.get_mut(hunk.offset..) {
+                let len = dst.len().min(hunk.data.len());
+                dst[..len].copy_from_slice(&hunk.data[..len]);
+            }
+        }
+        buf
+    }
+
+    /// Encodes the hunks as IPS records in the same order, for a source of `src_len` bytes.
+    /// Hunks longer than an IPS record are split, and the output size is recorded with the
+    /// truncation extension if it's smaller than the source or the hunks. Fails with
+    /// [`Error::OutOfRange`](crate::Error::OutOfRange) if a hunk reaches past the first 4 GiB.
+    pub fn to_ips(&self, src_len: usize) -> Result<IpsPatch> {
+        let mut runs = self.runs();
+        // IPS output ends at the source or the last record, so a longer output needs a record
+        // at its last byte, which is past the source and so already 0
+        let end = self.hunks.iter().map(Hunk::end).max().unwrap_or(0);
+        if self.size > src_len.max(end) {
+            runs.push((self.size - 1, vec![0]));
+        }
+        let mut patch = IpsPatch::from_runs(runs, src_len, self.size)?;
+        if end > self.size {
+            patch.set_output_size(Some(self.size));
+        }
+        Ok(patch)
+    }
+
+    /// Encodes the diff as a UPS patch against `source`.
+    pub fn to_ups(&self, source: &[u8]) -> UpsPatch {
+        let output = self.apply(source);
+        let runs = Self::between(source, &output).runs();
+        UpsPatch::from_runs(runs, source, Validation::new(&output))
+    }
+
+    /// Encodes the diff as a BPS patch against `source`.
+    pub fn to_bps(&self, source: &[u8]) -> BpsPatch {
+        BpsPatch::create(source, &self.apply(source))
+    }
+
+    fn runs(&self) -> Vec<(usize, Vec<u8>)> {
+        self.hunks
+            .iter()
+            .filter(|hunk| !hunk.data.is_empty())
+            .map(|hunk| (hunk.offset, hunk.data.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let src = b"The quick brown fox jumps over the lazy dog";
+        let mut diff = DiffSet::new(src.len() + 3);
+        diff.push(4, "slow ");
+        diff.push(10, *b"red  ");
+        diff.push(4, "S");
+        let dst = diff.apply(src);
+        assert_eq!(dst, b"The Slow  red   fox jumps over the lazy dog\0\0\0");
+
+        assert_eq!(diff.to_ips(src.len()).unwrap().apply(src).unwrap(), dst);
+        assert_eq!(diff.to_ups(src).apply(src).unwrap(), dst);
+        assert_eq!(diff.to_bps(src).apply(src).unwrap(), dst);
+
+        let ups = UpsPatch::create(src, &dst);
+        let canonical = DiffSet::from_patch(&ups, src).unwrap();
+        assert_eq!(canonical, DiffSet::between(src, &dst));
+        assert_eq!(canonical.hunks.len(), 3);
+        let ips = canonical.to_ips(src.len()).unwrap();
+        assert_eq!(ips.apply(src).unwrap(), dst);
+    }
+
+    #[test]
+    fn truncate() {
+        let src = b"abcdef";
+        let mut diff = DiffSet::new(3);
+        diff.push(1, "X");
+        let ips = diff.to_ips(src.len()).unwrap();
+        assert_eq!(ips.output_size(), Some(3));
+        assert_eq!(ips.apply(src).unwrap(), b"aXc");
+    }
+}
@@ -0,0 +1,166 @@
+//! The encoding shared by the beat formats (UPS and BPS), for crates implementing formats derived
+//! from them.
+//!
+//! Numbers are stored as variable-length integers: 7 bits per byte, least significant first, with
+//! the high bit marking the last byte. Each continuation also adds one to the remaining value, so
+//! every number has exactly one encoding. Patches end with a 12-byte footer holding the CRC32 of
+//! the source, of the target, and of every byte of the patch before the last four.
+
+use std::io::{Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::{hash, Error, Result};
+
+pub trait ReadVarExt: Read {
+    /// Reads a number, failing with [`Error::InvalidPatch`] wrapped in an
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData) error if it doesn't fit in a `usize`.
+    fn read_var_int(&mut self) -> std::io::Result<usize> {
+        let overflow = || std::io::Error::new(std::io::ErrorKind::InvalidData, Error::InvalidPatch);
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.read_u8()?;
+            value = (x as usize & 0x7f)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(overflow)?;
+            if (x & 0x80) != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+            value = value.checked_add(shift).ok_or_else(overflow)?;
+        }
+    }
+
+    /// Reads a signed number, stored as its magnitude shifted left by one with the sign in the
+    /// lowest bit, as in BPS copy offsets.
+    fn read_signed_var_int(&mut self) -> std::io::Result<isize> {
+        let num = self.read_var_int()?;
+        Ok(if num & 0b1 != 0 { -1 } else { 1 } * (num >> 1) as isize)
+    }
+}
+
+impl<T: Read + ?Sized> ReadVarExt for T {}
+
+pub trait WriteVarExt: Write {
+    fn write_var_int(&mut self, mut value: usize) -> std::io::Result<()> {
+        loop {
+            let x = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.write_u8(0x80 | x)?;
+                return Ok(());
+            }
+
+            self.write_u8(x)?;
+            value -= 1;
+        }
+    }
+
+    /// Writes a signed number in the form read by
+    /// [`read_signed_var_int`](ReadVarExt::read_signed_var_int).
+    fn write_signed_var_int(&mut self, value: isize) -> std::io::Result<()> {
+        self.write_var_int((value.unsigned_abs() << 1) | (value.is_negative() as usize))
+    }
+}
+
+impl<T: Write + ?Sized> WriteVarExt for T {}
+
+/// The checksums at the end of a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Footer {
+    pub source_crc: u32,
+    pub target_crc: u32,
+    /// The CRC32 of the patch up to, but not including, this checksum.
+    pub patch_crc: u32,
+}
+
+impl Footer {
+    pub const SIZE: usize = 12;
+}
+
+/// Appends the source and target checksums to `buf`, which holds the rest of the patch, followed
+/// by the checksum of everything before it. Returns the patch checksum.
+pub fn write_footer(buf: &mut Vec<u8>, source_crc: u32, target_crc: u32) -> u32 {
+    buf.write_u32::<LE>(source_crc).unwrap();
+    buf.write_u32::<LE>(target_crc).unwrap();
+    let patch_crc = hash::crc32(buf);
+    buf.write_u32::<LE>(patch_crc).unwrap();
+    patch_crc
+}
+
+/// Splits the footer off the end of `data`, returning the body of the patch and the footer.
+/// Fails if the patch is too short to have one, or if the patch checksum doesn't match.
+pub fn read_footer(data: &[u8]) -> Result<(&[u8], Footer)> {
+    if data.len() < Footer::SIZE {
+        return Err(Error::InvalidPatch);
+    }
+
+    let (body, mut footer) = data.split_at(data.len() - Footer::SIZE);
+    let footer = Footer {
+        source_crc: footer.read_u32::<LE>()?,
+        target_crc: footer.read_u32::<LE>()?,
+        patch_crc: footer.read_u32::<LE>()?,
+    };
+    let crc = hash::crc32(&data[..data.len() - 4]);
+    if crc != footer.patch_crc {
+        return Err(Error::CorruptPatch(crc, footer.patch_crc));
+    }
+    Ok((body, footer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn var_int() {
+        for value in [0, 1, 0x7f, 0x80, 0x407f, 0x4080, usize::MAX >> 1] {
+            let mut buf = Vec::new();
+            buf.write_var_int(value).unwrap();
+            assert_eq!((&buf[..]).read_var_int().unwrap(), value);
+        }
+        for value in [0, 5, -5, isize::MIN / 2] {
+            let mut buf = Vec::new();
+            buf.write_signed_var_int(value).unwrap();
+            assert_eq!((&buf[..]).read_signed_var_int().unwrap(), value);
+        }
+
+        let mut buf = Vec::new();
+        buf.write_var_int(0x80).unwrap();
+        assert_eq!(buf, [0x00, 0x80]);
+
+        // numbers past usize::MAX are a corrupt patch, not an overflow
+        let mut buf = Vec::new();
+        buf.write_var_int(usize::MAX).unwrap();
+        assert_eq!((&buf[..]).read_var_int().unwrap(), usize::MAX);
+        *buf.last_mut().unwrap() += 1;
+        for mut data in [&buf[..], &[0x7F; 12]] {
+            let err = Error::from(data.read_var_int().unwrap_err());
+            assert!(matches!(err, Error::InvalidPatch), "{data:?}: {err:?}");
+        }
+        // while running out of data is still an I/O error
+        let err = Error::from((&[0x7F][..]).read_var_int().unwrap_err());
+        assert!(matches!(err, Error::Io(_)), "{err:?}");
+    }
+
+    #[test]
+    fn footer() {
+        let patch = UpsPatch::create(b"source", b"target");
+        let mut data = patch.export(None).unwrap();
+        let (body, footer) = read_footer(&data).unwrap();
+        assert_eq!(footer.source_crc, crc32fast::hash(b"source"));
+        assert_eq!(footer.target_crc, crc32fast::hash(b"target"));
+
+        let mut rebuilt = body.to_vec();
+        write_footer(&mut rebuilt, footer.source_crc, footer.target_crc);
+        assert_eq!(rebuilt, data);
+
+        data[4] ^= 1;
+        assert!(matches!(read_footer(&data), Err(Error::CorruptPatch(..))));
+        assert!(matches!(read_footer(b"UPS1"), Err(Error::InvalidPatch)));
+    }
+}
@@ -0,0 +1,190 @@
+//! Comparing what patches do rather than how they're encoded.
+//!
+//! Without a base ROM, each patch is reduced to a model of its output as a function of the
+//! source: every output byte is either a constant or a source byte XORed with a constant. Two
+//! patches are equivalent if their models are identical for the sources they accept.
+
+use std::ops::Range;
+
+use crate::{prelude::*, Format};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sym {
+    Literal(u8),
+    /// The source byte at the given offset (or 0 past its end), XORed with a constant.
+    Source(usize, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Size {
+    Fixed(usize),
+    /// The larger of the given size and the size of the source.
+    AtLeast(usize),
+}
+
+pub(crate) struct Model {
+    /// The size of the source the patch accepts, if it records one.
+    pub source: Option<usize>,
+    pub size: Size,
+    /// The output, up to the last byte the patch writes. Later bytes are copied from the source.
+    pub output: Vec<Sym>,
+}
+
+impl Model {
+    fn sym(&self, offset: usize, source: Option<usize>) -> Sym {
+        match self
+            .output
+            .get(offset)
+            .copied()
+            .unwrap_or(Sym::Source(offset, 0))
+        {
+            Sym::Source(from, xor) if source.is_some_and(|size| from >= size) => Sym::Literal(xor),
+            sym => sym,
+        }
+    }
+}
+
+fn model(data: &[u8]) -> Option<Model> {
+    match crate::detect(data).ok()? {
+        Format::Ips => IpsPatch::load(data).ok().map(|p| p.model()),
+        Format::Ups => UpsPatch::load(data).ok().map(|p| p.model()),
+        Format::Bps => BpsPatch::load(data).ok()?.model().ok(),
+    }
+}
+
+/// The result of [`compare`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    /// The patches produce identical output.
+    Equivalent,
+    /// The outputs differ in the given ranges, including any bytes past the end of the shorter
+    /// output.
+    Different(Vec<Range<usize>>),
+    /// The patches can't be compared, because they expect different sources or one of them
+    /// couldn't be applied to the base.
+    Incomparable(String),
+}
+
+/// Compares the output of two patches, which may be in different formats. See
+/// [`Patch::equivalent`] for how patches are compared with and without a `base`.
+pub fn compare(a: &dyn Patch, b: &dyn Patch, base: Option<&[u8]>) -> Comparison {
+    compare_impl(a, b, base)
+}
+
+pub(crate) fn compare_impl(
+    a: &(impl Patch + ?Sized),
+    b: &dyn Patch,
+    base: Option<&[u8]>,
+) -> Comparison {
+    if let Some(base) = base {
+        return match (a.apply(base), b.apply(base)) {
+            (Ok(a), Ok(b)) => differences(a.len(), b.len(), |i| a[i] != b[i]),
+            (Err(err), _) => Comparison::Incomparable(format!("The first patch failed: {err}")),
+            (_, Err(err)) => Comparison::Incomparable(format!("The second patch failed: {err}")),
+        };
+    }
+
+    if let (Some(a), Some(b)) = (a.source(), b.source()) {
+        if (a.size, a.crc) != (b.size, b.crc) {
+            return Comparison::Incomparable("The patches expect different sources.".into());
+        }
+    }
+
+    let (Some(a), Some(b)) = (
+        a.export(None).ok().and_then(|data| model(&data)),
+        b.export(None).ok().and_then(|data| model(&data)),
+    ) else {
+        return Comparison::Incomparable("The patches couldn't be analyzed.".into());
+    };
+
+    let source = a.source.or(b.source);
+    let len = |size| match (size, source) {
+        (Size::Fixed(size), _) | (Size::AtLeast(size), None) => size,
+        (Size::AtLeast(size), Some(source)) => size.max(source),
+    };
+    differences(len(a.size), len(b.size), |i| {
+        a.sym(i, source) != b.sym(i, source)
+    })
+}
+
+pub(crate) fn is_noop(patch: &(impl Patch + ?Sized)) -> bool {
+    if let (Some(source), Some(target)) = (patch.source(), patch.target()) {
+        if (source.size, source.crc) != (target.size, target.crc) {
+            return false;
+        }
+    }
+
+    let Some(model) = patch.export(None).ok().and_then(|data| model(&data)) else {
+        return false;
+    };
+    let len = match (model.size, model.source) {
+        (Size::AtLeast(0), None) => 0,
+        (Size::Fixed(size), Some(source)) if size == source => size,
+        _ => return false,
+    };
+    (0..len).all(|i| model.sym(i, None) == Sym::Source(i, 0))
+}
+
+fn differences(a: usize, b: usize, differs: impl Fn(usize) -> bool) -> Comparison {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for i in (0..a.min(b)).filter(|&i| differs(i)) {
+        match ranges.last_mut() {
+            Some(range) if range.end == i => range.end += 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+
+    if a != b {
+        ranges.push(a.min(b)..a.max(b));
+    }
+
+    if ranges.is_empty() {
+        Comparison::Equivalent
+    } else {
+        Comparison::Different(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noop() {
+        let src = b"The quick brown fox";
+        assert!(IpsPatch::load(b"PATCHEOF").unwrap().is_noop());
+        assert!(UpsPatch::create(src, src).is_noop());
+        assert!(BpsPatch::create(src, src).is_noop());
+        assert!(!BpsPatch::create(src, b"The quick brown fox!").is_noop());
+        assert!(!IpsPatch::create(src, b"The quick brown cat").is_noop());
+    }
+
+    #[test]
+    fn equivalent() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!";
+        let ips = IpsPatch::create(src, dst);
+        let ups = UpsPatch::create(src, dst);
+        let bps = BpsPatch::create(src, dst);
+
+        // the same change split into differently sized records
+        let split =
+            IpsPatch::load(b"PATCH\0\0\x0A\0\x01r\0\0\x0B\0\x04ed f\0\0\x0F\0\x04ox!!EOF").unwrap();
+        assert!(ips.equivalent(&split, None));
+        assert!(!ips.equivalent(&IpsPatch::create(src, b"The quick red cat"), None));
+
+        assert!(ips.equivalent(&bps, None));
+
+        // XORs and literals only match on a particular source
+        assert!(!ips.equivalent(&ups, None));
+        assert!(ips.equivalent(&ups, Some(src)));
+        assert!(ups.equivalent(&bps, Some(src)));
+        assert!(!ups.equivalent(&bps, Some(dst)));
+
+        let cat = IpsPatch::create(src, b"The quick red cat!?");
+        assert_eq!(
+            compare(&ips, &cat, None),
+            Comparison::Different(vec![14..17, 18..19])
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! A C interface for emulators and GUI patchers that link against the library directly. The
+//! matching declarations in `include/patch_rs.h` are generated from this module by cbindgen,
+//! configured in `cbindgen.toml`, and the tests check they're up to date.
+//!
+//! Functions that can fail return one of the `PATCH_RS_*` status codes, and the message for the
+//! most recent failure on the calling thread can be retrieved with [`patch_rs_last_error`]. A
+//! panic is reported the same way instead of unwinding into the caller, which would abort it.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, c_int, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::prelude::*;
+
+pub const PATCH_RS_OK: c_int = 0;
+pub const PATCH_RS_ERROR: c_int = -1;
+pub const PATCH_RS_BUFFER_TOO_SMALL: c_int = -2;
+/// Returned by `patch_rs_validate` for patches that don't record a source checksum.
+pub const PATCH_RS_UNVERIFIABLE: c_int = -3;
+
+pub const PATCH_RS_FORMAT_IPS: c_int = 0;
+pub const PATCH_RS_FORMAT_UPS: c_int = 1;
+pub const PATCH_RS_FORMAT_BPS: c_int = 2;
+
+/// An opaque handle to a loaded patch.
+pub struct PatchRsPatch(Box<dyn Patch>);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|err| *err.borrow_mut() = Some(message));
+}
+
+/// Runs the body of an exported function, returning `failure` with the panic's message as the
+/// last error if it panics.
+fn catch<T>(failure: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload: Box<dyn Any + Send>| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+        set_error(format!("internal error: {message}"));
+        failure
+    })
+}
+
+unsafe fn as_slice<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Loads an IPS, UPS, or BPS patch, detecting the format from its contents. Returns null on
+/// failure. The patch must be released with `patch_rs_free`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_load(data: *const u8, len: usize) -> *mut PatchRsPatch {
+    catch(ptr::null_mut(), || {
+        if data.is_null() && len != 0 {
+            set_error("data is null");
+            return ptr::null_mut();
+        }
+
+        match crate::load(as_slice(data, len)) {
+            Ok(patch) => Box::into_raw(Box::new(PatchRsPatch(patch))),
+            Err(err) => {
+                set_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Creates a patch in the given `PATCH_RS_FORMAT_*` that turns `original` into `modified`.
+/// Returns null on failure, such as an IPS patch with changes past the first 4 GiB. The patch
+/// must be released with `patch_rs_free`.
+///
+/// # Safety
+///
+/// `original` and `modified` must point to `original_len` and `modified_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_create(
+    format: c_int,
+    original: *const u8,
+    original_len: usize,
+    modified: *const u8,
+    modified_len: usize,
+) -> *mut PatchRsPatch {
+    catch(ptr::null_mut(), || {
+        if (original.is_null() && original_len != 0) || (modified.is_null() && modified_len != 0) {
+            set_error("invalid argument");
+            return ptr::null_mut();
+        }
+
+        let (original, modified) = (
+            as_slice(original, original_len),
+            as_slice(modified, modified_len),
+        );
+        let options = CreateOptions::default();
+        let patch: crate::Result<Box<dyn Patch>> = match format {
+            PATCH_RS_FORMAT_IPS => IpsPatch::create_observed(original, modified, &options, &mut ())
+                .map(|patch| Box::new(patch) as _),
+            PATCH_RS_FORMAT_UPS => UpsPatch::create_observed(original, modified, &options, &mut ())
+                .map(|patch| Box::new(patch) as _),
+            PATCH_RS_FORMAT_BPS => BpsPatch::create_observed(original, modified, &options, &mut ())
+                .map(|patch| Box::new(patch) as _),
+            _ => {
+                set_error("unsupported format");
+                return ptr::null_mut();
+            }
+        };
+        match patch {
+            Ok(patch) => Box::into_raw(Box::new(PatchRsPatch(patch))),
+            Err(err) => {
+                set_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Copies `data` into `out`, following the buffer convention described on [`patch_rs_apply`].
+unsafe fn write_out(data: &[u8], out: *mut u8, out_len: *mut usize) -> c_int {
+    let capacity = *out_len;
+    *out_len = data.len();
+    if out.is_null() || capacity < data.len() {
+        return PATCH_RS_BUFFER_TOO_SMALL;
+    }
+
+    ptr::copy_nonoverlapping(data.as_ptr(), out, data.len());
+    PATCH_RS_OK
+}
+
+/// Checks that `rom` is the source `patch` expects. Returns `PATCH_RS_ERROR` with the reason
+/// available from `patch_rs_last_error` if it isn't, or `PATCH_RS_UNVERIFIABLE` if the patch
+/// doesn't record a source checksum.
+///
+/// # Safety
+///
+/// `patch` must come from `patch_rs_load` or `patch_rs_create`, and `rom` must point to
+/// `rom_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_validate(
+    patch: *const PatchRsPatch,
+    rom: *const u8,
+    rom_len: usize,
+) -> c_int {
+    catch(PATCH_RS_ERROR, || {
+        if patch.is_null() || (rom.is_null() && rom_len != 0) {
+            set_error("invalid argument");
+            return PATCH_RS_ERROR;
+        }
+
+        match (*patch).0.validate(as_slice(rom, rom_len)) {
+            Some(Ok(())) => PATCH_RS_OK,
+            Some(Err(err)) => {
+                set_error(err);
+                PATCH_RS_ERROR
+            }
+            None => PATCH_RS_UNVERIFIABLE,
+        }
+    })
+}
+
+/// Serializes `patch` into `out`, following the same buffer convention as `patch_rs_apply`.
+///
+/// # Safety
+///
+/// `patch` must come from `patch_rs_load` or `patch_rs_create`, and `out` must point to
+/// `*out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_export(
+    patch: *const PatchRsPatch,
+    out: *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    catch(PATCH_RS_ERROR, || {
+        if patch.is_null() || out_len.is_null() {
+            set_error("invalid argument");
+            return PATCH_RS_ERROR;
+        }
+
+        match (*patch).0.export(None) {
+            Ok(data) => write_out(&data, out, out_len),
+            Err(err) => {
+                set_error(err);
+                PATCH_RS_ERROR
+            }
+        }
+    })
+}
+
+/// Applies `patch` to `rom`, writing the output into `out`.
+///
+/// On entry `*out_len` is the capacity of `out`, and on return it's the size of the output. If
+/// the output doesn't fit, nothing is written and `PATCH_RS_BUFFER_TOO_SMALL` is returned, so
+/// callers can pass a null `out` to query the size first.
+///
+/// # Safety
+///
+/// `patch` must come from `patch_rs_load` or `patch_rs_create`, `rom` must point to `rom_len`
+/// readable bytes, and `out` must point to `*out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_apply(
+    patch: *const PatchRsPatch,
+    rom: *const u8,
+    rom_len: usize,
+    out: *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    catch(PATCH_RS_ERROR, || {
+        if patch.is_null() || out_len.is_null() || (rom.is_null() && rom_len != 0) {
+            set_error("invalid argument");
+            return PATCH_RS_ERROR;
+        }
+
+        let output = match (*patch).0.apply(as_slice(rom, rom_len)) {
+            Ok(output) => output,
+            Err(err) => {
+                set_error(err);
+                return PATCH_RS_ERROR;
+            }
+        };
+
+        write_out(&output, out, out_len)
+    })
+}
+
+/// Releases a patch returned by `patch_rs_load` or `patch_rs_create`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `patch` must come from `patch_rs_load` or `patch_rs_create` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn patch_rs_free(patch: *mut PatchRsPatch) {
+    catch((), || {
+        if !patch.is_null() {
+            drop(Box::from_raw(patch));
+        }
+    })
+}
+
+/// Returns the message for the most recent failure on the calling thread, or null if nothing has
+/// failed. The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn patch_rs_last_error() -> *const c_char {
+    catch(ptr::null(), || {
+        LAST_ERROR.with(|err| {
+            err.borrow()
+                .as_ref()
+                .map_or(ptr::null(), |err| err.as_ptr())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, ffi::CStr, fs};
+
+    use super::*;
+
+    #[test]
+    fn load_and_apply() {
+        let data = b"PATCH\0\0\x01\0\x02abEOF";
+        unsafe {
+            let patch = patch_rs_load(data.as_ptr(), data.len());
+            assert!(!patch.is_null());
+
+            let rom = b"0123";
+            let mut len = 0;
+            let status = patch_rs_apply(patch, rom.as_ptr(), rom.len(), ptr::null_mut(), &mut len);
+            assert_eq!((status, len), (PATCH_RS_BUFFER_TOO_SMALL, 4));
+
+            let mut out = vec![0; len];
+            let status = patch_rs_apply(patch, rom.as_ptr(), rom.len(), out.as_mut_ptr(), &mut len);
+            assert_eq!(status, PATCH_RS_OK);
+            assert_eq!(out, b"0ab3");
+            patch_rs_free(patch);
+
+            assert!(patch_rs_load(b"junk".as_ptr(), 4).is_null());
+            assert!(!patch_rs_last_error().is_null());
+        }
+    }
+
+    #[test]
+    fn create_and_validate() {
+        unsafe {
+            let patch =
+                patch_rs_create(PATCH_RS_FORMAT_UPS, b"abc".as_ptr(), 3, b"abd".as_ptr(), 3);
+            assert_eq!(patch_rs_validate(patch, b"abc".as_ptr(), 3), PATCH_RS_OK);
+            assert_eq!(patch_rs_validate(patch, b"xyz".as_ptr(), 3), PATCH_RS_ERROR);
+
+            let mut len = 0;
+            let status = patch_rs_export(patch, ptr::null_mut(), &mut len);
+            assert_eq!(status, PATCH_RS_BUFFER_TOO_SMALL);
+            let mut out = vec![0; len];
+            assert_eq!(
+                patch_rs_export(patch, out.as_mut_ptr(), &mut len),
+                PATCH_RS_OK
+            );
+            assert!(out.starts_with(b"UPS1"));
+            patch_rs_free(patch);
+
+            let patch =
+                patch_rs_create(PATCH_RS_FORMAT_BPS, b"abc".as_ptr(), 3, b"abd".as_ptr(), 3);
+            assert!(!patch.is_null());
+            assert_eq!((*patch).0.format(), "BPS");
+            patch_rs_free(patch);
+            assert!(patch_rs_create(3, ptr::null(), 0, ptr::null(), 0).is_null());
+        }
+    }
+
+    #[test]
+    fn panics() {
+        let last_error = || unsafe { CStr::from_ptr(patch_rs_last_error()) };
+        assert_eq!(catch(PATCH_RS_ERROR, || panic!("oops")), PATCH_RS_ERROR);
+        assert_eq!(last_error().to_str(), Ok("internal error: oops"));
+        assert!(catch(ptr::null::<u8>(), || panic!("{}", 1)).is_null());
+        assert_eq!(last_error().to_str(), Ok("internal error: 1"));
+    }
+
+    #[test]
+    fn header() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{dir}/cbindgen.toml")).unwrap();
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{dir}/src/ffi.rs"))
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+
+        let path = format!("{dir}/include/patch_rs.h");
+        if env::var_os("PATCH_RS_UPDATE_HEADER").is_some() {
+            fs::write(&path, &generated).unwrap();
+        }
+        assert!(
+            fs::read(&path).unwrap() == generated,
+            "include/patch_rs.h is out of date, rerun with PATCH_RS_UPDATE_HEADER=1 to regenerate it"
+        );
+    }
+}
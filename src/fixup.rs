@@ -0,0 +1,202 @@
+//! Recomputes the internal checksums stored in ROM headers, which patches frequently leave stale.
+//! Each function expects the ROM without any copier header.
+
+use crate::{Error, Result};
+
+/// Mapping modes used to locate the SNES internal header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnesMapping {
+    LoRom,
+    HiRom,
+    ExHiRom,
+}
+
+impl SnesMapping {
+    fn header_offset(self) -> usize {
+        match self {
+            SnesMapping::LoRom => 0x7FC0,
+            SnesMapping::HiRom => 0xFFC0,
+            SnesMapping::ExHiRom => 0x40FFC0,
+        }
+    }
+
+    /// How much the header at this mapping's offset looks like a real one: a map mode for this
+    /// mapping counts 2, a checksum and complement that add up counts 2, and a reset vector into
+    /// ROM counts 1. `None` if the ROM is too small to hold the header.
+    fn score(self, rom: &[u8]) -> Option<u32> {
+        let header = rom.get(self.header_offset()..self.header_offset() + 0x40)?;
+        let word = |offset: usize| u16::from_le_bytes([header[offset], header[offset + 1]]);
+
+        let mode = header[0x15];
+        let mode_matches = mode & 0xE0 == 0x20
+            && match self {
+                SnesMapping::LoRom => mode & 0x0F == 0x00 || mode & 0x0F == 0x02,
+                SnesMapping::HiRom => mode & 0x0F == 0x01,
+                SnesMapping::ExHiRom => mode & 0x0F == 0x05,
+            };
+        let checksum_matches = word(0x1C) ^ word(0x1E) == 0xFFFF;
+        let reset_in_rom = word(0x3C) >= 0x8000;
+        Some(2 * mode_matches as u32 + 2 * checksum_matches as u32 + reset_in_rom as u32)
+    }
+
+    /// Detects the mapping mode by scoring each candidate header and picking the best, since a
+    /// LoRom game's code can happen to hold a HiRom map mode where the HiRom header would be.
+    /// Returns `None` unless some candidate has at least a matching map mode or checksum.
+    pub fn detect(rom: &[u8]) -> Option<Self> {
+        // on a tie the last candidate wins, so the larger mappings are checked first
+        [SnesMapping::LoRom, SnesMapping::HiRom, SnesMapping::ExHiRom]
+            .into_iter()
+            .filter_map(|mapping| Some((mapping, mapping.score(rom)?)))
+            .filter(|&(_, score)| score >= 2)
+            .max_by_key(|&(_, score)| score)
+            .map(|(mapping, _)| mapping)
+    }
+}
+
+/// Recomputes the SNES internal checksum and its complement, returning the new checksum.
+pub fn snes(rom: &mut [u8]) -> Result<u16> {
+    let mapping = SnesMapping::detect(rom).ok_or(Error::UnrecognizedRom("SNES"))?;
+    let header = mapping.header_offset();
+
+    rom[header + 0x1C..header + 0x20].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+
+    let sum = |data: &[u8]| data.iter().fold(0u32, |acc, &b| acc.wrapping_add(b as u32));
+    // sizes that aren't a power of two are summed as if the remainder were mirrored to fill the
+    // next power of two, which is how the checksum is defined for e.g. 3MB and 6MB ROMs
+    let base = 1 << rom.len().ilog2();
+    let mut checksum = sum(&rom[..base]);
+    if base != rom.len() {
+        let rest = &rom[base..];
+        checksum = checksum.wrapping_add(sum(rest).wrapping_mul((base / rest.len()) as u32));
+    }
+
+    let checksum = checksum as u16;
+    rom[header + 0x1C..header + 0x1E].copy_from_slice(&(checksum ^ 0xFFFF).to_le_bytes());
+    rom[header + 0x1E..header + 0x20].copy_from_slice(&checksum.to_le_bytes());
+    Ok(checksum)
+}
+
+/// Recomputes the 16-bit checksum at 0x18E in a Sega Genesis/Mega Drive ROM header, returning
+/// the new checksum.
+pub fn genesis(rom: &mut [u8]) -> Result<u16> {
+    if rom.len() < 0x200
+        || !(rom[0x100..].starts_with(b"SEGA") || rom[0x101..].starts_with(b"SEGA"))
+    {
+        return Err(Error::UnrecognizedRom("Mega Drive"));
+    }
+
+    let checksum = rom[0x200..].chunks(2).fold(0u16, |acc, word| {
+        acc.wrapping_add(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]))
+    });
+    rom[0x18E..0x190].copy_from_slice(&checksum.to_be_bytes());
+    Ok(checksum)
+}
+
+/// Recomputes the Game Boy/Game Boy Color header checksum at 0x14D and global checksum at
+/// 0x14E-0x14F, returning both.
+pub fn game_boy(rom: &mut [u8]) -> Result<(u8, u16)> {
+    const LOGO: &[u8] = &[0xCE, 0xED, 0x66, 0x66];
+    if rom.len() < 0x150 || !rom[0x104..].starts_with(LOGO) {
+        return Err(Error::UnrecognizedRom("Game Boy"));
+    }
+
+    let header = rom[0x134..0x14D]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+    rom[0x14D] = header;
+
+    let global = rom
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+        .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16));
+    rom[0x14E..0x150].copy_from_slice(&global.to_be_bytes());
+    Ok((header, global))
+}
+
+/// Recomputes the GBA header complement check at 0xBD, returning the new value.
+pub fn gba(rom: &mut [u8]) -> Result<u8> {
+    if rom.len() < 0xC0 || rom[0xB2] != 0x96 {
+        return Err(Error::UnrecognizedRom("GBA"));
+    }
+
+    let check = rom[0xA0..0xBD]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b))
+        .wrapping_sub(0x19);
+    rom[0xBD] = check;
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snes_lorom() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x7FC0 + 0x15] = 0x20;
+        rom[0] = 0x12;
+        rom[0xFFFF] = 0x34;
+
+        let checksum = snes(&mut rom).unwrap();
+        assert_eq!(checksum, 0x12 + 0x34 + 0x20 + 0xFF * 2);
+        assert_eq!(&rom[0x7FDC..0x7FE0], &[0x9B, 0xFD, 0x64, 0x02]);
+
+        // the checksum is stable once written
+        assert_eq!(snes(&mut rom).unwrap(), checksum);
+    }
+
+    #[test]
+    fn snes_mapping() {
+        // a LoRom game whose code happens to hold a HiRom map mode where the HiRom header would be
+        for mode in [0x21, 0x31] {
+            let mut rom = vec![0; 0x10000];
+            rom[0x7FC0 + 0x15] = 0x20;
+            rom[0x7FDC..0x7FE0].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+            rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+            rom[0xFFC0 + 0x15] = mode;
+            assert_eq!(SnesMapping::detect(&rom), Some(SnesMapping::LoRom));
+
+            snes(&mut rom).unwrap();
+            assert!(rom[0xFFDC..0xFFE0].iter().all(|&b| b == 0));
+        }
+
+        // a ROM without anything that looks like a header is left alone
+        let mut rom = vec![0; 0x10000];
+        assert!(matches!(
+            snes(&mut rom),
+            Err(Error::UnrecognizedRom("SNES"))
+        ));
+        assert!(rom.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn genesis_checksum() {
+        let mut rom = vec![0; 0x205];
+        rom[0x100..0x104].copy_from_slice(b"SEGA");
+        rom[0x200..].copy_from_slice(&[0x12, 0x34, 0xFF, 0xFF, 0x01]);
+
+        assert_eq!(genesis(&mut rom).unwrap(), 0x1333);
+        assert_eq!(&rom[0x18E..0x190], &[0x13, 0x33]);
+        assert!(genesis(&mut [0; 0x200]).is_err());
+    }
+
+    #[test]
+    fn game_boy_checksums() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x108].copy_from_slice(&[0xCE, 0xED, 0x66, 0x66]);
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+
+        assert_eq!(game_boy(&mut rom).unwrap(), (0xA7, 0x46E));
+        assert_eq!(&rom[0x14D..0x150], &[0xA7, 0x04, 0x6E]);
+    }
+
+    #[test]
+    fn gba_complement() {
+        let mut rom = vec![0; 0xC0];
+        rom[0xB2] = 0x96;
+        assert_eq!(gba(&mut rom).unwrap(), 0x51);
+        assert_eq!(rom[0xBD], 0x51);
+    }
+}
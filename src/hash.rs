@@ -0,0 +1,417 @@
+//! Hash functions used for validating ROMs and patches.
+//!
+//! CRC32 is computed with `crc32fast` unless another engine is installed with [`set_crc32`], e.g.
+//! a hardware CRC unit on an embedded target. Every hash implements [`Checksum`], and
+//! [`Validation::validate_with`](crate::Validation::validate_with) checks data against any extra
+//! hashes an integrator keeps alongside a patch, such as xxh3 for a cache.
+
+use std::sync::OnceLock;
+
+use crate::Error;
+
+type Crc32Engine = Box<dyn Fn(u32, &[u8]) -> u32 + Send + Sync>;
+
+static CRC32: OnceLock<Crc32Engine> = OnceLock::new();
+
+/// Installs the function that computes every CRC32 in the crate. It's given the CRC32 of the data
+/// so far (0 for none) and the next bytes, and returns the CRC32 of all of it, like zlib's
+/// `crc32`. Returns `false` if an engine was already installed.
+pub fn set_crc32(engine: impl Fn(u32, &[u8]) -> u32 + Send + Sync + 'static) -> bool {
+    CRC32.set(Box::new(engine)).is_ok()
+}
+
+/// Computes the CRC32 of `data` with the installed engine.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = Crc32Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Computes a CRC32 incrementally with the installed engine.
+#[derive(Debug, Default, Clone)]
+pub struct Crc32Hasher {
+    crc: u32,
+}
+
+impl Crc32Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc = match CRC32.get() {
+            Some(engine) => engine(self.crc, data),
+            None => {
+                let mut hasher = crc32fast::Hasher::new_with_initial(self.crc);
+                hasher.update(data);
+                hasher.finalize()
+            }
+        };
+    }
+
+    pub fn finalize(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// A hash function data can be checked against.
+pub trait Checksum {
+    /// The name reported in [`Error::InvalidHash`](crate::Error::InvalidHash), e.g. `"SHA-1"`.
+    fn name(&self) -> &'static str;
+    /// Computes the digest of `data`.
+    fn digest(&self, data: &[u8]) -> Vec<u8>;
+}
+
+/// CRC32, computed with the engine installed by [`set_crc32`]. The digest is big-endian, the way
+/// CRCs are usually written.
+pub struct Crc32;
+pub struct Md5;
+pub struct Sha1;
+pub struct Sha256;
+
+impl Checksum for Crc32 {
+    fn name(&self) -> &'static str {
+        "CRC32"
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        crc32(data).to_be_bytes().to_vec()
+    }
+}
+
+impl Checksum for Md5 {
+    fn name(&self) -> &'static str {
+        "MD5"
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        md5(data).to_vec()
+    }
+}
+
+impl Checksum for Sha1 {
+    fn name(&self) -> &'static str {
+        "SHA-1"
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        sha1(data).to_vec()
+    }
+}
+
+impl Checksum for Sha256 {
+    fn name(&self) -> &'static str {
+        "SHA-256"
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        sha256(data).to_vec()
+    }
+}
+
+/// Calls `f` with each 64-byte block of `data` as processed by MD5 and the SHA-1/SHA-2 family,
+/// padding the last block with the message length in bits encoded by `encode_len`.
+fn for_each_block(data: &[u8], encode_len: fn(u64) -> [u8; 8], mut f: impl FnMut(&[u8])) {
+    let whole = data.len() / 64 * 64;
+    let mut tail = Vec::with_capacity(128);
+    tail.extend_from_slice(&data[whole..]);
+    tail.push(0x80);
+    while tail.len() % 64 != 56 {
+        tail.push(0);
+    }
+    tail.extend_from_slice(&encode_len((data.len() as u64).wrapping_mul(8)));
+
+    data[..whole]
+        .chunks_exact(64)
+        .chain(tail.chunks_exact(64))
+        .for_each(&mut f);
+}
+
+/// Computes the MD5 digest of `data`.
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    const K: [u32; 64] = [
+        0xD76AA478, 0xE8C7B756, 0x242070DB, 0xC1BDCEEE, 0xF57C0FAF, 0x4787C62A, 0xA8304613,
+        0xFD469501, 0x698098D8, 0x8B44F7AF, 0xFFFF5BB1, 0x895CD7BE, 0x6B901122, 0xFD987193,
+        0xA679438E, 0x49B40821, 0xF61E2562, 0xC040B340, 0x265E5A51, 0xE9B6C7AA, 0xD62F105D,
+        0x02441453, 0xD8A1E681, 0xE7D3FBC8, 0x21E1CDE6, 0xC33707D6, 0xF4D50D87, 0x455A14ED,
+        0xA9E3E905, 0xFCEFA3F8, 0x676F02D9, 0x8D2A4C8A, 0xFFFA3942, 0x8771F681, 0x6D9D6122,
+        0xFDE5380C, 0xA4BEEA44, 0x4BDECFA9, 0xF6BB4B60, 0xBEBFBC70, 0x289B7EC6, 0xEAA127FA,
+        0xD4EF3085, 0x04881D05, 0xD9D4D039, 0xE6DB99E5, 0x1FA27CF8, 0xC4AC5665, 0xF4292244,
+        0x432AFF97, 0xAB9423A7, 0xFC93A039, 0x655B59C3, 0x8F0CCC92, 0xFFEFF47D, 0x85845DD1,
+        0x6FA87E4F, 0xFE2CE6E0, 0xA3014314, 0x4E0811A1, 0xF7537E82, 0xBD3AF235, 0x2AD7D2BB,
+        0xEB86D391,
+    ];
+
+    let mut h: [u32; 4] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+    for_each_block(data, u64::to_le_bytes, |block| {
+        let mut m = [0u32; 16];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            m[i] = u32::from_le_bytes(word.try_into().unwrap());
+        }
+
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i {
+                0..=15 => ((b & c) | (!b & d), i),
+                16..=31 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                32..=47 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i / 16 * 4 + i % 4]));
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d]) {
+            *h = h.wrapping_add(v);
+        }
+    });
+
+    let mut digest = [0; 16];
+    for (chunk, h) in digest.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&h.to_le_bytes());
+    }
+    digest
+}
+
+/// Computes the SHA-1 digest of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    for_each_block(data, u64::to_be_bytes, |block| {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    });
+
+    let mut digest = [0; 20];
+    for (chunk, h) in digest.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Computes the SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428A2F98, 0x71374491, 0xB5C0FBCF, 0xE9B5DBA5, 0x3956C25B, 0x59F111F1, 0x923F82A4,
+        0xAB1C5ED5, 0xD807AA98, 0x12835B01, 0x243185BE, 0x550C7DC3, 0x72BE5D74, 0x80DEB1FE,
+        0x9BDC06A7, 0xC19BF174, 0xE49B69C1, 0xEFBE4786, 0x0FC19DC6, 0x240CA1CC, 0x2DE92C6F,
+        0x4A7484AA, 0x5CB0A9DC, 0x76F988DA, 0x983E5152, 0xA831C66D, 0xB00327C8, 0xBF597FC7,
+        0xC6E00BF3, 0xD5A79147, 0x06CA6351, 0x14292967, 0x27B70A85, 0x2E1B2138, 0x4D2C6DFC,
+        0x53380D13, 0x650A7354, 0x766A0ABB, 0x81C2C92E, 0x92722C85, 0xA2BFE8A1, 0xA81A664B,
+        0xC24B8B70, 0xC76C51A3, 0xD192E819, 0xD6990624, 0xF40E3585, 0x106AA070, 0x19A4C116,
+        0x1E376C08, 0x2748774C, 0x34B0BCB5, 0x391C0CB3, 0x4ED8AA4A, 0x5B9CCA4F, 0x682E6FF3,
+        0x748F82EE, 0x78A5636F, 0x84C87814, 0x8CC70208, 0x90BEFFFA, 0xA4506CEB, 0xBEF9A3F7,
+        0xC67178F2,
+    ];
+
+    let mut h: [u32; 8] = [
+        0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB,
+        0x5BE0CD19,
+    ];
+    for_each_block(data, u64::to_be_bytes, |block| {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for (&k, &w) in K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    });
+
+    let mut digest = [0; 32];
+    for (chunk, h) in digest.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Checks `data` against the `expected` digest, returning the error reporting a mismatch.
+pub(crate) fn mismatch(checksum: &dyn Checksum, data: &[u8], expected: &[u8]) -> Option<Error> {
+    let actual = checksum.digest(data);
+    (actual != expected)
+        .then(|| Error::InvalidHash(checksum.name(), to_hex(&actual), to_hex(expected)))
+}
+
+/// Formats `bytes` as a lowercase hexadecimal string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn sha1_vectors() {
+        assert_eq!(
+            to_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            to_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            to_hex(&sha1(&[b'a'; 1000])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+
+    #[test]
+    fn md5_vectors() {
+        assert_eq!(to_hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(to_hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            to_hex(&md5(&[b'a'; 1000])),
+            "cabe45dcc9ae5b66ba86600cca6b8ba8"
+        );
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(
+            to_hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            to_hex(&sha256(&[b'a'; 1000])),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn validation_hashes() {
+        let mut validation = crate::Validation::with_hashes(b"abc");
+        assert!(validation.validate(b"abc").is_ok());
+
+        validation.crc = crc32fast::hash(b"abd");
+        validation.sha256 = None;
+        let errors = validation.mismatches(b"abd");
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                crate::Error::InvalidHash("MD5", ..),
+                crate::Error::InvalidHash("SHA-1", ..)
+            ]
+        ));
+    }
+
+    #[test]
+    fn crc32_engine() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        // the engine stays installed for every other test, so it has to be correct
+        let installed = set_crc32(|crc, data| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+            hasher.update(data);
+            hasher.finalize()
+        });
+        assert!(installed);
+        assert!(!set_crc32(|crc, _| crc));
+
+        let validation = crate::Validation::new(b"abc");
+        assert_eq!(validation.crc, crc32fast::hash(b"abc"));
+        assert!(CALLS.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn validate_with() {
+        struct Sum;
+
+        impl Checksum for Sum {
+            fn name(&self) -> &'static str {
+                "sum"
+            }
+
+            fn digest(&self, data: &[u8]) -> Vec<u8> {
+                vec![data.iter().fold(0, |sum, &b| sum.wrapping_add(b))]
+            }
+        }
+
+        let validation = crate::Validation::new(b"abc");
+        assert!(validation.validate_with(b"abc", &[(&Sum, &[0x26])]).is_ok());
+        assert!(matches!(
+            validation.validate_with(b"abc", &[(&Crc32, &[0; 4]), (&Sum, &[0x26])]),
+            Err(crate::Error::InvalidHash("CRC32", ..))
+        ));
+        assert_eq!(Crc32.digest(b"abc"), crc32fast::hash(b"abc").to_be_bytes());
+    }
+}
@@ -0,0 +1,238 @@
+//! Support for patching ROMs whose dumps may or may not include a header that isn't part of the
+//! ROM data itself, such as the 512-byte copier header on SNES ROMs.
+
+use std::fmt;
+
+use crate::{Error, Patch, Result, Validation};
+
+/// How a header on the ROM should be handled when applying a patch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    /// Apply the patch to the ROM as-is.
+    #[default]
+    Keep,
+    /// The ROM has a header but the patch was made for a headerless ROM. The patch is applied to
+    /// the data after the header, and the header is reattached to the output.
+    Skip,
+    /// The ROM has no header but the patch was made for a headered ROM. A blank header is added
+    /// before applying the patch, and removed from the output. Patches that validate their source
+    /// will only accept this if the original header was blank too.
+    Add,
+    /// Choose between the other policies based on whether the ROM appears to have a header and
+    /// which variant matches the patch's expected source.
+    Auto,
+}
+
+/// A kind of header that may be present on a ROM dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderKind {
+    /// The 512-byte header added by SNES copier devices.
+    Snes,
+    /// The 16-byte iNES or NES 2.0 header on NES ROMs.
+    Ines,
+}
+
+impl HeaderKind {
+    const INES_MAGIC: &[u8; 4] = b"NES\x1A";
+
+    pub fn size(self) -> usize {
+        match self {
+            HeaderKind::Snes => 512,
+            HeaderKind::Ines => 16,
+        }
+    }
+
+    /// Whether `rom` appears to start with this kind of header.
+    pub fn is_present(self, rom: &[u8]) -> bool {
+        match self {
+            HeaderKind::Snes => rom.len() % 1024 == 512,
+            HeaderKind::Ines => rom.starts_with(Self::INES_MAGIC),
+        }
+    }
+
+    /// Returns the kind of header `rom` appears to start with, if any.
+    pub fn detect(rom: &[u8]) -> Option<Self> {
+        [HeaderKind::Ines, HeaderKind::Snes]
+            .into_iter()
+            .find(|kind| kind.is_present(rom))
+    }
+
+    /// Returns the policy that would make `rom` match the patch's expected source, if the
+    /// mismatch is likely caused by a header of this kind being present or missing.
+    pub fn diagnose<P: Patch + ?Sized>(self, patch: &P, rom: &[u8]) -> Option<HeaderPolicy> {
+        if !matches!(patch.validate(rom), Some(Err(_))) {
+            return None;
+        }
+
+        Some(resolve_policy(patch, rom, self)).filter(|&policy| policy != HeaderPolicy::Keep)
+    }
+
+    fn blank(self) -> Vec<u8> {
+        vec![0; self.size()]
+    }
+}
+
+impl fmt::Display for HeaderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderKind::Snes => write!(f, "SNES copier"),
+            HeaderKind::Ines => write!(f, "iNES"),
+        }
+    }
+}
+
+pub(crate) fn describe_mismatch(kind: HeaderKind, policy: HeaderPolicy) -> String {
+    match policy {
+        HeaderPolicy::Skip => format!("have an extra {}-byte {kind} header", kind.size()),
+        _ => format!("be missing a {}-byte {kind} header", kind.size()),
+    }
+}
+
+/// Checks whether the validation error `err` for `rom` would go away if a known kind of header
+/// were skipped or added, returning [`Error::HeaderMismatch`] describing the fix if so.
+pub fn explain_mismatch<P: Patch + ?Sized>(patch: &P, rom: &[u8], err: Error) -> Error {
+    for kind in [HeaderKind::Ines, HeaderKind::Snes] {
+        if let Some(policy) = kind.diagnose(patch, rom) {
+            return Error::HeaderMismatch {
+                inner: Box::new(err),
+                kind,
+                policy,
+            };
+        }
+    }
+
+    err
+}
+
+/// The checksums of a ROM both as-is and without its header, if one was detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomChecksums {
+    pub full: Validation,
+    pub headerless: Option<(HeaderKind, Validation)>,
+}
+
+impl RomChecksums {
+    pub fn new(rom: &[u8]) -> Self {
+        Self {
+            full: Validation::new(rom),
+            headerless: HeaderKind::detect(rom)
+                .filter(|kind| rom.len() >= kind.size())
+                .map(|kind| (kind, Validation::new(&rom[kind.size()..]))),
+        }
+    }
+}
+
+/// Applies `patch` to `rom`, handling a header of the given kind according to `policy`.
+pub fn apply_with_header<P: Patch + ?Sized>(
+    patch: &P,
+    rom: &[u8],
+    kind: HeaderKind,
+    policy: HeaderPolicy,
+) -> Result<Vec<u8>> {
+    match policy {
+        HeaderPolicy::Keep => patch.apply(rom),
+        HeaderPolicy::Skip => {
+            let (header, body) = rom.split_at(kind.size().min(rom.len()));
+            let mut buf = header.to_vec();
+            buf.extend(patch.apply(body)?);
+            Ok(buf)
+        }
+        HeaderPolicy::Add => {
+            let mut headered = kind.blank();
+            headered.extend_from_slice(rom);
+            let mut buf = patch.apply(&headered)?;
+            buf.drain(..kind.size().min(buf.len()));
+            Ok(buf)
+        }
+        HeaderPolicy::Auto => apply_with_header(patch, rom, kind, resolve_policy(patch, rom, kind)),
+    }
+}
+
+/// Picks the policy [`HeaderPolicy::Auto`] would use for `rom`.
+pub fn resolve_policy<P: Patch + ?Sized>(patch: &P, rom: &[u8], kind: HeaderKind) -> HeaderPolicy {
+    let present = kind.is_present(rom);
+    match patch.validate(rom) {
+        // the patch can't tell us which variant it expects, so trust the detection
+        None if present => return HeaderPolicy::Skip,
+        None | Some(Ok(())) => return HeaderPolicy::Keep,
+        Some(Err(_)) => {}
+    }
+
+    if present && rom.len() >= kind.size() {
+        if let Some(Ok(())) = patch.validate(&rom[kind.size()..]) {
+            return HeaderPolicy::Skip;
+        }
+    }
+
+    let mut headered = kind.blank();
+    headered.extend_from_slice(rom);
+    if let Some(Ok(())) = patch.validate(&headered) {
+        return HeaderPolicy::Add;
+    }
+
+    HeaderPolicy::Keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn auto_header() {
+        let src = vec![0x11; 1024];
+        let mut dst = src.clone();
+        dst[10] = 0x22;
+        let patch = UpsPatch::create(&src, &dst);
+
+        let mut headered = vec![0xAA; 512];
+        headered.extend_from_slice(&src);
+        assert_eq!(
+            resolve_policy(&patch, &headered, HeaderKind::Snes),
+            HeaderPolicy::Skip
+        );
+
+        let out = apply_with_header(&patch, &headered, HeaderKind::Snes, HeaderPolicy::Auto);
+        assert_eq!(&out.unwrap()[512..], &dst[..]);
+    }
+
+    #[test]
+    fn checksums() {
+        let mut rom = vec![0; 512];
+        rom.extend_from_slice(&[1; 1024]);
+        let checksums = RomChecksums::new(&rom);
+        assert_eq!(checksums.full, Validation::new(&rom));
+        assert_eq!(
+            checksums.headerless,
+            Some((HeaderKind::Snes, Validation::new(&rom[512..])))
+        );
+        assert_eq!(RomChecksums::new(&rom[512..]).headerless, None);
+    }
+
+    #[test]
+    fn ines_header() {
+        let mut headered = b"NES\x1A\x02\x01".to_vec();
+        headered.resize(16, 0);
+        headered.extend_from_slice(&[0x11; 64]);
+
+        let mut dst = headered[16..].to_vec();
+        dst[3] = 0x22;
+        let patch = UpsPatch::create(&headered[16..], &dst);
+
+        assert_eq!(HeaderKind::detect(&headered), Some(HeaderKind::Ines));
+        assert_eq!(
+            HeaderKind::Ines.diagnose(&patch, &headered),
+            Some(HeaderPolicy::Skip)
+        );
+
+        let err = patch.validate(&headered).unwrap().unwrap_err();
+        assert!(matches!(
+            explain_mismatch(&patch, &headered, err),
+            Error::HeaderMismatch {
+                kind: HeaderKind::Ines,
+                policy: HeaderPolicy::Skip,
+                ..
+            }
+        ));
+    }
+}
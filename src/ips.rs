@@ -1,128 +1,1283 @@
-use std::io::Write;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{Read, Write},
+};
 
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 
-use crate::{Error, Patch, ReadExt, Result};
+use crate::{
+    equiv::{Model, Size, Sym},
+    json::{Object, Value},
+    lint::Lint,
+    progress::{self, Event, Observer, Ticker},
+    script::{self, Script},
+    source::Source,
+    stream::{self, Edit, PatchReader, PatchWriter, Patcher, StreamSizes},
+    trace::Span,
+    vectored::Chunks,
+    CreateOptions, Error, Patch, ReadExt, RecordKind, Region, RegionKind, Result, Validation,
+};
 
+/// A record as stored, with its data in [`IpsPatch::data`]. Together with its 24-bit offset
+/// stored as a `u32`, a record takes 12 bytes however much it writes.
+#[derive(Debug, Clone, Copy)]
 enum Record {
-    Bytes(Vec<u8>),
+    /// The start and length of the record's bytes in the patch's data.
+    Bytes(u32, u16),
     ByteRun(u8, u16),
 }
 
+impl Record {
+    fn len(&self) -> usize {
+        match *self {
+            Record::Bytes(_, len) | Record::ByteRun(_, len) => len as usize,
+        }
+    }
+}
+
+/// A record with its bytes, as returned by [`IpsPatch::iter`] or read from a patch.
+#[derive(Clone, Copy)]
+enum Data<'a> {
+    Bytes(&'a [u8]),
+    ByteRun(u8, u16),
+}
+
+impl<'a> Data<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            Data::Bytes(data) => data.len(),
+            Data::ByteRun(_, len) => len as usize,
+        }
+    }
+
+    /// The byte written `i` bytes into the record.
+    fn byte(&self, i: usize) -> u8 {
+        match *self {
+            Data::Bytes(data) => data[i],
+            Data::ByteRun(byte, _) => byte,
+        }
+    }
+
+    /// The first byte, and the rest of the record if it isn't empty.
+    fn split_first(self) -> Option<(u8, Option<Data<'a>>)> {
+        match self {
+            Data::Bytes(data) => data
+                .split_first()
+                .map(|(&first, rest)| (first, (!rest.is_empty()).then_some(Data::Bytes(rest)))),
+            Data::ByteRun(byte, len) => {
+                (len != 0).then(|| (byte, (len > 1).then_some(Data::ByteRun(byte, len - 1))))
+            }
+        }
+    }
+
+    /// The last byte, and the rest of the record if it isn't empty.
+    fn split_last(self) -> Option<(u8, Option<Data<'a>>)> {
+        match self {
+            Data::Bytes(data) => data
+                .split_last()
+                .map(|(&last, rest)| (last, (!rest.is_empty()).then_some(Data::Bytes(rest)))),
+            Data::ByteRun(..) => self.split_first(),
+        }
+    }
+}
+
+/// A problem with how the records of an IPS patch interact, found by [`IpsPatch::conflicts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpsConflict {
+    /// The records at the two indices write some of the same bytes. The later record wins.
+    Overlap(usize, usize),
+    /// Every byte written by the record at this index is overwritten by later records.
+    Overwritten(usize),
+}
+
+#[derive(Clone)]
 pub struct IpsPatch {
-    records: Vec<(usize, Record)>,
+    records: Vec<(u32, Record)>,
+    /// The bytes of every record back to back, so patches with millions of small records don't
+    /// need an allocation for each.
+    data: Box<[u8]>,
     outsz: Option<usize>,
+    /// Whether the records are in offset order and don't overlap, so they can be applied in one
+    /// forward pass. Most patches are, but old tools sometimes wrote records in any order.
+    sorted: bool,
 }
 
 impl IpsPatch {
     const MAGIC: &[u8; 5] = b"PATCH";
+    const MAX_OFFSET: usize = 0xFFFFFF;
+    /// The offset spelled "EOF", which marks the end of the records. A record can't start here, so
+    /// the encoder starts it a byte early instead, and the decoder only reads it as the end marker
+    /// if what follows isn't more records.
+    pub(crate) const EOF_OFFSET: usize = 0x454F46;
+
+    pub fn load(data: &[u8]) -> Result<Self> {
+        let span = Span::load("IPS");
+        let result = Self::parse(data);
+        span.finish_with(&result, |patch| patch.records.len());
+        result
+    }
 
-    pub fn load(mut data: &[u8]) -> Result<Self> {
+    fn parse(mut data: &[u8]) -> Result<Self> {
         if data.read_arr()? != *Self::MAGIC {
             return Err(Error::Magic(unsafe {
                 std::str::from_utf8_unchecked(Self::MAGIC)
             }));
         }
 
-        let mut records = Vec::new();
-        while !data.is_empty() {
-            let offset = data.read_u24::<BE>()?;
-            if offset == u32::from_be_bytes(*b"\0EOF") {
-                if data.len() == 3 {
-                    return Ok(Self {
-                        records,
-                        outsz: Some(data.read_u24::<BE>()? as usize),
-                    });
-                }
+        // record data is stored with 32-bit offsets
+        if data.len() > u32::MAX as usize {
+            return Err(Error::InvalidPatch);
+        }
+
+        let mut patch = Builder::new(None);
+        let mut rest = Self::walk(data, |offset, record| match record {
+            Data::Bytes(bytes) => patch.push_bytes(offset, bytes),
+            Data::ByteRun(byte, len) => patch.push_run(offset, byte, len as usize),
+        })?;
+        if rest.len() == 3 {
+            patch.outsz = Some(rest.read_u24::<BE>()? as usize);
+        }
 
-                break;
+        Ok(patch.build())
+    }
+
+    /// The length of the IPS patch at the start of `data`, which may be followed by more patches.
+    /// The records are read as in [`load`](Self::load), and the three bytes after the end marker
+    /// are only read as the truncation size if they end the file or another patch follows them.
+    pub(crate) fn measure(data: &[u8]) -> Result<usize> {
+        let rest = Self::walk(&data[Self::MAGIC.len().min(data.len())..], |_, _| Ok(()))?;
+        let end = data.len() - rest.len();
+        let truncated = rest.len() == 3
+            || (rest.len() > 3
+                && crate::detect(rest).is_err()
+                && crate::detect(&rest[3..]).is_ok());
+        Ok(if truncated { end + 3 } else { end })
+    }
+
+    /// Reads the records in `data`, which follows the magic, passing each to `record`. Returns what
+    /// follows the end marker, or nothing if the data ends without one.
+    fn walk<'a>(
+        mut data: &'a [u8],
+        mut record: impl FnMut(usize, Data<'a>) -> Result<()>,
+    ) -> Result<&'a [u8]> {
+        while !data.is_empty() {
+            let offset = data.read_u24::<BE>()? as usize;
+            // naive encoders write a record at this offset as is, so it's only the end marker if
+            // the patch can't be read as that record followed by more
+            if offset == Self::EOF_OFFSET && !Self::is_record_tail(data) {
+                return Ok(data);
             }
 
             let len = data.read_u16::<BE>()?;
-            records.push((
-                offset as usize,
-                if len != 0 {
-                    Record::Bytes(data.read_vec(len as usize)?)
-                } else {
-                    let len = data.read_u16::<BE>()?;
-                    Record::ByteRun(data.read_u8()?, len)
-                },
-            ));
+            if len != 0 {
+                let bytes = data
+                    .get(..len as usize)
+                    .ok_or(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+                data = &data[len as usize..];
+                record(offset, Data::Bytes(bytes))?;
+            } else {
+                let len = data.read_u16::<BE>()?;
+                record(offset, Data::ByteRun(data.read_u8()?, len))?;
+            }
         }
 
-        Ok(Self {
-            records,
-            outsz: None,
+        Ok(data)
+    }
+
+    /// Whether `data`, which follows a record's offset, is the rest of that record and any others,
+    /// ending with the end marker as described in [`is_end`](Self::is_end).
+    fn is_record_tail(mut data: &[u8]) -> bool {
+        let mut record = || -> Option<bool> {
+            let len = data.read_u16::<BE>().ok()?;
+            if len != 0 {
+                data = data.get(len as usize..)?;
+            } else {
+                data.read_u16::<BE>().ok()?;
+                data.read_u8().ok()?;
+            }
+
+            let offset = data.read_u24::<BE>().ok()?;
+            Some(offset as usize == Self::EOF_OFFSET && Self::is_end(data))
+        };
+        loop {
+            match record() {
+                Some(true) => return true,
+                Some(false) => {}
+                None => return false,
+            }
+        }
+    }
+
+    /// Whether `rest`, which follows an end marker, ends the patch: nothing, the truncation size,
+    /// or either of those followed by another patch.
+    fn is_end(rest: &[u8]) -> bool {
+        matches!(rest.len(), 0 | 3)
+            || crate::detect(rest).is_ok()
+            || rest
+                .get(3..)
+                .is_some_and(|rest| crate::detect(rest).is_ok())
+    }
+
+    /// See [`lint::lint`].
+    pub(crate) fn lint(data: &[u8]) -> Result<Vec<Lint>> {
+        let patch = Self::load(data)?;
+        let mut lints = Vec::new();
+        let mut written = vec![None; patch.records_end()];
+        for (record, (offset, data)) in patch.iter().enumerate() {
+            let range = &mut written[offset..offset + data.len()];
+            if data.len() == 0 {
+                lints.push(Lint::ZeroLength { record });
+            } else if (0..data.len()).all(|i| range[i] == Some(data.byte(i))) {
+                lints.push(Lint::IdenticalRewrite { record });
+            }
+            for (i, b) in range.iter_mut().enumerate() {
+                *b = Some(data.byte(i));
+            }
+        }
+
+        // every record is read back as it was written
+        let end = Self::MAGIC.len()
+            + patch
+                .records
+                .iter()
+                .map(|(_, record)| match record {
+                    Record::Bytes(_, len) => 3 + 2 + *len as usize,
+                    Record::ByteRun(_, _) => 3 + 2 + 2 + 1,
+                })
+                .sum::<usize>()
+            + 3
+            + patch.outsz.map_or(0, |_| 3);
+        if data.len() > end {
+            lints.push(Lint::TrailingBytes {
+                offset: end,
+                len: data.len() - end,
+            });
+        }
+        Ok(lints)
+    }
+
+    /// The records with their offsets and bytes, in file order.
+    fn iter(&self) -> impl Iterator<Item = (usize, Data<'_>)> {
+        self.records.iter().map(|&(offset, record)| {
+            let data = match record {
+                Record::Bytes(start, len) => {
+                    Data::Bytes(&self.data[start as usize..start as usize + len as usize])
+                }
+                Record::ByteRun(byte, len) => Data::ByteRun(byte, len),
+            };
+            (offset as usize, data)
         })
     }
-}
 
-impl Patch for IpsPatch {
-    fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
-        let mut buf = vec![
-            0;
-            self.outsz.unwrap_or_else(|| {
-                self.records
-                    .iter()
-                    .fold(rom.len(), |acc, (offset, record)| {
-                        Some(match record {
-                            Record::Bytes(buf) => offset + buf.len(),
-                            &Record::ByteRun(_, len) => offset + len as usize,
-                        })
-                        .filter(|&v| v > acc)
-                        .unwrap_or(acc)
-                    })
+    /// Creates a patch turning `src` into `dst`. IPS offsets are 24-bit, so a patch changing bytes
+    /// past the first 16 MiB can still be applied, but fails to [`export`](Patch::export) with
+    /// [`Error::OutOfRange`].
+    ///
+    /// # Panics
+    ///
+    /// If `dst` changes bytes past the first 4 GiB, which no IPS record can address.
+    /// [`create_observed`](Self::create_observed) returns [`Error::OutOfRange`] instead.
+    pub fn create(src: &[u8], dst: &[u8]) -> Self {
+        Self::create_observed(src, dst, &CreateOptions::default(), &mut ())
+            .expect("IPS records can't address changes past the first 4 GiB")
+    }
+
+    /// Like [`create`](Self::create), reporting progress through the modified file to `observer`.
+    /// The options only affect BPS patches, and are accepted so every format is created the same way.
+    /// Fails with [`Error::Cancelled`] if the observer cancels, or [`Error::OutOfRange`] if `dst`
+    /// changes bytes past the first 4 GiB.
+    pub fn create_observed(
+        src: &[u8],
+        dst: &[u8],
+        _options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<Self> {
+        let span = Span::create("IPS");
+        let mut patch = Builder::new((dst.len() < src.len()).then_some(dst.len()));
+        let mut i = 0;
+        let mut ticker = Ticker::new(observer, dst.len());
+        while i < dst.len() {
+            ticker.tick(i)?;
+            if src.get(i) == Some(&dst[i]) {
+                i += 1;
+                continue;
+            }
+
+            let mut start = i;
+            // a record can't start at the offset spelled "EOF", so include the unchanged byte
+            // before it
+            if start == Self::EOF_OFFSET && src.get(start - 1) == Some(&dst[start - 1]) {
+                start -= 1;
+            }
+            while i < dst.len() && i - start < u16::MAX as usize && src.get(i) != Some(&dst[i]) {
+                i += 1;
+            }
+            patch.push_bytes(start, &dst[start..i])?;
+        }
+        ticker.finish();
+
+        span.finish(Some(patch.records.len()), None);
+        Ok(patch.build())
+    }
+
+    /// Like [`create`](Self::create), reading the original and modified files from streams a
+    /// window at a time, so neither has to fit in memory. Fails if a stream ends at a different
+    /// size than `sizes` gives. The original isn't kept, so a change starting at the offset spelled
+    /// "EOF" can't be moved a byte early, and exporting the patch fails with [`Error::EofOffset`].
+    pub fn create_from_readers(src: impl Read, dst: impl Read, sizes: StreamSizes) -> Result<Self> {
+        let span = Span::create("IPS");
+        let diff = stream::diff_readers(src, dst, sizes, false)?;
+        let patch = Self::from_runs(diff.runs, diff.source.size, diff.target.size)?;
+        span.finish(Some(patch.records.len()), None);
+        Ok(patch)
+    }
+
+    /// Builds a patch from runs of modified bytes, as collected by [`DiffWriter`](crate::stream::DiffWriter).
+    pub(crate) fn from_runs(
+        runs: Vec<(usize, Vec<u8>)>,
+        src_len: usize,
+        dst_len: usize,
+    ) -> Result<Self> {
+        let mut patch = Builder::new((dst_len < src_len).then_some(dst_len));
+        for (offset, run) in runs {
+            patch.push_bytes(offset, &run)?;
+        }
+
+        Ok(patch.build())
+    }
+
+    /// The size of the output file recorded by the truncation extension, if present.
+    pub fn output_size(&self) -> Option<usize> {
+        self.outsz
+    }
+
+    /// Forces the size of the output file, overriding the size guessed from the records.
+    /// Records that extend past the new size are clipped.
+    pub fn set_output_size(&mut self, size: Option<usize>) {
+        self.outsz = size;
+    }
+
+    /// The number of records in the patch.
+    pub fn record_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Removes the record at `index`, counting from 0 in file order.
+    pub fn remove_record(&mut self, index: usize) -> Result<()> {
+        if index >= self.records.len() {
+            return Err(Error::NoSuchRecord(index));
+        }
+
+        self.records.remove(index);
+        Ok(())
+    }
+
+    /// Moves every record starting at `offset` so that it starts at `new_offset` instead.
+    pub fn move_record(&mut self, offset: usize, new_offset: usize) -> Result<()> {
+        if new_offset > Self::MAX_OFFSET {
+            return Err(Error::OutOfRange("record offset", new_offset));
+        }
+
+        let mut found = false;
+        for (start, _) in self
+            .records
+            .iter_mut()
+            .filter(|(start, _)| *start as usize == offset)
+        {
+            *start = new_offset as u32;
+            found = true;
+        }
+
+        if !found {
+            return Err(Error::NoRecordAt(offset));
+        }
+
+        self.sorted = self.records.windows(2).all(|pair| {
+            let [(a, record), (b, _)] = pair else {
+                unreachable!()
+            };
+            *a as usize + record.len() <= *b as usize
+        });
+        Ok(())
+    }
+
+    /// Finds records that overlap or are completely overwritten by later records. These usually
+    /// come from old tools, and apply in whatever order the file has.
+    pub fn conflicts(&self) -> Vec<IpsConflict> {
+        let extent = |i: usize| {
+            let (offset, record) = self.records[i];
+            (offset as usize, offset as usize + record.len())
+        };
+
+        let mut conflicts = Vec::new();
+        let mut order: Vec<_> = (0..self.records.len())
+            .filter(|&i| self.records[i].1.len() != 0)
+            .collect();
+        order.sort_by_key(|&i| extent(i).0);
+        let mut active: Vec<usize> = Vec::new();
+        for &i in order.iter() {
+            let (start, _) = extent(i);
+            active.retain(|&j| extent(j).1 > start);
+            for &j in active.iter() {
+                conflicts.push(IpsConflict::Overlap(i.min(j), i.max(j)));
+            }
+            active.push(i);
+        }
+
+        // walk backwards, tracking the ranges later records have written
+        let mut covered = BTreeMap::new();
+        for i in (0..self.records.len()).rev() {
+            let (start, end) = extent(i);
+            if start == end {
+                continue;
+            }
+
+            let mut merged = (start, end);
+            if let Some((&s, &e)) = covered.range(..=start).next_back() {
+                if e >= end {
+                    conflicts.push(IpsConflict::Overwritten(i));
+                    continue;
+                }
+                if e >= start {
+                    merged = (s, end);
+                }
+            }
+            while let Some((&s, &e)) = covered.range(merged.0..=merged.1).next() {
+                covered.remove(&s);
+                merged.1 = merged.1.max(e);
+            }
+            covered.insert(merged.0, merged.1);
+        }
+
+        conflicts.sort_by_key(|conflict| match *conflict {
+            IpsConflict::Overlap(i, j) => (i, j),
+            IpsConflict::Overwritten(i) => (i, usize::MAX),
+        });
+        conflicts
+    }
+
+    /// Rewrites the records in canonical form with [`normalize`](Self::normalize), so patches that
+    /// write the same bytes export to identical bytes, whatever order their records were in and
+    /// however they were split up.
+    pub fn canonicalize(&mut self) {
+        self.normalize();
+    }
+
+    /// Rewrites the records so that none overlap, keeping the bytes that win when the patch is
+    /// applied in file order. The new records are sorted by offset, touching records are merged,
+    /// and long runs of a repeated byte become fills.
+    pub fn normalize(&mut self) {
+        const MIN_FILL: usize = 16;
+        const MAX_LEN: usize = u16::MAX as usize;
+
+        let mut order: Vec<_> = (0..self.records.len())
+            .filter(|&i| self.records[i].1.len() != 0)
+            .collect();
+        order.sort_by_key(|&i| self.records[i].0);
+
+        let old: Vec<_> = self.iter().collect();
+        let mut patch = Builder::new(self.outsz);
+        let mut next = 0;
+        while next < order.len() {
+            // gather every record touching this one, directly or through others
+            let start = old[order[next]].0;
+            let mut end = start;
+            let mut cluster = Vec::new();
+            while let Some(&i) = order.get(next) {
+                let (offset, record) = old[i];
+                if offset > end {
+                    break;
+                }
+                end = end.max(offset + record.len());
+                cluster.push(i);
+                next += 1;
+            }
+
+            cluster.sort_unstable();
+            let mut buf = vec![0; end - start];
+            for &i in cluster.iter() {
+                let (offset, record) = old[i];
+                let dst = &mut buf[offset - start..offset - start + record.len()];
+                match record {
+                    Data::Bytes(data) => dst.copy_from_slice(data),
+                    Data::ByteRun(byte, _) => dst.fill(byte),
+                }
+            }
+
+            let run = |i: usize| buf[i..].iter().take_while(|&&b| b == buf[i]).count();
+            let mut i = 0;
+            while i < buf.len() {
+                let len = run(i);
+                if len >= MIN_FILL {
+                    patch
+                        .push_run(start + i, buf[i], len)
+                        .expect("normalized records lie within the original ones");
+                    i += len;
+                    continue;
+                }
+
+                let literal = i;
+                while i < buf.len() && i - literal < MAX_LEN {
+                    let len = run(i);
+                    if len >= MIN_FILL {
+                        break;
+                    }
+                    i = (i + len).min(literal + MAX_LEN);
+                }
+                patch
+                    .push_bytes(start + literal, &buf[literal..i])
+                    .expect("normalized records lie within the original ones");
+            }
+        }
+
+        *self = patch.build();
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self> {
+        let invalid = |what: &str| Error::InvalidJson(format!("invalid IPS record: {what}"));
+        let mut patch = Builder::new(
+            value
+                .get_opt("output_size")
+                .map(Value::as_usize)
+                .transpose()?,
+        );
+        for record in value.get("records")?.as_array()? {
+            let offset = record.get("offset")?.as_usize()?;
+            if offset > Self::MAX_OFFSET {
+                return Err(invalid("offset is too large"));
+            }
+
+            if let Some(data) = record.get_opt("data") {
+                let data = data.as_bytes()?;
+                if data.is_empty() {
+                    return Err(invalid("data is empty"));
+                }
+                patch.push_bytes(offset, &data)?;
+            } else {
+                let len = record.get("length")?.as_usize()?;
+                let byte = record.get("fill")?.as_usize()?;
+                match u8::try_from(byte) {
+                    Ok(byte) if len != 0 => patch.push_run(offset, byte, len)?,
+                    _ => return Err(invalid("fill length or value is out of range")),
+                }
+            }
+        }
+
+        Ok(patch.build())
+    }
+
+    pub(crate) fn from_script(script: &Script) -> Result<Self> {
+        let check = |offset: usize| {
+            // a record at the "EOF" offset needs the byte before it, which the script doesn't have
+            if offset > Self::MAX_OFFSET || offset == Self::EOF_OFFSET {
+                return Err(Error::InvalidScript(format!(
+                    "offset {offset:#X} can't be represented in an IPS patch"
+                )));
+            }
+            Ok(offset)
+        };
+
+        let mut patch = Builder::new(script.size);
+        for entry in script.entries.iter() {
+            match &entry.data {
+                script::Data::Bytes(bytes) => {
+                    for start in (0..bytes.len()).step_by(Builder::MAX_LEN) {
+                        check(entry.offset + start)?;
+                    }
+                    patch.push_bytes(entry.offset, bytes)?;
+                }
+                &script::Data::Fill(byte, len) => {
+                    for start in (0..len).step_by(Builder::MAX_LEN) {
+                        check(entry.offset + start)?;
+                    }
+                    patch.push_run(entry.offset, byte, len)?;
+                }
+            }
+        }
+
+        Ok(patch.build())
+    }
+
+    /// Wraps `inner`, which reads the source, in a reader that yields the patched output.
+    pub fn reader<R: Read>(&self, inner: R) -> PatchReader<'_, R> {
+        PatchReader::new(self.patcher(), inner)
+    }
+
+    /// Wraps `inner` in a writer that patches the source written to it.
+    pub fn writer<W: Write>(&self, inner: W) -> PatchWriter<'_, W> {
+        PatchWriter::new(self.patcher(), inner)
+    }
+
+    fn patcher(&self) -> Patcher<'_> {
+        let edits = self
+            .iter()
+            .map(|(offset, record)| match record {
+                Data::Bytes(data) => (offset, Edit::Write(data)),
+                Data::ByteRun(byte, len) => (offset, Edit::Fill(byte, len as usize)),
             })
-        ];
-        let copy = buf.len().min(rom.len());
-        buf[..copy].copy_from_slice(&rom[..copy]);
+            .collect();
+        Patcher::new(edits, self.outsz, None)
+    }
 
-        for (offset, record) in self.records.iter() {
+    pub(crate) fn model(&self) -> Model {
+        let end = self.records_end();
+        let mut output: Vec<_> = (0..end).map(|i| Sym::Source(i, 0)).collect();
+        for (offset, record) in self.iter() {
             match record {
-                Record::Bytes(bytes) => buf[*offset..][..bytes.len()].copy_from_slice(bytes),
-                &Record::ByteRun(byte, len) => buf[*offset..][..len as usize].fill(byte),
+                Data::Bytes(data) => {
+                    for (sym, &byte) in output[offset..].iter_mut().zip(data) {
+                        *sym = Sym::Literal(byte);
+                    }
+                }
+                Data::ByteRun(byte, len) => {
+                    output[offset..offset + len as usize].fill(Sym::Literal(byte));
+                }
+            }
+        }
+
+        let size = match self.outsz {
+            Some(size) => {
+                output.truncate(size);
+                Size::Fixed(size)
+            }
+            None => Size::AtLeast(end),
+        };
+        Model {
+            source: None,
+            size,
+            output,
+        }
+    }
+
+    /// The offset one past the last byte written by any record.
+    pub fn records_end(&self) -> usize {
+        self.records
+            .iter()
+            .map(|(offset, record)| *offset as usize + record.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl IpsPatch {
+    /// Writes a byte held back from the end of a record at the "EOF" offset on its own, when no
+    /// record at that offset took it.
+    fn flush_carry(buf: &mut Vec<u8>, carry: &mut Option<u8>) -> Result<()> {
+        match carry.take() {
+            Some(byte) => Self::write_record(buf, Self::EOF_OFFSET - 1, Data::Bytes(&[byte])),
+            None => Ok(()),
+        }
+    }
+
+    fn write_record(buf: &mut Vec<u8>, offset: usize, record: Data) -> Result<()> {
+        buf.write_u24::<BE>(offset as u32)?;
+        match record {
+            Data::Bytes(data) => {
+                buf.write_u16::<BE>(data.len() as _)?;
+                buf.write_all(data)?;
+            }
+            Data::ByteRun(byte, len) => {
+                buf.write_u16::<BE>(0)?;
+                buf.write_u16::<BE>(len)?;
+                buf.write_u8(byte)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_records(&self, rom: &[u8], observer: &mut dyn Observer) -> Result<Vec<u8>> {
+        // only the bytes past the end of the ROM are zeroed, not the whole output
+        let copy = |buf: &mut Vec<u8>, len: usize| {
+            let start = buf.len();
+            let src = rom.get(start..).unwrap_or_default();
+            buf.extend_from_slice(&src[..len.min(src.len())]);
+            buf.resize(start + len, 0);
+            Ok(())
+        };
+        let size = self.output_len(rom.len());
+        if self.sorted {
+            return self.apply_sorted(size, copy, observer);
+        }
+
+        let mut buf = Vec::with_capacity(size);
+        copy(&mut buf, size)?;
+        self.write_records(&mut buf, observer)?;
+        Ok(buf)
+    }
+
+    fn output_len(&self, rom_len: usize) -> usize {
+        self.outsz
+            .unwrap_or_else(|| self.records_end().max(rom_len))
+    }
+
+    /// Applies records that are sorted and don't overlap in one forward pass, appending the
+    /// source between them with `copy`, which appends the next `len` bytes of the source to the
+    /// buffer, or zeros past its end.
+    fn apply_sorted(
+        &self,
+        size: usize,
+        mut copy: impl FnMut(&mut Vec<u8>, usize) -> Result<()>,
+        observer: &mut dyn Observer,
+    ) -> Result<Vec<u8>> {
+        let total = self.records.iter().map(|(_, record)| record.len()).sum();
+        let mut done = 0;
+        let mut buf = Vec::with_capacity(size);
+        for (i, (offset, record)) in self.iter().enumerate() {
+            done += record.len();
+            if offset < size {
+                let gap = offset - buf.len();
+                copy(&mut buf, gap)?;
+                let len = record.len().min(size - offset);
+                match record {
+                    Data::Bytes(bytes) => buf.extend_from_slice(&bytes[..len]),
+                    Data::ByteRun(byte, _) => buf.resize(offset + len, byte),
+                }
             }
+
+            observer.event(Event::RecordApplied(i));
+            progress::checkpoint(observer)?;
+            observer.event(Event::Progress { done, total });
         }
 
+        let rest = size - buf.len();
+        copy(&mut buf, rest)?;
         Ok(buf)
     }
 
+    /// Writes the records over `buf`, which starts out as a copy of the source.
+    fn write_records(&self, buf: &mut [u8], observer: &mut dyn Observer) -> Result<()> {
+        let total = self.records.iter().map(|(_, record)| record.len()).sum();
+        let mut done = 0;
+        for (i, (offset, record)) in self.iter().enumerate() {
+            done += record.len();
+            if let Some(dst) = buf.get_mut(offset..) {
+                match record {
+                    Data::Bytes(bytes) => {
+                        let len = bytes.len().min(dst.len());
+                        dst[..len].copy_from_slice(&bytes[..len]);
+                    }
+                    Data::ByteRun(byte, len) => {
+                        let len = (len as usize).min(dst.len());
+                        dst[..len].fill(byte);
+                    }
+                }
+            }
+
+            observer.event(Event::RecordApplied(i));
+            progress::checkpoint(observer)?;
+            observer.event(Event::Progress { done, total });
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the records of a patch as it's built, so the finished patch can keep its data in a
+/// boxed slice.
+struct Builder {
+    records: Vec<(u32, Record)>,
+    data: Vec<u8>,
+    outsz: Option<usize>,
+    sorted: bool,
+}
+
+impl Builder {
+    const MAX_LEN: usize = u16::MAX as usize;
+
+    fn new(outsz: Option<usize>) -> Self {
+        Self {
+            records: Vec::new(),
+            data: Vec::new(),
+            outsz,
+            sorted: true,
+        }
+    }
+
+    /// Adds records writing `bytes` at `offset`, split into as many as the 16-bit record length
+    /// needs. Offsets past the 24-bit limit are kept, and rejected by [`IpsPatch::export`], but a
+    /// record has to end in the first 4 GiB.
+    fn push_bytes(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        for (i, chunk) in bytes.chunks(Self::MAX_LEN).enumerate() {
+            let offset = offset + i * Self::MAX_LEN;
+            let record = Self::offset(offset, chunk.len())?;
+            let start = u32::try_from(self.data.len())
+                .map_err(|_| Error::OutOfRange("patch data size", self.data.len()))?;
+            self.sorted &= self.follows_last(offset);
+            self.data.extend_from_slice(chunk);
+            self.records
+                .push((record, Record::Bytes(start, chunk.len() as u16)));
+        }
+        Ok(())
+    }
+
+    /// Adds records filling `len` bytes at `offset` with `byte`, split like
+    /// [`push_bytes`](Self::push_bytes). An empty fill, which some patches contain, is kept as is.
+    fn push_run(&mut self, offset: usize, byte: u8, len: usize) -> Result<()> {
+        for start in (0..len.max(1)).step_by(Self::MAX_LEN) {
+            let offset = offset + start;
+            let len = (len - start).min(Self::MAX_LEN) as u16;
+            let record = Self::offset(offset, len as usize)?;
+            self.sorted &= self.follows_last(offset);
+            self.records.push((record, Record::ByteRun(byte, len)));
+        }
+        Ok(())
+    }
+
+    /// The offset of a record `len` bytes long at `offset`, which has to end in the first 4 GiB
+    /// so that normalizing can't move any of its bytes out of range.
+    fn offset(offset: usize, len: usize) -> Result<u32> {
+        match u32::try_from(offset) {
+            Ok(start) if offset as u64 + len as u64 <= 1 << 32 => Ok(start),
+            _ => Err(Error::OutOfRange("record offset", offset)),
+        }
+    }
+
+    /// Whether a record at `offset` would start after the end of the last one.
+    fn follows_last(&self, offset: usize) -> bool {
+        self.records
+            .last()
+            .is_none_or(|&(last, record)| last as usize + record.len() <= offset)
+    }
+
+    fn build(self) -> IpsPatch {
+        IpsPatch {
+            records: self.records,
+            data: self.data.into_boxed_slice(),
+            outsz: self.outsz,
+            sorted: self.sorted,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for IpsPatch {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::load(data)
+    }
+}
+
+impl Patch for IpsPatch {
+    fn apply_observed(&self, rom: &[u8], observer: &mut dyn Observer) -> Result<Vec<u8>> {
+        let span = Span::apply("IPS");
+        let result = self.apply_records(rom, observer);
+        span.finish_with(&result, |_| self.records.len());
+        result
+    }
+
+    fn apply_unchecked(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        self.apply_records(rom, &mut ())
+    }
+
+    fn apply_source(&self, source: &mut dyn Source) -> Result<Vec<u8>> {
+        let len = source.len();
+        let mut copy = |buf: &mut Vec<u8>, n: usize| {
+            let start = buf.len();
+            let read = len.saturating_sub(start).min(n);
+            buf.resize(start + n, 0);
+            if read > 0 {
+                source.read_at(start, &mut buf[start..start + read])?;
+            }
+            Ok(())
+        };
+        let size = self.output_len(len);
+        if self.sorted {
+            return self.apply_sorted(size, copy, &mut ());
+        }
+
+        let mut buf = Vec::with_capacity(size);
+        copy(&mut buf, size)?;
+        self.write_records(&mut buf, &mut ())?;
+        Ok(buf)
+    }
+
+    fn apply_into(&self, rom: &[u8], out: &mut [u8]) -> Result<()> {
+        let size = self.output_len(rom.len());
+        if out.len() != size {
+            return Err(Error::InvalidSize(out.len(), size));
+        }
+
+        let copy = rom.len().min(size);
+        out[..copy].copy_from_slice(&rom[..copy]);
+        out[copy..].fill(0);
+        self.write_records(out, &mut ())
+    }
+
+    fn output_size(&self, rom_len: usize) -> usize {
+        self.output_len(rom_len)
+    }
+
+    fn apply_vectored<'a>(&'a self, rom: &'a [u8]) -> Result<Vec<Cow<'a, [u8]>>> {
+        let size = self
+            .outsz
+            .unwrap_or_else(|| self.records_end().max(rom.len()));
+
+        // the visible part of each record by start offset, as (end, record offset, record), with
+        // later records cutting away the parts of earlier ones they overwrite
+        let mut visible: BTreeMap<usize, (usize, usize, Data)> = BTreeMap::new();
+        for (offset, record) in self.iter() {
+            let (start, end) = (offset, (offset + record.len()).min(size));
+            if start >= end {
+                continue;
+            }
+
+            let overlapping: Vec<_> = visible
+                .range(..end)
+                .rev()
+                .take_while(|(_, (e, ..))| *e > start)
+                .map(|(&s, &piece)| (s, piece))
+                .collect();
+            for (s, (e, from, record)) in overlapping {
+                visible.remove(&s);
+                if s < start {
+                    visible.insert(s, (start, from, record));
+                }
+                if e > end {
+                    visible.insert(end, (e, from, record));
+                }
+            }
+            visible.insert(start, (end, start, record));
+        }
+
+        let mut out = Chunks::new();
+        for (start, (end, from, record)) in visible {
+            out.source(rom, out.len()..start);
+            out.push(match record {
+                Data::Bytes(data) => Cow::Borrowed(&data[start - from..end - from]),
+                Data::ByteRun(byte, _) => Cow::Owned(vec![byte; end - start]),
+            });
+        }
+        out.source(rom, out.len()..size);
+        Ok(out.into_vec())
+    }
+
     fn validate(&self, _rom: &[u8]) -> Option<Result<()>> {
         None
     }
 
+    fn source(&self) -> Option<Validation> {
+        None
+    }
+
+    fn target(&self) -> Option<Validation> {
+        None
+    }
+
+    fn target_size(&self) -> Option<usize> {
+        self.outsz
+    }
+
+    fn estimated_peak_memory(&self, rom_len: usize) -> usize {
+        std::mem::size_of_val(&self.records[..]) + self.data.len() + self.output_len(rom_len)
+    }
+
+    fn format(&self) -> &'static str {
+        "IPS"
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.iter()
+            .map(|(offset, record)| match record {
+                Data::Bytes(data) => Region {
+                    offset,
+                    len: data.len(),
+                    kind: RegionKind::Literal,
+                },
+                Data::ByteRun(byte, len) => Region {
+                    offset,
+                    len: len as usize,
+                    kind: RegionKind::Fill(byte),
+                },
+            })
+            .collect()
+    }
+
+    fn records(&self) -> Vec<crate::Record<'_>> {
+        self.iter()
+            .map(|(offset, record)| match record {
+                Data::Bytes(data) => crate::Record {
+                    offset,
+                    len: data.len(),
+                    kind: RecordKind::Literal(data),
+                },
+                Data::ByteRun(byte, len) => crate::Record {
+                    offset,
+                    len: len as usize,
+                    kind: RecordKind::Fill(byte),
+                },
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = Object::new().str("format", "ips");
+        if let Some(size) = self.outsz {
+            json = json.num("output_size", size);
+        }
+
+        json.array(
+            "records",
+            self.iter().map(|(offset, record)| match record {
+                Data::Bytes(data) => Object::new()
+                    .num("offset", offset)
+                    .bytes("data", data)
+                    .finish(),
+                Data::ByteRun(byte, len) => Object::new()
+                    .num("offset", offset)
+                    .num("length", len)
+                    .num("fill", byte)
+                    .finish(),
+            }),
+        )
+        .finish()
+    }
+
     fn export(&self, _crc: Option<u32>) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(self.records.iter().fold(
-            Self::MAGIC.len(),
-            |acc, (_, record)| {
-                acc + match record {
-                    Record::Bytes(data) => 3 + 2 + data.len(),
-                    Record::ByteRun(_, _) => 3 + 2 + 2 + 1,
+        let span = Span::export("IPS");
+        let result = (|| {
+            let mut buf = Vec::with_capacity(self.records.iter().fold(
+                Self::MAGIC.len(),
+                |acc, (_, record)| {
+                    acc + match record {
+                        Record::Bytes(_, len) => 3 + 2 + *len as usize,
+                        Record::ByteRun(_, _) => 3 + 2 + 2 + 1,
+                    }
+                },
+            ));
+
+            buf.write_all(Self::MAGIC)?;
+            // the last byte of a record ending at the "EOF" offset, held back to start the next
+            // record a byte early
+            let mut carry = None;
+            let mut records = self.iter().peekable();
+            while let Some((offset, record)) = records.next() {
+                if offset > Self::MAX_OFFSET {
+                    return Err(Error::OutOfRange("record offset", offset));
                 }
-            },
-        ));
 
-        buf.write_all(Self::MAGIC)?;
-        for (offset, record) in self.records.iter() {
-            buf.write_u24::<BE>(*offset as u32)?;
-            match record {
-                Record::Bytes(data) => {
-                    buf.write_u16::<BE>(data.len() as _)?;
-                    buf.write_all(data)?;
+                if offset == Self::EOF_OFFSET {
+                    // an empty record there has nothing to write, and leaves a held back byte to the
+                    // next record or the end
+                    let Some((first, rest)) = record.split_first() else {
+                        continue;
+                    };
+                    let byte = carry.take().ok_or(Error::EofOffset)?;
+                    Self::write_record(&mut buf, offset - 1, Data::Bytes(&[byte, first]))?;
+                    if let Some(rest) = rest {
+                        Self::write_record(&mut buf, offset + 1, rest)?;
+                    }
+                    continue;
+                }
+
+                let ends_at_eof = offset + record.len() == Self::EOF_OFFSET
+                    && records
+                        .peek()
+                        .is_some_and(|&(next, _)| next == Self::EOF_OFFSET);
+                Self::flush_carry(&mut buf, &mut carry)?;
+                let record = match record.split_last() {
+                    Some((last, rest)) if ends_at_eof => {
+                        carry = Some(last);
+                        rest
+                    }
+                    _ => Some(record),
+                };
+                if let Some(record) = record {
+                    Self::write_record(&mut buf, offset, record)?;
                 }
-                Record::ByteRun(byte, len) => {
-                    buf.write_u16::<BE>(0)?;
-                    buf.write_u16::<BE>(*len)?;
-                    buf.write_u8(*byte)?;
+            }
+            Self::flush_carry(&mut buf, &mut carry)?;
+
+            buf.write_all(b"EOF")?;
+            if let Some(outsz) = self.outsz {
+                if outsz > Self::MAX_OFFSET {
+                    return Err(Error::OutOfRange("output size", outsz));
                 }
+
+                buf.write_u24::<BE>(outsz as _)?;
             }
+
+            Ok(buf)
+        })();
+        span.finish_with(&result, |_| self.records.len());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_roundtrip() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        for dst in [
+            &b"The quick brown cat jumps over the lazy dog."[..],
+            b"The quick brown fox",
+            b"The quick brown fox jumps over the lazy dog, twice.",
+        ] {
+            let patch = IpsPatch::load(&IpsPatch::create(src, dst).export(None).unwrap()).unwrap();
+            assert_eq!(&patch.apply(src).unwrap(), dst);
         }
+    }
 
-        buf.write_all(b"EOF")?;
-        if let Some(outsz) = self.outsz {
-            buf.write_u24::<BE>(outsz as _)?;
+    #[test]
+    fn compact_records() {
+        assert_eq!(std::mem::size_of::<(u32, Record)>(), 12);
+
+        let mut data = b"PATCH".to_vec();
+        for i in 0..10_000u32 {
+            data.extend_from_slice(&(i * 2).to_be_bytes()[1..]);
+            if i % 2 == 0 {
+                data.extend_from_slice(&[0, 1, i as u8]);
+            } else {
+                data.extend_from_slice(&[0, 0, 0, 1, i as u8]);
+            }
         }
+        data.extend_from_slice(b"EOF");
 
-        Ok(buf)
+        let patch = IpsPatch::load(&data).unwrap();
+        assert_eq!(patch.data.len(), 5_000);
+        assert_eq!(patch.export(None).unwrap(), data);
+    }
+
+    #[test]
+    fn range_checks() {
+        let runs = vec![(0, vec![1; 70000]), (0x10, vec![2; 3])];
+        let patch = IpsPatch::from_runs(runs, 0, 70000).unwrap();
+        assert_eq!(patch.record_count(), 3);
+        let data = patch.export(None).unwrap();
+        assert_eq!(IpsPatch::load(&data).unwrap().export(None).unwrap(), data);
+
+        let patch = IpsPatch::from_runs(vec![(0x1000000, vec![1])], 0, 0x1000001).unwrap();
+        assert!(matches!(
+            patch.export(None),
+            Err(Error::OutOfRange("record offset", 0x1000000))
+        ));
+
+        let mut patch = IpsPatch::from_runs(vec![], 0, 0).unwrap();
+        patch.set_output_size(Some(0x1000000));
+        assert!(matches!(
+            patch.export(None),
+            Err(Error::OutOfRange("output size", 0x1000000))
+        ));
+        assert!(matches!(
+            patch.move_record(0, 0x1000000),
+            Err(Error::OutOfRange(..))
+        ));
+
+        // records have to end in the first 4 GiB
+        #[cfg(target_pointer_width = "64")]
+        for offset in [u32::MAX as usize, 1 << 32] {
+            assert!(matches!(
+                IpsPatch::from_runs(vec![(offset, vec![1, 2])], 0, 0),
+                Err(Error::OutOfRange("record offset", o)) if o == offset
+            ));
+        }
+    }
+
+    #[test]
+    fn eof_offset() {
+        let eof = IpsPatch::EOF_OFFSET;
+        let src = vec![0; eof + 4];
+        let mut dst = src.clone();
+        dst[eof..eof + 2].copy_from_slice(b"ab");
+        let patch = IpsPatch::create(&src, &dst);
+        let data = patch.export(None).unwrap();
+        assert_eq!(&data[5..10], b"EOE\0\x03");
+        assert_eq!(IpsPatch::load(&data).unwrap().apply(&src).unwrap(), dst);
+        let mut writer = stream::DiffWriter::new(&src);
+        writer.write_all(&dst).unwrap();
+        assert_eq!(writer.into_ips().unwrap().export(None).unwrap(), data);
+
+        // the record before gives up its last byte
+        dst[eof - 2..eof].copy_from_slice(b"xy");
+        let runs = vec![(eof - 2, b"xy".to_vec()), (eof, b"ab".to_vec())];
+        let patch = IpsPatch::from_runs(runs, 0, 0).unwrap();
+        let data = patch.export(None).unwrap();
+        assert_eq!(IpsPatch::load(&data).unwrap().apply(&src).unwrap(), dst);
+
+        // a record written as is by a naive encoder
+        let patch = IpsPatch::load(b"PATCHEOF\0\x01a\0\0\0\0\x01bEOF").unwrap();
+        assert_eq!(patch.record_count(), 2);
+        assert!(matches!(patch.export(None), Err(Error::EofOffset)));
+
+        let patch = IpsPatch::load(b"PATCH\0\0\0\0\x01bEOF\0\x01").unwrap();
+        assert_eq!(patch.record_count(), 1);
+
+        // an empty fill at the "EOF" offset doesn't take the byte held back before it
+        let data = b"PATCH\x45\x4F\x44\0\x02xyEOF\0\0\0\0zEOF";
+        let patch = IpsPatch::load(data).unwrap();
+        assert_eq!(patch.record_count(), 2);
+        let exported = IpsPatch::load(&patch.export(None).unwrap()).unwrap();
+        dst[eof - 2..eof].copy_from_slice(b"xy");
+        dst[eof..eof + 2].copy_from_slice(&src[eof..eof + 2]);
+        assert_eq!(exported.apply(&src).unwrap()[eof - 2..], dst[eof - 2..]);
+        assert_eq!(patch.apply(&src).unwrap()[eof - 2..], dst[eof - 2..]);
+    }
+
+    #[test]
+    fn sorted_fast_path() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog, twice.";
+        let mut patch = IpsPatch::create(src, dst);
+        assert!(patch.sorted);
+        patch.set_output_size(Some(48));
+        let output = patch.apply(src).unwrap();
+
+        patch.sorted = false;
+        assert_eq!(patch.apply(src).unwrap(), output);
+
+        // out of order, and overlapping once moved
+        let mut patch = IpsPatch::load(b"PATCH\0\0\x04\0\x02ab\0\0\0\0\x02cdEOF").unwrap();
+        assert!(!patch.sorted);
+        patch.move_record(0, 6).unwrap();
+        assert!(patch.sorted);
+        patch.move_record(6, 5).unwrap();
+        assert!(!patch.sorted);
+        assert_eq!(&patch.apply(b"0123456").unwrap(), b"0123acd");
+    }
+
+    #[test]
+    fn output_size_clips_records() {
+        let mut patch = IpsPatch::load(b"PATCH\0\0\x02\0\x04abcdEOF").unwrap();
+        assert_eq!(patch.records_end(), 6);
+
+        patch.set_output_size(Some(4));
+        assert_eq!(&patch.apply(b"012345678").unwrap(), b"01ab");
+    }
+
+    #[test]
+    fn edit_records() {
+        let mut patch = IpsPatch::load(b"PATCH\0\0\0\0\x01a\0\0\x04\0\x01bEOF").unwrap();
+        patch.remove_record(0).unwrap();
+        patch.move_record(4, 2).unwrap();
+        assert!(patch.move_record(4, 2).is_err());
+        assert_eq!(&patch.apply(b"0123").unwrap(), b"01b3");
+    }
+
+    #[test]
+    fn normalize() {
+        // "abcd" at 2, "X" at 3, then a fill over both
+        let mut patch = IpsPatch::load(
+            b"PATCH\0\0\x02\0\x04abcd\0\0\x03\0\x01X\0\0\0\0\0\0\x08-\0\0\x08\0\x01!EOF",
+        )
+        .unwrap();
+        assert_eq!(
+            patch.conflicts(),
+            [
+                IpsConflict::Overlap(0, 1),
+                IpsConflict::Overlap(0, 2),
+                IpsConflict::Overwritten(0),
+                IpsConflict::Overlap(1, 2),
+                IpsConflict::Overwritten(1),
+            ]
+        );
+
+        let expected = patch.apply(b"0123456789").unwrap();
+        patch.normalize();
+        assert!(patch.conflicts().is_empty());
+        assert_eq!(patch.record_count(), 1);
+        assert_eq!(patch.apply(b"0123456789").unwrap(), expected);
     }
 }
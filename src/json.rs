@@ -0,0 +1,280 @@
+//! A textual JSON representation of patches, so they can be reviewed in pull requests and edited
+//! by scripts, then compiled back to binary with [`from_json`].
+//!
+//! Every document has a `"format"` of `"ips"`, `"ups"`, or `"bps"`, and binary data is base64.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{prelude::*, Error, Result};
+
+/// Parses a patch from its JSON representation, as produced by [`Patch::to_json`].
+pub fn from_json(text: &str) -> Result<Box<dyn Patch>> {
+    let value = Value::parse(text)?;
+    match value.get("format")?.as_str()? {
+        "ips" => Ok(Box::new(IpsPatch::from_json(&value)?)),
+        "ups" => Ok(Box::new(UpsPatch::from_json(&value)?)),
+        "bps" => Ok(Box::new(BpsPatch::from_json(&value)?)),
+        format => Err(Error::InvalidJson(format!("unknown format '{format}'"))),
+    }
+}
+
+/// A parsed JSON document. Numbers keep serde_json's representation, so offsets and sizes past
+/// 2^53 aren't rounded.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(serde_json::Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Result<&Value> {
+        self.get_opt(key)
+            .ok_or_else(|| Error::InvalidJson(format!("missing '{key}'")))
+    }
+
+    pub fn get_opt(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(map) => map.get(key).filter(|v| **v != Value::Null),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(Error::InvalidJson("expected a string".into())),
+        }
+    }
+
+    pub fn as_usize(&self) -> Result<usize> {
+        match self {
+            Value::Number(n) => n.as_u64().and_then(|n| usize::try_from(n).ok()),
+            _ => None,
+        }
+        .ok_or_else(|| Error::InvalidJson("expected a non-negative integer".into()))
+    }
+
+    pub fn as_isize(&self) -> Result<isize> {
+        match self {
+            Value::Number(n) => n.as_i64().and_then(|n| isize::try_from(n).ok()),
+            _ => None,
+        }
+        .ok_or_else(|| Error::InvalidJson("expected an integer".into()))
+    }
+
+    pub fn as_array(&self) -> Result<&[Value]> {
+        match self {
+            Value::Array(values) => Ok(values),
+            _ => Err(Error::InvalidJson("expected an array".into())),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        base64_decode(self.as_str()?)
+            .ok_or_else(|| Error::InvalidJson("invalid base64 data".into()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        serde_json::from_str(text).map_err(|err| Error::InvalidJson(err.to_string()))
+    }
+}
+
+/// Builds a JSON object one field at a time, keeping the layout of the documents stable so they
+/// diff well in reviews. Keys and values are encoded by serde_json.
+pub(crate) struct Object(String);
+
+impl Object {
+    pub fn new() -> Self {
+        Self(String::from("{"))
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        self.0 += &encode(key);
+        self.0.push(':');
+    }
+
+    pub fn str(mut self, key: &str, value: &str) -> Self {
+        self.key(key);
+        self.0 += &encode(value);
+        self
+    }
+
+    pub fn num(mut self, key: &str, value: impl Serialize) -> Self {
+        self.key(key);
+        self.0 += &encode(value);
+        self
+    }
+
+    pub fn bytes(self, key: &str, value: &[u8]) -> Self {
+        self.str(key, &base64_encode(value))
+    }
+
+    pub fn raw(mut self, key: &str, json: &str) -> Self {
+        self.key(key);
+        self.0.push_str(json);
+        self
+    }
+
+    /// Adds an array of already-serialized values, one per line.
+    pub fn array(self, key: &str, items: impl IntoIterator<Item = String>) -> Self {
+        let items: Vec<_> = items.into_iter().collect();
+        let json = if items.is_empty() {
+            "[]".to_string()
+        } else {
+            format!("[\n  {}\n]", items.join(",\n  "))
+        };
+        self.raw(key, &json)
+    }
+
+    pub fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+fn encode(value: impl Serialize) -> String {
+    serde_json::to_string(&value).expect("strings and numbers always serialize")
+}
+
+/// Serializes the size and CRC32 of a [`Validation`].
+pub(crate) fn validation(validation: &Validation) -> String {
+    Object::new()
+        .num("size", validation.size)
+        .str("crc32", &format!("{:08x}", validation.crc))
+        .finish()
+}
+
+pub(crate) fn parse_validation(value: &Value) -> Result<Validation> {
+    Ok(Validation {
+        size: value.get("size")?.as_usize()?,
+        crc: u32::from_str_radix(value.get("crc32")?.as_str()?, 16)
+            .map_err(|_| Error::InvalidJson("invalid crc32".into()))?,
+        ..Default::default()
+    })
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() {
+                BASE64[(n >> (18 - 6 * i)) as usize & 63] as char
+            } else {
+                '='
+            });
+        }
+    }
+    out
+}
+
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for byte in text.bytes() {
+        n = n << 6 | BASE64.iter().position(|&b| b == byte)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for (data, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+        ] {
+            assert_eq!(base64_encode(data), text);
+            assert_eq!(base64_decode(text).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog!!";
+        let patches: [Box<dyn Patch>; 2] = [
+            Box::new(IpsPatch::create(src, dst)),
+            Box::new(UpsPatch::create(src, dst)),
+        ];
+        for patch in patches {
+            let json = patch.to_json();
+            let parsed = from_json(&json).unwrap();
+            assert_eq!(parsed.export(None).unwrap(), patch.export(None).unwrap());
+            assert_eq!(parsed.apply(src).unwrap(), dst);
+        }
+
+        assert!(from_json(r#"{"format":"ips","records":[{"offset":0}]}"#).is_err());
+
+        // offsets that would overflow are rejected
+        for json in [
+            r#"{"format":"ups","records":[{"offset":18446744073709551615,"xor":"AQ=="}]}"#,
+            r#"{"format":"bps","actions":[{"action":"source-copy","length":1,"from":9223372036854775807}]}"#,
+            r#"{"format":"bps","actions":[{"action":"target-copy","length":9223372036854775808,"from":0}]}"#,
+        ] {
+            assert!(
+                matches!(from_json(json), Err(Error::InvalidJson(_))),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "quote \" backslash \\ controls \0\n\t\u{1f} non-ASCII é 漢 🎮";
+        let json = Object::new()
+            .str("text", text)
+            .num("size", 1u64 << 60)
+            .finish();
+        assert!(!json.contains(|c: char| c.is_control()));
+        assert!(json.contains(r#"\u0000\n\t\u001f"#));
+
+        let value = Value::parse(&json).unwrap();
+        assert_eq!(value.get("text").unwrap().as_str().unwrap(), text);
+        assert_eq!(value.get("size").unwrap().as_usize().unwrap(), 1 << 60);
+
+        // escaped surrogate pairs decode to the character they encode
+        let value = Value::parse(r#"{"text":"\ud83c\udfae\u00e9"}"#).unwrap();
+        assert_eq!(value.get("text").unwrap().as_str().unwrap(), "🎮é");
+        for json in [
+            r#"{"text":"\ud83c"}"#,
+            "{\"text\":\"\n\"}",
+            r#"{"text":"\x"}"#,
+        ] {
+            assert!(Value::parse(json).is_err(), "{json}");
+        }
+
+        let value = Value::parse(r#"{"a":-1,"b":1.5,"c":null}"#).unwrap();
+        assert!(value.get("a").unwrap().as_usize().is_err());
+        assert_eq!(value.get("a").unwrap().as_isize().unwrap(), -1);
+        assert!(value.get("b").unwrap().as_usize().is_err());
+        assert!(value.get("c").is_err());
+    }
+}
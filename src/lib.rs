@@ -1,28 +1,87 @@
 use std::{
+    borrow::Cow,
     io::{self, Read},
     mem::{self, MaybeUninit},
+    path::Path,
     slice,
+    time::{Duration, Instant},
 };
 
 use thiserror::Error;
 
+use manifest::Manifest;
+use progress::Observer;
+use source::Source;
+
+#[cfg(feature = "async")]
+pub mod async_io;
 mod bps;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod cue;
+#[cfg(feature = "dat")]
+pub mod dat;
+pub mod diff;
+pub mod encoding;
+pub mod equiv;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixup;
+pub mod hash;
+pub mod header;
 mod ips;
+pub mod json;
+pub mod lint;
+pub mod manifest;
+#[cfg(all(feature = "mmap", unix))]
+pub mod mmap;
+pub mod n64;
+pub mod progress;
+pub mod rom;
+pub mod script;
+pub mod set;
+#[cfg(feature = "sevenz")]
+pub mod sevenz;
+pub mod sidecar;
+pub mod smd;
+pub mod softpatch;
+pub mod source;
+pub mod stream;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(not(feature = "tracing"))]
+mod trace;
 mod ups;
+mod vectored;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod xml;
+#[cfg(feature = "zip")]
+pub mod zip;
 
 pub mod prelude {
     pub use super::bps::BpsPatch;
-    pub use super::ips::IpsPatch;
+    pub use super::ips::{IpsConflict, IpsPatch};
+    pub use super::progress::{Event, Observer};
+    pub use super::set::PatchSet;
     pub use super::ups::UpsPatch;
+    pub use super::CreateOptions;
     pub use super::Patch;
+    pub use super::Validation;
+    pub use super::{ApplyOptions, ApplyReport, Check};
+    pub use super::{Record, RecordKind, Region, RegionKind, SizePolicy, SourceInfo};
 }
 
+pub use bps_ups::Validation;
+
 pub(crate) trait ReadExt: Read {
     #[inline]
     fn read_arr<const N: usize>(&mut self) -> io::Result<[u8; N]> {
         unsafe {
             let mut buf: [MaybeUninit<u8>; N] = MaybeUninit::uninit().assume_init();
-            self.read_exact(mem::transmute(buf.as_mut_slice()))?;
+            self.read_exact(mem::transmute::<&mut [MaybeUninit<u8>], &mut [u8]>(
+                buf.as_mut_slice(),
+            ))?;
             // we'd like to use `Ok(std::mem::transmute(buf))`
             // but as of rust 1.69 this won't compile with N as a generic const
             Ok(*(&buf as *const _ as *const _))
@@ -52,86 +111,1251 @@ pub enum Error {
     InvalidSize(usize, usize),
     #[error("CRC ({0:#X}) does not match expected value ({1:#X}).")]
     InvalidCRC(u32, u32),
+    #[error("{0} ({1}) does not match expected value ({2}).")]
+    InvalidHash(&'static str, String, String),
     #[error("The patch is invalid.")]
     InvalidPatch,
+    #[error("The patch file itself is corrupt (CRC {0:#X}, expected {1:#X}).")]
+    CorruptPatch(u32, u32),
+    #[error("{inner} The ROM appears to {}.", header::describe_mismatch(*.kind, *.policy))]
+    HeaderMismatch {
+        inner: Box<Error>,
+        kind: header::HeaderKind,
+        policy: header::HeaderPolicy,
+    },
+    #[error("The patched output is invalid: {0}")]
+    OutputMismatch(Box<Error>),
+    #[error("The DAT file is invalid.")]
+    InvalidDat,
+    #[error("The cue sheet doesn't reference any files.")]
+    InvalidCue,
+    #[error("The checksum file is invalid.")]
+    InvalidSidecar,
+    #[error("The JSON patch is invalid: {0}.")]
+    InvalidJson(String),
+    #[error("The patch script is invalid: {0}.")]
+    InvalidScript(String),
+    #[error("The ROM doesn't have a recognizable {0} header.")]
+    UnrecognizedRom(&'static str),
+    #[error("Record {0} does not exist.")]
+    NoSuchRecord(usize),
+    #[error("No record starts at offset {0:#X}.")]
+    NoRecordAt(usize),
+    #[error("Record {index} ends at {end:#X}, past the end of the output ({size:#X} bytes).")]
+    RecordOverrun {
+        index: usize,
+        end: usize,
+        size: usize,
+    },
+    #[error("Record {index} reads up to {end:#X}, past the end of the source ({size:#X} bytes).")]
+    SourceOverrun {
+        index: usize,
+        end: usize,
+        size: usize,
+    },
+    #[error("Record {index} copies from outside the source ({size:#X} bytes).")]
+    SourceOutOfRange { index: usize, size: usize },
+    #[error("Record {index} copies from output that hasn't been written yet.")]
+    TargetOutOfRange { index: usize },
+    #[error("The records only produce {0:#X} of the {1:#X} bytes of output.")]
+    IncompleteOutput(usize, usize),
+    #[error("The {0} {1:#X} doesn't fit in the 24 bits an IPS patch allows.")]
+    OutOfRange(&'static str, usize),
+    #[error("A record starts at offset 0x454F46, which IPS reads as the end of the patch.")]
+    EofOffset,
+    #[error("The archive is invalid or uses an unsupported compression method.")]
+    InvalidArchive,
+    #[error("Couldn't decide which file in the archive to patch.")]
+    NoArchiveEntry,
+    #[error("The operation was cancelled.")]
+    Cancelled,
+    #[error("Conformance check failed: {0}.")]
+    Conformance(String),
+    #[error("The patch format is unsupported.")]
+    Unsupported,
+    #[error("{0} patches aren't supported.")]
+    UnsupportedFormat(&'static str),
+    #[error("Patch {0} in the set expects a different source than the previous one outputs.")]
+    ChainMismatch(usize),
+    #[error("Patch {0} in the set failed: {1}")]
+    PatchFailed(usize, Box<Error>),
 }
 
 impl From<std::io::Error> for Error {
+    /// Errors of this crate that had to pass through an [`io::Error`](std::io::Error), like a
+    /// corrupt number read by [`encoding::ReadVarExt`], come back out as themselves.
     fn from(value: std::io::Error) -> Self {
+        if value.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *value.into_inner().unwrap().downcast().unwrap();
+        }
         Self::Io(value)
     }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub trait Patch {
-    fn apply(&self, rom: &[u8]) -> Result<Vec<u8>>;
+pub trait Patch: Send + Sync {
+    /// Applies the patch. Same as [`apply_verified`](Patch::apply_verified).
+    fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        self.apply_verified(rom)
+    }
+    /// Applies the patch, failing if the source or the output don't match the checksums the
+    /// patch records.
+    fn apply_verified(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        self.apply_observed(rom, &mut ())
+    }
+    /// Like [`apply`](Patch::apply), reading the original from `source` as it's needed instead
+    /// of from a slice, so it doesn't have to be loaded into memory first.
+    fn apply_source(&self, source: &mut dyn Source) -> Result<Vec<u8>>;
+    /// Applies the patch without assembling the output in memory, returning it as an ordered list
+    /// of chunks borrowed from `rom` and the patch where possible, plus small owned runs for
+    /// bytes the patch computes. Servers can `writev` the chunks straight to a socket. Checksums
+    /// are validated like [`apply`](Patch::apply).
+    fn apply_vectored<'a>(&'a self, rom: &'a [u8]) -> Result<Vec<Cow<'a, [u8]>>>;
+    /// Applies the patch, choosing the size of the output according to `policy`. The size is
+    /// settled before anything is allocated, so [`SizePolicy::Exact`] fails without applying.
+    fn apply_sized(&self, rom: &[u8], policy: SizePolicy) -> Result<Vec<u8>> {
+        let size = policy.output_size(self, rom.len())?;
+        let natural = self.output_size(rom.len());
+        let unwritten = match policy {
+            SizePolicy::ExtendWithByte(_) => SizePolicy::unwritten(self, rom.len(), natural),
+            _ => Vec::new(),
+        };
+
+        let mut output = vec![0; size];
+        self.apply_into(rom, &mut output[..natural])?;
+        match policy {
+            SizePolicy::ExtendWithByte(byte) => {
+                for range in unwritten {
+                    output[range].fill(byte);
+                }
+            }
+            SizePolicy::KeepSource => {
+                if let Some(rest) = rom.get(natural..) {
+                    output[natural..].copy_from_slice(rest);
+                }
+            }
+            SizePolicy::Exact | SizePolicy::TruncateToTarget => {}
+        }
+        Ok(output)
+    }
+    /// Applies the patch into `out`, which must be exactly
+    /// [`output_size`](Patch::output_size) bytes, instead of allocating the output. `out` can be a
+    /// memory-mapped file, as `mmap::apply_to_file` does with the `mmap` feature, so a huge output
+    /// is never held in anonymous memory. Checksums are validated like [`apply`](Patch::apply).
+    fn apply_into(&self, rom: &[u8], out: &mut [u8]) -> Result<()>;
+    /// The size of the output of applying the patch to a ROM of `rom_len` bytes.
+    fn output_size(&self, rom_len: usize) -> usize {
+        self.target_size().unwrap_or(rom_len)
+    }
+    /// Applies the patch with every offset shifted by `bias`, e.g. to patch a headered ROM or a
+    /// file embedded in a larger container. The first `bias` bytes are kept, as are any bytes
+    /// past the end of the source the patch records.
+    fn apply_at(&self, rom: &[u8], bias: usize) -> Result<Vec<u8>> {
+        if bias > rom.len() {
+            return Err(Error::InvalidSize(rom.len(), bias));
+        }
+
+        let (head, body) = rom.split_at(bias);
+        let (body, tail) = match self.source() {
+            Some(source) if source.size <= body.len() => body.split_at(source.size),
+            _ => (body, &[][..]),
+        };
+
+        let mut buf = head.to_vec();
+        buf.extend(self.apply(body)?);
+        buf.extend_from_slice(tail);
+        Ok(buf)
+    }
+    /// Like [`apply`](Patch::apply), reporting progress and events to `observer` as the patch is
+    /// applied.
+    fn apply_observed(&self, rom: &[u8], observer: &mut dyn Observer) -> Result<Vec<u8>>;
+    /// Applies the patch without validating the source or output checksums, for callers that
+    /// validate the ROM themselves and don't want to hash it twice.
+    fn apply_unchecked(&self, rom: &[u8]) -> Result<Vec<u8>>;
+    /// Like [`apply`](Patch::apply), checking the source against `info` instead of hashing `rom`,
+    /// so applying many patches to the same ROM only hashes it once. The output is still
+    /// validated. If `info` is for a ROM of a different size, or the patch records hashes other
+    /// than the CRC32, `rom` is hashed as usual.
+    fn apply_with_info(&self, rom: &[u8], info: &SourceInfo) -> Result<Vec<u8>> {
+        let Some(source) = self.source() else {
+            return self.apply(rom);
+        };
+
+        source.validate_info(rom, info)?;
+        let output = self.apply_unchecked(rom)?;
+        if let Some(target) = self.target() {
+            target
+                .validate(&output)
+                .map_err(|err| Error::OutputMismatch(Box::new(err)))?;
+        }
+        Ok(output)
+    }
+    /// Like [`apply`](Patch::apply), skipping the checks `options` turns off, and returning an
+    /// [`ApplyReport`] of what was done along with the output so callers can log it.
+    fn apply_report(&self, rom: &[u8], options: &ApplyOptions) -> Result<(Vec<u8>, ApplyReport)> {
+        let start = Instant::now();
+        let source_check = match self.source() {
+            None => Check::NotRecorded,
+            Some(_) if options.skip_source_check => Check::Skipped,
+            Some(source) => {
+                source.validate(rom)?;
+                Check::Passed
+            }
+        };
+
+        let output = self.apply_unchecked(rom)?;
+        let output_check = match self.target() {
+            None => Check::NotRecorded,
+            Some(_) if options.skip_output_check => Check::Skipped,
+            Some(target) => {
+                target
+                    .validate(&output)
+                    .map_err(|err| Error::OutputMismatch(Box::new(err)))?;
+                Check::Passed
+            }
+        };
+
+        let report = ApplyReport {
+            format: self.format(),
+            records_applied: self.records().len(),
+            bytes_written: self.regions().iter().map(|region| region.len).sum(),
+            output_size: output.len(),
+            source_check,
+            output_check,
+            elapsed: start.elapsed(),
+        };
+        Ok((output, report))
+    }
     fn validate(&self, rom: &[u8]) -> Option<Result<()>>;
+    /// Checks every property of the source the patch records, returning all mismatches instead of
+    /// stopping at the first. Returns `None` if the patch doesn't record its source.
+    fn validate_full(&self, rom: &[u8]) -> Option<Vec<Error>> {
+        self.source().map(|source| source.mismatches(rom))
+    }
+    fn source(&self) -> Option<Validation>;
+    /// The expected output, if the patch records it.
+    fn target(&self) -> Option<Validation>;
+    /// The size of the output, if the patch records it.
+    fn target_size(&self) -> Option<usize> {
+        self.target().map(|target| target.size)
+    }
+    /// Whether the patch was made against an empty source, so it creates a new file rather than
+    /// modifying one. Apply it to an empty slice.
+    fn creates_file(&self) -> bool {
+        self.source().is_some_and(|source| source.size == 0)
+    }
+    /// Estimates the most memory, in bytes, applying the patch to a ROM of `rom_len` bytes holds
+    /// at once: the loaded patch and the output, not counting the ROM. Services can check it
+    /// against a memory budget to reject or queue a job before starting it.
+    fn estimated_peak_memory(&self, rom_len: usize) -> usize;
+    /// The name of the patch format, e.g. `"BPS"`.
+    fn format(&self) -> &'static str;
+    /// Free-form metadata stored in the patch.
+    fn metadata(&self) -> Option<&[u8]> {
+        None
+    }
+    /// The regions of the output written by the patch, in patch order. Bytes carried over from
+    /// the same offset in the source aren't included.
+    fn regions(&self) -> Vec<Region>;
+    /// Every record in the patch, in file order, so patches can be audited without applying them.
+    fn records(&self) -> Vec<Record<'_>>;
+    /// Serializes the patch to JSON, which [`json::from_json`] compiles back into a patch.
+    fn to_json(&self) -> String;
+    /// Encodes the patch. The bytes depend only on the patch's records, sizes, and metadata, with
+    /// every variable-length integer in its shortest form, so exporting the same patch always
+    /// gives the same bytes. Use [`canonicalize`] to also get the same bytes from patches that
+    /// only differ in how their records are laid out.
     fn export(&self, crc: Option<u32>) -> Result<Vec<u8>>;
+    /// Checks whether `other`, which may be in a different format, produces the same output.
+    ///
+    /// With a `base`, both patches are applied to it and the outputs compared. Without one, the
+    /// patches must produce the same output from every source they both accept, regardless of how
+    /// their records are laid out. Since an XOR and a literal byte only agree on a particular
+    /// source, comparing a UPS patch to another format generally needs the base.
+    fn equivalent(&self, other: &dyn Patch, base: Option<&[u8]>) -> bool {
+        equiv::compare_impl(self, other, base) == equiv::Comparison::Equivalent
+    }
+    /// Checks whether the patch leaves every source unchanged, e.g. because it has no records or
+    /// every record copies the source to the same offset. Pipelines can skip writing the output
+    /// and warn that the patch probably isn't the one the user meant.
+    fn is_noop(&self) -> bool {
+        equiv::is_noop(self)
+    }
 }
 
-pub(crate) mod bps_ups {
-    use std::io::{Read, Write};
+/// The size and CRC32 of a source ROM, computed once and passed to
+/// [`apply_with_info`](Patch::apply_with_info) for every patch applied to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceInfo {
+    pub len: usize,
+    pub crc32: u32,
+}
+
+impl SourceInfo {
+    pub fn new(data: &[u8]) -> Self {
+        Self {
+            len: data.len(),
+            crc32: hash::crc32(data),
+        }
+    }
+}
+
+impl From<Validation> for SourceInfo {
+    fn from(value: Validation) -> Self {
+        Self {
+            len: value.size,
+            crc32: value.crc,
+        }
+    }
+}
+
+/// Options for [`Patch::apply_report`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApplyOptions {
+    /// Don't check the source against the checksums the patch records, e.g. because the caller
+    /// already has.
+    pub skip_source_check: bool,
+    /// Don't check the output against the checksums the patch records.
+    pub skip_output_check: bool,
+}
+
+/// What [`Patch::apply_report`] did, for callers that log exactly how each output was produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// The name of the patch format, as returned by [`Patch::format`].
+    pub format: &'static str,
+    /// The number of records applied. Applying stops at the first error, so this is every record
+    /// in the patch.
+    pub records_applied: usize,
+    /// The bytes written by the records, not counting bytes carried over from the same offset in
+    /// the source. Bytes written by more than one record are counted each time.
+    pub bytes_written: usize,
+    pub output_size: usize,
+    pub source_check: Check,
+    pub output_check: Check,
+    pub elapsed: Duration,
+}
+
+/// What happened to one of the checksum checks in an [`ApplyReport`]. A check that fails stops
+/// the apply with an error, so it never appears in a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    Passed,
+    /// The check was turned off in the [`ApplyOptions`].
+    Skipped,
+    /// The patch doesn't record the checksums, as with IPS.
+    NotRecorded,
+}
+
+/// A supported patch format, as identified by [`detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Ips,
+    Ups,
+    Bps,
+}
+
+/// Magic values of formats that are recognized but can't be loaded, with their names.
+const UNSUPPORTED_MAGIC: &[(&[u8], &str)] = &[
+    (b"PPF", "PPF"),
+    (b"\xD6\xC3\xC4", "xdelta"),
+    (b"BSDIFF40", "bsdiff"),
+];
+
+/// Identifies the format of a patch from its magic value alone, so the right parser is picked
+/// without trying others on untrusted input first. Fails with [`Error::UnsupportedFormat`] for
+/// other known patch formats, and [`Error::Unsupported`] for anything else.
+pub fn detect(data: &[u8]) -> Result<Format> {
+    match data {
+        [b'P', b'A', b'T', b'C', b'H', ..] => Ok(Format::Ips),
+        [b'U', b'P', b'S', b'1', ..] => Ok(Format::Ups),
+        [b'B', b'P', b'S', b'1', ..] => Ok(Format::Bps),
+        _ => Err(UNSUPPORTED_MAGIC
+            .iter()
+            .find(|(magic, _)| data.starts_with(magic))
+            .map_or(Error::Unsupported, |&(_, name)| {
+                Error::UnsupportedFormat(name)
+            })),
+    }
+}
+
+/// Loads a patch of any supported format, detecting the format from its magic value.
+pub fn load(data: &[u8]) -> Result<Box<dyn Patch>> {
+    use prelude::*;
+
+    match detect(data)? {
+        Format::Ips => Ok(Box::new(IpsPatch::load(data)?)),
+        Format::Ups => Ok(Box::new(UpsPatch::load(data)?)),
+        Format::Bps => Ok(Box::new(BpsPatch::load(data)?)),
+    }
+}
+
+/// How far into a file [`load_resync`] looks for a patch by default. Enough for a byte order
+/// mark, a resource fork header, or a mail message's headers.
+pub const RESYNC_LIMIT: usize = 1024;
+
+/// Finds the first offset, no more than `limit` bytes into `data`, where the magic value of a
+/// supported format starts.
+pub fn find_magic(data: &[u8], limit: usize) -> Option<usize> {
+    (0..=limit.min(data.len())).find(|&offset| detect(&data[offset..]).is_ok())
+}
+
+/// Like [`load`], but skips up to `limit` bytes of junk before the patch, such as a byte order
+/// mark or mail armor, returning the patch and the number of bytes skipped. Each offset where a
+/// magic value appears is tried in turn, so a magic value in the junk itself (e.g. "PATCH" in a
+/// mail subject) doesn't stop the real patch from loading. Fails with the error from the first
+/// candidate if none load.
+pub fn load_resync(data: &[u8], limit: usize) -> Result<(Box<dyn Patch>, usize)> {
+    let mut first = None;
+    let candidates = (0..=limit.min(data.len())).filter(|&offset| detect(&data[offset..]).is_ok());
+    for skipped in candidates {
+        match load(&data[skipped..]) {
+            Ok(patch) => return Ok((patch, skipped)),
+            Err(err) => _ = first.get_or_insert(err),
+        }
+    }
+    Err(first.unwrap_or_else(|| detect(data).err().unwrap_or(Error::Unsupported)))
+}
+
+/// Like [`load`], but records that disagree with the sizes a patch declares are returned as
+/// warnings instead of failing the load. Such a patch can't be applied, and was most likely
+/// corrupted, e.g. by an incomplete download, but can still be inspected. IPS patches don't
+/// declare sizes, so never have warnings.
+pub fn load_lenient(data: &[u8]) -> Result<(Box<dyn Patch>, Vec<Error>)> {
+    use prelude::*;
+
+    match detect(data)? {
+        Format::Ips => Ok((Box::new(IpsPatch::load(data)?), Vec::new())),
+        Format::Ups => {
+            let (patch, warnings) = UpsPatch::load_lenient(data)?;
+            Ok((Box::new(patch), warnings))
+        }
+        Format::Bps => {
+            let (patch, warnings) = BpsPatch::load_lenient(data)?;
+            Ok((Box::new(patch), warnings))
+        }
+    }
+}
+
+/// Loads a patch of any supported format and exports it in canonical form, so patch files can be
+/// content-addressed: patches that make the same changes, but order or split their records
+/// differently, or encode numbers in longer forms than needed, give identical bytes. See each
+/// format's `canonicalize` for what counts as the same changes.
+pub fn canonicalize(data: &[u8]) -> Result<Vec<u8>> {
+    use prelude::*;
+
+    match detect(data)? {
+        Format::Ips => {
+            let mut patch = IpsPatch::load(data)?;
+            patch.canonicalize();
+            patch.export(None)
+        }
+        Format::Ups => {
+            let mut patch = UpsPatch::load(data)?;
+            patch.canonicalize();
+            patch.export(None)
+        }
+        Format::Bps => {
+            let mut patch = BpsPatch::load(data)?;
+            patch.canonicalize();
+            patch.export(None)
+        }
+    }
+}
+
+/// Loads a patch, choosing the format from the extension of `name`. JSON patches and patch
+/// scripts are compiled, and files with any other extension are detected from their magic value,
+/// as in [`load`].
+pub fn load_named(data: &[u8], name: impl AsRef<Path>) -> Result<Box<dyn Patch>> {
+    use prelude::*;
+
+    let extension = name
+        .as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("ips") => Ok(Box::new(IpsPatch::load(data)?)),
+        Some("ups") => Ok(Box::new(UpsPatch::load(data)?)),
+        Some("bps") => Ok(Box::new(BpsPatch::load(data)?)),
+        Some("toml") => Ok(Box::new(
+            script::Script::parse(&String::from_utf8_lossy(data))?.to_ips()?,
+        )),
+        Some("json") => json::from_json(&String::from_utf8_lossy(data)),
+        _ => load(data),
+    }
+}
+
+/// Reads and loads the patch at `path`, as in [`load_named`].
+pub fn load_file(path: impl AsRef<Path>) -> Result<Box<dyn Patch>> {
+    load_named(&std::fs::read(path.as_ref())?, path)
+}
+
+/// Where the data in a region of the patched output comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Bytes stored in the patch.
+    Literal,
+    /// A single repeated byte.
+    Fill(u8),
+    /// Source bytes XORed with bytes stored in the patch.
+    Xor,
+    /// Bytes copied from the given offset in the source.
+    SourceCopy(usize),
+    /// Bytes copied from the given offset earlier in the output.
+    TargetCopy(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub offset: usize,
+    pub len: usize,
+    pub kind: RegionKind,
+}
+
+/// Options for creating patches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreateOptions {
+    /// Embed a [`Manifest`](manifest::Manifest) in the metadata recording this tool, the time,
+    /// and the names below, so distributed patches describe where they came from. Only BPS
+    /// patches have metadata.
+    pub provenance: bool,
+    /// The name of the original file.
+    pub source_name: Option<String>,
+    /// The name of the modified file.
+    pub target_name: Option<String>,
+    /// The title, author, version, and description to record in the manifest. Only BPS patches
+    /// have metadata.
+    pub manifest: Manifest,
+    /// Metadata stored as-is in place of a generated manifest, e.g. a hand-written XML file.
+    pub metadata: Option<Vec<u8>>,
+}
+
+impl CreateOptions {
+    /// The metadata to store in a created patch, if there's anything to record.
+    pub(crate) fn metadata(&self) -> Option<Vec<u8>> {
+        if let Some(metadata) = &self.metadata {
+            return Some(metadata.clone());
+        }
+
+        let mut manifest = self.manifest.clone();
+        if self.provenance {
+            let provenance =
+                Manifest::provenance(self.source_name.clone(), self.target_name.clone());
+            manifest = Manifest {
+                tool: provenance.tool,
+                created: provenance.created,
+                source: provenance.source,
+                target: provenance.target,
+                ..manifest
+            };
+        }
+        (manifest != Manifest::default()).then(|| manifest.to_xml().into_bytes())
+    }
+}
+
+/// How the size of the output is chosen when it differs from the size of the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizePolicy {
+    /// The output must be the size the patch records, or the size of the source if it doesn't
+    /// record one, e.g. an IPS patch without a truncation record. A patch that records the size
+    /// of its source also rejects ROMs of any other size.
+    Exact,
+    /// The size the patch records. IPS patches without one produce the larger of the source and
+    /// the end of the last record, and bytes past the end of the source are zero.
+    #[default]
+    TruncateToTarget,
+    /// Like [`TruncateToTarget`](Self::TruncateToTarget), but bytes past the end of the source
+    /// that no record writes are set to the given byte, e.g. `0xFF` for cartridge padding.
+    ExtendWithByte(u8),
+    /// The output is never shorter than the source. Source bytes past the size the patch records
+    /// are kept.
+    KeepSource,
+}
+
+impl SizePolicy {
+    /// The size of the output of applying `patch` to a ROM of `rom_len` bytes under the policy.
+    /// Fails with [`Error::InvalidSize`] if the policy is [`Exact`](Self::Exact) and the ROM or
+    /// the output isn't the size the patch expects.
+    pub fn output_size<P: Patch + ?Sized>(self, patch: &P, rom_len: usize) -> Result<usize> {
+        let size = patch.output_size(rom_len);
+        match self {
+            SizePolicy::Exact => {
+                let expected = Self::exact_size(patch, rom_len)?;
+                if size != expected {
+                    return Err(Error::InvalidSize(size, expected));
+                }
+                Ok(size)
+            }
+            SizePolicy::TruncateToTarget | SizePolicy::ExtendWithByte(_) => Ok(size),
+            SizePolicy::KeepSource => Ok(size.max(rom_len)),
+        }
+    }
+
+    /// The size [`Exact`](Self::Exact) requires of the output, failing if `patch` records the
+    /// size of its source and the ROM is a different size.
+    fn exact_size<P: Patch + ?Sized>(patch: &P, rom_len: usize) -> Result<usize> {
+        if let Some(source) = patch.source().filter(|source| source.size != rom_len) {
+            return Err(Error::InvalidSize(rom_len, source.size));
+        }
+        Ok(patch.target_size().unwrap_or(rom_len))
+    }
+
+    /// The ranges of an output of `size` bytes past the end of the source that no record of
+    /// `patch` writes.
+    fn unwritten<P: Patch + ?Sized>(
+        patch: &P,
+        rom_len: usize,
+        size: usize,
+    ) -> Vec<std::ops::Range<usize>> {
+        let Some(len) = size.checked_sub(rom_len) else {
+            return Vec::new();
+        };
+        let mut written = vec![false; len];
+        for region in patch.regions() {
+            let start = region.offset.saturating_sub(rom_len).min(len);
+            let end = (region.offset + region.len)
+                .saturating_sub(rom_len)
+                .min(len);
+            written[start..end].fill(true);
+        }
+
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < len {
+            let start = i + written[i..].iter().take_while(|&&w| w).count();
+            let end = start + written[start..].iter().take_while(|&&w| !w).count();
+            if start < end {
+                ranges.push(rom_len + start..rom_len + end);
+            }
+            i = end;
+        }
+        ranges
+    }
+
+    /// Adjusts `output`, produced by applying `patch` to `rom` some other way, e.g. after skipping
+    /// its header, to the size the policy calls for. [`Patch::apply_sized`] sizes the output as
+    /// it's applied instead.
+    pub fn resize<P: Patch + ?Sized>(
+        self,
+        patch: &P,
+        rom: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        match self {
+            SizePolicy::Exact => {
+                let expected = Self::exact_size(patch, rom.len())?;
+                if output.len() != expected {
+                    return Err(Error::InvalidSize(output.len(), expected));
+                }
+            }
+            SizePolicy::TruncateToTarget => {}
+            SizePolicy::ExtendWithByte(byte) => {
+                for range in Self::unwritten(patch, rom.len(), output.len()) {
+                    output[range].fill(byte);
+                }
+            }
+            SizePolicy::KeepSource => {
+                if let Some(rest) = rom.get(output.len()..) {
+                    output.extend_from_slice(rest);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a record produces its part of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind<'a> {
+    /// Bytes stored in the patch.
+    Literal(&'a [u8]),
+    /// A single repeated byte.
+    Fill(u8),
+    /// Bytes stored in the patch, XORed with the source.
+    Xor(&'a [u8]),
+    /// Bytes carried over from the same offset in the source.
+    SourceRead,
+    /// Bytes copied from the given offset in the source.
+    SourceCopy(usize),
+    /// Bytes copied from the given offset earlier in the output.
+    TargetCopy(usize),
+}
+
+/// A record as it's stored in a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// The offset in the output the record writes to.
+    pub offset: usize,
+    pub len: usize,
+    pub kind: RecordKind<'a>,
+}
 
-    use byteorder::{ReadBytesExt, WriteBytesExt};
+pub(crate) mod bps_ups {
+    use std::{borrow::Cow, io::Write};
 
-    use crate::{Error, Result};
+    use crate::{
+        hash::{self, Checksum},
+        Error, Result, SourceInfo,
+    };
 
-    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    /// The expected size and checksums of a file. CRC32 is always present, stronger hashes are
+    /// only checked when known.
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
     pub struct Validation {
         pub size: usize,
         pub crc: u32,
+        pub md5: Option<[u8; 16]>,
+        pub sha1: Option<[u8; 20]>,
+        pub sha256: Option<[u8; 32]>,
     }
 
     impl Validation {
+        pub fn new(data: &[u8]) -> Self {
+            Self {
+                size: data.len(),
+                crc: hash::crc32(data),
+                ..Default::default()
+            }
+        }
+
+        /// Like [`Validation::new`], but also computes MD5, SHA-1, and SHA-256.
+        pub fn with_hashes(data: &[u8]) -> Self {
+            Self {
+                md5: Some(hash::md5(data)),
+                sha1: Some(hash::sha1(data)),
+                sha256: Some(hash::sha256(data)),
+                ..Self::new(data)
+            }
+        }
+
+        /// Whether data matching `self` also matches `other`, comparing the size and CRC32 and
+        /// every stronger hash both record.
+        pub fn agrees(&self, other: &Validation) -> bool {
+            fn agree<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+                a.zip(b).is_none_or(|(a, b)| a == b)
+            }
+
+            self.size == other.size
+                && self.crc == other.crc
+                && agree(self.md5, other.md5)
+                && agree(self.sha1, other.sha1)
+                && agree(self.sha256, other.sha256)
+        }
+
         pub fn validate(&self, data: &[u8]) -> Result<()> {
-            if self.size != data.len() {
+            match self.mismatches(data).into_iter().next() {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        }
+
+        /// Like [`validate`](Self::validate), taking the size and CRC32 of `data` from `info`
+        /// instead of hashing it. Falls back to hashing if `info` doesn't describe `data` or
+        /// other hashes are expected.
+        pub fn validate_info(&self, data: &[u8], info: &SourceInfo) -> Result<()> {
+            if info.len != data.len() {
+                return self.validate(data);
+            }
+            self.validate_crc(data, info.crc32)
+        }
+
+        /// Like [`validate`](Self::validate), given the CRC32 of `data`. Other expected hashes
+        /// are still computed.
+        pub fn validate_crc(&self, data: &[u8], crc: u32) -> Result<()> {
+            if self.md5.is_some() || self.sha1.is_some() || self.sha256.is_some() {
+                return self.validate(data);
+            }
+
+            if data.len() != self.size {
                 return Err(Error::InvalidSize(data.len(), self.size));
             }
+            if crc != self.crc {
+                return Err(Error::InvalidCRC(crc, self.crc));
+            }
+            Ok(())
+        }
 
-            let hash = crc32fast::hash(data);
-            if hash != self.crc {
-                return Err(Error::InvalidCRC(hash, self.crc));
+        /// Like [`validate`](Self::validate), for data split into chunks.
+        pub fn validate_chunks(&self, chunks: &[Cow<[u8]>]) -> Result<()> {
+            if self.md5.is_some() || self.sha1.is_some() || self.sha256.is_some() {
+                return self.validate(&chunks.concat());
             }
 
+            let size = chunks.iter().map(|chunk| chunk.len()).sum();
+            if size != self.size {
+                return Err(Error::InvalidSize(size, self.size));
+            }
+            let mut hasher = hash::Crc32Hasher::new();
+            chunks.iter().for_each(|chunk| hasher.update(chunk));
+            let crc = hasher.finalize();
+            if crc != self.crc {
+                return Err(Error::InvalidCRC(crc, self.crc));
+            }
             Ok(())
         }
-    }
 
-    pub trait ReadVarExt: Read {
-        fn read_var_int(&mut self) -> std::io::Result<usize> {
-            let mut value = 0;
-            let mut shift = 1;
-            loop {
-                let x = self.read_u8()?;
-                value += (x as usize & 0x7f) * shift;
-                if (x & 0x80) != 0 {
-                    return Ok(value);
+        /// Returns every way `data` differs from the expected size and checksum.
+        pub fn mismatches(&self, data: &[u8]) -> Vec<Error> {
+            let mut errors = Vec::new();
+            if self.size != data.len() {
+                errors.push(Error::InvalidSize(data.len(), self.size));
+            }
+
+            let crc = hash::crc32(data);
+            if crc != self.crc {
+                errors.push(Error::InvalidCRC(crc, self.crc));
+            }
+
+            let expected: [(&dyn Checksum, Option<&[u8]>); 3] = [
+                (&hash::Md5, self.md5.as_ref().map(|h| &h[..])),
+                (&hash::Sha1, self.sha1.as_ref().map(|h| &h[..])),
+                (&hash::Sha256, self.sha256.as_ref().map(|h| &h[..])),
+            ];
+            for (checksum, expected) in expected {
+                if let Some(expected) = expected {
+                    errors.extend(hash::mismatch(checksum, data, expected));
                 }
+            }
+
+            errors
+        }
 
-                shift <<= 7;
-                value += shift;
+        /// Like [`validate`](Self::validate), also checking `data` against extra checksums the
+        /// patch doesn't carry, each with its expected digest.
+        pub fn validate_with(&self, data: &[u8], extra: &[(&dyn Checksum, &[u8])]) -> Result<()> {
+            self.validate(data)?;
+            match extra
+                .iter()
+                .find_map(|&(checksum, expected)| hash::mismatch(checksum, data, expected))
+            {
+                Some(err) => Err(err),
+                None => Ok(()),
             }
         }
     }
 
-    impl<T: Read + ?Sized> ReadVarExt for T {}
+    /// Collects patched output, hashing it as it's written so validating it doesn't take a second
+    /// pass over the whole buffer.
+    pub struct HashingWriter {
+        buf: Vec<u8>,
+        hasher: Option<hash::Crc32Hasher>,
+        /// How much of `buf` has been hashed. Bytes added with [`push`](Self::push) are hashed
+        /// with the next write.
+        hashed: usize,
+    }
+
+    impl HashingWriter {
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                buf: Vec::with_capacity(capacity),
+                hasher: Some(hash::Crc32Hasher::new()),
+                hashed: 0,
+            }
+        }
+
+        /// A writer that doesn't hash, for output that won't be validated.
+        pub fn unhashed(capacity: usize) -> Self {
+            Self {
+                hasher: None,
+                ..Self::new(capacity)
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.buf.len()
+        }
+
+        pub fn get(&self, i: usize) -> Option<u8> {
+            self.buf.get(i).copied()
+        }
+
+        pub fn push(&mut self, byte: u8) {
+            self.buf.push(byte);
+        }
+
+        /// Appends `len` bytes filled in by `fill`.
+        pub fn append_with<E>(
+            &mut self,
+            len: usize,
+            fill: impl FnOnce(&mut [u8]) -> std::result::Result<(), E>,
+        ) -> std::result::Result<(), E> {
+            let start = self.buf.len();
+            self.buf.resize(start + len, 0);
+            fill(&mut self.buf[start..])?;
+            self.update();
+            Ok(())
+        }
+
+        fn update(&mut self) {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&self.buf[self.hashed..]);
+            }
+            self.hashed = self.buf.len();
+        }
 
-    pub trait WriteVarExt: Write {
-        fn write_var_int(&mut self, mut value: usize) -> std::io::Result<()> {
-            loop {
-                let x = (value & 0x7f) as u8;
-                value >>= 7;
-                if value == 0 {
-                    self.write_u8(0x80 | x)?;
-                    return Ok(());
+        /// Checks the output against `expected`, returning it if it matches.
+        pub fn validate(mut self, expected: &Validation) -> Result<Vec<u8>> {
+            self.update();
+            let result = match self.hasher.take() {
+                Some(hasher) => expected.validate_crc(&self.buf, hasher.finalize()),
+                None => expected.validate(&self.buf),
+            };
+            result.map_err(|err| Error::OutputMismatch(Box::new(err)))?;
+            Ok(self.buf)
+        }
+
+        pub fn into_inner(self) -> Vec<u8> {
+            self.buf
+        }
+    }
+
+    impl Write for HashingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            self.update();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prelude::*;
+
+    #[test]
+    fn load_detects_format() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!";
+        let bps = BpsPatch::create(src, dst).export(None).unwrap();
+        assert_eq!(load(&bps).unwrap().format(), "BPS");
+        assert!(BpsPatch::try_from(bps.as_slice()).is_ok());
+        assert!(UpsPatch::try_from(bps.as_slice()).is_err());
+        assert_eq!(load(b"PATCHEOF").unwrap().format(), "IPS");
+        assert!(matches!(load(b"not a patch"), Err(Error::Unsupported)));
+        assert!(matches!(
+            load(b"BSDIFF40\0\0"),
+            Err(Error::UnsupportedFormat("bsdiff"))
+        ));
+        assert_eq!(detect(b"UPS1").unwrap(), Format::Ups);
+        assert_eq!(detect(&bps).unwrap(), Format::Bps);
+        assert!(matches!(
+            detect(b"PPF30"),
+            Err(Error::UnsupportedFormat("PPF"))
+        ));
+        assert!(matches!(
+            detect(b"\xD6\xC3\xC4\0"),
+            Err(Error::UnsupportedFormat("xdelta"))
+        ));
+    }
+
+    #[test]
+    fn load_resync() {
+        let patch = UpsPatch::create(b"abcd", b"abXd").export(None).unwrap();
+        let (loaded, skipped) = super::load_resync(&patch, RESYNC_LIMIT).unwrap();
+        assert_eq!((loaded.format(), skipped), ("UPS", 0));
+
+        // a byte order mark, then mail headers mentioning a magic value
+        let mut data = b"\xEF\xBB\xBFSubject: [PATCH] fix\r\n\r\n".to_vec();
+        let prefix = data.len();
+        data.extend(&patch);
+        assert!(load(&data).is_err());
+        assert_eq!(find_magic(&data, RESYNC_LIMIT), Some(13));
+        let (loaded, skipped) = super::load_resync(&data, RESYNC_LIMIT).unwrap();
+        assert_eq!(skipped, prefix);
+        assert_eq!(loaded.apply(b"abcd").unwrap(), b"abXd");
+
+        assert!(matches!(
+            super::load_resync(&data, prefix - 1),
+            Err(Error::Io(..))
+        ));
+        assert!(matches!(
+            super::load_resync(b"junk", RESYNC_LIMIT),
+            Err(Error::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn apply_report() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog, twice.";
+        let patch = BpsPatch::create(src, dst);
+        let (output, report) = patch.apply_report(src, &ApplyOptions::default()).unwrap();
+        assert_eq!(output, dst);
+        assert_eq!(report.format, "BPS");
+        assert_eq!(report.records_applied, patch.records().len());
+        assert_eq!(report.output_size, dst.len());
+        assert!(report.bytes_written > 0 && report.bytes_written <= dst.len());
+        assert_eq!(
+            (report.source_check, report.output_check),
+            (Check::Passed, Check::Passed)
+        );
+
+        let wrong = b"The quick brown fox";
+        assert!(patch.apply_report(wrong, &ApplyOptions::default()).is_err());
+        let options = ApplyOptions {
+            skip_source_check: true,
+            ..Default::default()
+        };
+        let (_, report) = patch.apply_report(src, &options).unwrap();
+        assert_eq!(report.source_check, Check::Skipped);
+
+        let (_, report) = IpsPatch::create(src, dst)
+            .apply_report(src, &options)
+            .unwrap();
+        assert_eq!(report.source_check, Check::NotRecorded);
+        assert_eq!(report.output_check, Check::NotRecorded);
+    }
+
+    #[test]
+    fn corrupt_patch() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!";
+        for patch in [
+            Box::new(BpsPatch::create(src, dst)) as Box<dyn Patch>,
+            Box::new(UpsPatch::create(src, dst)),
+        ] {
+            let mut data = patch.export(None).unwrap();
+            assert!(matches!(
+                load(&data).unwrap().apply(b"The quick brown cat"),
+                Err(Error::InvalidCRC(..))
+            ));
+
+            data[6] ^= 1;
+            assert!(matches!(load(&data), Err(Error::CorruptPatch(..))));
+        }
+    }
+
+    #[test]
+    fn canonicalize() {
+        // the same bytes, written by records in a different order and split differently
+        let a = b"PATCH\0\0\x02\0\x02cd\0\0\0\0\x02abEOF";
+        let b = b"PATCH\0\0\0\0\x03abc\0\0\x03\0\x01dEOF";
+        let canonical = super::canonicalize(a).unwrap();
+        assert_eq!(canonical, b"PATCH\0\0\0\0\x04abcdEOF");
+        assert_eq!(super::canonicalize(b).unwrap(), canonical);
+        assert_eq!(super::canonicalize(&canonical).unwrap(), canonical);
+    }
+
+    #[test]
+    fn apply_with_info() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!";
+        let info = SourceInfo::new(src);
+        for patch in [
+            Box::new(BpsPatch::create(src, dst)) as Box<dyn Patch>,
+            Box::new(UpsPatch::create(src, dst)),
+            Box::new(IpsPatch::create(src, dst)),
+        ] {
+            assert_eq!(patch.apply_with_info(src, &info).unwrap(), dst);
+        }
+
+        // the precomputed CRC is trusted, so a wrong one fails even for the right ROM
+        let patch = BpsPatch::create(src, dst);
+        let wrong = SourceInfo { crc32: 0, ..info };
+        assert!(matches!(
+            patch.apply_with_info(src, &wrong),
+            Err(Error::InvalidCRC(0, _))
+        ));
+        // info for a ROM of another size is ignored
+        let other = SourceInfo::new(b"abc");
+        assert_eq!(patch.apply_with_info(src, &other).unwrap(), dst);
+    }
+
+    #[test]
+    fn apply_into() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog, twice.";
+        let patches: [Box<dyn Patch>; 3] = [
+            Box::new(IpsPatch::create(src, dst)),
+            Box::new(UpsPatch::create(src, dst)),
+            Box::new(BpsPatch::create(src, dst)),
+        ];
+        for patch in patches {
+            let mut out = vec![0xFF; patch.output_size(src.len())];
+            patch.apply_into(src, &mut out).unwrap();
+            assert_eq!(out, dst);
+            assert!(matches!(
+                patch.apply_into(src, &mut out[1..]),
+                Err(Error::InvalidSize(..))
+            ));
+        }
+
+        let patch = UpsPatch::create(src, dst);
+        let mut out = vec![0; dst.len()];
+        assert!(patch.apply_into(b"wrong", &mut out).is_err());
+    }
+
+    #[test]
+    fn estimated_peak_memory() {
+        let src = vec![0; 0x1000];
+        let mut dst = src.clone();
+        // no runs, so BPS stores every changed byte
+        dst[0x100..0x180].copy_from_slice(&[1, 2].repeat(0x40));
+        dst.extend([1, 2].repeat(0x400));
+
+        let patches: [Box<dyn Patch>; 3] = [
+            Box::new(IpsPatch::create(&src, &dst)),
+            Box::new(UpsPatch::create(&src, &dst)),
+            Box::new(BpsPatch::create(&src, &dst)),
+        ];
+        for patch in patches {
+            let estimate = patch.estimated_peak_memory(src.len());
+            assert!((dst.len() + 0x880..dst.len() + 0x1000).contains(&estimate));
+        }
+
+        // an IPS patch keeps bytes past its last record, so a larger ROM needs more
+        let patch = IpsPatch::create(&src, &dst);
+        assert!(patch.estimated_peak_memory(0x10000) >= 0x10000);
+    }
+
+    #[test]
+    fn hashing_writer() {
+        use std::io::Write;
+
+        let mut buf = bps_ups::HashingWriter::new(0);
+        buf.write_all(b"abc").unwrap();
+        buf.push(b'd');
+        buf.push(b'e');
+        buf.append_with(2, |dst| {
+            dst.copy_from_slice(b"fg");
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+        buf.push(b'h');
+
+        let expected = Validation::new(b"abcdefgh");
+        assert_eq!(buf.validate(&expected).unwrap(), b"abcdefgh");
+
+        let mut buf = bps_ups::HashingWriter::new(0);
+        buf.write_all(b"abcdefgX").unwrap();
+        buf.push(b'h');
+        assert!(matches!(
+            buf.validate(&expected),
+            Err(Error::OutputMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn apply_at() {
+        let patch = UpsPatch::create(b"abcd", b"abXd");
+        assert_eq!(patch.apply_at(b"HDRabcdTAIL", 3).unwrap(), b"HDRabXdTAIL");
+        assert!(patch.apply_at(b"HDRabcdTAIL", 2).is_err());
+
+        let patch = IpsPatch::create(b"abcd", b"abXd");
+        assert_eq!(patch.apply_at(b"HDRabcdTAIL", 3).unwrap(), b"HDRabXdTAIL");
+        assert!(patch.apply_at(b"HDR", 4).is_err());
+    }
+
+    #[test]
+    fn size_policy() {
+        use SizePolicy::*;
+
+        let policies = [Exact, TruncateToTarget, ExtendWithByte(0xFF), KeepSource];
+        let check = |patch: &dyn Patch, rom: &[u8], expected: [Option<&[u8]>; 4]| {
+            for (policy, expected) in policies.into_iter().zip(expected) {
+                let output = patch.apply_sized(rom, policy);
+                match expected {
+                    Some(expected) => assert_eq!(output.unwrap(), expected, "{policy:?}"),
+                    None => assert!(matches!(output, Err(Error::InvalidSize(..))), "{policy:?}"),
+                }
+
+                // the same sizes when the policy is applied to an output after the fact
+                if let Ok(mut output) = patch.apply(rom) {
+                    match policy.resize(patch, rom, &mut output) {
+                        Ok(()) => assert_eq!(Some(&output[..]), expected, "{policy:?}"),
+                        Err(_) => assert!(expected.is_none(), "{policy:?}"),
+                    }
                 }
+            }
+        };
+
+        // an IPS patch without a size grows an undersized ROM to its last record
+        let mut patch = IpsPatch::load(b"PATCH\0\0\x04\0\x01!EOF").unwrap();
+        check(
+            &patch,
+            b"ab",
+            [
+                None,
+                Some(b"ab\0\0!"),
+                Some(b"ab\xFF\xFF!"),
+                Some(b"ab\0\0!"),
+            ],
+        );
+        check(&patch, b"abcdefgh", [Some(b"abcd!fgh"); 4]);
+
+        // with one, undersized ROMs are padded and oversized ones cut
+        patch.set_output_size(Some(6));
+        check(
+            &patch,
+            b"ab",
+            [
+                Some(b"ab\0\0!\0"),
+                Some(b"ab\0\0!\0"),
+                Some(b"ab\xFF\xFF!\xFF"),
+                Some(b"ab\0\0!\0"),
+            ],
+        );
+        check(
+            &patch,
+            b"abcdefgh",
+            [
+                Some(b"abcd!f"),
+                Some(b"abcd!f"),
+                Some(b"abcd!f"),
+                Some(b"abcd!fgh"),
+            ],
+        );
 
-                self.write_u8(x)?;
-                value -= 1;
+        // a patch that records its source rejects other sizes under every policy, and the bytes
+        // it writes past the source aren't filled
+        let patch = UpsPatch::create(b"abcd", b"abX");
+        check(
+            &patch,
+            b"abcd",
+            [Some(b"abX"), Some(b"abX"), Some(b"abX"), Some(b"abXd")],
+        );
+        let patch = BpsPatch::create(b"ab", b"ab\0d");
+        check(&patch, b"ab", [Some(b"ab\0d"); 4]);
+
+        // exact sizing rejects the ROM up front, and an output forced from the wrong ROM after
+        // the fact
+        for patch in [
+            Box::new(UpsPatch::create(b"abcd", b"abcd")) as Box<dyn Patch>,
+            Box::new(BpsPatch::create(b"abcd", b"abcd")),
+        ] {
+            assert!(matches!(
+                Exact.output_size(&*patch, 6),
+                Err(Error::InvalidSize(6, 4))
+            ));
+            assert_eq!(TruncateToTarget.output_size(&*patch, 6).unwrap(), 4);
+            let mut output = b"abcd".to_vec();
+            assert!(Exact.resize(&*patch, b"abcdef", &mut output).is_err());
+            TruncateToTarget
+                .resize(&*patch, b"abcdef", &mut output)
+                .unwrap();
+        }
+        for rom in [&b"a"[..], b"abcdef"] {
+            for policy in policies {
+                assert!(patch.apply_sized(rom, policy).is_err(), "{policy:?}");
             }
         }
     }
 
-    impl<T: Write + ?Sized> WriteVarExt for T {}
+    #[test]
+    fn load_by_extension() {
+        let script = b"[[patch]]\noffset = 2\ntext = \"hi\"\n";
+        assert_eq!(load_named(script, "hack.toml").unwrap().format(), "IPS");
+        assert!(matches!(
+            load_named(b"PATCHEOF", "hack.BPS"),
+            Err(Error::Magic(_))
+        ));
+        assert_eq!(load_named(b"PATCHEOF", "hack.bin").unwrap().format(), "IPS");
+    }
 }
@@ -0,0 +1,146 @@
+//! Structural diagnostics for patch files, for curating patch archives. A patch with lints still
+//! loads and applies correctly, but was written by a sloppy or broken tool, or has been tampered
+//! with since.
+//!
+//! ```
+//! let lints = patch_rs::lint::lint(b"PATCH\0\0\x01\0\0\0\0\x2AEOF")?;
+//! assert_eq!(lints, [patch_rs::lint::Lint::ZeroLength { record: 0 }]);
+//! # Ok::<(), patch_rs::Error>(())
+//! ```
+
+use std::fmt;
+
+use crate::{prelude::*, Format, Result};
+
+/// Declared sizes above this are reported as [`Lint::HugeSize`]. No cartridge or disc image comes
+/// close.
+pub const HUGE_SIZE: u64 = 1 << 32;
+
+/// A structural oddity in a patch file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// The variable-length integer at this offset in the file isn't encoded the one canonical
+    /// way, e.g. a BPS copy offset of negative zero.
+    NonCanonicalVarInt { offset: usize },
+    /// The record at this index has no effect: an IPS fill of no bytes, or a UPS record that
+    /// only skips ahead.
+    ZeroLength { record: usize },
+    /// The record at this index writes the bytes that are already there: an IPS record repeating
+    /// what earlier records wrote, or a BPS copy from the same offset in the source.
+    IdenticalRewrite { record: usize },
+    /// Bytes after the end of the patch that nothing reads, e.g. after an IPS end marker.
+    TrailingBytes { offset: usize, len: usize },
+    /// The patch declares a source or target larger than [`HUGE_SIZE`].
+    HugeSize { what: &'static str, size: usize },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::NonCanonicalVarInt { offset } => {
+                write!(f, "non-canonical variable-length integer at {offset:#X}")
+            }
+            Lint::ZeroLength { record } => write!(f, "record {record} has no effect"),
+            Lint::IdenticalRewrite { record } => {
+                write!(f, "record {record} rewrites the bytes already there")
+            }
+            Lint::TrailingBytes { offset, len } => {
+                write!(f, "{len} unreachable bytes at {offset:#X}")
+            }
+            Lint::HugeSize { what, size } => write!(f, "declared {what} size {size:#X} is huge"),
+        }
+    }
+}
+
+/// Loads the patch in `data`, detecting its format like [`load`](crate::load), and returns
+/// everything odd about how it's written. Fails if the patch doesn't load.
+pub fn lint(data: &[u8]) -> Result<Vec<Lint>> {
+    match crate::detect(data)? {
+        Format::Ips => IpsPatch::lint(data),
+        Format::Ups => UpsPatch::lint(data),
+        Format::Bps => BpsPatch::lint(data),
+    }
+}
+
+/// Reports `size` if it's larger than [`HUGE_SIZE`].
+pub(crate) fn check_size(lints: &mut Vec<Lint>, what: &'static str, size: usize) {
+    if size as u64 > HUGE_SIZE {
+        lints.push(Lint::HugeSize { what, size });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{self, WriteVarExt};
+
+    #[test]
+    fn ips() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog.";
+        let clean = IpsPatch::create(src, dst).export(None).unwrap();
+        assert_eq!(lint(&clean).unwrap(), []);
+
+        let data = b"PATCH\0\0\x01\0\x02ab\0\0\x02\0\x01b\0\0\x05\0\0\0\0xEOF\0\0\x10junk";
+        assert_eq!(
+            lint(data).unwrap(),
+            [
+                Lint::IdenticalRewrite { record: 1 },
+                Lint::ZeroLength { record: 2 },
+                Lint::TrailingBytes {
+                    offset: 0x1D,
+                    len: 7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn beat() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog.";
+        for patch in [
+            Box::new(UpsPatch::create(src, dst)) as Box<dyn Patch>,
+            Box::new(BpsPatch::create(src, dst)),
+        ] {
+            assert_eq!(lint(&patch.export(None).unwrap()).unwrap(), []);
+        }
+
+        // copies of the source from offset -0 to the same place, then from -4 after it
+        let mut data = b"BPS1".to_vec();
+        data.write_var_int(4).unwrap();
+        data.write_var_int(8).unwrap();
+        data.write_var_int(0).unwrap();
+        for offset in [1, 9] {
+            data.write_var_int((3 << 2) | 2).unwrap();
+            data.write_var_int(offset).unwrap();
+        }
+        let crc = crc32fast::hash(b"abcd");
+        let out = crc32fast::hash(b"abcdabcd");
+        encoding::write_footer(&mut data, crc, out);
+        assert_eq!(
+            lint(&data).unwrap(),
+            [
+                Lint::NonCanonicalVarInt { offset: 8 },
+                Lint::IdenticalRewrite { record: 0 },
+            ]
+        );
+
+        let mut data = b"UPS1".to_vec();
+        let huge = HUGE_SIZE as usize + 1;
+        data.write_var_int(huge).unwrap();
+        data.write_var_int(4).unwrap();
+        data.extend_from_slice(b"\x82\x00\x80\x01\x00");
+        encoding::write_footer(&mut data, 0, 0);
+        assert_eq!(
+            lint(&data).unwrap(),
+            [
+                Lint::HugeSize {
+                    what: "source",
+                    size: huge
+                },
+                Lint::ZeroLength { record: 0 },
+            ]
+        );
+    }
+}
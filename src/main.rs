@@ -1,62 +1,1329 @@
-use anyhow::{bail, Result};
-use clap::Parser;
-use patch_rs::prelude::*;
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use patch_rs::{
+    cue::Disc,
+    fixup, hash,
+    header::{apply_with_header, explain_mismatch, HeaderKind, HeaderPolicy, RomChecksums},
+    n64::{self, ByteOrder},
+    prelude::*,
+    sidecar::Sidecar,
+    smd,
+};
 use std::{
-    ffi::OsStr,
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
     fs,
+    io::{self, IsTerminal, Write},
     path::{Path, PathBuf},
+    process::{self, ExitCode},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+use thiserror::Error;
+
+mod cli;
+
+use cli::{
+    config::Config,
+    log::{self, notice, status, Level},
 };
 
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Other failure, including batch runs where only some files could be patched
+  2  Invalid arguments
+  3  The patch file is unsupported, invalid, or corrupt
+  4  The ROM doesn't match the patch's expected source
+  5  The patched output doesn't match the patch's expected result
+  6  I/O error
+
+Defaults for some options are read from ~/.config/patch-rs/config.toml.";
+
+#[derive(Debug, Error)]
+enum Failure {
+    #[error("Patch file is unsupported.")]
+    Unsupported,
+    #[error("Couldn't load patch '{0}'.")]
+    InvalidPatch(PathBuf, #[source] patch_rs::Error),
+    #[error("'{0}' is the wrong base ROM for this patch.")]
+    WrongRom(PathBuf, #[source] patch_rs::Error),
+    #[error("The patched output's {0} ({1}) does not match the expected value ({2}).")]
+    UnexpectedHash(&'static str, String, String),
+    #[error("Neither the ROM nor the patched output match any checksum in '{0}'.")]
+    SidecarMismatch(PathBuf),
+    /// Arguments that parsed but can't be used together, reported like clap's own errors.
+    #[error("{0}")]
+    Usage(String),
+}
+
+fn exit_code(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return match failure {
+                Failure::Usage(_) => 2,
+                Failure::Unsupported | Failure::InvalidPatch(..) => 3,
+                Failure::UnexpectedHash(..) => 5,
+                Failure::SidecarMismatch(_) | Failure::WrongRom(..) => 4,
+            };
+        } else if let Some(err) = cause.downcast_ref::<patch_rs::Error>() {
+            return match err {
+                patch_rs::Error::Magic(_)
+                | patch_rs::Error::InvalidPatch
+                | patch_rs::Error::CorruptPatch(..)
+                | patch_rs::Error::RecordOverrun { .. }
+                | patch_rs::Error::SourceOverrun { .. }
+                | patch_rs::Error::SourceOutOfRange { .. }
+                | patch_rs::Error::TargetOutOfRange { .. }
+                | patch_rs::Error::IncompleteOutput(..)
+                | patch_rs::Error::Unsupported
+                | patch_rs::Error::UnsupportedFormat(_) => 3,
+                patch_rs::Error::InvalidSize(..)
+                | patch_rs::Error::InvalidCRC(..)
+                | patch_rs::Error::InvalidHash(..)
+                | patch_rs::Error::HeaderMismatch { .. } => 4,
+                patch_rs::Error::OutputMismatch(_) => 5,
+                patch_rs::Error::Io(_) => 6,
+                _ => 1,
+            };
+        } else if cause.is::<io::Error>() {
+            return 6;
+        }
+    }
+
+    1
+}
+
 #[derive(Parser)]
+#[command(version, about, after_help = EXIT_CODES)]
 struct Arguments {
-    /// The ROM file to patch
+    #[command(subcommand)]
+    command: Command,
+    /// Only print errors and the output of commands like info and dump
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print a line of key=value fields for each file processed, or with -vv for each step
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Skip up to 1 KiB of junk before a patch's magic value, like a byte order mark or mail
+    /// headers
+    #[arg(long, global = true)]
+    resync: bool,
+    /// Show the progress of applying and creating patches. On by default when stderr is a
+    /// terminal
+    #[arg(long, global = true, overrides_with = "no_progress")]
+    progress: bool,
+    /// Don't show progress
+    #[arg(long, global = true, overrides_with = "progress")]
+    no_progress: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply a patch to one or more ROM files
+    Apply(ApplyArgs),
+    /// Pair each patch in a directory with the ROM whose checksum matches its expected source,
+    /// and apply them all
+    Match(MatchArgs),
+    /// Find the patch next to a ROM, by name or by source checksum, and apply it
+    Auto(cli::auto::AutoArgs),
+    /// Check whether two patches produce the same output, and list the ranges where they don't
+    Compare(cli::compare::CompareArgs),
+    /// Print a hexdump of the regions that differ between two files
+    Diff(cli::diff::DiffArgs),
+    /// Print every record in a patch
+    Dump(cli::dump::DumpArgs),
+    /// Browse a patch's records interactively
+    #[cfg(feature = "tui")]
+    Inspect(cli::inspect::InspectArgs),
+    /// Delete or move records in an IPS patch
+    Edit(cli::edit::EditArgs),
+    /// Create a patch from an original and a modified file
+    Create(cli::create::CreateArgs),
+    /// Compile a TOML patch script into a patch
+    Build(cli::build::BuildArgs),
+    /// Print the checksums of files, both with and without any detected header
+    Hash(cli::hash::HashArgs),
+    /// Convert a patch to its JSON representation, or compile one back to binary
+    Json(cli::json::JsonArgs),
+    /// Print a patch's checksums, metadata, and statistics about its records
+    Info(cli::info::InfoArgs),
+    /// List the regions of the output a patch modifies
+    Map(cli::map::MapArgs),
+    /// Remove a patch's metadata and re-encode its records as compactly as possible
+    Minify(cli::minify::MinifyArgs),
+    /// Regenerate a patch whenever the modified file changes
+    Watch(cli::watch::WatchArgs),
+    /// Serve a directory of patches over HTTP, applying them to uploaded ROMs
+    #[cfg(feature = "serve")]
+    Serve(cli::serve::ServeArgs),
+    /// Identify ROMs using a No-Intro/clrmamepro DAT file
+    #[cfg(feature = "dat")]
+    Verify(cli::verify::VerifyArgs),
+    /// Print a shell completion script
+    Completions(cli::completions::CompletionsArgs),
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// The ROM file to patch. May be a directory or a wildcard pattern such as "roms/*.gba"
     rom: PathBuf,
     /// The patch file (Supported formats are UPS, BPS, and IPS)
     patch: PathBuf,
     /// The output file
     dest: Option<PathBuf>,
+    /// The directory to write patched files to. Required when patching multiple ROMs
+    #[arg(long, conflicts_with = "dest")]
+    dest_dir: Option<PathBuf>,
+    /// Name each output after a template such as "{rom_stem} ({patch_stem}).{rom_ext}", placed in
+    /// --dest-dir or next to the ROM. Also accepts {patch_ext}
+    #[arg(long, value_name = "PATTERN", value_parser = parse_dest_pattern, conflicts_with = "dest")]
+    dest_pattern: Option<String>,
+    /// Overwrite the ROM with the patched output
+    #[arg(long, conflicts_with_all = ["dest", "dest_dir", "dest_pattern"])]
+    in_place: bool,
+    /// Keep a copy of the original ROM with the given suffix appended to its name
+    #[arg(
+        long,
+        requires = "in_place",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".bak"
+    )]
+    backup: Option<OsString>,
+    /// Force the size of the output file (IPS only)
+    #[arg(long, value_parser = parse_int)]
+    output_size: Option<usize>,
+    /// Truncate the output file to the end of the last record (IPS only)
+    #[arg(long, conflicts_with = "output_size")]
+    truncate: bool,
+    /// How to size the output when it differs from the ROM: exact, truncate (the default),
+    /// keep-source, or extend:BYTE to pad with BYTE instead of zeros
+    #[arg(long, value_name = "POLICY", value_parser = parse_size_policy, conflicts_with_all = ["header", "offset"])]
+    size_policy: Option<SizePolicy>,
+    /// Apply the patch to the part of the ROM starting at this offset, e.g. 512 to patch a
+    /// headered ROM or a file embedded in a larger container
+    #[arg(long, value_name = "BYTES", value_parser = parse_int, conflicts_with = "header")]
+    offset: Option<usize>,
+    /// The ROM has a header (SNES copier or iNES) the patch doesn't expect
+    #[arg(long, group = "header")]
+    skip_header: bool,
+    /// The ROM is missing a header (SNES copier or iNES) the patch expects
+    #[arg(long, group = "header")]
+    add_header: bool,
+    /// Detect whether the ROM's header needs to be skipped or added
+    #[arg(long, group = "header")]
+    auto_header: bool,
+    /// Convert byteswapped (.v64) or little-endian (.n64) N64 ROMs to big-endian before patching
+    #[arg(long)]
+    normalize_n64: bool,
+    /// Convert the patched output back to the N64 ROM's original byte order
+    #[arg(long, requires = "normalize_n64")]
+    restore_n64: bool,
+    /// Convert interleaved Genesis/Mega Drive .smd dumps to the plain layout before patching
+    #[arg(long)]
+    deinterleave_smd: bool,
+    /// Convert the patched output back to the interleaved .smd layout
+    #[arg(long, requires = "deinterleave_smd")]
+    reinterleave_smd: bool,
+    /// Pad or trim the ROM to the size the patch expects, if that makes it match
+    #[arg(long)]
+    fit_size: bool,
+    /// Recompute the internal header checksum of the patched output
+    #[arg(long, value_name = "PLATFORM")]
+    fix_checksum: Option<Platform>,
+    /// Report the DAT entries matching the ROM and the patched output
+    #[cfg(feature = "dat")]
+    #[arg(long)]
+    dat: Option<PathBuf>,
+    /// Fail unless the patched output has this CRC32
+    #[arg(long, value_name = "HASH", value_parser = parse_hash::<4>)]
+    expect_crc32: Option<String>,
+    /// Fail unless the patched output has this SHA-1
+    #[arg(long, value_name = "HASH", value_parser = parse_hash::<20>)]
+    expect_sha1: Option<String>,
+    /// Don't verify against a .sfv/.md5/.sha1/.sha256 file next to the patch
+    #[arg(long)]
+    no_sidecar: bool,
+    /// Run a command after each ROM is patched, e.g. "retroarch {out}". The command is split on
+    /// whitespace, and {out} is replaced with the path of the patched file
+    #[arg(long, value_name = "COMMAND")]
+    exec: Option<String>,
+    /// Patch up to N ROMs at once when patching multiple ROMs, or one per core if N is 0
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Platform {
+    Snes,
+    Genesis,
+    Gb,
+    Gba,
+}
+
+impl Platform {
+    fn fix_checksum(self, rom: &mut [u8]) -> patch_rs::Result<()> {
+        match self {
+            Platform::Snes => {
+                let start = HeaderKind::Snes.is_present(rom) as usize * HeaderKind::Snes.size();
+                fixup::snes(&mut rom[start..])?;
+            }
+            Platform::Genesis => _ = fixup::genesis(rom)?,
+            Platform::Gb => _ = fixup::game_boy(rom)?,
+            Platform::Gba => _ = fixup::gba(rom)?,
+        }
+
+        Ok(())
+    }
 }
 
+#[derive(Args)]
+struct MatchArgs {
+    /// The directory containing the ROM files
+    roms: PathBuf,
+    /// The directory containing the patch files
+    patches: PathBuf,
+    /// The directory to write patched files to
+    #[arg(long)]
+    dest_dir: PathBuf,
+    /// Name each output after a template, as in `apply --dest-pattern`
+    #[arg(long, value_name = "PATTERN", value_parser = parse_dest_pattern)]
+    dest_pattern: Option<String>,
+    /// Apply up to N patches at once, or one per core if N is 0
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+}
+
+/// Parses an `N` byte hash written in hexadecimal, normalized to lowercase.
+fn parse_hash<const N: usize>(s: &str) -> Result<String, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s).to_ascii_lowercase();
+    if hex.len() != N * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("expected {} hexadecimal digits", N * 2));
+    }
+
+    Ok(hex)
+}
+
+/// Parses a decimal integer, or a hexadecimal integer prefixed with `0x`.
+fn parse_int(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|err| err.to_string())
+}
+
+fn parse_size_policy(s: &str) -> Result<SizePolicy, String> {
+    match s {
+        "exact" => Ok(SizePolicy::Exact),
+        "truncate" => Ok(SizePolicy::TruncateToTarget),
+        "keep-source" => Ok(SizePolicy::KeepSource),
+        _ => {
+            let byte = s.strip_prefix("extend:").ok_or_else(|| {
+                "expected exact, truncate, keep-source, or extend:BYTE".to_string()
+            })?;
+            u8::try_from(parse_int(byte)?)
+                .map(SizePolicy::ExtendWithByte)
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// Replaces each `@FILE` argument with the lines of FILE, one argument per line, so batches too
+/// long for the command line can be generated. Lines are trimmed and blank ones skipped, a line
+/// wrapped in double quotes is taken literally, and other lines may name further response files.
+/// An argument naming a file that doesn't exist is kept as is.
+fn expand_response_files(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        expand_response_file(arg, &mut expanded, &mut Vec::new())?;
+    }
+    Ok(expanded)
+}
+
+/// Expands `arg` into `expanded`. `open` holds the response files currently being read, so one
+/// that includes itself is an error instead of endless recursion.
+fn expand_response_file(
+    arg: OsString,
+    expanded: &mut Vec<OsString>,
+    open: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let Some(path) = arg
+        .to_str()
+        .and_then(|arg| arg.strip_prefix('@'))
+        .map(Path::new)
+        .filter(|path| path.is_file())
+    else {
+        expanded.push(arg);
+        return Ok(());
+    };
+
+    let context = || format!("Couldn't read response file '{}'", path.display());
+    let canonical = fs::canonicalize(path).with_context(context)?;
+    if open.contains(&canonical) {
+        bail!("Response file '{}' includes itself.", path.display());
+    }
+    let text = fs::read_to_string(path).with_context(context)?;
+    open.push(canonical);
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line
+            .strip_prefix('"')
+            .and_then(|line| line.strip_suffix('"'))
+        {
+            Some(quoted) => expanded.push(quoted.into()),
+            None => expand_response_file(line.into(), expanded, open)?,
+        }
+    }
+    open.pop();
+    Ok(())
+}
+
+/// Reads a patch file, downloading it first if `path` is an `https://` URL.
+fn read_patch(path: &Path) -> Result<Vec<u8>> {
+    match path.to_str().and_then(|s| s.strip_prefix("https://")) {
+        #[cfg(feature = "http")]
+        Some(_) => cli::http::download(path.to_str().unwrap()),
+        #[cfg(not(feature = "http"))]
+        Some(_) => bail!("Downloading patches requires the 'http' feature."),
+        None => Ok(fs::read(path)?),
+    }
+}
+
+/// Set by `--resync`.
+static RESYNC: AtomicBool = AtomicBool::new(false);
+
+/// Loads a patch as in [`load_patches`], failing if the file holds several.
 fn load_patch(path: impl AsRef<Path>) -> Result<Box<dyn Patch>> {
-    macro_rules! box_inner {
-        ($e: expr) => {
-            $e.map(|patch| Box::new(patch) as Box<dyn Patch>)
+    let path = path.as_ref();
+    let mut patches = load_patches(path)?;
+    if patches.len() > 1 {
+        bail!(
+            "'{}' holds {} patches back to back, which only apply supports",
+            path.display(),
+            patches.len()
+        );
+    }
+    Ok(patches.remove(0))
+}
+
+/// Loads the patches in a file, extracting it first if it's a 7z archive. Most files hold one,
+/// but some distribution scripts write several back to back.
+#[cfg_attr(not(feature = "sevenz"), allow(unused_mut))]
+fn load_patches(path: &Path) -> Result<PatchSet> {
+    let mut data = read_patch(path)?;
+    let mut name = path.to_owned();
+    #[cfg(feature = "sevenz")]
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("7z"))
+    {
+        use patch_rs::sevenz::{SevenZipArchive, PATCH_EXTENSIONS};
+
+        let archive = SevenZipArchive::new(&data)?;
+        let entry = archive.select(PATCH_EXTENSIONS)?;
+        name = path.join(&entry.name);
+        data = archive.read(entry)?;
+    }
+
+    let is_source = name
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml") || ext.eq_ignore_ascii_case("json"));
+    let concatenated =
+        !is_source && patch_rs::set::split(&data).is_ok_and(|patches| patches.len() > 1);
+    let loaded = if concatenated {
+        PatchSet::load(&data)
+    } else if RESYNC.load(Ordering::Relaxed) && !is_source {
+        patch_rs::load_resync(&data, patch_rs::RESYNC_LIMIT).map(|(patch, skipped)| {
+            if skipped != 0 {
+                notice!(
+                    "note: {}: skipped {skipped} bytes before the patch",
+                    name.display()
+                );
+            }
+            PatchSet::from_iter([patch])
+        })
+    } else {
+        patch_rs::load_named(&data, &name).map(|patch| PatchSet::from_iter([patch]))
+    };
+    loaded.map_err(|err| match err {
+        patch_rs::Error::Unsupported => Failure::Unsupported.into(),
+        err => Failure::InvalidPatch(name, err).into(),
+    })
+}
+
+/// Reads a ROM, extracting it first if it's a zip archive. Returns the name of the extracted file
+/// along with its contents.
+#[cfg_attr(not(feature = "zip"), allow(unused_variables))]
+fn read_rom(rom: &Path, patch: &dyn Patch) -> Result<(Vec<u8>, Option<PathBuf>)> {
+    let data = fs::read(rom)?;
+    #[cfg(feature = "zip")]
+    if rom
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        use patch_rs::zip::{ZipArchive, ROM_EXTENSIONS};
+
+        let archive = ZipArchive::new(&data)?;
+        let entry = archive.select(Some(patch), ROM_EXTENSIONS)?;
+        return Ok((archive.read(entry)?, Some(PathBuf::from(&entry.name))));
+    }
+
+    Ok((data, None))
+}
+
+fn default_dest(rom: &Path, patch: &Path) -> PathBuf {
+    rom.with_file_name(patch.file_stem().unwrap())
+        .with_extension(rom.extension().unwrap_or(OsStr::new("out")))
+}
+
+/// Expands the `{rom_stem}`, `{rom_ext}`, `{patch_stem}`, and `{patch_ext}` placeholders in a
+/// `--dest-pattern`. A ROM without an extension uses `out`, as in [`default_dest`].
+fn expand_dest_pattern(pattern: &str, rom: &Path, patch: &Path) -> Result<PathBuf, String> {
+    let part = |part: Option<&OsStr>| part.map(|s| s.to_string_lossy().into_owned());
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            return Err("unclosed '{'".to_string());
         };
+        out.push_str(&match &rest[start + 1..start + len] {
+            "rom_stem" => part(rom.file_stem()).unwrap_or_default(),
+            "rom_ext" => part(rom.extension()).unwrap_or_else(|| "out".into()),
+            "patch_stem" => part(patch.file_stem()).unwrap_or_default(),
+            "patch_ext" => part(patch.extension()).unwrap_or_default(),
+            name => {
+                return Err(format!(
+                    "unknown placeholder '{{{name}}}', expected rom_stem, rom_ext, patch_stem, or \
+                     patch_ext"
+                ))
+            }
+        });
+        rest = &rest[start + len + 1..];
     }
+    out.push_str(rest);
+    Ok(PathBuf::from(out))
+}
 
-    let path = path.as_ref();
-    let data = fs::read(path)?;
-    match path.extension().and_then(|s| s.to_str()) {
-        Some("ips") => Ok(box_inner!(IpsPatch::load(&data))?),
-        Some("ups") => Ok(box_inner!(UpsPatch::load(&data))?),
-        Some("bps") => Ok(box_inner!(BpsPatch::load(&data))?),
-        _ => {
-            if let Ok(patch) = IpsPatch::load(&data) {
-                Ok(Box::new(patch))
-            } else if let Ok(patch) = UpsPatch::load(&data) {
-                Ok(Box::new(patch))
-            } else if let Ok(patch) = BpsPatch::load(&data) {
-                Ok(Box::new(patch))
-            } else {
-                bail!("Patch file is unsupported.");
+fn parse_dest_pattern(s: &str) -> Result<String, String> {
+    expand_dest_pattern(s, Path::new(""), Path::new("")).map(|_| s.to_string())
+}
+
+/// Calls `f` on every item using up to `jobs` threads, or one per core if `jobs` is 0, and
+/// returns the results in the same order as the items.
+fn run_jobs<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, usize::from),
+        jobs => jobs,
+    };
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(items.iter().map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+/// Fails if two ROMs in a batch would be written to the same file, before anything is written.
+fn check_collisions(roms: &[PathBuf], dests: &[PathBuf]) -> Result<()> {
+    let mut seen = HashMap::new();
+    for (rom, dest) in roms.iter().zip(dests) {
+        if let Some(other) = seen.insert(dest, rom) {
+            bail!(
+                "'{}' and '{}' would both be written to '{}'.",
+                other.display(),
+                rom.display(),
+                dest.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Matches `name` against a pattern containing `*` and `?` wildcards.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            wildcard_match(rest, name) || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => wildcard_match(rest, name),
+        (Some((p, rest)), Some((n, name))) => p == n && wildcard_match(rest, name),
+        _ => false,
+    }
+}
+
+/// Expands `rom` into the list of files it refers to. A directory yields every file inside it,
+/// and a final path component containing wildcards yields every matching file.
+fn expand_roms(rom: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let (dir, pattern) = if rom.is_dir() {
+        (rom, "*")
+    } else {
+        match rom.file_name().and_then(|s| s.to_str()) {
+            Some(name) if name.contains(['*', '?']) => (
+                rom.parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new(".")),
+                name,
+            ),
+            _ => return Ok(None),
+        }
+    };
+
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Couldn't read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .is_some_and(|name| wildcard_match(pattern.as_bytes(), name.as_bytes()))
+        {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    Ok(Some(roms))
+}
+
+/// Writes `data` to a temporary file next to `dest` and renames it into place, so a failed write
+/// never leaves a partially written file behind.
+fn write_atomic(dest: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    if let Err(err) = fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, dest)) {
+        _ = fs::remove_file(&tmp);
+        return Err(err).with_context(|| format!("Couldn't write {}", dest.display()));
+    }
+
+    Ok(())
+}
+
+/// Runs `command`, split on whitespace, with `{out}` in each argument replaced by `out`.
+fn run_exec(command: &str, out: &Path) -> Result<()> {
+    let out = out.to_string_lossy();
+    let mut words = command.split_whitespace().map(|word| word.replace("{out}", &out));
+    let Some(program) = words.next() else {
+        return Err(Failure::Usage("--exec needs a command to run".into()).into());
+    };
+
+    let status = process::Command::new(&program)
+        .args(words)
+        .status()
+        .with_context(|| format!("Couldn't run '{program}'"))?;
+    if !status.success() {
+        bail!("'{program}' exited with {status}");
+    }
+
+    Ok(())
+}
+
+enum Recovery {
+    Force,
+    SkipHeader,
+    Abort,
+}
+
+/// Reports a ROM that doesn't match the patch's expected source as the wrong base ROM. Patch files
+/// are checked against their own CRC when they're loaded, so a corrupt download fails before this
+/// with [`patch_rs::Error::CorruptPatch`] instead.
+fn wrong_rom(rom: &Path, err: patch_rs::Error) -> anyhow::Error {
+    match err {
+        patch_rs::Error::InvalidSize(..)
+        | patch_rs::Error::InvalidCRC(..)
+        | patch_rs::Error::InvalidHash(..)
+        | patch_rs::Error::HeaderMismatch { .. } => Failure::WrongRom(rom.to_owned(), err).into(),
+        err => err.into(),
+    }
+}
+
+/// Asks the user how to proceed after `rom` failed validation. Always aborts when stdin isn't a
+/// terminal.
+fn prompt_recovery(rom: &Path, err: &patch_rs::Error, kind: HeaderKind) -> Result<Recovery> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(Recovery::Abort);
+    }
+
+    eprintln!("{}: {err}", rom.display());
+    loop {
+        eprint!(
+            "[a]pply anyway, try with a {}-byte header [s]kipped, or a[b]ort? ",
+            kind.size()
+        );
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(Recovery::Abort);
+        }
+
+        match line.trim() {
+            "a" => return Ok(Recovery::Force),
+            "s" => return Ok(Recovery::SkipHeader),
+            "b" | "" => return Ok(Recovery::Abort),
+            _ => {}
+        }
+    }
+}
+
+fn apply(mut args: ApplyArgs, config: &Config) -> Result<()> {
+    if args.dest.is_none() && args.dest_dir.is_none() && !args.in_place {
+        args.dest_dir = config.dest_dir.clone();
+    }
+
+    // in a file of several patches, the earlier ones are applied first and the last is handled
+    // like any other patch
+    let mut earlier = load_patches(&args.patch)?;
+    earlier.validate_chain()?;
+    let mut patch = earlier.remove(earlier.len() - 1);
+    if args.output_size.is_some() || args.truncate {
+        if patch.format() != "IPS" {
+            return Err(Failure::Usage(
+                "--output-size and --truncate can only be used with IPS patches".into(),
+            )
+            .into());
+        }
+        // reload the patch as the concrete type, since only IPS records the output size
+        let mut ips = IpsPatch::load(&patch.export(None)?)?;
+        ips.set_output_size(Some(args.output_size.unwrap_or_else(|| ips.records_end())));
+        patch = Box::new(ips);
+    }
+    log::fields(
+        Level::Debug,
+        "load",
+        &[
+            ("patch", &args.patch.display()),
+            ("format", &patch.format()),
+            ("records", &patch.records().len()),
+        ],
+    );
+    if patch.is_noop() {
+        notice!(
+            "warning: {}: the patch doesn't change anything, so it may not be the one you meant",
+            args.patch.display()
+        );
+    }
+    if let Some(dir) = &args.dest_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let header = if args.skip_header {
+        HeaderPolicy::Skip
+    } else if args.add_header {
+        HeaderPolicy::Add
+    } else if args.auto_header {
+        HeaderPolicy::Auto
+    } else {
+        config.header
+    };
+
+    #[cfg(feature = "dat")]
+    let dat = args.dat.as_ref().map(cli::verify::load_dat).transpose()?;
+
+    let sidecar = match Sidecar::find(&args.patch).filter(|_| !args.no_sidecar) {
+        Some(path) => {
+            let sidecar = Sidecar::load(&path)
+                .with_context(|| format!("Couldn't load '{}'", path.display()))?;
+            Some((path, sidecar))
+        }
+        None => None,
+    };
+
+    let patch_rom = |rom: &Path, mut dest: PathBuf| -> Result<PathBuf> {
+        let start = Instant::now();
+        let is_cue = rom
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+        if is_cue && args.backup.is_some() {
+            return Err(Failure::Usage(format!(
+                "{}: --backup isn't supported for cue sheets",
+                rom.display()
+            ))
+            .into());
+        }
+
+        let (disc, (mut data, entry)) = if is_cue {
+            let (disc, image) = Disc::open(rom)?;
+            (Some(disc), (image, None))
+        } else {
+            (None, read_rom(rom, &*patch)?)
+        };
+        log::fields(
+            Level::Debug,
+            "read",
+            &[
+                ("rom", &rom.display()),
+                ("size", &data.len()),
+                ("crc", &log::crc(&data)),
+            ],
+        );
+        let rom = match &entry {
+            Some(entry) if args.in_place => bail!(
+                "{}: can't patch {} in place inside a zip archive",
+                rom.display(),
+                entry.display()
+            ),
+            Some(entry) => {
+                if let Some(ext) = entry.extension() {
+                    dest.set_extension(ext);
+                }
+                entry.as_path()
+            }
+            None => rom,
+        };
+        let order = args
+            .normalize_n64
+            .then(|| ByteOrder::detect(&data))
+            .flatten();
+        if let Some(order) = order {
+            n64::convert(&mut data, order, ByteOrder::BigEndian);
+        }
+
+        let smd_header = (args.deinterleave_smd && smd::is_smd(&data)).then(|| {
+            let header = data[..512].to_vec();
+            data = smd::deinterleave(&data);
+            header
+        });
+
+        for earlier in earlier.patches() {
+            data = earlier.apply(&data).map_err(|err| wrong_rom(rom, err))?;
+        }
+
+        if args.fit_size {
+            if let Some(fitted) = patch_rs::rom::fit_to_source(&*patch, &data) {
+                data = fitted;
+            }
+        }
+
+        let kind =
+            HeaderKind::detect(&data).unwrap_or(match rom.extension().and_then(|s| s.to_str()) {
+                Some("nes") => HeaderKind::Ines,
+                _ => HeaderKind::Snes,
+            });
+        let mut sized = false;
+        let mut output = match args.offset {
+            Some(bias) => patch
+                .apply_at(&data, bias)
+                .map_err(|err| wrong_rom(rom, err))?,
+            None => match patch.validate(&data) {
+                Some(Err(err)) if header == HeaderPolicy::Keep => {
+                    for mismatch in patch.validate_full(&data).unwrap_or_default() {
+                        notice!("note: {}: {mismatch}", rom.display());
+                    }
+
+                    let checksums = RomChecksums::new(&data);
+                    if let Some((kind, headerless)) = checksums.headerless {
+                        notice!(
+                            "note: {}: CRC32 {:08X}, or {:08X} without its {kind} header",
+                            rom.display(),
+                            checksums.full.crc,
+                            headerless.crc
+                        );
+                    }
+
+                    let err = explain_mismatch(&*patch, &data, err);
+                    let kind = match &err {
+                        patch_rs::Error::HeaderMismatch { kind, policy, .. } => {
+                            notice!(
+                                "hint: {}: retry with {}",
+                                rom.display(),
+                                match policy {
+                                    HeaderPolicy::Skip => "--skip-header",
+                                    _ => "--add-header",
+                                }
+                            );
+                            *kind
+                        }
+                        _ => kind,
+                    };
+
+                    let recovery = match args.jobs {
+                        1 => prompt_recovery(rom, &err, kind)?,
+                        // prompts from several workers would interleave
+                        _ => Recovery::Abort,
+                    };
+                    match recovery {
+                        Recovery::Abort => return Err(wrong_rom(rom, err)),
+                        Recovery::Force => patch.apply_unchecked(&data)?,
+                        Recovery::SkipHeader => {
+                            apply_with_header(&*patch, &data, kind, HeaderPolicy::Skip)?
+                        }
+                    }
+                }
+                _ => match args.size_policy {
+                    Some(policy) if header == HeaderPolicy::Keep => {
+                        sized = true;
+                        patch.apply_sized(&data, policy)
+                    }
+                    // progress from several workers would interleave
+                    None if header == HeaderPolicy::Keep && args.jobs == 1 => {
+                        patch.apply_observed(&data, &mut log::Progress::new("Applying"))
+                    }
+                    _ => apply_with_header(&*patch, &data, kind, header),
+                }
+                .map_err(|err| wrong_rom(rom, err))?,
+            },
+        };
+
+        // an output that wasn't sized as it was applied, after a forced or headered apply, is
+        // adjusted instead
+        if let Some(policy) = args.size_policy.filter(|_| !sized) {
+            policy.resize(&*patch, &data, &mut output)?;
+        }
+
+        if let Some(platform) = args.fix_checksum {
+            platform.fix_checksum(&mut output)?;
+        }
+
+        if let Some(order) = order.filter(|_| args.restore_n64) {
+            n64::convert(&mut output, ByteOrder::BigEndian, order);
+        }
+
+        if let Some(header) = smd_header.filter(|_| args.reinterleave_smd) {
+            output = smd::interleave(&output, Some(&header));
+        }
+
+        #[cfg(feature = "dat")]
+        if let Some(dat) = &dat {
+            cli::verify::report(dat, &rom.display().to_string(), &data);
+            cli::verify::report(dat, &dest.display().to_string(), &output);
+        }
+
+        // only hash the output for the digests that were asked for
+        type Digest = fn(&[u8]) -> String;
+        let expected: [(_, _, Digest); 2] = [
+            ("CRC32", &args.expect_crc32, |data| {
+                hash::to_hex(&hash::crc32(data).to_be_bytes())
+            }),
+            ("SHA-1", &args.expect_sha1, |data| {
+                hash::to_hex(&hash::sha1(data))
+            }),
+        ];
+        for (name, expected, digest) in expected {
+            let Some(expected) = expected else {
+                continue;
+            };
+            let actual = digest(&output);
+            if *expected != actual {
+                return Err(Failure::UnexpectedHash(name, actual, expected.clone()).into());
+            }
+        }
+
+        if let Some((path, sidecar)) = &sidecar {
+            let source = sidecar.find_match(&data);
+            let target = sidecar.find_match(&output);
+            for (what, entry) in [("ROM", source), ("output", target)] {
+                if let Some(entry) = entry {
+                    notice!(
+                        "note: {}: {what} matches {} ({})",
+                        path.display(),
+                        entry.name,
+                        entry.checksum.name()
+                    );
+                }
+            }
+
+            if source.is_none() && target.is_none() {
+                return Err(Failure::SidecarMismatch(path.clone()).into());
+            }
+        }
+
+        if let Some(suffix) = &args.backup {
+            let mut backup = rom.as_os_str().to_owned();
+            backup.push(suffix);
+            fs::copy(rom, &backup)?;
+        }
+
+        match &disc {
+            Some(disc) => {
+                for (path, data) in disc.outputs(&output, &dest) {
+                    write_atomic(&path, &data)?;
+                }
+            }
+            None => write_atomic(&dest, &output)?,
+        }
+        log::fields(
+            Level::Verbose,
+            "apply",
+            &[
+                ("rom", &rom.display()),
+                ("dest", &dest.display()),
+                ("format", &patch.format()),
+                ("source_crc", &log::crc(&data)),
+                ("output_crc", &log::crc(&output)),
+                ("size", &output.len()),
+                ("elapsed", &log::elapsed(start)),
+            ],
+        );
+
+        if let Some(command) = &args.exec {
+            run_exec(command, &dest)?;
+        }
+        Ok(dest)
+    };
+
+    // relative patterns are resolved against --dest-dir, or the ROM's directory
+    let dest_for = |rom: &Path| {
+        let pattern = args.dest_pattern.as_deref().unwrap_or_default();
+        let name = expand_dest_pattern(pattern, rom, &args.patch).expect("validated by clap");
+        match &args.dest_dir {
+            Some(dir) => dir.join(name),
+            None => rom.with_file_name(name),
+        }
+    };
+
+    let Some(roms) = expand_roms(&args.rom)? else {
+        let dest = match (args.dest.clone(), &args.dest_dir) {
+            _ if args.in_place => args.rom.clone(),
+            (Some(dest), _) => dest,
+            _ if args.dest_pattern.is_some() => dest_for(&args.rom),
+            (None, Some(dir)) => {
+                dir.join(default_dest(&args.rom, &args.patch).file_name().unwrap())
+            }
+            (None, None) => default_dest(&args.rom, &args.patch),
+        };
+        patch_rom(&args.rom, dest)?;
+        return Ok(());
+    };
+
+    if args.dest_dir.is_none() && args.dest_pattern.is_none() && !args.in_place {
+        return Err(Failure::Usage(
+            "--dest-dir, --dest-pattern, or --in-place is required when patching multiple ROMs."
+                .into(),
+        )
+        .into());
+    }
+
+    if roms.is_empty() {
+        bail!("No ROMs matched '{}'.", args.rom.display());
+    }
+
+    let dests: Vec<_> = roms
+        .iter()
+        .map(|rom| match &args.dest_dir {
+            _ if args.dest_pattern.is_some() => dest_for(rom),
+            Some(dir) => dir.join(rom.file_name().unwrap()),
+            None => rom.clone(),
+        })
+        .collect();
+    check_collisions(&roms, &dests)?;
+
+    let jobs: Vec<_> = roms.iter().zip(dests).collect();
+    let results = run_jobs(&jobs, args.jobs, |(rom, dest)| {
+        let result = patch_rom(rom, dest.clone());
+        match &result {
+            Ok(dest) => status!("{} -> {}", rom.display(), dest.display()),
+            Err(err) => eprintln!("{}: skipped: {err}", rom.display()),
+        }
+        result
+    });
+
+    let failed: Vec<_> = roms
+        .iter()
+        .zip(&results)
+        .filter(|(_, result)| result.is_err())
+        .map(|(rom, _)| rom)
+        .collect();
+    status!("Patched {} of {} ROMs.", roms.len() - failed.len(), roms.len());
+    if !failed.is_empty() {
+        // with several jobs, the errors are scattered through the output
+        if args.jobs != 1 {
+            for rom in failed.iter() {
+                eprintln!("failed: {}", rom.display());
             }
         }
+        bail!("{} ROM(s) could not be patched.", failed.len());
     }
+
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Arguments::parse();
-    let patch = load_patch(&args.patch)?;
-    let rom = fs::read(&args.rom)?;
+/// The files `match` loads from the patch directory. Others, like readmes, are skipped.
+const MATCH_EXTENSIONS: [&str; 4] = ["ips", "ups", "bps", "json"];
+
+fn auto_match(args: MatchArgs) -> Result<()> {
+    // keyed by size and CRC32, which every patch that records its source has
+    let mut roms: HashMap<_, Vec<_>> = HashMap::new();
+    for path in expand_roms(&args.roms)?.unwrap_or_default() {
+        let data = fs::read(&path)?;
+        let validation = Validation::new(&data);
+        roms.entry((validation.size, validation.crc))
+            .or_default()
+            .push((validation, path));
+    }
+
+    let (patches, others): (Vec<_>, Vec<_>) = expand_roms(&args.patches)?
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|path| {
+            path.extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|ext| MATCH_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        });
+    if !others.is_empty() {
+        notice!(
+            "note: ignored {} file(s) in '{}' that aren't patches",
+            others.len(),
+            args.patches.display()
+        );
+    }
+    if patches.is_empty() {
+        bail!("No patches found in '{}'.", args.patches.display());
+    }
+
+    fs::create_dir_all(&args.dest_dir)?;
+
+    let results = run_jobs(&patches, args.jobs, |path| {
+        let result = load_patch(path).and_then(|patch| {
+            let Some(source) = patch.source() else {
+                bail!("patch doesn't record a source checksum");
+            };
+            let candidates = roms
+                .get(&(source.size, source.crc))
+                .map_or(&[][..], Vec::as_slice);
+            // the stronger hashes the patch records are only computed for ROMs that could match
+            let strong = source.md5.is_some() || source.sha1.is_some() || source.sha256.is_some();
+            let Some((validation, rom)) = candidates.iter().find(|(_, rom)| {
+                !strong
+                    || fs::read(rom)
+                        .is_ok_and(|data| Validation::with_hashes(&data).agrees(&source))
+            }) else {
+                bail!("no ROM matches source CRC {:#X}", source.crc);
+            };
 
-    fs::write(
-        args.dest.unwrap_or_else(|| {
-            args.rom
-                .with_file_name(args.patch.file_stem().unwrap())
-                .with_extension(args.rom.extension().unwrap_or(OsStr::new("out")))
-        }),
-        patch.apply(&rom)?,
-    )?;
+            let dest = match &args.dest_pattern {
+                Some(pattern) => args
+                    .dest_dir
+                    .join(expand_dest_pattern(pattern, rom, path).expect("validated by clap")),
+                None => args
+                    .dest_dir
+                    .join(default_dest(rom, path).file_name().unwrap()),
+            };
+            // the ROM was hashed when it was added to the map
+            let output = patch.apply_with_info(&fs::read(rom)?, &(*validation).into())?;
+            write_atomic(&dest, &output)?;
+            Ok((rom, dest))
+        });
+        match result {
+            Ok((rom, dest)) => {
+                status!(
+                    "{} + {} -> {}",
+                    rom.display(),
+                    path.display(),
+                    dest.display()
+                );
+                true
+            }
+            Err(err) => {
+                eprintln!("{}: skipped: {err}", path.display());
+                false
+            }
+        }
+    });
+
+    let applied = results.iter().filter(|&&ok| ok).count();
+    status!("Applied {applied} of {} patches.", patches.len());
+    if applied != patches.len() {
+        bail!(
+            "{} patch(es) could not be applied.",
+            patches.len() - applied
+        );
+    }
 
     Ok(())
 }
+
+fn main() -> ExitCode {
+    let args = match expand_response_files(env::args_os()) {
+        Ok(args) => Arguments::parse_from(args),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            return ExitCode::from(2);
+        }
+    };
+    log::set_level(args.quiet, args.verbose);
+    RESYNC.store(args.resync, Ordering::Relaxed);
+    let progress = match (args.progress, args.no_progress) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let result = Config::load().and_then(|config| {
+        log::set_progress(
+            progress
+                .or(config.progress)
+                .unwrap_or_else(|| io::stderr().is_terminal()),
+        );
+        match args.command {
+            Command::Apply(args) => apply(args, &config),
+            Command::Match(args) => auto_match(args),
+            Command::Auto(args) => cli::auto::auto(args),
+            Command::Diff(args) => cli::diff::diff(args),
+            Command::Dump(args) => cli::dump::dump(args),
+            #[cfg(feature = "tui")]
+            Command::Inspect(args) => cli::inspect::inspect(args),
+            Command::Edit(args) => cli::edit::edit(args),
+            Command::Compare(args) => cli::compare::compare(args),
+            Command::Create(args) => cli::create::create(args, &config),
+            Command::Build(args) => cli::build::build(args),
+            Command::Hash(args) => cli::hash::hash(args),
+            Command::Info(args) => cli::info::info(args),
+            Command::Json(args) => cli::json::json(args),
+            Command::Map(args) => cli::map::map(args),
+            Command::Minify(args) => cli::minify::minify(args),
+            Command::Watch(args) => cli::watch::watch(args, &config),
+            #[cfg(feature = "serve")]
+            Command::Serve(args) => cli::serve::serve(args, &config),
+            #[cfg(feature = "dat")]
+            Command::Verify(args) => cli::verify::verify(args),
+            Command::Completions(args) => cli::completions::completions(args),
+        }
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn response_files() {
+        let dir = env::temp_dir().join(format!("patch-rs-response-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, text: &str| {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            format!("@{}", path.display())
+        };
+        let inner = file("inner.txt", "b.bps\r\n\n  \"@literal\"\n");
+        let outer = file(
+            "outer.txt",
+            &format!("  a rom.sfc  \n\n\"  spaced \"\n\"\"\n{inner}\n@missing.txt\n"),
+        );
+        let looping = file("loop.txt", "");
+        fs::write(dir.join("loop.txt"), format!("x\n{looping}\n")).unwrap();
+
+        let expand = |args: &[&str]| expand_response_files(args.iter().map(OsString::from));
+        let expanded = expand(&["patch-rs", &outer, "-v"]);
+        let recursive = expand(&[&looping]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            expanded.unwrap(),
+            [
+                "patch-rs",
+                "a rom.sfc",
+                "  spaced ",
+                "",
+                "b.bps",
+                "@literal",
+                "@missing.txt",
+                "-v"
+            ]
+        );
+        assert!(recursive
+            .unwrap_err()
+            .to_string()
+            .contains("includes itself"));
+    }
+
+    #[test]
+    fn dest_pattern() {
+        let expand =
+            |pattern, rom| expand_dest_pattern(pattern, Path::new(rom), Path::new("hack.bps"));
+        assert_eq!(
+            expand("{rom_stem} ({patch_stem}).{rom_ext}", "dir/game.sfc"),
+            Ok(PathBuf::from("game (hack).sfc"))
+        );
+        assert_eq!(
+            expand("{patch_stem}.{patch_ext}.{rom_ext}", "game"),
+            Ok(PathBuf::from("hack.bps.out"))
+        );
+        assert_eq!(expand("plain", "game.sfc"), Ok(PathBuf::from("plain")));
+        assert_eq!(
+            expand("{rom_stem", "game.sfc"),
+            Err("unclosed '{'".to_string())
+        );
+        assert!(expand("{name}", "game.sfc")
+            .unwrap_err()
+            .contains("'{name}'"));
+    }
+
+    #[test]
+    fn collisions() {
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let roms = paths(&["a/game.sfc", "b/game.sfc", "c/other.sfc"]);
+        assert!(check_collisions(&roms, &paths(&["a.sfc", "b.sfc", "c.sfc"])).is_ok());
+
+        let err = check_collisions(&roms, &paths(&["a.sfc", "c.sfc", "c.sfc"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'b/game.sfc' and 'c/other.sfc' would both be written to 'c.sfc'."
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let io = || io::Error::new(io::ErrorKind::NotFound, "missing");
+        let cases = [
+            (anyhow!("bad input"), 1),
+            (Failure::Unsupported.into(), 3),
+            (
+                Failure::InvalidPatch("p".into(), patch_rs::Error::Io(io())).into(),
+                3,
+            ),
+            (patch_rs::Error::InvalidPatch.into(), 3),
+            (
+                Failure::WrongRom("r".into(), patch_rs::Error::InvalidCRC(1, 2)).into(),
+                4,
+            ),
+            (Failure::SidecarMismatch("s".into()).into(), 4),
+            (Failure::Usage("--a and --b conflict".into()).into(), 2),
+            (patch_rs::Error::InvalidCRC(1, 2).into(), 4),
+            (
+                Failure::UnexpectedHash("CRC32", "1".into(), "2".into()).into(),
+                5,
+            ),
+            (
+                patch_rs::Error::OutputMismatch(Box::new(patch_rs::Error::InvalidCRC(1, 2))).into(),
+                5,
+            ),
+            (patch_rs::Error::Io(io()).into(), 6),
+            (anyhow::Error::new(io()).context("Couldn't read 'rom'"), 6),
+        ];
+        for (err, code) in cases {
+            assert_eq!(exit_code(&err), code, "{err:?}");
+        }
+    }
+}
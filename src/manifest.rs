@@ -0,0 +1,197 @@
+//! The XML manifest that BPS patches conventionally store in their metadata:
+//!
+//! ```xml
+//! <?xml version="1.0" encoding="UTF-8"?>
+//! <patch>
+//!   <title>My Hack</title>
+//!   <author>Someone</author>
+//!   <version>1.1</version>
+//!   <description>Fixes the thing.</description>
+//! </patch>
+//! ```
+//!
+//! Patches created with [`CreateOptions::provenance`](crate::CreateOptions) also record the tool
+//! that made them, when, and the names of the files they were made from.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::xml::unescape;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// The name and version of the tool that created the patch.
+    pub tool: Option<String>,
+    /// When the patch was created, as an ISO 8601 UTC timestamp.
+    pub created: Option<String>,
+    /// The name of the original file.
+    pub source: Option<String>,
+    /// The name of the modified file.
+    pub target: Option<String>,
+}
+
+impl Manifest {
+    /// Parses a manifest out of patch metadata. Returns `None` if the metadata isn't XML or
+    /// doesn't contain any of the known fields.
+    pub fn parse(metadata: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(metadata).ok()?;
+        let text = text.trim_start_matches('\u{feff}').trim_start();
+        if !text.starts_with('<') {
+            return None;
+        }
+
+        let manifest = Self {
+            title: element(text, "title"),
+            author: element(text, "author"),
+            version: element(text, "version"),
+            description: element(text, "description"),
+            tool: element(text, "tool"),
+            created: element(text, "created"),
+            source: element(text, "source"),
+            target: element(text, "target"),
+        };
+        (manifest != Self::default()).then_some(manifest)
+    }
+
+    /// A manifest recording this crate as the tool and the current time.
+    pub fn provenance(source: Option<String>, target: Option<String>) -> Self {
+        Self {
+            tool: Some(concat!("patch-rs ", env!("CARGO_PKG_VERSION")).into()),
+            created: Some(timestamp(SystemTime::now())),
+            source,
+            target,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<patch>\n");
+        for (name, value) in [
+            ("title", &self.title),
+            ("author", &self.author),
+            ("version", &self.version),
+            ("description", &self.description),
+            ("tool", &self.tool),
+            ("created", &self.created),
+            ("source", &self.source),
+            ("target", &self.target),
+        ] {
+            if let Some(value) = value {
+                xml += &format!("  <{name}>{}</{name}>\n", escape(value));
+            }
+        }
+        xml + "</patch>\n"
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Formats a time as `YYYY-MM-DDTHH:MM:SSZ`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Returns the text of the first `<name>` element, wherever it's nested.
+fn element(xml: &str, name: &str) -> Option<String> {
+    let mut rest = xml;
+    let content = loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let Some(after) = rest.strip_prefix(name) else {
+            continue;
+        };
+        match after.chars().next()? {
+            '>' => break &after[1..],
+            c if c.is_whitespace() => {
+                let open = &after[..after.find('>')?];
+                if open.ends_with('/') {
+                    return None;
+                }
+                break &after[open.len() + 1..];
+            }
+            _ => continue,
+        }
+    };
+
+    let content = &content[..content.find(&format!("</{name}"))?];
+    let content = content.trim();
+    let text = match content
+        .strip_prefix("<![CDATA[")
+        .and_then(|c| c.strip_suffix("]]>"))
+    {
+        Some(cdata) => cdata.to_string(),
+        None => unescape(content),
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let manifest = Manifest::parse(
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<patch>
+  <information>
+    <title>Hack &amp; Slash</title>
+    <author lang="en">Someone</author>
+    <description><![CDATA[Fixes <everything>.]]></description>
+  </information>
+</patch>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                title: Some("Hack & Slash".into()),
+                author: Some("Someone".into()),
+                version: None,
+                description: Some("Fixes <everything>.".into()),
+                ..Default::default()
+            }
+        );
+
+        assert!(Manifest::parse(b"just some notes").is_none());
+    }
+
+    #[test]
+    fn provenance() {
+        let manifest = Manifest::provenance(Some("a & b.sfc".into()), None);
+        assert_eq!(
+            Manifest::parse(manifest.to_xml().as_bytes()),
+            Some(manifest)
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + std::time::Duration::from_secs(1_709_210_096)),
+            "2024-02-29T12:34:56Z"
+        );
+    }
+}
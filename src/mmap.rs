@@ -0,0 +1,129 @@
+//! Applying patches straight into a memory-mapped output file, so patching a huge image never
+//! holds the output in anonymous memory. Pages are written back to the file by the kernel as the
+//! patch is applied.
+
+use std::{
+    ffi::{c_int, c_void},
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+
+use crate::{Patch, Result};
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: isize,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A writable shared mapping of a whole file, unmapped on drop.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// Applies `patch` to `rom`, writing the output straight into the file at `path` with
+/// [`Patch::apply_into`]. The file is created, or truncated, and sized to the output first. If
+/// applying fails, the file is removed.
+pub fn apply_to_file(patch: &dyn Patch, rom: &[u8], path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let size = patch.output_size(rom.len());
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+
+    let result = (|| {
+        file.set_len(size as u64)?;
+        // empty files can't be mapped
+        if size == 0 {
+            return patch.apply_into(rom, &mut []);
+        }
+
+        let mut map = Mapping::new(&file, size)?;
+        patch.apply_into(rom, map.as_mut_slice())
+    })();
+    if result.is_err() {
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn apply_to_file() {
+        let src = b"The quick brown fox jumps over the lazy dog.";
+        let dst = b"The quick brown cat jumps over the lazy dog, twice.";
+        let path = env::temp_dir().join(format!("patch-rs-mmap-{}", process::id()));
+        let patches: [Box<dyn Patch>; 3] = [
+            Box::new(IpsPatch::create(src, dst)),
+            Box::new(UpsPatch::create(src, dst)),
+            Box::new(BpsPatch::create(src, dst)),
+        ];
+        for patch in patches {
+            super::apply_to_file(&*patch, src, &path).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), dst);
+        }
+
+        let patch = BpsPatch::create(src, dst);
+        assert!(super::apply_to_file(&patch, b"wrong", &path).is_err());
+        assert!(!path.exists());
+    }
+}
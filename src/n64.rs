@@ -0,0 +1,59 @@
+//! Conversion between the byte orders N64 ROMs are commonly dumped in. Patches almost always
+//! target the big-endian (.z64) order.
+
+/// The byte order of an N64 ROM dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Native big-endian order, usually `.z64`.
+    BigEndian,
+    /// Every pair of bytes swapped, usually `.v64`.
+    ByteSwapped,
+    /// Every 32-bit word reversed, usually `.n64`.
+    LittleEndian,
+}
+
+impl ByteOrder {
+    /// Detects the byte order from the first word of the ROM, which is always `80 37 12 40` in
+    /// big-endian order.
+    pub fn detect(rom: &[u8]) -> Option<Self> {
+        match rom.get(..4)? {
+            [0x80, 0x37, 0x12, 0x40] => Some(ByteOrder::BigEndian),
+            [0x37, 0x80, 0x40, 0x12] => Some(ByteOrder::ByteSwapped),
+            [0x40, 0x12, 0x37, 0x80] => Some(ByteOrder::LittleEndian),
+            _ => None,
+        }
+    }
+}
+
+/// Converts `rom` from one byte order to another in place.
+pub fn convert(rom: &mut [u8], from: ByteOrder, to: ByteOrder) {
+    if from == to {
+        return;
+    }
+
+    // both conversions from big-endian are their own inverse, so we can go through it
+    for order in [from, to] {
+        match order {
+            ByteOrder::BigEndian => {}
+            ByteOrder::ByteSwapped => rom.chunks_exact_mut(2).for_each(|c| c.swap(0, 1)),
+            ByteOrder::LittleEndian => rom.chunks_exact_mut(4).for_each(|c| c.reverse()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let z64 = [0x80, 0x37, 0x12, 0x40, 1, 2, 3, 4];
+        let mut rom = z64;
+        convert(&mut rom, ByteOrder::BigEndian, ByteOrder::ByteSwapped);
+        assert_eq!(ByteOrder::detect(&rom), Some(ByteOrder::ByteSwapped));
+        convert(&mut rom, ByteOrder::ByteSwapped, ByteOrder::LittleEndian);
+        assert_eq!(rom, [0x40, 0x12, 0x37, 0x80, 4, 3, 2, 1]);
+        convert(&mut rom, ByteOrder::LittleEndian, ByteOrder::BigEndian);
+        assert_eq!(rom, z64);
+    }
+}
@@ -0,0 +1,199 @@
+//! Reporting progress while patches are applied or created, so GUIs can drive progress bars from
+//! the library instead of guessing.
+//!
+//! Pass an [`Observer`] to [`Patch::apply_observed`](crate::Patch::apply_observed) or a format's
+//! `create_observed`. Any `FnMut(Event)` closure is an observer, and `()` ignores every event.
+//!
+//! Observers can also cancel the operation, which then fails with [`Error::Cancelled`]. An
+//! `&AtomicBool` cancels once it's set, and [`Cancellable`] adds a flag to another observer, so a
+//! GUI can abort a long creation from another thread without killing the process.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Error, Result};
+
+/// Something that happened while a patch was applied or created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// `done` of `total` bytes have been processed. When applying, these are the bytes the
+    /// records write. When creating, they're bytes of the modified file. The last progress event
+    /// always has `done == total`.
+    Progress { done: usize, total: usize },
+    /// The record at the given index, counting from 0 in file order, was applied.
+    RecordApplied(usize),
+    /// The source matched the checksums recorded in the patch.
+    SourceValidated,
+    /// The output matched the checksums recorded in the patch.
+    OutputValidated,
+}
+
+/// Receives [`Event`]s as a patch is applied or created.
+pub trait Observer {
+    fn event(&mut self, event: Event);
+    /// Checked regularly during the operation, which stops as soon as this returns `true`.
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl Observer for () {
+    fn event(&mut self, _event: Event) {}
+}
+
+impl<F: FnMut(Event)> Observer for F {
+    fn event(&mut self, event: Event) {
+        self(event)
+    }
+}
+
+impl Observer for &AtomicBool {
+    fn event(&mut self, _event: Event) {}
+
+    fn cancelled(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+/// Forwards events to `observer`, and cancels the operation once `flag` is set.
+pub struct Cancellable<'a, O> {
+    pub observer: O,
+    pub flag: &'a AtomicBool,
+}
+
+impl<O: Observer> Observer for Cancellable<'_, O> {
+    fn event(&mut self, event: Event) {
+        self.observer.event(event)
+    }
+
+    fn cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed) || self.observer.cancelled()
+    }
+}
+
+pub(crate) fn checkpoint(observer: &dyn Observer) -> Result<()> {
+    if observer.cancelled() {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// How many bytes creation gets through between progress events.
+pub(crate) const CREATE_INTERVAL: usize = 0x10000;
+
+/// Reports progress through the modified file while a patch is created, checking for
+/// cancellation every [`CREATE_INTERVAL`] bytes.
+pub(crate) struct Ticker<'a> {
+    observer: &'a mut dyn Observer,
+    total: usize,
+    reported: usize,
+}
+
+impl<'a> Ticker<'a> {
+    pub fn new(observer: &'a mut dyn Observer, total: usize) -> Self {
+        Self {
+            observer,
+            total,
+            reported: 0,
+        }
+    }
+
+    #[inline]
+    pub fn tick(&mut self, done: usize) -> Result<()> {
+        if done - self.reported >= CREATE_INTERVAL {
+            self.observer.event(Event::Progress {
+                done,
+                total: self.total,
+            });
+            checkpoint(self.observer)?;
+            self.reported = done;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) {
+        self.observer.event(Event::Progress {
+            done: self.total,
+            total: self.total,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn events() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!";
+        let mut events = Vec::new();
+        let patch =
+            BpsPatch::create_observed(src, dst, &Default::default(), &mut |e| events.push(e))
+                .unwrap();
+        assert_eq!(
+            events,
+            [Event::Progress {
+                done: 19,
+                total: 19
+            }]
+        );
+
+        events.clear();
+        assert_eq!(
+            patch.apply_observed(src, &mut |e| events.push(e)).unwrap(),
+            dst
+        );
+        assert_eq!(events.first(), Some(&Event::SourceValidated));
+        assert_eq!(events.last(), Some(&Event::OutputValidated));
+        assert_eq!(
+            events[events.len() - 2],
+            Event::Progress {
+                done: 19,
+                total: 19
+            }
+        );
+
+        events.clear();
+        IpsPatch::create(src, dst)
+            .apply_observed(src, &mut |e| events.push(e))
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                Event::RecordApplied(0),
+                Event::Progress { done: 9, total: 9 }
+            ]
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let src = vec![0; CREATE_INTERVAL * 4];
+        let dst = vec![1; CREATE_INTERVAL * 4];
+        let flag = AtomicBool::new(false);
+        let mut seen = 0;
+        let mut observer = Cancellable {
+            observer: |e| {
+                if let Event::Progress { done, .. } = e {
+                    seen = done;
+                    flag.store(done >= CREATE_INTERVAL * 2, Ordering::Relaxed);
+                }
+            },
+            flag: &flag,
+        };
+        assert!(matches!(
+            UpsPatch::create_observed(&src, &dst, &Default::default(), &mut observer),
+            Err(Error::Cancelled)
+        ));
+        assert_eq!(seen, CREATE_INTERVAL * 2);
+
+        flag.store(true, Ordering::Relaxed);
+        let patch = IpsPatch::create(&src, &dst);
+        assert!(matches!(
+            patch.apply_observed(&src, &mut &flag),
+            Err(Error::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+//! Utilities for padding and trimming ROMs, since many patches expect a specific padded size.
+
+use crate::Patch;
+
+/// Pads `rom` with `fill` up to the next power of two.
+pub fn pad_to_power_of_two(rom: &mut Vec<u8>, fill: u8) {
+    rom.resize(rom.len().next_power_of_two(), fill);
+}
+
+/// Returns the length of `rom` once any trailing run of `0x00` or `0xFF` padding is removed.
+pub fn trimmed_len(rom: &[u8]) -> usize {
+    let Some(&last) = rom.last().filter(|&&b| b == 0x00 || b == 0xFF) else {
+        return rom.len();
+    };
+
+    rom.iter().rposition(|&b| b != last).map_or(0, |i| i + 1)
+}
+
+/// Removes any trailing run of `0x00` or `0xFF` padding from `rom`.
+pub fn trim_padding(rom: &mut Vec<u8>) {
+    rom.truncate(trimmed_len(rom));
+}
+
+/// If `rom` doesn't have the size `patch` expects, tries padding it with `0x00` or `0xFF`, or
+/// trimming padding from it, and returns the adjusted ROM if it then matches the expected source.
+pub fn fit_to_source<P: Patch + ?Sized>(patch: &P, rom: &[u8]) -> Option<Vec<u8>> {
+    let source = patch.source()?;
+    if source.size == rom.len() {
+        return None;
+    }
+
+    if source.size > rom.len() {
+        return [0x00, 0xFF].into_iter().find_map(|fill| {
+            let mut padded = rom.to_vec();
+            padded.resize(source.size, fill);
+            source.validate(&padded).is_ok().then_some(padded)
+        });
+    }
+
+    let trimmed = &rom[..source.size];
+    (trimmed_len(rom) <= source.size && source.validate(trimmed).is_ok()).then(|| trimmed.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn trim_and_pad() {
+        let mut rom = vec![1, 2, 3, 0xFF, 0xFF];
+        assert_eq!(trimmed_len(&rom), 3);
+        trim_padding(&mut rom);
+        assert_eq!(rom, [1, 2, 3]);
+        pad_to_power_of_two(&mut rom, 0xFF);
+        assert_eq!(rom, [1, 2, 3, 0xFF]);
+        assert_eq!(trimmed_len(&[0, 0]), 0);
+    }
+
+    #[test]
+    fn fit() {
+        let src = [1, 2, 3, 0xFF];
+        let patch = UpsPatch::create(&src, &[1, 2, 4, 0xFF]);
+        assert_eq!(fit_to_source(&patch, &[1, 2, 3]).unwrap(), src);
+        assert_eq!(fit_to_source(&patch, &[1, 2, 3, 0xFF, 0xFF]).unwrap(), src);
+        assert!(fit_to_source(&patch, &[1, 2, 3, 0xFF, 0x01]).is_none());
+    }
+}
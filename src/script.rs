@@ -0,0 +1,230 @@
+//! A declarative patch source for small fixes, which are easier to review and maintain as text
+//! than as binary patches. A script is a TOML document listing the bytes to write:
+//!
+//! ```toml
+//! # the size of the output, if it should differ from the source
+//! size = 0x100000
+//!
+//! [[patch]]
+//! offset = 0x7FC0
+//! text = "MY HACK"
+//!
+//! [[patch]]
+//! offset = 0x8000
+//! bytes = "EA EA 60" # or [0xEA, 0xEA, 0x60]
+//!
+//! [[patch]]
+//! offset = 0x9000
+//! fill = 0xFF
+//! length = 0x20
+//! ```
+//!
+//! Scripts compile directly to IPS with [`Script::to_ips`]. UPS and BPS patches record the
+//! checksums of their source, so they're created from a base ROM and the output of
+//! [`Script::apply`].
+
+use toml_edit::{Document, Item, Table};
+
+use crate::{prelude::*, Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Data {
+    Bytes(Vec<u8>),
+    /// A byte repeated the given number of times.
+    Fill(u8, usize),
+}
+
+impl Data {
+    pub fn len(&self) -> usize {
+        match self {
+            Data::Bytes(bytes) => bytes.len(),
+            &Data::Fill(_, len) => len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub offset: usize,
+    pub data: Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    /// The size of the output. Without it, the output is the size of the source, extended to fit
+    /// every entry.
+    pub size: Option<usize>,
+    pub entries: Vec<Entry>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self> {
+        let doc: Document = text
+            .parse()
+            .map_err(|err: toml_edit::TomlError| invalid(err.message()))?;
+
+        let mut script = Self {
+            size: None,
+            entries: Vec::new(),
+        };
+        for (key, item) in doc.iter() {
+            match key {
+                "size" => script.size = Some(integer(key, item)?),
+                "patch" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| invalid("'patch' must be an array of tables"))?;
+                    for table in tables.iter() {
+                        script.entries.push(Self::entry(table)?);
+                    }
+                }
+                _ => return Err(invalid(&format!("unknown key '{key}'"))),
+            }
+        }
+
+        Ok(script)
+    }
+
+    fn entry(table: &Table) -> Result<Entry> {
+        let mut offset = None;
+        let mut data = None;
+        let mut fill = None;
+        let mut length = None;
+        for (key, item) in table.iter() {
+            match key {
+                "offset" => offset = Some(integer(key, item)?),
+                "bytes" => data = Some(bytes(item)?),
+                "text" => {
+                    let text = item
+                        .as_str()
+                        .ok_or_else(|| invalid("'text' must be a string"))?;
+                    data = Some(text.as_bytes().to_vec());
+                }
+                "fill" => {
+                    fill = Some(
+                        u8::try_from(integer(key, item)?)
+                            .map_err(|_| invalid("'fill' must be a byte"))?,
+                    )
+                }
+                "length" => length = Some(integer(key, item)?),
+                _ => return Err(invalid(&format!("unknown key '{key}'"))),
+            }
+        }
+
+        let offset = offset.ok_or_else(|| invalid("every patch needs an 'offset'"))?;
+        let data = match (data, fill, length) {
+            (Some(data), None, None) => Data::Bytes(data),
+            (None, Some(byte), Some(len)) => Data::Fill(byte, len),
+            _ => {
+                return Err(invalid(&format!(
+                    "the patch at {offset:#X} needs either 'bytes', 'text', or 'fill' and 'length'"
+                )))
+            }
+        };
+        if data.is_empty() {
+            return Err(invalid(&format!("the patch at {offset:#X} is empty")));
+        }
+
+        Ok(Entry { offset, data })
+    }
+
+    /// Writes every entry to a copy of `rom`.
+    pub fn apply(&self, rom: &[u8]) -> Vec<u8> {
+        let mut out = rom.to_vec();
+        for entry in self.entries.iter() {
+            let end = entry.offset + entry.data.len();
+            if out.len() < end {
+                out.resize(end, 0);
+            }
+
+            match &entry.data {
+                Data::Bytes(bytes) => out[entry.offset..end].copy_from_slice(bytes),
+                &Data::Fill(byte, _) => out[entry.offset..end].fill(byte),
+            }
+        }
+
+        if let Some(size) = self.size {
+            out.resize(size, 0);
+        }
+        out
+    }
+
+    pub fn to_ips(&self) -> Result<IpsPatch> {
+        IpsPatch::from_script(self)
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidScript(message.trim_end().to_string())
+}
+
+fn integer(key: &str, item: &Item) -> Result<usize> {
+    item.as_integer()
+        .and_then(|value| usize::try_from(value).ok())
+        .ok_or_else(|| invalid(&format!("'{key}' must be a non-negative integer")))
+}
+
+/// Parses a hex string such as `"EA EA 60"` or an array of integers.
+fn bytes(item: &Item) -> Result<Vec<u8>> {
+    let err = || invalid("'bytes' must be a hex string or an array of bytes");
+    if let Some(hex) = item.as_str() {
+        let digits: Vec<_> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() % 2 != 0 {
+            return Err(err());
+        }
+
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).map_err(|_| err()))
+            .collect()
+    } else if let Some(array) = item.as_array() {
+        array
+            .iter()
+            .map(|value| {
+                value
+                    .as_integer()
+                    .and_then(|byte| u8::try_from(byte).ok())
+                    .ok_or_else(err)
+            })
+            .collect()
+    } else {
+        Err(err())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile() {
+        let script = Script::parse(
+            r#"
+            [[patch]]
+            offset = 1
+            bytes = "41 42"
+
+            [[patch]]
+            offset = 4
+            text = "yz"
+
+            [[patch]]
+            offset = 7
+            fill = 0x2A
+            length = 2
+            "#,
+        )
+        .unwrap();
+
+        let out = script.apply(b"0123456");
+        assert_eq!(out, b"0AB3yz6**");
+        assert_eq!(script.to_ips().unwrap().apply(b"0123456").unwrap(), out);
+
+        assert!(Script::parse("[[patch]]\noffset = 0\n").is_err());
+        assert!(Script::parse("[[patch]]\noffset = 0\nbytes = [256]\n").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Applying a stack of patches in order, e.g. a base translation followed by add-ons made
+//! against its output.
+//!
+//! ```
+//! use patch_rs::prelude::*;
+//!
+//! let base = IpsPatch::create(b"abcd", b"abxd");
+//! let addon = BpsPatch::create(b"abxd", b"zbxd");
+//! let set: PatchSet = [Box::new(base) as Box<dyn Patch>, Box::new(addon)]
+//!     .into_iter()
+//!     .collect();
+//! assert_eq!(set.apply(b"abcd")?, b"zbxd");
+//! # Ok::<(), patch_rs::Error>(())
+//! ```
+//!
+//! Some distribution scripts write the whole stack to one file, patch after patch. [`split`]
+//! finds where each one ends, and [`PatchSet::load`] loads them all.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    encoding::{self, Footer},
+    hash,
+    prelude::*,
+    ApplyOptions, ApplyReport, Error, Format, Result,
+};
+
+/// An ordered list of patches, each applied to the output of the one before it.
+#[derive(Default)]
+pub struct PatchSet {
+    patches: Vec<Box<dyn Patch>>,
+}
+
+/// What [`PatchSet::apply_report`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetReport {
+    /// The report of each patch, in the order they were applied.
+    pub patches: Vec<ApplyReport>,
+    pub output_size: usize,
+    pub elapsed: Duration,
+}
+
+/// Splits a file of patches written back to back into the bytes of each one. An IPS patch ends
+/// at its end marker, and a UPS or BPS patch at the first footer whose patch checksum matches
+/// and that's followed by the end of the file or another patch. A single patch gives a list of
+/// one.
+pub fn split(mut data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut patches = Vec::new();
+    loop {
+        let len = match crate::detect(data)? {
+            Format::Ips => IpsPatch::measure(data)?,
+            Format::Ups | Format::Bps => footer_end(data)?,
+        };
+        let (patch, rest) = data.split_at(len);
+        patches.push(patch);
+        if rest.is_empty() {
+            return Ok(patches);
+        }
+        data = rest;
+    }
+}
+
+/// The length of the UPS or BPS patch at the start of `data`. Fails like
+/// [`encoding::read_footer`] if no footer matches.
+fn footer_end(data: &[u8]) -> Result<usize> {
+    (4 + Footer::SIZE..=data.len())
+        .filter(|&end| end == data.len() || crate::detect(&data[end..]).is_ok())
+        .find(|&end| {
+            let crc = u32::from_le_bytes(data[end - 4..end].try_into().unwrap());
+            hash::crc32(&data[..end - 4]) == crc
+        })
+        .map_or_else(|| encoding::read_footer(data).map(|_| data.len()), Ok)
+}
+
+impl PatchSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every patch in a file of patches written back to back, as found by [`split`]. A
+    /// patch that doesn't load is reported as [`Error::PatchFailed`] with its index.
+    pub fn load(data: &[u8]) -> Result<Self> {
+        split(data)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| {
+                crate::load(data).map_err(|err| Error::PatchFailed(index, Box::new(err)))
+            })
+            .collect()
+    }
+
+    /// Adds a patch to the end of the set.
+    pub fn push(&mut self, patch: Box<dyn Patch>) {
+        self.patches.push(patch);
+    }
+
+    /// Inserts a patch at `index`, shifting the ones after it.
+    pub fn insert(&mut self, index: usize, patch: Box<dyn Patch>) {
+        self.patches.insert(index, patch);
+    }
+
+    /// Removes and returns the patch at `index`.
+    pub fn remove(&mut self, index: usize) -> Box<dyn Patch> {
+        self.patches.remove(index)
+    }
+
+    pub fn patches(&self) -> &[Box<dyn Patch>] {
+        &self.patches
+    }
+
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    /// Checks, without a ROM, that each patch's recorded output is the source the next patch
+    /// expects. Patches that don't record them, like IPS, are taken on trust. Fails with
+    /// [`Error::ChainMismatch`] naming the first patch that doesn't fit.
+    pub fn validate_chain(&self) -> Result<()> {
+        for (index, pair) in self.patches.windows(2).enumerate() {
+            if let (Some(output), Some(source)) = (pair[0].target(), pair[1].source()) {
+                if !output.agrees(&source) {
+                    return Err(Error::ChainMismatch(index + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the chain, then applies every patch in order, validating each like
+    /// [`Patch::apply`]. An empty set returns the ROM unchanged.
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        self.apply_report(rom, &ApplyOptions::default())
+            .map(|(output, _)| output)
+    }
+
+    /// Like [`apply`](Self::apply), applying each patch with [`Patch::apply_report`] and
+    /// returning their reports. A failure is reported as [`Error::PatchFailed`] with the index of
+    /// the patch.
+    pub fn apply_report(&self, rom: &[u8], options: &ApplyOptions) -> Result<(Vec<u8>, SetReport)> {
+        let start = Instant::now();
+        self.validate_chain()?;
+
+        let mut output = rom.to_vec();
+        let mut reports = Vec::with_capacity(self.patches.len());
+        for (index, patch) in self.patches.iter().enumerate() {
+            let (next, report) = patch
+                .apply_report(&output, options)
+                .map_err(|err| Error::PatchFailed(index, Box::new(err)))?;
+            output = next;
+            reports.push(report);
+        }
+
+        let report = SetReport {
+            patches: reports,
+            output_size: output.len(),
+            elapsed: start.elapsed(),
+        };
+        Ok((output, report))
+    }
+}
+
+impl FromIterator<Box<dyn Patch>> for PatchSet {
+    fn from_iter<T: IntoIterator<Item = Box<dyn Patch>>>(iter: T) -> Self {
+        Self {
+            patches: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoding::WriteVarExt, Check};
+
+    #[test]
+    fn chain() {
+        let (a, b, c) = (
+            b"The quick brown fox",
+            b"The quick red fox!!",
+            b"The slow red fox!!!",
+        );
+        let mut set = PatchSet::new();
+        set.push(Box::new(UpsPatch::create(a, b)));
+        set.push(Box::new(BpsPatch::create(b, c)));
+        assert!(set.validate_chain().is_ok());
+
+        let (output, report) = set.apply_report(a, &ApplyOptions::default()).unwrap();
+        assert_eq!(output, c);
+        assert_eq!(report.output_size, c.len());
+        let formats: Vec<_> = report.patches.iter().map(|r| r.format).collect();
+        assert_eq!(formats, ["UPS", "BPS"]);
+        assert!(report
+            .patches
+            .iter()
+            .all(|r| r.source_check == Check::Passed));
+
+        // an IPS patch in between is taken on trust
+        set.insert(1, Box::new(IpsPatch::create(b, b)));
+        assert_eq!(set.apply(a).unwrap(), c);
+        set.remove(1);
+
+        // made against the wrong base
+        set.push(Box::new(BpsPatch::create(a, c)));
+        assert!(matches!(set.validate_chain(), Err(Error::ChainMismatch(2))));
+
+        set.remove(2);
+        assert!(matches!(
+            set.apply(c),
+            Err(Error::PatchFailed(0, err)) if matches!(*err, Error::InvalidCRC(..))
+        ));
+        assert_eq!(PatchSet::new().apply(a).unwrap(), a);
+    }
+
+    #[test]
+    fn concatenated() {
+        let (a, b, c, d) = (
+            &b"The quick brown fox"[..],
+            &b"The quick red fox!!"[..],
+            &b"The slow red fox!!!"[..],
+            &b"The slow red fox"[..],
+        );
+        let mut ips = IpsPatch::create(c, d);
+        ips.set_output_size(Some(d.len()));
+        let parts = [
+            UpsPatch::create(a, b).export(None).unwrap(),
+            BpsPatch::create(b, c).export(None).unwrap(),
+            ips.export(None).unwrap(),
+            // made against the output of the truncated IPS patch
+            BpsPatch::create(d, a).export(None).unwrap(),
+        ];
+        let data = parts.concat();
+        assert_eq!(split(&data).unwrap(), parts);
+        assert!(crate::load(&data).is_err());
+
+        let set = PatchSet::load(&data).unwrap();
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.apply(a).unwrap(), a);
+        assert_eq!(PatchSet::load(&parts[1]).unwrap().len(), 1);
+
+        // the last patch is cut short, so its footer doesn't match
+        assert!(matches!(
+            PatchSet::load(&data[..data.len() - 1]),
+            Err(Error::CorruptPatch(..))
+        ));
+        // so is a corrupt patch in the middle, since its end can't be found
+        let mut corrupt = data.clone();
+        corrupt[parts[0].len() + 6] ^= 1;
+        assert!(matches!(
+            PatchSet::load(&corrupt),
+            Err(Error::CorruptPatch(..))
+        ));
+
+        // a well-formed UPS patch with a record past the sizes it declares
+        let mut bad = b"UPS1".to_vec();
+        for n in [1, 1, 5] {
+            bad.write_var_int(n).unwrap();
+        }
+        bad.extend(b"\x01\x00");
+        encoding::write_footer(&mut bad, 0, 0);
+        let data = [&parts[0][..], &bad].concat();
+        assert_eq!(split(&data).unwrap().len(), 2);
+        assert!(matches!(
+            PatchSet::load(&data),
+            Err(Error::PatchFailed(1, _))
+        ));
+    }
+
+    #[test]
+    fn concatenated_eof_offset() {
+        // written by a naive encoder, with a record at the offset spelled "EOF"
+        let naive = &b"PATCHEOF\0\x01a\0\0\0\0\x01bEOF"[..];
+        let truncated = &b"PATCHEOF\0\x01a\0\0\0\0\x01bEOF\0\0\x01"[..];
+        let next = IpsPatch::create(b"ab", b"cd").export(None).unwrap();
+        for first in [naive, truncated] {
+            let data = [first, &next].concat();
+            assert_eq!(split(&data).unwrap(), [first, &next[..]]);
+
+            let alone = IpsPatch::load(first).unwrap();
+            assert_eq!(alone.record_count(), 2);
+            let set = PatchSet::load(&data).unwrap();
+            assert_eq!(set.len(), 2);
+            assert_eq!(set.patches()[0].records().len(), alone.record_count());
+        }
+    }
+}
@@ -0,0 +1,1111 @@
+//! A minimal reader for 7z archives, so patches downloaded as 7z can be applied without
+//! extracting them by hand. Only folders with a single copy, LZMA, or LZMA2 coder are supported,
+//! which covers archives made with 7-Zip's default settings for anything but executables.
+
+use byteorder::{ByteOrder, LE};
+
+use crate::{hash, Error, Result};
+
+/// Extensions of the patch formats this crate loads, for picking the patch out of an archive
+/// that also contains readmes or screenshots.
+pub const PATCH_EXTENSIONS: &[&str] = &["ips", "ups", "bps", "json", "toml"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SevenZipEntry {
+    pub name: String,
+    pub size: usize,
+    /// The CRC32 of the entry, if the archive records one.
+    pub crc: Option<u32>,
+    pub is_dir: bool,
+    /// The folder holding the entry's data, and where in the unpacked folder it starts. Empty
+    /// files aren't stored in a folder.
+    location: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Copy,
+    Lzma([u8; 5]),
+    Lzma2(u8),
+}
+
+/// A unit of compressed data, which may hold several files back to back.
+#[derive(Debug, Clone)]
+struct Folder {
+    method: Method,
+    pack_offset: usize,
+    pack_size: usize,
+    size: usize,
+    crc: Option<u32>,
+}
+
+#[derive(Default)]
+struct StreamsInfo {
+    folders: Vec<Folder>,
+    /// The size and CRC of each file stored in each folder.
+    streams: Vec<Vec<(usize, Option<u32>)>>,
+}
+
+pub struct SevenZipArchive<'a> {
+    data: &'a [u8],
+    folders: Vec<Folder>,
+    entries: Vec<SevenZipEntry>,
+}
+
+impl<'a> SevenZipArchive<'a> {
+    const MAGIC: &'static [u8; 6] = b"7z\xBC\xAF\x27\x1C";
+    const SIGNATURE_HEADER_SIZE: usize = 32;
+
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let start = data
+            .get(..Self::SIGNATURE_HEADER_SIZE)
+            .filter(|header| header.starts_with(Self::MAGIC))
+            .ok_or(Error::InvalidArchive)?;
+        if hash::crc32(&start[12..32]) != LE::read_u32(&start[8..]) {
+            return Err(Error::InvalidArchive);
+        }
+
+        let offset = to_usize(LE::read_u64(&start[12..]))?
+            .checked_add(Self::SIGNATURE_HEADER_SIZE)
+            .ok_or(Error::InvalidArchive)?;
+        let size = to_usize(LE::read_u64(&start[20..]))?;
+        let header = data
+            .get(offset..offset.checked_add(size).ok_or(Error::InvalidArchive)?)
+            .ok_or(Error::InvalidArchive)?;
+        if hash::crc32(header) != LE::read_u32(&start[28..]) {
+            return Err(Error::InvalidArchive);
+        }
+
+        let mut archive = Self {
+            data,
+            folders: Vec::new(),
+            entries: Vec::new(),
+        };
+        let mut cursor = Cursor(header);
+        let mut id = cursor.byte()?;
+        let unpacked;
+        // the real header is compressed, and described by these streams. 7-Zip only ever
+        // compresses it once, and a header that unpacks to another could do so forever
+        if id == property::ENCODED_HEADER {
+            let streams = StreamsInfo::parse(&mut cursor)?;
+            let folder = streams.folders.first().ok_or(Error::InvalidArchive)?;
+            unpacked = archive.unpack(folder)?;
+            cursor = Cursor(&unpacked);
+            id = cursor.byte()?;
+        }
+        if id != property::HEADER {
+            return Err(Error::InvalidArchive);
+        }
+        archive.parse_header(&mut cursor)?;
+
+        Ok(archive)
+    }
+
+    pub fn entries(&self) -> &[SevenZipEntry] {
+        &self.entries
+    }
+
+    /// Decompresses `entry`, verifying its size and CRC.
+    pub fn read(&self, entry: &SevenZipEntry) -> Result<Vec<u8>> {
+        let Some((folder, offset)) = entry.location else {
+            return Ok(Vec::new());
+        };
+
+        let unpacked = self.unpack(&self.folders[folder])?;
+        let data = unpacked
+            .get(offset..offset + entry.size)
+            .ok_or(Error::InvalidArchive)?
+            .to_vec();
+        if entry.crc.is_some_and(|crc| crc != hash::crc32(&data)) {
+            return Err(Error::InvalidArchive);
+        }
+
+        Ok(data)
+    }
+
+    /// Picks the only file with one of the given extensions, otherwise the only file.
+    pub fn select(&self, extensions: &[&str]) -> Result<&SevenZipEntry> {
+        let files: Vec<_> = self.entries.iter().filter(|e| !e.is_dir).collect();
+        let by_ext: Vec<_> = files
+            .iter()
+            .copied()
+            .filter(|e| {
+                e.name
+                    .rsplit_once('.')
+                    .is_some_and(|(_, ext)| extensions.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+            })
+            .collect();
+        match (by_ext.as_slice(), files.as_slice()) {
+            ([entry], _) | (_, [entry]) => Ok(*entry),
+            _ => Err(Error::NoArchiveEntry),
+        }
+    }
+
+    fn unpack(&self, folder: &Folder) -> Result<Vec<u8>> {
+        let packed = self
+            .data
+            .get(folder.pack_offset..)
+            .and_then(|data| data.get(..folder.pack_size))
+            .ok_or(Error::InvalidArchive)?;
+        let data = match folder.method {
+            Method::Copy => packed.to_vec(),
+            Method::Lzma(props) => lzma::decode(packed, props, folder.size)?,
+            Method::Lzma2(_) => lzma::decode2(packed, folder.size)?,
+        };
+
+        if data.len() != folder.size || folder.crc.is_some_and(|crc| crc != hash::crc32(&data)) {
+            return Err(Error::InvalidArchive);
+        }
+        Ok(data)
+    }
+
+    fn parse_header(&mut self, cursor: &mut Cursor) -> Result<()> {
+        let mut id = cursor.byte()?;
+        if id == property::ARCHIVE_PROPERTIES {
+            while cursor.byte()? != property::END {
+                let size = cursor.usize()?;
+                cursor.take(size)?;
+            }
+            id = cursor.byte()?;
+        }
+        if id == property::ADDITIONAL_STREAMS {
+            StreamsInfo::parse(cursor)?;
+            id = cursor.byte()?;
+        }
+
+        let mut streams = StreamsInfo::default();
+        if id == property::MAIN_STREAMS {
+            streams = StreamsInfo::parse(cursor)?;
+            id = cursor.byte()?;
+        }
+
+        let mut files = Vec::new();
+        if id == property::FILES {
+            files = parse_files(cursor)?;
+            id = cursor.byte()?;
+        }
+        if id != property::END {
+            return Err(Error::InvalidArchive);
+        }
+
+        let mut stored = streams
+            .streams
+            .iter()
+            .enumerate()
+            .flat_map(|(folder, streams)| {
+                streams.iter().scan(0, move |offset, &(size, crc)| {
+                    let start = *offset;
+                    *offset += size;
+                    Some(((folder, start), size, crc))
+                })
+            });
+        for (name, kind) in files {
+            self.entries.push(match kind {
+                FileKind::Stored => {
+                    let (location, size, crc) = stored.next().ok_or(Error::InvalidArchive)?;
+                    SevenZipEntry {
+                        name,
+                        size,
+                        crc,
+                        is_dir: false,
+                        location: Some(location),
+                    }
+                }
+                FileKind::Empty | FileKind::Dir => SevenZipEntry {
+                    name,
+                    size: 0,
+                    crc: None,
+                    is_dir: kind == FileKind::Dir,
+                    location: None,
+                },
+            });
+        }
+
+        self.folders = streams.folders;
+        Ok(())
+    }
+}
+
+mod property {
+    pub const END: u8 = 0x00;
+    pub const HEADER: u8 = 0x01;
+    pub const ARCHIVE_PROPERTIES: u8 = 0x02;
+    pub const ADDITIONAL_STREAMS: u8 = 0x03;
+    pub const MAIN_STREAMS: u8 = 0x04;
+    pub const FILES: u8 = 0x05;
+    pub const PACK_INFO: u8 = 0x06;
+    pub const UNPACK_INFO: u8 = 0x07;
+    pub const SUBSTREAMS_INFO: u8 = 0x08;
+    pub const SIZE: u8 = 0x09;
+    pub const CRC: u8 = 0x0A;
+    pub const FOLDER: u8 = 0x0B;
+    pub const UNPACK_SIZE: u8 = 0x0C;
+    pub const NUM_UNPACK_STREAMS: u8 = 0x0D;
+    pub const EMPTY_STREAM: u8 = 0x0E;
+    pub const EMPTY_FILE: u8 = 0x0F;
+    pub const NAME: u8 = 0x11;
+    pub const ENCODED_HEADER: u8 = 0x17;
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            return Err(Error::InvalidArchive);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(LE::read_u32(self.take(4)?))
+    }
+
+    /// Reads a number whose first byte's leading 1 bits count the bytes that follow it.
+    fn number(&mut self) -> Result<u64> {
+        let first = self.byte()?;
+        let mut value = 0;
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+            if first & mask == 0 {
+                return Ok(value | (((first & (mask - 1)) as u64) << (8 * i)));
+            }
+            value |= (self.byte()? as u64) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        self.number().and_then(to_usize)
+    }
+
+    /// Reads a number of items that the rest of the header goes on to describe. Each takes at
+    /// least a byte, so a count larger than what's left is rejected before anything is allocated
+    /// for it.
+    fn count(&mut self) -> Result<usize> {
+        match self.usize()? {
+            count if count > self.0.len() => Err(Error::InvalidArchive),
+            count => Ok(count),
+        }
+    }
+
+    fn expect(&mut self, id: u8) -> Result<()> {
+        match self.byte()? {
+            byte if byte == id => Ok(()),
+            _ => Err(Error::InvalidArchive),
+        }
+    }
+
+    /// Reads `len` bits, most significant first.
+    fn bits(&mut self, len: usize) -> Result<Vec<bool>> {
+        let bytes = self.take(len.div_ceil(8))?;
+        Ok((0..len)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+
+    /// Reads `len` optional CRCs, preceded by which of them are present.
+    fn digests(&mut self, len: usize) -> Result<Vec<Option<u32>>> {
+        let defined = match self.byte()? {
+            0 => self.bits(len)?,
+            _ if len > self.0.len() / 4 => return Err(Error::InvalidArchive),
+            _ => vec![true; len],
+        };
+        defined
+            .into_iter()
+            .map(|defined| defined.then(|| self.u32()).transpose())
+            .collect()
+    }
+}
+
+fn to_usize(value: u64) -> Result<usize> {
+    usize::try_from(value).map_err(|_| Error::InvalidArchive)
+}
+
+impl StreamsInfo {
+    fn parse(cursor: &mut Cursor) -> Result<Self> {
+        let mut pack_offset = SevenZipArchive::SIGNATURE_HEADER_SIZE;
+        let mut pack_sizes = Vec::new();
+        let mut folders = Vec::new();
+        let mut id = cursor.byte()?;
+        if id == property::PACK_INFO {
+            pack_offset = pack_offset
+                .checked_add(cursor.usize()?)
+                .ok_or(Error::InvalidArchive)?;
+            let count = cursor.count()?;
+            loop {
+                match cursor.byte()? {
+                    property::END => break,
+                    property::SIZE => {
+                        pack_sizes = (0..count).map(|_| cursor.usize()).collect::<Result<_>>()?
+                    }
+                    property::CRC => _ = cursor.digests(count)?,
+                    _ => return Err(Error::InvalidArchive),
+                }
+            }
+            id = cursor.byte()?;
+        }
+
+        if id == property::UNPACK_INFO {
+            cursor.expect(property::FOLDER)?;
+            let count = cursor.count()?;
+            if cursor.byte()? != 0 {
+                // folders stored in another stream, which 7-Zip never writes
+                return Err(Error::InvalidArchive);
+            }
+
+            let mut offsets = pack_sizes.iter().scan(pack_offset, |offset, &size| {
+                let start = *offset;
+                // an offset past the end fails when the folder is read
+                *offset = start.saturating_add(size);
+                Some((start, size))
+            });
+            for _ in 0..count {
+                let method = parse_folder(cursor)?;
+                let (pack_offset, pack_size) = offsets.next().ok_or(Error::InvalidArchive)?;
+                folders.push(Folder {
+                    method,
+                    pack_offset,
+                    pack_size,
+                    size: 0,
+                    crc: None,
+                });
+            }
+
+            cursor.expect(property::UNPACK_SIZE)?;
+            for folder in folders.iter_mut() {
+                folder.size = cursor.usize()?;
+            }
+            loop {
+                match cursor.byte()? {
+                    property::END => break,
+                    property::CRC => {
+                        for (folder, crc) in folders.iter_mut().zip(cursor.digests(count)?) {
+                            folder.crc = crc;
+                        }
+                    }
+                    _ => return Err(Error::InvalidArchive),
+                }
+            }
+            id = cursor.byte()?;
+        }
+
+        // without substream info, each folder holds one file
+        let mut counts = vec![1; folders.len()];
+        let mut sizes: Vec<Vec<usize>> = folders.iter().map(|f| vec![f.size]).collect();
+        let mut crcs: Vec<Vec<Option<u32>>> = folders.iter().map(|f| vec![f.crc]).collect();
+        if id == property::SUBSTREAMS_INFO {
+            id = cursor.byte()?;
+            if id == property::NUM_UNPACK_STREAMS {
+                for count in counts.iter_mut() {
+                    *count = cursor.count()?;
+                }
+                // each stream is a file named later in the header, so together they're bounded
+                // by what's left of it, as each one alone is by `count`
+                let total = counts
+                    .iter()
+                    .try_fold(0usize, |total, &count| total.checked_add(count));
+                if total.is_none_or(|total| total > cursor.0.len()) {
+                    return Err(Error::InvalidArchive);
+                }
+                id = cursor.byte()?;
+            }
+
+            for ((folder, &count), sizes) in folders.iter().zip(&counts).zip(sizes.iter_mut()) {
+                sizes.clear();
+                if count == 0 {
+                    continue;
+                }
+                if id == property::SIZE {
+                    for _ in 1..count {
+                        sizes.push(cursor.usize()?);
+                    }
+                }
+                let rest = sizes
+                    .iter()
+                    .try_fold(0usize, |sum, &size| sum.checked_add(size))
+                    .and_then(|sum| folder.size.checked_sub(sum))
+                    .ok_or(Error::InvalidArchive)?;
+                sizes.push(rest);
+            }
+            if id == property::SIZE {
+                id = cursor.byte()?;
+            }
+
+            // a folder holding a single file already has its CRC
+            let unknown = |(folder, &count): (&Folder, &usize)| count != 1 || folder.crc.is_none();
+            for ((folder, &count), crcs) in folders.iter().zip(&counts).zip(crcs.iter_mut()) {
+                *crcs = match count {
+                    1 => vec![folder.crc],
+                    _ => vec![None; count],
+                };
+            }
+            if id == property::CRC {
+                let total = folders
+                    .iter()
+                    .zip(&counts)
+                    .filter(|&pair| unknown(pair))
+                    .map(|(_, &count)| count)
+                    .sum();
+                let mut digests = cursor.digests(total)?.into_iter();
+                for ((folder, count), crcs) in folders.iter().zip(&counts).zip(crcs.iter_mut()) {
+                    if unknown((folder, count)) {
+                        for crc in crcs.iter_mut() {
+                            *crc = digests.next().flatten();
+                        }
+                    }
+                }
+                id = cursor.byte()?;
+            }
+
+            if id != property::END {
+                return Err(Error::InvalidArchive);
+            }
+            id = cursor.byte()?;
+        }
+
+        if id != property::END {
+            return Err(Error::InvalidArchive);
+        }
+
+        Ok(Self {
+            folders,
+            streams: sizes
+                .into_iter()
+                .zip(crcs)
+                .map(|(sizes, crcs)| sizes.into_iter().zip(crcs).collect())
+                .collect(),
+        })
+    }
+}
+
+fn parse_folder(cursor: &mut Cursor) -> Result<Method> {
+    if cursor.usize()? != 1 {
+        return Err(Error::InvalidArchive);
+    }
+
+    let flags = cursor.byte()?;
+    let id = cursor.take((flags & 0x0F) as usize)?;
+    // complex coders have more than one input or output, and alternative methods aren't used
+    if flags & 0x10 != 0 || flags & 0x80 != 0 {
+        return Err(Error::InvalidArchive);
+    }
+    let props = match flags & 0x20 {
+        0 => &[][..],
+        _ => {
+            let len = cursor.usize()?;
+            cursor.take(len)?
+        }
+    };
+
+    match (id, props) {
+        ([0x00], _) => Ok(Method::Copy),
+        ([0x03, 0x01, 0x01], &[a, b, c, d, e]) => Ok(Method::Lzma([a, b, c, d, e])),
+        ([0x21], &[dict]) => Ok(Method::Lzma2(dict)),
+        _ => Err(Error::InvalidArchive),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Stored,
+    Empty,
+    Dir,
+}
+
+fn parse_files(cursor: &mut Cursor) -> Result<Vec<(String, FileKind)>> {
+    let count = cursor.count()?;
+    let mut names = Vec::new();
+    let mut empty_stream = vec![false; count];
+    let mut empty_file = Vec::new();
+    loop {
+        let id = cursor.byte()?;
+        if id == property::END {
+            break;
+        }
+
+        let size = cursor.usize()?;
+        let mut data = Cursor(cursor.take(size)?);
+        match id {
+            property::EMPTY_STREAM => empty_stream = data.bits(count)?,
+            property::EMPTY_FILE => {
+                empty_file = data.bits(empty_stream.iter().filter(|&&e| e).count())?
+            }
+            property::NAME => {
+                if data.byte()? != 0 {
+                    return Err(Error::InvalidArchive);
+                }
+                let units: Vec<u16> = data.0.chunks_exact(2).map(LE::read_u16).collect();
+                names = units
+                    .split(|&unit| unit == 0)
+                    .take(count)
+                    .map(String::from_utf16_lossy)
+                    .collect();
+            }
+            // times, attributes, and padding
+            _ => {}
+        }
+    }
+
+    if names.len() != count {
+        return Err(Error::InvalidArchive);
+    }
+
+    let mut empty_file = empty_file.into_iter();
+    Ok(names
+        .into_iter()
+        .zip(empty_stream)
+        .map(|(name, empty)| {
+            // an empty stream is a directory unless it's marked as an empty file
+            let kind = if !empty {
+                FileKind::Stored
+            } else if empty_file.next().unwrap_or(false) {
+                FileKind::Empty
+            } else {
+                FileKind::Dir
+            };
+            (name, kind)
+        })
+        .collect())
+}
+
+/// LZMA and LZMA2 decoding, following the reference decoder in the LZMA SDK.
+mod lzma {
+    use crate::{Error, Result};
+
+    const BIT_MODEL_TOTAL: u16 = 1 << 11;
+    const PROB_INIT: u16 = BIT_MODEL_TOTAL / 2;
+    const NUM_STATES: usize = 12;
+    const POS_BITS_MAX: usize = 4;
+    const END_POS_MODEL_INDEX: usize = 14;
+    const NUM_FULL_DISTANCES: usize = 128;
+    const MATCH_MIN_LEN: usize = 2;
+
+    struct RangeDecoder<'a> {
+        data: &'a [u8],
+        pos: usize,
+        range: u32,
+        code: u32,
+    }
+
+    impl<'a> RangeDecoder<'a> {
+        fn new(data: &'a [u8]) -> Result<Self> {
+            if data.len() < 5 || data[0] != 0 {
+                return Err(Error::InvalidArchive);
+            }
+            Ok(Self {
+                data,
+                pos: 5,
+                range: u32::MAX,
+                code: u32::from_be_bytes([data[1], data[2], data[3], data[4]]),
+            })
+        }
+
+        fn normalize(&mut self) -> Result<()> {
+            if self.range < 1 << 24 {
+                let byte = *self.data.get(self.pos).ok_or(Error::InvalidArchive)?;
+                self.pos += 1;
+                self.range <<= 8;
+                self.code = (self.code << 8) | byte as u32;
+            }
+            Ok(())
+        }
+
+        fn bit(&mut self, prob: &mut u16) -> Result<u32> {
+            let bound = (self.range >> 11) * *prob as u32;
+            let bit = if self.code < bound {
+                self.range = bound;
+                *prob += (BIT_MODEL_TOTAL - *prob) >> 5;
+                0
+            } else {
+                self.range -= bound;
+                self.code -= bound;
+                *prob -= *prob >> 5;
+                1
+            };
+            self.normalize()?;
+            Ok(bit)
+        }
+
+        fn direct(&mut self, count: usize) -> Result<u32> {
+            let mut value = 0u32;
+            for _ in 0..count {
+                self.range >>= 1;
+                let bit = (self.code >= self.range) as u32;
+                if bit == 1 {
+                    self.code -= self.range;
+                }
+                value = (value << 1) | bit;
+                self.normalize()?;
+            }
+            Ok(value)
+        }
+
+        fn tree(&mut self, probs: &mut [u16], bits: usize) -> Result<usize> {
+            let mut m = 1;
+            for _ in 0..bits {
+                m = (m << 1) | self.bit(&mut probs[m])? as usize;
+            }
+            Ok(m - (1 << bits))
+        }
+
+        fn reverse_tree(&mut self, probs: &mut [u16], bits: usize) -> Result<usize> {
+            let mut m = 1;
+            let mut symbol = 0;
+            for i in 0..bits {
+                let bit = self.bit(&mut probs[m])? as usize;
+                m = (m << 1) | bit;
+                symbol |= bit << i;
+            }
+            Ok(symbol)
+        }
+    }
+
+    #[derive(Clone)]
+    struct LenDecoder {
+        choice: u16,
+        choice2: u16,
+        low: [[u16; 8]; 1 << POS_BITS_MAX],
+        mid: [[u16; 8]; 1 << POS_BITS_MAX],
+        high: [u16; 256],
+    }
+
+    impl LenDecoder {
+        fn new() -> Self {
+            Self {
+                choice: PROB_INIT,
+                choice2: PROB_INIT,
+                low: [[PROB_INIT; 8]; 1 << POS_BITS_MAX],
+                mid: [[PROB_INIT; 8]; 1 << POS_BITS_MAX],
+                high: [PROB_INIT; 256],
+            }
+        }
+
+        fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> Result<usize> {
+            if rc.bit(&mut self.choice)? == 0 {
+                return rc.tree(&mut self.low[pos_state], 3);
+            }
+            if rc.bit(&mut self.choice2)? == 0 {
+                return Ok(8 + rc.tree(&mut self.mid[pos_state], 3)?);
+            }
+            Ok(16 + rc.tree(&mut self.high, 8)?)
+        }
+    }
+
+    /// The decoder state that persists between LZMA2 chunks unless they reset it.
+    struct Decoder {
+        lc: usize,
+        lp: usize,
+        pb: usize,
+        literals: Vec<u16>,
+        pos_slot: [[u16; 64]; 4],
+        pos: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX],
+        align: [u16; 16],
+        is_match: [u16; NUM_STATES << POS_BITS_MAX],
+        is_rep: [u16; NUM_STATES],
+        is_rep_g0: [u16; NUM_STATES],
+        is_rep_g1: [u16; NUM_STATES],
+        is_rep_g2: [u16; NUM_STATES],
+        is_rep0_long: [u16; NUM_STATES << POS_BITS_MAX],
+        len: LenDecoder,
+        rep_len: LenDecoder,
+        state: usize,
+        reps: [usize; 4],
+        /// Where the current dictionary starts in the output.
+        dict_start: usize,
+    }
+
+    impl Decoder {
+        fn new(props: u8) -> Result<Self> {
+            let mut decoder = Self::fresh(0, 0, 0, 0);
+            decoder.set_props(props)?;
+            Ok(decoder)
+        }
+
+        fn fresh(lc: usize, lp: usize, pb: usize, dict_start: usize) -> Self {
+            Self {
+                lc,
+                lp,
+                pb,
+                literals: vec![PROB_INIT; 0x300 << (lc + lp)],
+                pos_slot: [[PROB_INIT; 64]; 4],
+                pos: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX],
+                align: [PROB_INIT; 16],
+                is_match: [PROB_INIT; NUM_STATES << POS_BITS_MAX],
+                is_rep: [PROB_INIT; NUM_STATES],
+                is_rep_g0: [PROB_INIT; NUM_STATES],
+                is_rep_g1: [PROB_INIT; NUM_STATES],
+                is_rep_g2: [PROB_INIT; NUM_STATES],
+                is_rep0_long: [PROB_INIT; NUM_STATES << POS_BITS_MAX],
+                len: LenDecoder::new(),
+                rep_len: LenDecoder::new(),
+                state: 0,
+                reps: [0; 4],
+                dict_start,
+            }
+        }
+
+        fn set_props(&mut self, props: u8) -> Result<()> {
+            let props = props as usize;
+            if props >= 9 * 5 * 5 {
+                return Err(Error::InvalidArchive);
+            }
+            self.lc = props % 9;
+            self.lp = props / 9 % 5;
+            self.pb = props / 45;
+            self.reset_state();
+            Ok(())
+        }
+
+        fn reset_state(&mut self) {
+            *self = Self::fresh(self.lc, self.lp, self.pb, self.dict_start);
+        }
+
+        /// Decodes until `out` holds `end` bytes.
+        fn decode(&mut self, rc: &mut RangeDecoder, out: &mut Vec<u8>, end: usize) -> Result<()> {
+            let pb_mask = (1 << self.pb) - 1;
+            let lp_mask = (1 << self.lp) - 1;
+            while out.len() < end {
+                let pos = out.len() - self.dict_start;
+                let pos_state = pos & pb_mask;
+                let state = self.state;
+                if rc.bit(&mut self.is_match[(state << POS_BITS_MAX) + pos_state])? == 0 {
+                    let prev = if pos > 0 { out[out.len() - 1] } else { 0 };
+                    let lit_state = ((pos & lp_mask) << self.lc) + (prev as usize >> (8 - self.lc));
+                    let probs = &mut self.literals[0x300 * lit_state..0x300 * (lit_state + 1)];
+                    let mut symbol = 1;
+                    if state >= 7 {
+                        let mut match_byte = out[out.len() - self.reps[0] - 1] as usize;
+                        while symbol < 0x100 {
+                            let match_bit = (match_byte >> 7) & 1;
+                            match_byte <<= 1;
+                            let bit = rc.bit(&mut probs[((1 + match_bit) << 8) + symbol])?;
+                            symbol = (symbol << 1) | bit as usize;
+                            if match_bit != bit as usize {
+                                break;
+                            }
+                        }
+                    }
+                    while symbol < 0x100 {
+                        symbol = (symbol << 1) | rc.bit(&mut probs[symbol])? as usize;
+                    }
+                    out.push(symbol as u8);
+                    self.state = match state {
+                        0..=3 => 0,
+                        4..=9 => state - 3,
+                        _ => state - 6,
+                    };
+                    continue;
+                }
+
+                let len = if rc.bit(&mut self.is_rep[state])? != 0 {
+                    if pos == 0 {
+                        return Err(Error::InvalidArchive);
+                    }
+                    if rc.bit(&mut self.is_rep_g0[state])? == 0 {
+                        if rc.bit(&mut self.is_rep0_long[(state << POS_BITS_MAX) + pos_state])? == 0
+                        {
+                            self.state = if state < 7 { 9 } else { 11 };
+                            out.push(out[out.len() - self.reps[0] - 1]);
+                            continue;
+                        }
+                    } else {
+                        let dist = if rc.bit(&mut self.is_rep_g1[state])? == 0 {
+                            self.reps[1]
+                        } else if rc.bit(&mut self.is_rep_g2[state])? == 0 {
+                            let dist = self.reps[2];
+                            self.reps[2] = self.reps[1];
+                            dist
+                        } else {
+                            let dist = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                            self.reps[2] = self.reps[1];
+                            dist
+                        };
+                        self.reps[1] = self.reps[0];
+                        self.reps[0] = dist;
+                    }
+                    self.state = if state < 7 { 8 } else { 11 };
+                    self.rep_len.decode(rc, pos_state)?
+                } else {
+                    self.reps.copy_within(0..3, 1);
+                    let len = self.len.decode(rc, pos_state)?;
+                    self.state = if state < 7 { 7 } else { 10 };
+                    self.reps[0] = self.distance(rc, len)?;
+                    if self.reps[0] == u32::MAX as usize {
+                        // an end marker
+                        return Err(Error::InvalidArchive);
+                    }
+                    len
+                };
+
+                let dist = self.reps[0] + 1;
+                if dist > pos {
+                    return Err(Error::InvalidArchive);
+                }
+                let start = out.len() - dist;
+                for i in 0..(len + MATCH_MIN_LEN).min(end - out.len()) {
+                    out.push(out[start + i]);
+                }
+            }
+            Ok(())
+        }
+
+        fn distance(&mut self, rc: &mut RangeDecoder, len: usize) -> Result<usize> {
+            let slot = rc.tree(&mut self.pos_slot[len.min(3)], 6)?;
+            if slot < 4 {
+                return Ok(slot);
+            }
+
+            let direct = (slot >> 1) - 1;
+            let mut dist = (2 | (slot & 1)) << direct;
+            if slot < END_POS_MODEL_INDEX {
+                dist += rc.reverse_tree(&mut self.pos[dist - slot..], direct)?;
+            } else {
+                dist += (rc.direct(direct - 4)? as usize) << 4;
+                dist += rc.reverse_tree(&mut self.align, 4)?;
+            }
+            Ok(dist)
+        }
+    }
+
+    /// How many times the size of the compressed data is reserved for the output up front. The
+    /// output grows past it as needed, so an archive declaring a huge size can't reserve memory
+    /// it has no data for.
+    const RESERVE_RATIO: usize = 8;
+
+    fn output(data: &[u8], size: usize) -> Vec<u8> {
+        Vec::with_capacity(size.min(data.len().saturating_mul(RESERVE_RATIO)))
+    }
+
+    /// Decodes an LZMA stream with the given 5-byte properties, producing `size` bytes.
+    pub fn decode(data: &[u8], props: [u8; 5], size: usize) -> Result<Vec<u8>> {
+        let mut decoder = Decoder::new(props[0])?;
+        let mut out = output(data, size);
+        decoder.decode(&mut RangeDecoder::new(data)?, &mut out, size)?;
+        Ok(out)
+    }
+
+    /// Decodes an LZMA2 stream, a series of chunks that are either LZMA or stored.
+    /// Fails if the chunks produce more than `size` bytes.
+    pub fn decode2(mut data: &[u8], size: usize) -> Result<Vec<u8>> {
+        let mut out = output(data, size);
+        let mut decoder: Option<Decoder> = None;
+        loop {
+            let (&control, rest) = data.split_first().ok_or(Error::InvalidArchive)?;
+            data = rest;
+            match control {
+                0x00 => return Ok(out),
+                0x01 | 0x02 => {
+                    let header = data.get(..2).ok_or(Error::InvalidArchive)?;
+                    let len = u16::from_be_bytes([header[0], header[1]]) as usize + 1;
+                    let chunk = data.get(2..2 + len).ok_or(Error::InvalidArchive)?;
+                    if out.len() + len > size {
+                        return Err(Error::InvalidArchive);
+                    }
+                    if control == 0x01 {
+                        if let Some(decoder) = &mut decoder {
+                            decoder.dict_start = out.len();
+                        }
+                    }
+                    out.extend_from_slice(chunk);
+                    data = &data[2 + len..];
+                }
+                0x80.. => {
+                    let header = data.get(..4).ok_or(Error::InvalidArchive)?;
+                    let unpacked = ((control as usize & 0x1F) << 16)
+                        + ((header[0] as usize) << 8)
+                        + header[1] as usize
+                        + 1;
+                    let packed = ((header[2] as usize) << 8) + header[3] as usize + 1;
+                    data = &data[4..];
+                    if out.len() + unpacked > size {
+                        return Err(Error::InvalidArchive);
+                    }
+
+                    let reset = (control >> 5) & 0b11;
+                    if reset >= 2 {
+                        let (&props, rest) = data.split_first().ok_or(Error::InvalidArchive)?;
+                        data = rest;
+                        match &mut decoder {
+                            Some(decoder) => decoder.set_props(props)?,
+                            None => decoder = Some(Decoder::new(props)?),
+                        }
+                    }
+                    let decoder = decoder.as_mut().ok_or(Error::InvalidArchive)?;
+                    if reset == 3 {
+                        decoder.dict_start = out.len();
+                    }
+                    if reset >= 1 {
+                        decoder.reset_state();
+                    }
+
+                    let chunk = data.get(..packed).ok_or(Error::InvalidArchive)?;
+                    let end = out.len() + unpacked;
+                    decoder.decode(&mut RangeDecoder::new(chunk)?, &mut out, end)?;
+                    data = &data[packed..];
+                }
+                _ => return Err(Error::InvalidArchive),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An LZMA2 folder holding `docs/readme.txt` and `hack.ips`, plus the `docs` directory and an
+    // empty `empty.txt`, with the header compressed with LZMA.
+    const ARCHIVE: &[u8] = &[
+        0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c, 0x00, 0x04, 0x89, 0x9d, 0x68, 0x4d, 0xb0, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x88, 0x9a,
+        0x17, 0x1f, 0xe0, 0x00, 0x73, 0x00, 0x3d, 0x5d, 0x00, 0x20, 0x9c, 0x2c, 0xd8, 0x79, 0x10,
+        0x15, 0x36, 0x0e, 0x9f, 0x67, 0x4d, 0xfd, 0x19, 0xba, 0xe8, 0x66, 0x5a, 0x66, 0xa3, 0x18,
+        0xb5, 0x87, 0xff, 0x44, 0x79, 0x80, 0x80, 0x9a, 0x0c, 0x40, 0x6e, 0xa0, 0x44, 0x52, 0xfa,
+        0x41, 0xf6, 0xb0, 0x88, 0x2c, 0xe6, 0xc0, 0x03, 0xe4, 0xe1, 0x70, 0x9e, 0x06, 0xb3, 0x43,
+        0x86, 0xc0, 0xae, 0xfe, 0x58, 0xf6, 0x79, 0x1c, 0x11, 0x00, 0x00, 0x00, 0x00, 0x81, 0x33,
+        0x07, 0xae, 0x0f, 0xd2, 0x33, 0x80, 0x3d, 0x40, 0xc0, 0x90, 0xd2, 0xff, 0x74, 0xa1, 0xcd,
+        0x8a, 0xab, 0xe8, 0xdb, 0xe1, 0xae, 0x6e, 0xc3, 0x9c, 0xf4, 0x4b, 0x0c, 0x88, 0xd3, 0x87,
+        0x37, 0xde, 0xea, 0x1d, 0xc2, 0x03, 0xd3, 0x49, 0x2c, 0xbf, 0x80, 0x19, 0x74, 0xb1, 0xb5,
+        0x90, 0x27, 0xbd, 0x71, 0xb9, 0x02, 0x6f, 0x76, 0xd2, 0x02, 0xb5, 0x45, 0xee, 0x99, 0x13,
+        0x98, 0xa5, 0x66, 0x08, 0x9a, 0xe9, 0x59, 0xe9, 0xb0, 0xd9, 0x54, 0x79, 0x80, 0xdf, 0x21,
+        0x64, 0xbb, 0x25, 0xda, 0x7e, 0x94, 0x30, 0xd7, 0x3e, 0x19, 0xf0, 0x4d, 0x52, 0x6d, 0xcb,
+        0xf7, 0xcd, 0x1f, 0x0b, 0x6e, 0x3a, 0xeb, 0x6f, 0xff, 0xfe, 0x7f, 0x14, 0x00, 0x17, 0x06,
+        0x45, 0x01, 0x09, 0x6b, 0x00, 0x07, 0x0b, 0x01, 0x00, 0x01, 0x23, 0x03, 0x01, 0x01, 0x05,
+        0x5d, 0x00, 0x00, 0x01, 0x00, 0x0c, 0x80, 0x82, 0x0a, 0x01, 0xdf, 0xdc, 0x0f, 0x63, 0x00,
+        0x00,
+    ];
+
+    #[test]
+    fn read_entries() {
+        let archive = SevenZipArchive::new(ARCHIVE).unwrap();
+        let names: Vec<_> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["docs", "docs/readme.txt", "hack.ips", "empty.txt"]);
+        assert!(archive.entries()[0].is_dir);
+
+        let readme = archive.read(&archive.entries()[1]).unwrap();
+        assert_eq!(readme, b"Apply hack.ips to a clean ROM.\n".repeat(3));
+        assert!(archive.read(&archive.entries()[3]).unwrap().is_empty());
+
+        let patch = archive.select(PATCH_EXTENSIONS).unwrap();
+        assert_eq!(patch.name, "hack.ips");
+        let patch = crate::load_named(&archive.read(patch).unwrap(), &patch.name).unwrap();
+        assert_eq!(patch.apply(b"\0\0\0").unwrap(), b"\0hi\0\0!!!");
+    }
+
+    #[test]
+    fn nested_encoded_header() {
+        // an encoded header whose packed stream, stored with the Copy method, is itself
+        let header = [
+            0x17, 0x06, 0x00, 0x01, 0x09, 0x12, 0x00, 0x07, 0x0B, 0x01, 0x00, 0x01, 0x01, 0x00,
+            0x0C, 0x12, 0x00, 0x00,
+        ];
+        let mut data = b"7z\xBC\xAF\x27\x1C\0\x04".to_vec();
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        data.extend_from_slice(&hash::crc32(&header).to_le_bytes());
+        let crc = hash::crc32(&data[12..]);
+        data[8..12].copy_from_slice(&crc.to_le_bytes());
+        data.extend_from_slice(&header);
+
+        assert_eq!(data.len(), 50);
+        assert!(matches!(
+            SevenZipArchive::new(&data),
+            Err(Error::InvalidArchive)
+        ));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut data = ARCHIVE.to_vec();
+        data[40] ^= 1;
+        let archive = SevenZipArchive::new(&data).unwrap();
+        assert!(matches!(
+            archive.read(&archive.entries()[1]),
+            Err(Error::InvalidArchive)
+        ));
+
+        data[20] ^= 1;
+        assert!(matches!(
+            SevenZipArchive::new(&data),
+            Err(Error::InvalidArchive)
+        ));
+        assert!(matches!(
+            SevenZipArchive::new(&ARCHIVE[..100]),
+            Err(Error::InvalidArchive)
+        ));
+    }
+
+    #[test]
+    fn malformed_header() {
+        // counts and digests the rest of the header can't hold are rejected before allocating
+        assert!(matches!(
+            Cursor(&[0x05, 1, 2]).count(),
+            Err(Error::InvalidArchive)
+        ));
+        assert_eq!(Cursor(&[0x02, 1, 2]).count().unwrap(), 2);
+        let huge = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert!(Cursor(&huge).count().is_err());
+        assert!(matches!(
+            Cursor(&[1, 0, 0, 0, 0]).digests(usize::MAX),
+            Err(Error::InvalidArchive)
+        ));
+        assert_eq!(Cursor(&[1, 1, 0, 0, 0]).digests(1).unwrap(), [Some(1)]);
+
+        // two stored folders of a byte each, split into more streams than the header has room
+        // to name, though each count alone fits
+        let streams = |counts: [u8; 2]| {
+            let mut data = vec![
+                0x06, 0x00, 0x02, 0x09, 0x01, 0x01, 0x00, 0x07, 0x0B, 0x02, 0x00, 0x01, 0x01, 0x00,
+                0x01, 0x01, 0x00, 0x0C, 0x01, 0x01, 0x00, 0x08, 0x0D,
+            ];
+            data.extend_from_slice(&counts);
+            data.extend_from_slice(&[0; 4]);
+            StreamsInfo::parse(&mut Cursor(&data))
+        };
+        assert!(matches!(streams([3, 3]), Err(Error::InvalidArchive)));
+        assert_eq!(streams([2, 2]).unwrap().streams.len(), 2);
+    }
+
+    #[test]
+    fn malformed_streams() {
+        use lzma::{decode, decode2};
+
+        // stored LZMA2 chunks, declaring a size far larger than the data
+        let stored = b"\x01\x00\x02abc\x02\x00\x00d\x00";
+        assert_eq!(decode2(stored, usize::MAX).unwrap(), b"abcd");
+        assert!(matches!(decode2(stored, 3), Err(Error::InvalidArchive)));
+        for truncated in [&stored[..stored.len() - 1], &stored[..4], b""] {
+            assert!(matches!(decode2(truncated, 4), Err(Error::InvalidArchive)));
+        }
+        // an LZMA chunk before any properties, and invalid properties
+        for data in [
+            &b"\x80\x00\x00\x00\x04\0\0\0\0\0\x00"[..],
+            b"\xE0\x00\x00\x00\x05\xFF\0\0\0\0\0\x00",
+        ] {
+            assert!(matches!(decode2(data, 1), Err(Error::InvalidArchive)));
+        }
+
+        // the largest dictionary and output size, with too little data to produce them
+        let props = [0x5D, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(matches!(
+            decode(&[0; 16], props, usize::MAX),
+            Err(Error::InvalidArchive)
+        ));
+        for (data, props) in [
+            (&b"\0\0\0"[..], props),
+            (b"\x01\0\0\0\0\0", props),
+            (&[0; 16], [225, 0, 0, 1, 0]),
+        ] {
+            assert!(matches!(decode(data, props, 1), Err(Error::InvalidArchive)));
+        }
+    }
+}
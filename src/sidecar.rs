@@ -0,0 +1,163 @@
+//! Checksum sidecar files (`.sfv`, `.md5`, `.sha1`, and `.sha256`) that often ship alongside
+//! patches to identify the ROM they target or the ROM they produce.
+
+use std::path::{Path, PathBuf};
+
+use crate::{hash, Error, Result};
+
+/// The extensions checked next to a patch, in order.
+pub const EXTENSIONS: [&str; 4] = ["sfv", "md5", "sha1", "sha256"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Crc32(u32),
+    Md5([u8; 16]),
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl Checksum {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Checksum::Crc32(_) => "CRC32",
+            Checksum::Md5(_) => "MD5",
+            Checksum::Sha1(_) => "SHA-1",
+            Checksum::Sha256(_) => "SHA-256",
+        }
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            Checksum::Crc32(crc) => hash::crc32(data) == *crc,
+            Checksum::Md5(digest) => hash::md5(data) == *digest,
+            Checksum::Sha1(digest) => hash::sha1(data) == *digest,
+            Checksum::Sha256(digest) => hash::sha256(data) == *digest,
+        }
+    }
+
+    fn parse(name: &str, hex: &str) -> Option<Self> {
+        fn digest<const N: usize>(hex: &str) -> Option<[u8; N]> {
+            let mut out = [0; N];
+            if hex.len() != N * 2 {
+                return None;
+            }
+            for (byte, i) in out.iter_mut().zip((0..hex.len()).step_by(2)) {
+                *byte = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+            }
+            Some(out)
+        }
+
+        match name.to_ascii_lowercase().as_str() {
+            "sfv" | "crc32" => Some(Self::Crc32(u32::from_be_bytes(digest(hex)?))),
+            "md5" => Some(Self::Md5(digest(hex)?)),
+            "sha1" => Some(Self::Sha1(digest(hex)?)),
+            "sha256" => Some(Self::Sha256(digest(hex)?)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub checksum: Checksum,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    pub entries: Vec<Entry>,
+}
+
+impl Sidecar {
+    /// Returns the first sidecar sitting next to `patch`, e.g. `hack.sfv` for `hack.bps`.
+    pub fn find(patch: &Path) -> Option<PathBuf> {
+        EXTENSIONS
+            .iter()
+            .map(|ext| patch.with_extension(ext))
+            .find(|path| path.is_file())
+    }
+
+    /// Loads a sidecar, using its extension to decide the format.
+    pub fn load(path: &Path) -> Result<Self> {
+        let kind = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or(Error::InvalidSidecar)?;
+        Self::parse(&std::fs::read_to_string(path)?, kind)
+    }
+
+    /// Parses a sidecar of the given kind (`sfv`, `md5`, `sha1`, or `sha256`). Both the GNU
+    /// (`<hash>  <file>`) and BSD (`MD5 (<file>) = <hash>`) styles are accepted.
+    pub fn parse(text: &str, kind: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            let bsd = line
+                .split_once(" (")
+                .zip(line.rsplit_once(") = "))
+                .filter(|((algorithm, _), _)| !algorithm.contains(' '));
+            let (name, kind, hex) = if let Some(((algorithm, _), (_, hex))) = bsd {
+                let name = &line[algorithm.len() + 2..line.len() - hex.len() - 4];
+                (name, algorithm.replace('-', ""), hex)
+            } else if kind.eq_ignore_ascii_case("sfv") {
+                let (name, hex) = line.rsplit_once(' ').ok_or(Error::InvalidSidecar)?;
+                (name.trim_end(), kind.to_string(), hex)
+            } else {
+                let (hex, name) = line.split_once(' ').ok_or(Error::InvalidSidecar)?;
+                (
+                    name.trim_start().trim_start_matches('*'),
+                    kind.to_string(),
+                    hex,
+                )
+            };
+
+            entries.push(Entry {
+                name: name.to_string(),
+                checksum: Checksum::parse(&kind, hex).ok_or(Error::InvalidSidecar)?,
+            });
+        }
+
+        if entries.is_empty() {
+            return Err(Error::InvalidSidecar);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Returns the entry whose checksum matches `data`, if any.
+    pub fn find_match(&self, data: &[u8]) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.checksum.matches(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        let sfv = Sidecar::parse("; comment\nGame (USA).sfc 352441C2\n", "sfv").unwrap();
+        assert_eq!(sfv.entries[0].name, "Game (USA).sfc");
+        assert!(sfv.find_match(b"abc").is_some());
+
+        let md5 = Sidecar::parse("900150983cd24fb0d6963f7d28e17f72 *game.sfc\n", "md5").unwrap();
+        assert_eq!(md5.entries[0].name, "game.sfc");
+        assert!(md5.find_match(b"abc").is_some());
+        assert!(md5.find_match(b"abd").is_none());
+
+        let bsd = Sidecar::parse(
+            "SHA1 (game.sfc) = a9993e364706816aba3e25717850c26c9cd0d89d\n",
+            "txt",
+        )
+        .unwrap();
+        assert_eq!(bsd.entries[0].checksum.name(), "SHA-1");
+        assert!(bsd.find_match(b"abc").is_some());
+
+        assert!(Sidecar::parse("not a checksum\n", "md5").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Conversion of Sega Genesis/Mega Drive `.smd` dumps, which interleave the ROM in 16KB blocks
+//! behind a 512-byte header, to and from the plain `.bin` layout patches target.
+
+const HEADER_SIZE: usize = 512;
+const BLOCK_SIZE: usize = 0x4000;
+
+/// Whether `rom` appears to be an interleaved `.smd` dump.
+pub fn is_smd(rom: &[u8]) -> bool {
+    rom.len() > HEADER_SIZE
+        && (rom.len() - HEADER_SIZE).is_multiple_of(BLOCK_SIZE)
+        && rom[8] == 0xAA
+        && rom[9] == 0xBB
+}
+
+/// Converts an `.smd` dump to the plain layout, dropping the header.
+pub fn deinterleave(rom: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0; rom.len().saturating_sub(HEADER_SIZE)];
+    for (src, dst) in rom[HEADER_SIZE.min(rom.len())..]
+        .chunks(BLOCK_SIZE)
+        .zip(buf.chunks_mut(BLOCK_SIZE))
+    {
+        let (odd, even) = src.split_at(src.len() / 2);
+        for (i, pair) in dst.chunks_exact_mut(2).enumerate() {
+            pair[0] = even[i];
+            pair[1] = odd[i];
+        }
+    }
+    buf
+}
+
+/// Converts a plain ROM to an `.smd` dump. The given header is reused if provided, otherwise a
+/// standard one is generated. The ROM is padded with zeroes to a multiple of 16KB.
+pub fn interleave(rom: &[u8], header: Option<&[u8]>) -> Vec<u8> {
+    let blocks = rom.len().div_ceil(BLOCK_SIZE);
+    let mut buf = vec![0; HEADER_SIZE + blocks * BLOCK_SIZE];
+    match header {
+        Some(header) => {
+            let len = header.len().min(HEADER_SIZE);
+            buf[..len].copy_from_slice(&header[..len]);
+        }
+        None => {
+            buf[0] = blocks as u8;
+            buf[1] = 0x03;
+            buf[8] = 0xAA;
+            buf[9] = 0xBB;
+            buf[10] = 0x06;
+        }
+    }
+
+    for (i, &byte) in rom.iter().enumerate() {
+        let (block, offset) = (i / BLOCK_SIZE, i % BLOCK_SIZE);
+        let half = if offset.is_multiple_of(2) {
+            BLOCK_SIZE / 2
+        } else {
+            0
+        };
+        buf[HEADER_SIZE + block * BLOCK_SIZE + half + offset / 2] = byte;
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let rom: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| (i * 7 % 251) as u8).collect();
+        let smd = interleave(&rom, None);
+        assert!(is_smd(&smd));
+        assert_eq!(smd[HEADER_SIZE], rom[1]);
+        assert_eq!(smd[HEADER_SIZE + BLOCK_SIZE / 2], rom[0]);
+        assert_eq!(deinterleave(&smd), rom);
+    }
+}
@@ -0,0 +1,78 @@
+//! Discovery of soft-patches following RetroArch's conventions, so emulator frontends can find
+//! the patches to apply to a ROM at load time.
+
+use std::path::{Path, PathBuf};
+
+/// The extensions checked, in the order RetroArch checks them. Only the first format with a
+/// patch present is used.
+pub const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+/// Returns the ordered list of soft-patches for `rom`, looking in the ROM's own directory.
+pub fn softpatches(rom: &Path) -> Vec<PathBuf> {
+    softpatches_in(rom, rom.parent().unwrap_or(Path::new("")))
+}
+
+/// Returns the ordered list of soft-patches for `rom`, looking in `dir`.
+///
+/// For a ROM named `game.sfc`, the first of `game.ips`, `game.bps`, and `game.ups` that exists
+/// determines the format. Additional patches of that format are then picked up as `game.ips1`,
+/// `game.ips2`, and so on, stopping at the first missing index.
+pub fn softpatches_in(rom: &Path, dir: &Path) -> Vec<PathBuf> {
+    let Some(stem) = rom.file_stem() else {
+        return Vec::new();
+    };
+
+    let base = dir.join(stem);
+    let with_ext = |ext: &str| {
+        let mut path = base.clone().into_os_string();
+        path.push(".");
+        path.push(ext);
+        PathBuf::from(path)
+    };
+
+    for ext in EXTENSIONS {
+        let first = with_ext(ext);
+        if !first.is_file() {
+            continue;
+        }
+
+        let mut patches = vec![first];
+        patches.extend(
+            (1..)
+                .map(|i| with_ext(&format!("{ext}{i}")))
+                .take_while(|path| path.is_file()),
+        );
+        return patches;
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn discovery_order() {
+        let dir = env::temp_dir().join(format!("patch-rs-softpatch-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "game.bps",
+            "game.ips",
+            "game.ips1",
+            "game.ips2",
+            "game.ips4",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let found = softpatches(&dir.join("game.sfc"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            found,
+            ["game.ips", "game.ips1", "game.ips2"].map(|name| dir.join(name))
+        );
+    }
+}
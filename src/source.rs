@@ -0,0 +1,123 @@
+//! Where patches read the original file from when it shouldn't be loaded into memory first.
+//!
+//! [`Patch::apply_source`](crate::Patch::apply_source) reads only the parts of a [`Source`] the
+//! patch needs, in chunks, so a gigantic source image never has to be fully resident alongside
+//! the output. Slices are sources, so a memory-mapped file can be passed as `&mut &*mmap`, and
+//! [`SeekSource`] reads from a [`File`](std::fs::File) or anything else that can seek.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{hash::Crc32Hasher, Error, Result, Validation};
+
+/// A source file that can be read at arbitrary offsets.
+pub trait Source {
+    /// The size of the source in bytes.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Fills `buf` with the bytes starting at `offset`, failing if that would read past the end.
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl Source for &[u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        let data = offset
+            .checked_add(buf.len())
+            .and_then(|end| self.get(offset..end))
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A [`Source`] that seeks and reads, such as a [`File`](std::fs::File).
+pub struct SeekSource<R> {
+    inner: R,
+    len: usize,
+}
+
+impl<R: Read + Seek> SeekSource<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let len = inner.seek(SeekFrom::End(0))? as usize;
+        Ok(Self { inner, len })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Source for SeekSource<R> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset as u64))?;
+        self.inner.read_exact(buf)
+    }
+}
+
+/// How much of the source is read at once while it's being hashed.
+const CHUNK_SIZE: usize = 0x10000;
+
+/// Checks the source against `expected` without reading all of it into memory at once.
+pub(crate) fn validate(source: &mut dyn Source, expected: &Validation) -> Result<()> {
+    if source.len() != expected.size {
+        return Err(Error::InvalidSize(source.len(), expected.size));
+    }
+    if expected.md5.is_some() || expected.sha1.is_some() || expected.sha256.is_some() {
+        let mut data = vec![0; source.len()];
+        source.read_at(0, &mut data)?;
+        return expected.validate(&data);
+    }
+
+    let mut hasher = Crc32Hasher::new();
+    let mut buf = vec![0; CHUNK_SIZE.min(source.len())];
+    for offset in (0..source.len()).step_by(CHUNK_SIZE) {
+        let buf = &mut buf[..CHUNK_SIZE.min(source.len() - offset)];
+        source.read_at(offset, buf)?;
+        hasher.update(buf);
+    }
+
+    let crc = hasher.finalize();
+    if crc != expected.crc {
+        return Err(Error::InvalidCRC(crc, expected.crc));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn apply_source() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!!!!";
+        let patches: [Box<dyn Patch>; 3] = [
+            Box::new(IpsPatch::create(src, dst)),
+            Box::new(UpsPatch::create(src, dst)),
+            Box::new(BpsPatch::create(src, dst)),
+        ];
+        for patch in patches {
+            let mut file = SeekSource::new(Cursor::new(src.to_vec())).unwrap();
+            assert_eq!(patch.apply_source(&mut file).unwrap(), dst);
+            assert_eq!(patch.apply_source(&mut &src[..]).unwrap(), dst);
+        }
+
+        let wrong = &mut &b"The quick brown cat"[..];
+        assert!(matches!(
+            UpsPatch::create(src, dst).apply_source(wrong),
+            Err(Error::InvalidCRC(..))
+        ));
+    }
+}
@@ -0,0 +1,598 @@
+//! Applying patches to data as it streams past, so ROMs being downloaded or decompressed can be
+//! patched mid-pipeline without buffering the whole file. This works for IPS and UPS, whose output
+//! at each offset only depends on the source at the same offset.
+//!
+//! [`PatchReader`] wraps the [`Read`] that produces the source, and [`PatchWriter`] wraps the
+//! [`Write`] the output is sent to. UPS checksums can only be checked once the whole file has
+//! passed through, so a mismatch is reported as an [`io::ErrorKind::InvalidData`] error at the
+//! end of the stream, after the output has already been produced.
+//!
+//! Going the other way, [`DiffWriter`] is a sink for a modified file that builds a patch against
+//! the original as the data is written, for build systems that generate the modified ROM directly.
+//! When neither file fits in memory, `create_from_readers` on [`IpsPatch`] and [`UpsPatch`] diffs
+//! two [`Read`] streams a window at a time, so only the differences are kept.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    diff::{DiffSet, Hunk},
+    hash::Crc32Hasher,
+    prelude::*,
+    Error, Result, Validation,
+};
+
+pub(crate) enum Edit<'a> {
+    Write(&'a [u8]),
+    Fill(u8, usize),
+    Xor(&'a [u8]),
+}
+
+impl Edit<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Edit::Write(data) | Edit::Xor(data) => data.len(),
+            &Edit::Fill(_, len) => len,
+        }
+    }
+}
+
+/// The state shared by [`PatchReader`] and [`PatchWriter`].
+pub(crate) struct Patcher<'a> {
+    /// The edits in patch order, which decides the winner when they overlap.
+    edits: Vec<(usize, Edit<'a>)>,
+    /// Indices into `edits`, sorted by offset.
+    order: Vec<usize>,
+    next: usize,
+    active: Vec<usize>,
+    end: usize,
+    /// The size of the output, if the patch records it.
+    size: Option<usize>,
+    /// The expected source and output, and their running checksums.
+    checks: Option<(Validation, Validation, Crc32Hasher, Crc32Hasher)>,
+    src_len: usize,
+    pos: usize,
+    finished: bool,
+}
+
+impl<'a> Patcher<'a> {
+    pub fn new(
+        edits: Vec<(usize, Edit<'a>)>,
+        size: Option<usize>,
+        checks: Option<(Validation, Validation)>,
+    ) -> Self {
+        let mut order: Vec<_> = (0..edits.len()).collect();
+        order.sort_by_key(|&i| edits[i].0);
+        Self {
+            end: edits
+                .iter()
+                .map(|(offset, edit)| offset + edit.len())
+                .max()
+                .unwrap_or(0),
+            edits,
+            order,
+            next: 0,
+            active: Vec::new(),
+            size,
+            checks: checks.map(|(src, out)| (src, out, Default::default(), Default::default())),
+            src_len: 0,
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// How many more bytes of output the source may contribute.
+    fn remaining(&self) -> usize {
+        self.size.map_or(usize::MAX, |size| size - self.pos)
+    }
+
+    fn source(&mut self, data: &[u8]) {
+        self.src_len += data.len();
+        if let Some((_, _, src, _)) = &mut self.checks {
+            src.update(data);
+        }
+    }
+
+    /// Applies the edits to `buf`, which holds the source at the current position.
+    fn patch(&mut self, buf: &mut [u8]) {
+        let (start, end) = (self.pos, self.pos + buf.len());
+        while let Some(&i) = self.order.get(self.next) {
+            if self.edits[i].0 >= end {
+                break;
+            }
+            self.active.push(i);
+            self.next += 1;
+        }
+        self.active
+            .retain(|&i| self.edits[i].0 + self.edits[i].1.len() > start);
+        self.active.sort_unstable();
+
+        for &i in self.active.iter() {
+            let (offset, edit) = &self.edits[i];
+            let lo = start.max(*offset);
+            let hi = end.min(offset + edit.len());
+            if lo >= hi {
+                continue;
+            }
+
+            let dst = &mut buf[lo - start..hi - start];
+            match edit {
+                Edit::Write(data) => dst.copy_from_slice(&data[lo - offset..hi - offset]),
+                &Edit::Fill(byte, _) => dst.fill(byte),
+                Edit::Xor(data) => dst
+                    .iter_mut()
+                    .zip(&data[lo - offset..hi - offset])
+                    .for_each(|(b, x)| *b ^= x),
+            }
+        }
+
+        self.pos = end;
+        if let Some((_, _, _, out)) = &mut self.checks {
+            out.update(buf);
+        }
+    }
+
+    /// Fills `buf` with output past the end of the source. Returns the number of bytes written.
+    fn tail(&mut self, buf: &mut [u8]) -> usize {
+        let total = self.size.unwrap_or(self.end.max(self.src_len));
+        let len = buf.len().min(total.saturating_sub(self.pos));
+        buf[..len].fill(0);
+        self.patch(&mut buf[..len]);
+        len
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if std::mem::replace(&mut self.finished, true) {
+            return Ok(());
+        }
+
+        let Some((src, out, src_crc, out_crc)) = self.checks.take() else {
+            return Ok(());
+        };
+        let check = |expected: Validation, size, crc| {
+            if size != expected.size {
+                Err(Error::InvalidSize(size, expected.size))
+            } else if crc != expected.crc {
+                Err(Error::InvalidCRC(crc, expected.crc))
+            } else {
+                Ok(())
+            }
+        };
+        check(src, self.src_len, src_crc.finalize())
+            .and_then(|_| {
+                check(out, self.pos, out_crc.finalize())
+                    .map_err(|err| Error::OutputMismatch(Box::new(err)))
+            })
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// A reader that yields the patched output of the source it wraps.
+pub struct PatchReader<'a, R> {
+    inner: R,
+    patcher: Patcher<'a>,
+    eof: bool,
+}
+
+impl<'a, R: Read> PatchReader<'a, R> {
+    pub(crate) fn new(patcher: Patcher<'a>, inner: R) -> Self {
+        Self {
+            inner,
+            patcher,
+            eof: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the rest of the source after the output is complete, so its checksum can be checked.
+    fn drain(&mut self) -> io::Result<()> {
+        let mut scratch = [0; 8192];
+        loop {
+            match self.inner.read(&mut scratch)? {
+                0 => return Ok(()),
+                n => self.patcher.source(&scratch[..n]),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for PatchReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if !self.eof {
+            let limit = buf.len().min(self.patcher.remaining());
+            if limit == 0 {
+                self.drain()?;
+                self.eof = true;
+            } else {
+                match self.inner.read(&mut buf[..limit])? {
+                    0 => self.eof = true,
+                    n => {
+                        self.patcher.source(&buf[..n]);
+                        self.patcher.patch(&mut buf[..n]);
+                        return Ok(n);
+                    }
+                }
+            }
+        }
+
+        match self.patcher.tail(buf) {
+            0 => self.patcher.finish().map(|_| 0),
+            n => Ok(n),
+        }
+    }
+}
+
+/// A writer that patches the source written to it and forwards the output to the writer it
+/// wraps. [`PatchWriter::finish`] must be called once the whole source has been written.
+pub struct PatchWriter<'a, W: Write> {
+    inner: W,
+    patcher: Patcher<'a>,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> PatchWriter<'a, W> {
+    pub(crate) fn new(patcher: Patcher<'a>, inner: W) -> Self {
+        Self {
+            inner,
+            patcher,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The wrapped writer, e.g. to drain output buffered in a `Vec` between writes.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Writes any output past the end of the source and checks the checksums, returning the
+    /// wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.buf.resize(8192, 0);
+        loop {
+            match self.patcher.tail(&mut self.buf) {
+                0 => break,
+                n => self.inner.write_all(&self.buf[..n])?,
+            }
+        }
+
+        self.patcher.finish()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for PatchWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(self.patcher.remaining());
+        self.patcher.source(data);
+        self.buf.clear();
+        self.buf.extend_from_slice(&data[..len]);
+        self.patcher.patch(&mut self.buf);
+        self.inner.write_all(&self.buf)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer that compares the data written to it against an original and turns the differences
+/// into a patch, producing the same patch as `create` would with the whole file in memory.
+pub struct DiffWriter<'a> {
+    original: &'a [u8],
+    /// Runs of bytes that differ from the original (or extend past it), with their offsets.
+    runs: Vec<(usize, Vec<u8>)>,
+    crc: Crc32Hasher,
+    len: usize,
+}
+
+impl<'a> DiffWriter<'a> {
+    pub fn new(original: &'a [u8]) -> Self {
+        Self {
+            original,
+            runs: Vec::new(),
+            crc: Crc32Hasher::new(),
+            len: 0,
+        }
+    }
+
+    pub fn into_ips(mut self) -> Result<IpsPatch> {
+        // like `create`, start a run at the offset spelled "EOF" with the unchanged byte before it
+        let eof = IpsPatch::EOF_OFFSET;
+        if let Some((offset, run)) = self.runs.iter_mut().find(|(offset, _)| *offset == eof) {
+            *offset -= 1;
+            run.insert(0, self.original[eof - 1]);
+        }
+        IpsPatch::from_runs(self.runs, self.original.len(), self.len)
+    }
+
+    pub fn into_ups(self) -> UpsPatch {
+        let out = Validation {
+            size: self.len,
+            crc: self.crc.finalize(),
+            ..Default::default()
+        };
+        UpsPatch::from_runs(self.runs, self.original, out)
+    }
+
+    /// The differences as a format-neutral [`DiffSet`].
+    pub fn into_diff(self) -> DiffSet {
+        DiffSet {
+            hunks: self
+                .runs
+                .into_iter()
+                .map(|(offset, data)| Hunk { offset, data })
+                .collect(),
+            size: self.len,
+        }
+    }
+}
+
+impl Write for DiffWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc.update(data);
+        for (offset, &byte) in (self.len..).zip(data) {
+            if self.original.get(offset) == Some(&byte) {
+                continue;
+            }
+
+            match self.runs.last_mut() {
+                Some((start, run)) if *start + run.len() == offset => run.push(byte),
+                _ => self.runs.push((offset, vec![byte])),
+            }
+        }
+
+        self.len += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The sizes of the streams passed to `create_from_readers`, if they're known in advance. A stream
+/// that ends at a different size is an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamSizes {
+    pub source: Option<usize>,
+    pub target: Option<usize>,
+}
+
+/// The differences between two streams, as collected by [`diff_readers`].
+pub(crate) struct StreamDiff {
+    /// Runs of differing bytes with their offsets. Each run holds the target bytes, or the target
+    /// XORed with the source when diffing for UPS.
+    pub runs: Vec<(usize, Vec<u8>)>,
+    pub source: Validation,
+    pub target: Validation,
+}
+
+const WINDOW: usize = 64 * 1024;
+
+/// Reads until `buf` is full or `reader` is exhausted, returning the number of bytes read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Compares `src` and `dst` a window at a time. Target bytes past the end of the source always
+/// differ, unless `xor` is set, in which case they're XORed with 0 like the rest of UPS.
+pub(crate) fn diff_readers(
+    src: impl Read,
+    dst: impl Read,
+    sizes: StreamSizes,
+    xor: bool,
+) -> Result<StreamDiff> {
+    let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+    let (source, target) = diff_readers_with(src, dst, sizes, xor, |offset, byte| {
+        match runs.last_mut() {
+            Some((start, run)) if *start + run.len() == offset => run.push(byte),
+            _ => runs.push((offset, vec![byte])),
+        }
+        Ok(())
+    })?;
+    Ok(StreamDiff {
+        runs,
+        source,
+        target,
+    })
+}
+
+/// Like [`diff_readers`], passing each differing byte and its offset to `emit` in order instead
+/// of collecting them, and returning the source and target validations.
+pub(crate) fn diff_readers_with(
+    mut src: impl Read,
+    mut dst: impl Read,
+    sizes: StreamSizes,
+    xor: bool,
+    mut emit: impl FnMut(usize, u8) -> Result<()>,
+) -> Result<(Validation, Validation)> {
+    let (mut src_buf, mut dst_buf) = (vec![0; WINDOW], vec![0; WINDOW]);
+    let (mut src_crc, mut dst_crc) = (Crc32Hasher::new(), Crc32Hasher::new());
+    let (mut src_len, mut dst_len) = (0, 0);
+    let mut src_done = false;
+    loop {
+        let n = fill(&mut dst, &mut dst_buf)?;
+        let m = if src_done { 0 } else { fill(&mut src, &mut src_buf)? };
+        src_done |= m < WINDOW;
+        if n == 0 && m == 0 {
+            break;
+        }
+
+        dst_crc.update(&dst_buf[..n]);
+        src_crc.update(&src_buf[..m]);
+        for (i, &new) in dst_buf[..n].iter().enumerate() {
+            let byte = match src_buf[..m].get(i) {
+                Some(&old) if xor => new ^ old,
+                Some(&old) if old == new => continue,
+                _ => new,
+            };
+            if xor && byte == 0 {
+                continue;
+            }
+
+            emit(dst_len + i, byte)?;
+        }
+
+        dst_len += n;
+        src_len += m;
+    }
+
+    for (len, expected) in [(src_len, sizes.source), (dst_len, sizes.target)] {
+        match expected {
+            Some(expected) if expected != len => return Err(Error::InvalidSize(len, expected)),
+            _ => {}
+        }
+    }
+
+    let validation = |size, crc: Crc32Hasher| Validation {
+        size,
+        crc: crc.finalize(),
+        ..Default::default()
+    };
+    Ok((validation(src_len, src_crc), validation(dst_len, dst_crc)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    /// Returns at most 3 bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn ips() {
+        // overlapping records, a run, and a record past the end of the source
+        let patch = IpsPatch::load(
+            b"PATCH\0\0\x02\0\x04abcd\0\0\x03\0\x01X\0\0\x04\0\0\0\x05-\0\0\x0A\0\x02yzEOF",
+        )
+        .unwrap();
+        let rom = b"0123456";
+        let mut out = Vec::new();
+        std::io::Read::read_to_end(&mut patch.reader(Trickle(rom)), &mut out).unwrap();
+        assert_eq!(out, patch.apply(rom).unwrap());
+
+        let mut writer = patch.writer(Vec::new());
+        for chunk in rom.chunks(2) {
+            std::io::Write::write_all(&mut writer, chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), out);
+    }
+
+    #[test]
+    fn ups() {
+        let src = b"The quick brown fox";
+        let patch = UpsPatch::create(src, b"The quick red fox!!!");
+        let mut out = Vec::new();
+        std::io::Read::read_to_end(&mut patch.reader(Trickle(src)), &mut out).unwrap();
+        assert_eq!(out, b"The quick red fox!!!");
+
+        let mut reader = patch.reader(&b"The quick brown cat"[..]);
+        let err = std::io::Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn diff_writer() {
+        let src = b"The quick brown fox jumps over the lazy dog";
+        for dst in [
+            &b"The quick red fox jumps over the lazy dog!\0!"[..],
+            b"The quick brown",
+        ] {
+            let mut ips = super::DiffWriter::new(src);
+            let mut ups = super::DiffWriter::new(src);
+            for chunk in dst.chunks(4) {
+                std::io::Write::write_all(&mut ips, chunk).unwrap();
+                std::io::Write::write_all(&mut ups, chunk).unwrap();
+            }
+
+            let expected = IpsPatch::create(src, dst).export(None).unwrap();
+            assert_eq!(ips.into_ips().unwrap().export(None).unwrap(), expected);
+            let expected = UpsPatch::create(src, dst).export(None).unwrap();
+            assert_eq!(ups.into_ups().export(None).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn create_from_readers() {
+        // spans several windows, with the modified file longer than the original
+        let src: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        let mut dst = src.clone();
+        dst[70_000..70_010].fill(0xAA);
+        dst[65_530..65_540].fill(0);
+        dst.extend_from_slice(b"\0tail");
+
+        for (src, dst) in [(&src, &dst), (&dst, &src)] {
+            let sizes = super::StreamSizes::default();
+            let ips = IpsPatch::create_from_readers(Trickle(src), &dst[..], sizes).unwrap();
+            let expected = IpsPatch::create(src, dst).export(None).unwrap();
+            assert_eq!(ips.export(None).unwrap(), expected);
+
+            let ups = UpsPatch::create_from_readers(&src[..], Trickle(dst), sizes).unwrap();
+            let expected = UpsPatch::create(src, dst).export(None).unwrap();
+            assert_eq!(ups.export(None).unwrap(), expected);
+        }
+
+        let sizes = super::StreamSizes {
+            source: Some(4),
+            target: None,
+        };
+        assert!(matches!(
+            UpsPatch::create_from_readers(&b"abc"[..], &b"abd"[..], sizes),
+            Err(crate::Error::InvalidSize(3, 4))
+        ));
+    }
+
+    #[test]
+    fn create_streaming() {
+        // records spanning windows and flushes, and one running to the end of the target
+        let src: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut dst = src.clone();
+        dst[65_530..65_540].fill(0xAA);
+        for i in (100_000..180_000).step_by(3) {
+            dst[i] ^= 0x55;
+        }
+        dst[199_990..].fill(0);
+        let mut longer = dst.clone();
+        longer.extend_from_slice(b"tail\0");
+
+        for (src, dst) in [(&src, &dst), (&src, &longer), (&longer, &src)] {
+            let mut out = Vec::new();
+            let footer =
+                UpsPatch::create_streaming(Trickle(src), &dst[..], src.len(), dst.len(), &mut out)
+                    .unwrap();
+            assert_eq!(out, UpsPatch::create(src, dst).export(None).unwrap());
+            assert_eq!(footer, crate::encoding::read_footer(&out).unwrap().1);
+        }
+
+        assert!(matches!(
+            UpsPatch::create_streaming(&b"abc"[..], &b"abd"[..], 3, 4, Vec::new()),
+            Err(crate::Error::InvalidSize(3, 4))
+        ));
+    }
+}
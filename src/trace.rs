@@ -0,0 +1,102 @@
+//! Instrumentation of loading, applying, creating, and exporting patches. With the `tracing`
+//! feature enabled, each operation runs in a [`tracing`] span named after it, with the patch
+//! format as a field, and ends with a debug event carrying the record count or the error. Services
+//! see them through whichever subscriber they install, alongside their own spans.
+
+use crate::{Error, Result};
+
+/// An operation in progress, entered until it's finished. Without the `tracing` feature this
+/// does nothing.
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+macro_rules! operation {
+    ($($name:ident),*) => {
+        $(
+            #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+            #[inline]
+            pub fn $name(format: &'static str) -> Self {
+                Self {
+                    #[cfg(feature = "tracing")]
+                    _entered: tracing::info_span!(stringify!($name), format).entered(),
+                }
+            }
+        )*
+    };
+}
+
+impl Span {
+    operation!(load, apply, create, export);
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    #[inline]
+    pub fn finish(self, records: Option<usize>, error: Option<&Error>) {
+        #[cfg(feature = "tracing")]
+        match error {
+            Some(error) => tracing::debug!(%error, "failed"),
+            None => tracing::debug!(records, "finished"),
+        }
+    }
+
+    /// Finishes the span with the outcome of `result`, counting records with `records`.
+    #[inline]
+    pub fn finish_with<T>(self, result: &Result<T>, records: impl FnOnce(&T) -> usize) {
+        match result {
+            Ok(value) => self.finish(Some(records(value)), None),
+            Err(err) => self.finish(None, Some(err)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::prelude::*;
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events() {
+        let buf = Buffer::default();
+        let writer = buf.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .with_target(false)
+            .without_time()
+            .finish();
+
+        // the subscriber only applies to this thread, so other tests don't show up
+        tracing::subscriber::with_default(subscriber, || {
+            let patch = UpsPatch::create(b"abc", b"abd");
+            patch.apply(b"abc").unwrap();
+            patch.apply(b"xyz").unwrap_err();
+            patch.export(None).unwrap();
+        });
+
+        let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = log.lines().map(str::trim).collect();
+        assert_eq!(lines.len(), 4, "{log}");
+        assert!(lines[0].ends_with(r#"create{format="UPS"}: finished records=1"#));
+        assert!(lines[1].ends_with(r#"apply{format="UPS"}: finished records=1"#));
+        assert!(lines[2].contains(r#"apply{format="UPS"}: failed error="#));
+        assert!(lines[3].ends_with(r#"export{format="UPS"}: finished records=1"#));
+    }
+}
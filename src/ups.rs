@@ -1,25 +1,57 @@
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
 use crate::{
-    bps_ups::{self, ReadVarExt, WriteVarExt},
-    Error, Patch, ReadExt, Result,
+    bps_ups::{self, HashingWriter},
+    encoding::{self, ReadVarExt, WriteVarExt},
+    equiv::{Model, Size, Sym},
+    hash::Crc32Hasher,
+    json::{self, Object, Value},
+    lint::{self, Lint},
+    progress::{self, Event, Observer, Ticker},
+    source::{self, Source},
+    stream::{self, Edit, PatchReader, PatchWriter, Patcher, StreamSizes},
+    trace::Span,
+    vectored::Chunks,
+    CreateOptions, Error, Patch, ReadExt, Record, RecordKind, Region, RegionKind, Result,
 };
 use std::{
-    io::{BufRead, Write},
-    iter,
+    borrow::Cow,
+    io::{BufRead, Read, Write},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct UpsPatch {
     src_data: bps_ups::Validation,
     out_data: bps_ups::Validation,
-    records: Vec<(usize, Vec<u8>)>,
+    /// Each record's offset and the start of its bytes in `xor`. A record's bytes end where the
+    /// next one's start.
+    records: Vec<(usize, usize)>,
+    /// The XOR bytes of every record back to back, without their terminating zeros.
+    xor: Box<[u8]>,
 }
 
 impl UpsPatch {
     const MAGIC: &[u8; 4] = b"UPS1";
 
-    pub fn load(mut patch: &[u8]) -> Result<Self> {
+    pub fn load(patch: &[u8]) -> Result<Self> {
+        let span = Span::load("UPS");
+        let result = Self::parse(patch).and_then(Self::strict);
+        span.finish_with(&result, |patch| patch.records.len());
+        result
+    }
+
+    /// Like [`load`](Self::load), but returns records that run past the declared output size as
+    /// warnings instead of failing. See [`crate::load_lenient`].
+    pub fn load_lenient(patch: &[u8]) -> Result<(Self, Vec<Error>)> {
+        let span = Span::load("UPS");
+        let result = Self::parse(patch);
+        span.finish_with(&result, |patch| patch.records.len());
+        let patch = result?;
+        let warnings = patch.size_problems();
+        Ok((patch, warnings))
+    }
+
+    fn parse(mut patch: &[u8]) -> Result<Self> {
         if patch.read_arr()? != *Self::MAGIC {
             return Err(Error::Magic(unsafe {
                 std::str::from_utf8_unchecked(Self::MAGIC)
@@ -30,26 +62,30 @@ impl UpsPatch {
         let new_size = patch.read_var_int()?;
 
         let mut records = Vec::new();
+        let mut xor = Vec::new();
         let mut fpos = 0;
         while patch.len() > 12 {
             fpos += patch.read_var_int()?;
-            records.push((fpos, {
-                let mut buf = Vec::new();
-                fpos += patch.read_until(0, &mut buf)?;
-                buf
-            }));
+            records.push((fpos, xor.len()));
+            fpos += patch.read_until(0, &mut xor)?;
+            if xor.pop() != Some(0) {
+                return Err(Error::InvalidPatch);
+            }
         }
 
         let result = Self {
             src_data: bps_ups::Validation {
                 size: old_size,
                 crc: patch.read_u32::<LE>()?,
+                ..Default::default()
             },
             out_data: bps_ups::Validation {
                 size: new_size,
                 crc: patch.read_u32::<LE>()?,
+                ..Default::default()
             },
             records,
+            xor: xor.into_boxed_slice(),
         };
 
         result.export(Some(patch.read_u32::<LE>()?))?;
@@ -57,93 +93,596 @@ impl UpsPatch {
     }
 
     pub fn create(src: &[u8], dst: &[u8]) -> Self {
+        Self::create_observed(src, dst, &CreateOptions::default(), &mut ())
+            .expect("creation can't be cancelled without an observer")
+    }
+
+    /// Like [`create`](Self::create), reporting progress through the modified file to `observer`.
+    /// The options only affect BPS patches, and are accepted so every format is created the same way.
+    /// Fails with [`Error::Cancelled`] if the observer cancels.
+    pub fn create_observed(
+        src: &[u8],
+        dst: &[u8],
+        _options: &CreateOptions,
+        observer: &mut dyn Observer,
+    ) -> Result<Self> {
+        let span = Span::create("UPS");
         let mut records = Vec::new();
-        let mut iter = src
+        let mut bytes = Vec::new();
+        let xor = |i: usize| src.get(i).copied().unwrap_or(0) ^ dst[i];
+        let mut ticker = Ticker::new(observer, dst.len());
+        let mut i = 0;
+        while i < dst.len() {
+            if xor(i) == 0 {
+                i += 1;
+                ticker.tick(i)?;
+                continue;
+            }
+
+            let start = i;
+            while i < dst.len() && xor(i) != 0 {
+                i += 1;
+                ticker.tick(i)?;
+            }
+            records.push((start, bytes.len()));
+            bytes.extend((start..i).map(xor));
+        }
+        ticker.finish();
+
+        span.finish(Some(records.len()), None);
+        Ok(Self {
+            src_data: bps_ups::Validation::new(src),
+            out_data: bps_ups::Validation::new(dst),
+            records,
+            xor: bytes.into_boxed_slice(),
+        })
+    }
+}
+
+impl UpsPatch {
+    /// Like [`create`](Self::create), reading the original and modified files from streams a
+    /// window at a time, so neither has to fit in memory. Fails if a stream ends at a different
+    /// size than `sizes` gives.
+    pub fn create_from_readers(src: impl Read, dst: impl Read, sizes: StreamSizes) -> Result<Self> {
+        let span = Span::create("UPS");
+        let diff = stream::diff_readers(src, dst, sizes, true)?;
+        let mut records = Vec::with_capacity(diff.runs.len());
+        let mut xor = Vec::new();
+        for (offset, run) in diff.runs {
+            records.push((offset, xor.len()));
+            xor.extend_from_slice(&run);
+        }
+
+        span.finish(Some(records.len()), None);
+        Ok(Self {
+            src_data: diff.source,
+            out_data: diff.target,
+            records,
+            xor: xor.into_boxed_slice(),
+        })
+    }
+
+    /// Like [`create_from_readers`](Self::create_from_readers), but writes the patch to `out` as
+    /// the streams are compared instead of building it in memory, so memory use stays constant
+    /// however large the files are. The header records both sizes, so they must be known in
+    /// advance; a stream that ends at a different size fails with [`Error::InvalidSize`] after
+    /// part of the patch has been written. Returns the patch's footer.
+    pub fn create_streaming(
+        src: impl Read,
+        dst: impl Read,
+        source_size: usize,
+        target_size: usize,
+        out: impl Write,
+    ) -> Result<encoding::Footer> {
+        let span = Span::create("UPS");
+        let mut writer = RecordWriter {
+            out,
+            buf: Self::MAGIC.to_vec(),
+            crc: Crc32Hasher::new(),
+            pos: 0,
+            record_end: None,
+            records: 0,
+        };
+        writer.buf.write_var_int(source_size)?;
+        writer.buf.write_var_int(target_size)?;
+
+        let sizes = StreamSizes {
+            source: Some(source_size),
+            target: Some(target_size),
+        };
+        let (source, target) = stream::diff_readers_with(src, dst, sizes, true, |offset, xor| {
+            writer.push(offset, xor)
+        })?;
+        let records = writer.records;
+        let footer = writer.finish(source.crc, target.crc)?;
+
+        span.finish(Some(records), None);
+        Ok(footer)
+    }
+
+    /// Builds a patch from runs of modified bytes, as collected by
+    /// [`DiffWriter`](crate::stream::DiffWriter).
+    pub(crate) fn from_runs(
+        runs: Vec<(usize, Vec<u8>)>,
+        src: &[u8],
+        out_data: bps_ups::Validation,
+    ) -> Self {
+        let mut records = Vec::new();
+        let mut xor = Vec::new();
+        for (offset, run) in runs {
+            // bytes past the end of the source are XORed with 0, so they only differ if nonzero,
+            // and a zero would terminate the record early
+            let mut open = false;
+            for (i, byte) in (offset..).zip(run) {
+                match byte ^ src.get(i).copied().unwrap_or(0) {
+                    0 => open = false,
+                    x => {
+                        if !open {
+                            records.push((i, xor.len()));
+                            open = true;
+                        }
+                        xor.push(x);
+                    }
+                }
+            }
+        }
+
+        Self {
+            src_data: bps_ups::Validation::new(src),
+            out_data,
+            records,
+            xor: xor.into_boxed_slice(),
+        }
+    }
+
+    /// The records whose XOR bytes run past the declared output. A record's terminating zero may
+    /// sit just past the end, since it doesn't change anything. Records past the end of the
+    /// source are fine, as UPS treats missing source bytes as zeros.
+    fn size_problems(&self) -> Vec<Error> {
+        self.iter()
+            .enumerate()
+            .map(|(index, (offset, xor))| (index, offset.saturating_add(xor.len())))
+            .filter(|&(_, end)| end > self.out_data.size)
+            .map(|(index, end)| Error::RecordOverrun {
+                index,
+                end,
+                size: self.out_data.size,
+            })
+            .collect()
+    }
+
+    /// Fails with the first of the [size problems](Self::size_problems).
+    fn strict(self) -> Result<Self> {
+        match self.size_problems().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(self),
+        }
+    }
+
+    /// See [`lint::lint`].
+    pub(crate) fn lint(data: &[u8]) -> Result<Vec<Lint>> {
+        let patch = Self::load(data)?;
+        let mut lints = Vec::new();
+        lint::check_size(&mut lints, "source", patch.src_data.size);
+        lint::check_size(&mut lints, "target", patch.out_data.size);
+        for (record, (_, xor)) in patch.iter().enumerate() {
+            if xor.is_empty() {
+                lints.push(Lint::ZeroLength { record });
+            }
+        }
+        Ok(lints)
+    }
+
+    /// Removes records that skip ahead without changing anything, so equal patches export alike.
+    pub fn canonicalize(&mut self) {
+        let lens: Vec<_> = self.iter().map(|(_, xor)| xor.len()).collect();
+        self.records = self
+            .records
+            .iter()
+            .zip(lens)
+            .filter(|&(_, len)| len != 0)
+            .map(|(&record, _)| record)
+            .collect();
+    }
+
+    /// The records' offsets and XOR bytes, in patch order.
+    fn iter(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, &(offset, start))| {
+                let end = self
+                    .records
+                    .get(i + 1)
+                    .map_or(self.xor.len(), |next| next.1);
+                (offset, &self.xor[start..end])
+            })
+    }
+
+    pub(crate) fn model(&self) -> Model {
+        let mut output = Vec::new();
+        for (offset, xor) in self.iter() {
+            output.extend((output.len()..offset).map(|i| Sym::Source(i, 0)));
+            output.extend((offset..).zip(xor).map(|(i, &x)| Sym::Source(i, x)));
+        }
+
+        output.truncate(self.out_data.size);
+        Model {
+            source: Some(self.src_data.size),
+            size: Size::Fixed(self.out_data.size),
+            output,
+        }
+    }
+
+    /// Wraps `inner`, which reads the source, in a reader that yields the patched output.
+    pub fn reader<R: Read>(&self, inner: R) -> PatchReader<'_, R> {
+        PatchReader::new(self.patcher(), inner)
+    }
+
+    /// Wraps `inner` in a writer that patches the source written to it.
+    pub fn writer<W: Write>(&self, inner: W) -> PatchWriter<'_, W> {
+        PatchWriter::new(self.patcher(), inner)
+    }
+
+    fn patcher(&self) -> Patcher<'_> {
+        let edits = self
             .iter()
-            .chain(iter::repeat(&0).take(dst.len().saturating_sub(src.len())))
-            .zip(dst.iter())
-            .enumerate();
-        while let Some((i, (s, d))) = iter.next() {
-            if s != d {
-                records.push((
-                    i,
-                    iter::once((s, d))
-                        .chain(
-                            iter.by_ref()
-                                .take_while(|(_, (s, d))| s != d)
-                                .map(|(_, bytes)| bytes),
-                        )
-                        .map(|(s, d)| s ^ d)
-                        .chain(iter::once(0))
-                        .collect(),
+            .map(|(offset, xor)| (offset, Edit::Xor(xor)))
+            .collect();
+        Patcher::new(
+            edits,
+            Some(self.out_data.size),
+            Some((self.src_data, self.out_data)),
+        )
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self> {
+        let invalid = |what: &str| Error::InvalidJson(format!("invalid UPS record: {what}"));
+        let mut records = Vec::new();
+        let mut bytes = Vec::new();
+        let mut end = 0;
+        for record in value.get("records")?.as_array()? {
+            let offset = record.get("offset")?.as_usize()?;
+            let xor = record.get("xor")?.as_bytes()?;
+            if offset < end {
+                return Err(invalid("records must be sorted and not overlap"));
+            }
+            if xor.is_empty() || xor.contains(&0) {
+                return Err(invalid(
+                    "xor data must be non-empty and contain no zero bytes",
                 ));
             }
+
+            // the terminating zero takes a byte of the output
+            end = offset
+                .checked_add(xor.len() + 1)
+                .ok_or_else(|| invalid("offset out of range"))?;
+            records.push((offset, bytes.len()));
+            bytes.extend_from_slice(&xor);
         }
 
-        Self {
-            src_data: bps_ups::Validation {
-                size: src.len(),
-                crc: crc32fast::hash(src),
-            },
-            out_data: bps_ups::Validation {
-                size: dst.len(),
-                crc: crc32fast::hash(dst),
-            },
+        let patch = Self {
+            src_data: json::parse_validation(value.get("source")?)?,
+            out_data: json::parse_validation(value.get("target")?)?,
             records,
+            xor: bytes.into_boxed_slice(),
+        };
+
+        patch.strict()
+    }
+}
+
+impl UpsPatch {
+    fn apply_records(
+        &self,
+        rom: &[u8],
+        buf: &mut HashingWriter,
+        observer: &mut dyn Observer,
+    ) -> Result<()> {
+        let read = |offset: usize, dst: &mut [u8]| {
+            let src = rom.get(offset..).unwrap_or_default();
+            let len = src.len().min(dst.len());
+            dst[..len].copy_from_slice(&src[..len]);
+            Ok(())
+        };
+        self.write_records(buf, read, observer)
+    }
+
+    /// Writes the output to `buf` in order: the source between the records, as filled in by
+    /// `read`, and each record XORed with the source under it. `read` fills its buffer with the
+    /// source at an offset, leaving bytes past the end of the source as 0.
+    fn write_records(
+        &self,
+        buf: &mut HashingWriter,
+        mut read: impl FnMut(usize, &mut [u8]) -> Result<()>,
+        observer: &mut dyn Observer,
+    ) -> Result<()> {
+        let size = self.out_data.size;
+        let total = self.xor.len();
+        let mut done = 0;
+        for (i, (offset, xor_bytes)) in self.iter().enumerate() {
+            let end = offset + xor_bytes.len();
+            if offset < buf.len() || end > size {
+                return Err(Error::InvalidPatch);
+            }
+
+            let start = buf.len();
+            buf.append_with(end - start, |dst| {
+                read(start, dst)?;
+                dst[offset - start..]
+                    .iter_mut()
+                    .zip(xor_bytes)
+                    .for_each(|(b, x)| *b ^= x);
+                Ok::<_, Error>(())
+            })?;
+
+            done += xor_bytes.len();
+            observer.event(Event::RecordApplied(i));
+            progress::checkpoint(observer)?;
+            observer.event(Event::Progress { done, total });
+        }
+
+        let start = buf.len();
+        buf.append_with(size - start, |dst| read(start, dst))
+    }
+}
+
+/// Encodes records for [`UpsPatch::create_streaming`] as the XORed bytes arrive, flushing to
+/// `out` a window at a time.
+struct RecordWriter<W> {
+    out: W,
+    buf: Vec<u8>,
+    /// The checksum of everything flushed so far.
+    crc: Crc32Hasher,
+    /// The offset the next record's skip is relative to.
+    pos: usize,
+    /// The offset just past the open record's last byte, if there is one.
+    record_end: Option<usize>,
+    records: usize,
+}
+
+impl<W: Write> RecordWriter<W> {
+    const FLUSH_SIZE: usize = 64 * 1024;
+
+    fn push(&mut self, offset: usize, xor: u8) -> Result<()> {
+        if self.record_end != Some(offset) {
+            self.close();
+            self.buf.write_var_int(offset - self.pos)?;
+            self.records += 1;
         }
+        self.buf.push(xor);
+        self.record_end = Some(offset + 1);
+
+        if self.buf.len() >= Self::FLUSH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Terminates the open record. The terminator stands for the unchanged byte after it.
+    fn close(&mut self) {
+        if let Some(end) = self.record_end.take() {
+            self.buf.push(0);
+            self.pos = end + 1;
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.crc.update(&self.buf);
+        self.out.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn finish(mut self, source_crc: u32, target_crc: u32) -> Result<encoding::Footer> {
+        self.close();
+        self.buf.extend_from_slice(&source_crc.to_le_bytes());
+        self.buf.extend_from_slice(&target_crc.to_le_bytes());
+        self.flush()?;
+
+        let patch_crc = self.crc.finalize();
+        self.out.write_all(&patch_crc.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(encoding::Footer {
+            source_crc,
+            target_crc,
+            patch_crc,
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for UpsPatch {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        Self::load(data)
     }
 }
 
 impl Patch for UpsPatch {
-    fn apply(&self, rom: &[u8]) -> Result<Vec<u8>> {
+    fn apply_observed(&self, rom: &[u8], observer: &mut dyn Observer) -> Result<Vec<u8>> {
+        let span = Span::apply("UPS");
+        let result = (|| {
+            self.validate(rom).unwrap()?;
+            observer.event(Event::SourceValidated);
+            let mut buf = HashingWriter::new(self.out_data.size);
+            self.apply_records(rom, &mut buf, observer)?;
+            let buf = buf.validate(&self.out_data)?;
+            observer.event(Event::OutputValidated);
+            Ok(buf)
+        })();
+        span.finish_with(&result, |_| self.records.len());
+        result
+    }
+
+    fn apply_unchecked(&self, rom: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = HashingWriter::unhashed(self.out_data.size);
+        self.apply_records(rom, &mut buf, &mut ())?;
+        Ok(buf.into_inner())
+    }
+
+    fn apply_source(&self, source: &mut dyn Source) -> Result<Vec<u8>> {
+        source::validate(source, &self.src_data)?;
+        let mut buf = HashingWriter::new(self.out_data.size);
+        let len = source.len();
+        let read = |offset: usize, dst: &mut [u8]| match len.saturating_sub(offset).min(dst.len()) {
+            0 => Ok(()),
+            n => Ok(source.read_at(offset, &mut dst[..n])?),
+        };
+        self.write_records(&mut buf, read, &mut ())?;
+        buf.validate(&self.out_data)
+    }
+
+    fn apply_into(&self, rom: &[u8], out: &mut [u8]) -> Result<()> {
         self.validate(rom).unwrap()?;
+        let size = self.out_data.size;
+        if out.len() != size {
+            return Err(Error::InvalidSize(out.len(), size));
+        }
+
+        // copy the source up to the end of each record and XOR it, hashing as we go
+        let mut hasher = Crc32Hasher::new();
+        let mut pos = 0;
+        let copy = |out: &mut [u8], pos: usize, end: usize| {
+            let src = rom.get(pos..end.min(rom.len())).unwrap_or_default();
+            out[pos..pos + src.len()].copy_from_slice(src);
+            out[pos + src.len()..end].fill(0);
+        };
+        for (offset, xor_bytes) in self.iter() {
+            let end = offset + xor_bytes.len();
+            if offset < pos || end > size {
+                return Err(Error::InvalidPatch);
+            }
 
-        let mut buf = vec![0; self.out_data.size];
-        let size = rom.len().min(buf.len());
-        buf[..size].copy_from_slice(&rom[..size]);
+            copy(out, pos, end);
+            out[offset..end]
+                .iter_mut()
+                .zip(xor_bytes)
+                .for_each(|(b, x)| *b ^= x);
+            hasher.update(&out[pos..end]);
+            pos = end;
+        }
+        copy(out, pos, size);
+        hasher.update(&out[pos..]);
+
+        self.out_data
+            .validate_crc(out, hasher.finalize())
+            .map_err(|err| Error::OutputMismatch(Box::new(err)))
+    }
 
-        for (offset, xor_bytes) in self.records.iter() {
-            for u in 0..xor_bytes.len() - 1 {
-                buf[*offset + u] ^= xor_bytes[u];
+    fn apply_vectored<'a>(&'a self, rom: &'a [u8]) -> Result<Vec<Cow<'a, [u8]>>> {
+        self.validate(rom).unwrap()?;
+        let size = self.out_data.size;
+        let mut out = Chunks::new();
+        for (offset, xor_bytes) in self.iter() {
+            let end = offset + xor_bytes.len();
+            if offset < out.len() || end > size {
+                return Err(Error::InvalidPatch);
             }
+
+            out.source(rom, out.len()..offset);
+            out.push(Cow::Owned(
+                (offset..end)
+                    .zip(xor_bytes)
+                    .map(|(i, x)| rom.get(i).unwrap_or(&0) ^ x)
+                    .collect(),
+            ));
         }
+        out.source(rom, out.len()..size);
 
-        self.out_data.validate(&buf)?;
-        Ok(buf)
+        let chunks = out.into_vec();
+        self.out_data
+            .validate_chunks(&chunks)
+            .map_err(|err| Error::OutputMismatch(Box::new(err)))?;
+        Ok(chunks)
     }
 
     fn validate(&self, rom: &[u8]) -> Option<Result<()>> {
         Some(self.src_data.validate(rom))
     }
 
-    fn export(&self, crc: Option<u32>) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
+    fn source(&self) -> Option<bps_ups::Validation> {
+        Some(self.src_data)
+    }
 
-        buf.write_all(Self::MAGIC)?;
-        buf.write_var_int(self.src_data.size)?;
-        buf.write_var_int(self.out_data.size)?;
+    fn target(&self) -> Option<bps_ups::Validation> {
+        Some(self.out_data)
+    }
 
-        for (i, record) in self.records.iter().enumerate() {
-            buf.write_var_int(if i > 0 {
-                record.0 - (self.records[i - 1].0 + self.records[i - 1].1.len())
-            } else {
-                record.0
-            })?;
-            buf.write_all(&record.1)?;
-        }
+    fn estimated_peak_memory(&self, _rom_len: usize) -> usize {
+        std::mem::size_of_val(&self.records[..]) + self.xor.len() + self.out_data.size
+    }
+
+    fn format(&self) -> &'static str {
+        "UPS"
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.iter()
+            .map(|(offset, xor_bytes)| Region {
+                offset,
+                len: xor_bytes.len(),
+                kind: RegionKind::Xor,
+            })
+            .collect()
+    }
 
-        buf.write_u32::<LE>(self.src_data.crc)?;
-        buf.write_u32::<LE>(self.out_data.crc)?;
+    fn records(&self) -> Vec<Record<'_>> {
+        self.iter()
+            .map(|(offset, xor_bytes)| Record {
+                offset,
+                len: xor_bytes.len(),
+                kind: RecordKind::Xor(xor_bytes),
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> String {
+        Object::new()
+            .str("format", "ups")
+            .raw("source", &json::validation(&self.src_data))
+            .raw("target", &json::validation(&self.out_data))
+            .array(
+                "records",
+                self.iter().map(|(offset, xor)| {
+                    Object::new()
+                        .num("offset", offset)
+                        .bytes("xor", xor)
+                        .finish()
+                }),
+            )
+            .finish()
+    }
+
+    fn export(&self, crc: Option<u32>) -> Result<Vec<u8>> {
+        let span = Span::export("UPS");
+        let result = (|| {
+            let mut buf = Vec::new();
 
-        let hash = crc32fast::hash(&buf);
-        if let Some(crc) = crc {
-            if hash != crc {
-                return Err(Error::InvalidCRC(hash, crc));
+            buf.write_all(Self::MAGIC)?;
+            buf.write_var_int(self.src_data.size)?;
+            buf.write_var_int(self.out_data.size)?;
+
+            let mut end = 0;
+            for (offset, xor) in self.iter() {
+                buf.write_var_int(offset - end)?;
+                buf.write_all(xor)?;
+                buf.write_u8(0)?;
+                // the terminating zero takes a byte of the output
+                end = offset + xor.len() + 1;
             }
-        }
 
-        buf.write_u32::<LE>(hash)?;
-        Ok(buf)
+            let hash = encoding::write_footer(&mut buf, self.src_data.crc, self.out_data.crc);
+            if let Some(crc) = crc {
+                if hash != crc {
+                    return Err(Error::CorruptPatch(hash, crc));
+                }
+            }
+
+            Ok(buf)
+        })();
+        span.finish_with(&result, |_| self.records.len());
+        result
     }
 }
 
@@ -151,6 +690,34 @@ impl Patch for UpsPatch {
 mod tests {
     use super::*;
 
+    #[test]
+    fn extents() {
+        let data = |out_size: u8| {
+            let mut data = b"UPS1\x84".to_vec();
+            data.push(0x80 | out_size);
+            data.extend_from_slice(b"\x82\x01\x01\x00");
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+            data
+        };
+
+        assert!(UpsPatch::load(&data(4)).is_ok());
+        assert!(matches!(
+            UpsPatch::load(&data(3)),
+            Err(Error::RecordOverrun {
+                index: 0,
+                end: 4,
+                size: 3
+            })
+        ));
+        let (patch, warnings) = UpsPatch::load_lenient(&data(3)).unwrap();
+        assert_eq!(patch.records.len(), 1);
+        assert!(matches!(
+            warnings[..],
+            [Error::RecordOverrun { index: 0, .. }]
+        ));
+    }
+
     #[test]
     fn patch_same_len() {
         let src = b"It's better to be happy than to be right.";
@@ -160,6 +727,23 @@ mod tests {
         assert_eq!(&patch.apply(src).unwrap(), dst);
     }
 
+    #[test]
+    fn unchecked() {
+        let src = b"It's better to be happy than to be right.";
+        let dst = b"It's better to be right than to be happy.";
+        let patch = UpsPatch::create(src, dst);
+
+        let wrong = b"it's better to be happy than to be right.";
+        assert!(matches!(
+            patch.apply_verified(wrong),
+            Err(Error::InvalidCRC(..))
+        ));
+        assert_eq!(
+            &patch.apply_unchecked(wrong).unwrap(),
+            b"it's better to be right than to be happy."
+        );
+    }
+
     #[test]
     fn patch_shorter_src() {
         let src = b"/bin/true";
@@ -177,4 +761,37 @@ mod tests {
         let patch = UpsPatch::create(src, dst);
         assert_eq!(&patch.apply(src).unwrap(), dst);
     }
+
+    #[test]
+    fn validate_full() {
+        let src = b"The original ROM.";
+        let patch = UpsPatch::create(src, b"The modified ROM.");
+
+        assert!(patch.validate_full(src).unwrap().is_empty());
+        let errors = patch.validate_full(b"A different ROM").unwrap();
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::InvalidSize(15, 17), Error::InvalidCRC(..)]
+        ));
+    }
+
+    #[test]
+    fn canonicalize() {
+        let data = |records: &[u8]| {
+            let mut data = b"UPS1\x84\x84".to_vec();
+            data.extend_from_slice(records);
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+            data
+        };
+        // an empty record taking up offset 0 before one at offset 1, or just the one at 1
+        let skipping = data(b"\x80\x00\x80\x01\x00");
+        let direct = data(b"\x81\x01\x00");
+
+        let mut patch = UpsPatch::load(&skipping).unwrap();
+        assert_eq!(patch.records().len(), 2);
+        patch.canonicalize();
+        assert_eq!(patch.export(None).unwrap(), direct);
+        assert_eq!(crate::canonicalize(&skipping).unwrap(), direct);
+    }
 }
@@ -0,0 +1,101 @@
+//! Building patched output as a list of chunks instead of one buffer, for
+//! [`Patch::apply_vectored`](crate::Patch::apply_vectored).
+
+use std::{borrow::Cow, ops::Range};
+
+pub(crate) struct Chunks<'a> {
+    chunks: Vec<Cow<'a, [u8]>>,
+    /// The offset in the output each chunk starts at.
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl<'a> Chunks<'a> {
+    pub fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, chunk: Cow<'a, [u8]>) {
+        if !chunk.is_empty() {
+            self.starts.push(self.len);
+            self.len += chunk.len();
+            self.chunks.push(chunk);
+        }
+    }
+
+    /// Appends the source bytes in `range`, borrowed from `rom` as far as it reaches and zero
+    /// past its end.
+    pub fn source(&mut self, rom: &'a [u8], range: Range<usize>) {
+        let end = range.end.min(rom.len());
+        if range.start < end {
+            self.push(Cow::Borrowed(&rom[range.start..end]));
+        }
+        let zeros = range.end - range.start.max(end);
+        if zeros > 0 {
+            self.push(Cow::Owned(vec![0; zeros]));
+        }
+    }
+
+    /// The output byte at `offset`, if it's been produced yet.
+    pub fn get(&self, offset: usize) -> Option<u8> {
+        let i = self.starts.partition_point(|&start| start <= offset);
+        let i = i.checked_sub(1)?;
+        self.chunks[i].get(offset - self.starts[i]).copied()
+    }
+
+    pub fn into_vec(self) -> Vec<Cow<'a, [u8]>> {
+        self.chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn check(patch: &dyn Patch, rom: &[u8]) {
+        let chunks = patch.apply_vectored(rom).unwrap();
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+        assert_eq!(chunks.concat(), patch.apply(rom).unwrap());
+    }
+
+    #[test]
+    fn matches_apply() {
+        let src = b"The quick brown fox";
+        let dst = b"The quick red fox!!!!!";
+        check(&IpsPatch::create(src, dst), src);
+        check(&UpsPatch::create(src, dst), src);
+        check(&UpsPatch::create(dst, src), dst);
+        check(&BpsPatch::create(src, dst), src);
+
+        // overlapping records, a fill, and a record past the end of the ROM
+        let ips = IpsPatch::load(
+            b"PATCH\0\0\x02\0\x04ABCD\0\0\x04\0\x02xy\0\0\x01\0\0\0\x03-\0\0\x18\0\x01!EOF",
+        )
+        .unwrap();
+        check(&ips, src);
+
+        // a target copy overlapping its own output
+        let json = format!(
+            r#"{{"format": "bps",
+                "source": {{"size": 2, "crc32": "{:08x}"}},
+                "target": {{"size": 7, "crc32": "{:08x}"}},
+                "actions": [
+                    {{"action": "source-read", "length": 2}},
+                    {{"action": "target-copy", "length": 5, "from": 0}}
+                ]}}"#,
+            crc32fast::hash(b"ab"),
+            crc32fast::hash(b"abababa"),
+        );
+        let bps = crate::json::from_json(&json).unwrap();
+        assert_eq!(bps.apply(b"ab").unwrap(), b"abababa");
+        check(&*bps, b"ab");
+    }
+}
@@ -0,0 +1,91 @@
+//! WebAssembly bindings for browser-based patchers, generated with wasm-bindgen. Byte arrays are
+//! passed as `Uint8Array`s, and failures are thrown as `Error`s carrying the library's message.
+
+use wasm_bindgen::prelude::*;
+
+use crate::prelude::*;
+
+fn error(err: crate::Error) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// An IPS, UPS, or BPS patch. JavaScript's garbage collector doesn't see the memory it holds, so
+/// call `free()` once it's no longer needed.
+#[wasm_bindgen(js_name = Patch)]
+pub struct WasmPatch(Box<dyn Patch>);
+
+#[wasm_bindgen(js_class = Patch)]
+impl WasmPatch {
+    /// Loads a patch, detecting its format from its contents.
+    pub fn load(data: &[u8]) -> Result<WasmPatch, JsError> {
+        crate::load(data).map(Self).map_err(error)
+    }
+
+    /// Creates a patch that turns `original` into `modified` in the given format, "ips" (the
+    /// default), "ups", or "bps".
+    pub fn create(
+        original: &[u8],
+        modified: &[u8],
+        format: Option<String>,
+    ) -> Result<WasmPatch, JsError> {
+        let patch: Box<dyn Patch> = match format.as_deref().unwrap_or("ips") {
+            "ips" => Box::new(IpsPatch::create(original, modified)),
+            "ups" => Box::new(UpsPatch::create(original, modified)),
+            "bps" => Box::new(BpsPatch::create(original, modified)),
+            format => return Err(JsError::new(&format!("unsupported format: {format:?}"))),
+        };
+        Ok(Self(patch))
+    }
+
+    /// The name of the patch format, e.g. "BPS".
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> String {
+        self.0.format().into()
+    }
+
+    /// Throws if `rom` isn't the source the patch expects. Returns false if the patch doesn't
+    /// record a source checksum (IPS), true otherwise.
+    pub fn verify(&self, rom: &[u8]) -> Result<bool, JsError> {
+        match self.0.validate(rom) {
+            Some(Ok(())) => Ok(true),
+            Some(Err(err)) => Err(error(err)),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the patch to `rom`, returning the output.
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>, JsError> {
+        self.0.apply(rom).map_err(error)
+    }
+
+    /// Serializes the patch.
+    pub fn export(&self) -> Result<Vec<u8>, JsError> {
+        self.0.export(None).map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // creating a `JsError` calls into JavaScript, so only the successful paths run natively
+
+    #[test]
+    fn load_and_apply() {
+        let patch = WasmPatch::load(b"PATCH\0\0\x01\0\x02abEOF").unwrap();
+        assert_eq!(patch.format(), "IPS");
+        assert!(!patch.verify(b"0123").unwrap());
+        assert_eq!(patch.apply(b"0123").unwrap(), b"0ab3");
+    }
+
+    #[test]
+    fn create_and_export() {
+        for format in [None, Some("ups"), Some("bps")] {
+            let patch = WasmPatch::create(b"abc", b"abd", format.map(String::from)).unwrap();
+            let loaded = WasmPatch::load(&patch.export().unwrap()).unwrap();
+            assert_eq!(loaded.apply(b"abc").unwrap(), b"abd");
+        }
+        let patch = WasmPatch::create(b"abc", b"abd", Some("ups".into())).unwrap();
+        assert!(patch.verify(b"abc").unwrap());
+    }
+}
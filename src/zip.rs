@@ -0,0 +1,590 @@
+//! A minimal reader for zip archives, so zipped ROM sets can be patched without extracting them
+//! to disk first. Only stored and deflated entries are supported, in archives with or without
+//! ZIP64 records.
+
+use byteorder::{ByteOrder, LE};
+
+use crate::{hash, Error, Patch, Result};
+
+/// Extensions commonly used for ROM dumps, for picking the ROM out of an archive that also
+/// contains readmes or other files.
+pub const ROM_EXTENSIONS: &[&str] = &[
+    "sfc", "smc", "nes", "fds", "gb", "gbc", "gba", "nds", "md", "gen", "smd", "32x", "sms", "gg",
+    "pce", "z64", "n64", "v64", "bin", "a26", "ws", "wsc", "ngp", "ngc", "lnx",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub crc: u32,
+    pub size: usize,
+    method: u16,
+    compressed_size: usize,
+    header_offset: usize,
+}
+
+pub struct ZipArchive<'a> {
+    data: &'a [u8],
+    entries: Vec<ZipEntry>,
+}
+
+impl<'a> ZipArchive<'a> {
+    const EOCD_MAGIC: u32 = 0x06054B50;
+    const ZIP64_EOCD_MAGIC: u32 = 0x06064B50;
+    const ZIP64_LOCATOR_MAGIC: u32 = 0x07064B50;
+    const CENTRAL_MAGIC: u32 = 0x02014B50;
+    const LOCAL_MAGIC: u32 = 0x04034B50;
+    /// The ID of the extra field holding an entry's 64-bit sizes and offset.
+    const ZIP64_EXTRA: u16 = 0x0001;
+    const CENTRAL_SIZE: usize = 46;
+
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let eocd = (0..data.len().saturating_sub(21))
+            .rev()
+            .take(u16::MAX as usize + 1)
+            .find(|&i| LE::read_u32(&data[i..]) == Self::EOCD_MAGIC)
+            .ok_or(Error::InvalidArchive)?;
+
+        let (count, mut pos) = match Self::zip64_eocd(data, eocd)? {
+            Some(zip64) => zip64,
+            None => (
+                LE::read_u16(&data[eocd + 10..]) as usize,
+                LE::read_u32(&data[eocd + 16..]) as usize,
+            ),
+        };
+        // every entry takes at least a header, so a count that can't fit isn't trusted
+        let mut entries = Vec::with_capacity(count.min(data.len() / Self::CENTRAL_SIZE));
+        for _ in 0..count {
+            let header = pos
+                .checked_add(Self::CENTRAL_SIZE)
+                .and_then(|end| data.get(pos..end))
+                .ok_or(Error::InvalidArchive)?;
+            if LE::read_u32(header) != Self::CENTRAL_MAGIC {
+                return Err(Error::InvalidArchive);
+            }
+
+            let name_len = LE::read_u16(&header[28..]) as usize;
+            let extra_len = LE::read_u16(&header[30..]) as usize;
+            let comment_len = LE::read_u16(&header[32..]) as usize;
+            let name_start = pos + Self::CENTRAL_SIZE;
+            let name = data
+                .get(name_start..name_start + name_len)
+                .ok_or(Error::InvalidArchive)?;
+            let extra = data
+                .get(name_start + name_len..name_start + name_len + extra_len)
+                .ok_or(Error::InvalidArchive)?;
+
+            // fields that don't fit in 32 bits are all ones, with the real values in order in
+            // the ZIP64 extra field
+            let mut zip64 = Self::extra_field(extra, Self::ZIP64_EXTRA).unwrap_or_default();
+            let mut field = |value: u32| -> Result<usize> {
+                let value = match value {
+                    u32::MAX => {
+                        let (value, rest) =
+                            zip64.split_first_chunk().ok_or(Error::InvalidArchive)?;
+                        zip64 = rest;
+                        u64::from_le_bytes(*value)
+                    }
+                    value => value as u64,
+                };
+                usize::try_from(value).map_err(|_| Error::InvalidArchive)
+            };
+            let size = field(LE::read_u32(&header[24..]))?;
+            let compressed_size = field(LE::read_u32(&header[20..]))?;
+            let header_offset = field(LE::read_u32(&header[42..]))?;
+            entries.push(ZipEntry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                crc: LE::read_u32(&header[16..]),
+                size,
+                method: LE::read_u16(&header[10..]),
+                compressed_size,
+                header_offset,
+            });
+            pos = name_start + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    /// Reads the entry count and central directory offset from the ZIP64 end of central directory
+    /// record, if the archive has one before the regular record at `eocd`.
+    fn zip64_eocd(data: &[u8], eocd: usize) -> Result<Option<(usize, usize)>> {
+        let Some(locator) = eocd.checked_sub(20).map(|start| &data[start..eocd]) else {
+            return Ok(None);
+        };
+        if LE::read_u32(locator) != Self::ZIP64_LOCATOR_MAGIC {
+            return Ok(None);
+        }
+
+        let record = usize::try_from(LE::read_u64(&locator[8..]))
+            .ok()
+            .and_then(|start| data.get(start..start.checked_add(56)?))
+            .filter(|record| LE::read_u32(record) == Self::ZIP64_EOCD_MAGIC)
+            .ok_or(Error::InvalidArchive)?;
+        let to_usize = |value: u64| usize::try_from(value).map_err(|_| Error::InvalidArchive);
+        Ok(Some((
+            to_usize(LE::read_u64(&record[32..]))?,
+            to_usize(LE::read_u64(&record[48..]))?,
+        )))
+    }
+
+    /// Finds the extra field with the given ID, returning its data.
+    fn extra_field(mut extra: &[u8], id: u16) -> Option<&[u8]> {
+        while extra.len() >= 4 {
+            let len = LE::read_u16(&extra[2..]) as usize;
+            let field = extra.get(4..4 + len)?;
+            if LE::read_u16(extra) == id {
+                return Some(field);
+            }
+            extra = &extra[4 + len..];
+        }
+        None
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Decompresses `entry`, verifying its size and CRC. Decompression stops as soon as the
+    /// output passes the size the archive declares, so a zip bomb fails instead of exhausting
+    /// memory.
+    pub fn read(&self, entry: &ZipEntry) -> Result<Vec<u8>> {
+        let pos = entry.header_offset;
+        let header = pos
+            .checked_add(30)
+            .and_then(|end| self.data.get(pos..end))
+            .ok_or(Error::InvalidArchive)?;
+        if LE::read_u32(header) != Self::LOCAL_MAGIC {
+            return Err(Error::InvalidArchive);
+        }
+
+        let start =
+            pos + 30 + LE::read_u16(&header[26..]) as usize + LE::read_u16(&header[28..]) as usize;
+        let raw = start
+            .checked_add(entry.compressed_size)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or(Error::InvalidArchive)?;
+        let data = match entry.method {
+            0 => raw.to_vec(),
+            8 => inflate(raw, entry.size)?,
+            _ => return Err(Error::InvalidArchive),
+        };
+
+        if data.len() != entry.size || hash::crc32(&data) != entry.crc {
+            return Err(Error::InvalidArchive);
+        }
+
+        Ok(data)
+    }
+
+    /// Picks the ROM to patch: the entry whose size and CRC match the patch's expected source if
+    /// known, otherwise the only entry with one of the given extensions, otherwise the only entry.
+    pub fn select<P: Patch + ?Sized>(
+        &self,
+        patch: Option<&P>,
+        extensions: &[&str],
+    ) -> Result<&ZipEntry> {
+        let files: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|e| !e.name.ends_with('/'))
+            .collect();
+        if let Some(source) = patch.and_then(|patch| patch.source()) {
+            if let Some(entry) = files
+                .iter()
+                .find(|e| e.size == source.size && e.crc == source.crc)
+            {
+                return Ok(*entry);
+            }
+        }
+
+        let by_ext: Vec<_> = files
+            .iter()
+            .copied()
+            .filter(|e| {
+                e.name
+                    .rsplit_once('.')
+                    .is_some_and(|(_, ext)| extensions.iter().any(|x| x.eq_ignore_ascii_case(ext)))
+            })
+            .collect();
+        match (by_ext.as_slice(), files.as_slice()) {
+            ([entry], _) | (_, [entry]) => Ok(*entry),
+            _ => Err(Error::NoArchiveEntry),
+        }
+    }
+}
+
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    len: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.len < n {
+            let byte = *self.data.get(self.pos).ok_or(Error::InvalidArchive)?;
+            self.buf |= (byte as u32) << self.len;
+            self.pos += 1;
+            self.len += 8;
+        }
+
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.len -= n;
+        Ok(value)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in huffman.counts[1..].iter() {
+            code |= self.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::InvalidArchive)
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// How many times the size of the compressed data is reserved for the output up front. DEFLATE
+/// can compress over a thousand to one, so the output grows past it as needed.
+const RESERVE_RATIO: usize = 8;
+
+/// Decompresses a raw DEFLATE stream, failing if it produces more than `limit` bytes.
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(limit.min(data.len().saturating_mul(RESERVE_RATIO)));
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buf: 0,
+        len: 0,
+    };
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.buf = 0;
+                bits.len = 0;
+                let header = data
+                    .get(bits.pos..bits.pos + 4)
+                    .ok_or(Error::InvalidArchive)?;
+                let len = LE::read_u16(header) as usize;
+                if LE::read_u16(&header[2..]) != !(len as u16) {
+                    return Err(Error::InvalidArchive);
+                }
+
+                bits.pos += 4;
+                if out.len() + len > limit {
+                    return Err(Error::InvalidArchive);
+                }
+                out.extend_from_slice(
+                    data.get(bits.pos..bits.pos + len)
+                        .ok_or(Error::InvalidArchive)?,
+                );
+                bits.pos += len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(
+                    &mut bits,
+                    &mut out,
+                    limit,
+                    &Huffman::new(&lengths),
+                    &Huffman::new(&[5; 30]),
+                )?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut bits)?;
+                inflate_block(&mut bits, &mut out, limit, &lit, &dist)?;
+            }
+            _ => return Err(Error::InvalidArchive),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let lit_count = bits.bits(5)? as usize + 257;
+    let dist_count = bits.bits(5)? as usize + 1;
+    let code_count = bits.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in ORDER[..code_count].iter() {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(lit_count + dist_count);
+    while lengths.len() < lit_count + dist_count {
+        let (value, repeat) = match bits.decode(&codes)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (
+                *lengths.last().ok_or(Error::InvalidArchive)?,
+                3 + bits.bits(2)?,
+            ),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+
+    if lengths.len() != lit_count + dist_count {
+        return Err(Error::InvalidArchive);
+    }
+
+    Ok((
+        Huffman::new(&lengths[..lit_count]),
+        Huffman::new(&lengths[lit_count..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<()> {
+    loop {
+        let symbol = bits.decode(lit)? as usize;
+        match symbol {
+            0..=255 if out.len() < limit => out.push(symbol as u8),
+            0..=255 => return Err(Error::InvalidArchive),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                let len = *LENGTH_BASE.get(i).ok_or(Error::InvalidArchive)? as usize
+                    + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                let i = bits.decode(dist)? as usize;
+                let distance = *DIST_BASE.get(i).ok_or(Error::InvalidArchive)? as usize
+                    + bits.bits(DIST_EXTRA[i] as u32)? as usize;
+                let start = out
+                    .len()
+                    .checked_sub(distance)
+                    .ok_or(Error::InvalidArchive)?;
+                if out.len() + len > limit {
+                    return Err(Error::InvalidArchive);
+                }
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `readme.txt` (deflated) and `game.sfc` (stored, containing "rom").
+    const ARCHIVE: &[u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x00, 0xc5,
+        0x70, 0xab, 0x34, 0x27, 0x00, 0x00, 0x00, 0xe0, 0x01, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00,
+        0x72, 0x65, 0x61, 0x64, 0x6d, 0x65, 0x2e, 0x74, 0x78, 0x74, 0x2b, 0x48, 0x2c, 0x49, 0xce,
+        0xd0, 0x2d, 0x2a, 0x56, 0x30, 0xd0, 0x51, 0x28, 0x80, 0xb1, 0x0d, 0x91, 0xd8, 0x46, 0x48,
+        0x6c, 0x63, 0x24, 0xb6, 0x09, 0x12, 0xdb, 0x14, 0x89, 0x6d, 0x86, 0xc4, 0x1e, 0x35, 0x73,
+        0xc0, 0xcd, 0x04, 0x00, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x21, 0x00, 0xa1, 0x0f, 0x52, 0x79, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x67, 0x61, 0x6d, 0x65, 0x2e, 0x73, 0x66, 0x63, 0x72, 0x6f, 0x6d,
+        0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21,
+        0x00, 0xc5, 0x70, 0xab, 0x34, 0x27, 0x00, 0x00, 0x00, 0xe0, 0x01, 0x00, 0x00, 0x0a, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x72, 0x65, 0x61, 0x64, 0x6d, 0x65, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01, 0x02,
+        0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0xa1, 0x0f, 0x52,
+        0x79, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x4f, 0x00, 0x00, 0x00, 0x67, 0x61, 0x6d,
+        0x65, 0x2e, 0x73, 0x66, 0x63, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x02, 0x00, 0x6e, 0x00, 0x00, 0x00, 0x78, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn read_entries() {
+        let zip = ZipArchive::new(ARCHIVE).unwrap();
+        assert_eq!(zip.entries().len(), 2);
+
+        let readme = zip.read(&zip.entries()[0]).unwrap();
+        let expected: Vec<u8> = (0..40)
+            .flat_map(|i| format!("patch-rs {}, ", i % 7).into_bytes())
+            .collect();
+        assert_eq!(readme, expected);
+
+        let rom = zip.select::<dyn Patch>(None, ROM_EXTENSIONS).unwrap();
+        assert_eq!(rom.name, "game.sfc");
+        assert_eq!(zip.read(rom).unwrap(), b"rom");
+    }
+
+    #[test]
+    fn inflate_dynamic() {
+        const DATA: &[u8] = &[
+            0xed, 0x4e, 0xc9, 0x11, 0x03, 0x40, 0x08, 0x6a, 0x69, 0x01, 0xf1, 0xe8, 0xbf, 0xb1,
+            0x90, 0x26, 0xf2, 0xca, 0xe8, 0x03, 0x05, 0x85, 0x87, 0x3a, 0x34, 0xad, 0xae, 0xeb,
+            0x5a, 0x88, 0x55, 0x33, 0xa4, 0xb2, 0x3c, 0x4b, 0xf3, 0x10, 0x32, 0x54, 0x64, 0xa5,
+            0x2b, 0xbf, 0x6d, 0x73, 0x76, 0x5d, 0xe4, 0x5e, 0x43, 0x06, 0xb6, 0x3b, 0xb7, 0x62,
+            0xc0, 0x69, 0x3d, 0x37, 0x1e, 0xa5, 0x82, 0xd6, 0xa7, 0x46, 0xb8, 0x8a, 0x68, 0x1b,
+            0xd1, 0x67, 0x3c, 0xd2, 0xb5, 0xcb, 0x69, 0xaf, 0xdf, 0x55, 0x3e, 0x23, 0x0e, 0x6d,
+            0x81, 0xf3, 0xa4, 0x73, 0xf3, 0x0b, 0x59, 0x69, 0x2d, 0xbe, 0xf6, 0x4a, 0x4a, 0xf4,
+            0x15, 0xde, 0x3f, 0xf2, 0xcf, 0x23, 0x7f, 0x00,
+        ];
+
+        let expected: String = (0..300).map(|i| (i * i % 97).to_string()).collect();
+        assert_eq!(inflate(DATA, usize::MAX).unwrap(), expected.as_bytes());
+        assert_eq!(inflate(DATA, expected.len()).unwrap(), expected.as_bytes());
+        assert!(matches!(
+            inflate(DATA, expected.len() - 1),
+            Err(Error::InvalidArchive)
+        ));
+    }
+
+    #[test]
+    fn declared_size() {
+        // an entry that inflates to more than it declares stops there, like a zip bomb would
+        let zip = ZipArchive::new(ARCHIVE).unwrap();
+        for size in [10, 0x1E0 - 1, 0x1E0 + 1, usize::MAX] {
+            let entry = ZipEntry {
+                size,
+                ..zip.entries()[0].clone()
+            };
+            assert!(matches!(zip.read(&entry), Err(Error::InvalidArchive)));
+        }
+        let entry = ZipEntry {
+            compressed_size: usize::MAX,
+            ..zip.entries()[1].clone()
+        };
+        assert!(matches!(zip.read(&entry), Err(Error::InvalidArchive)));
+    }
+
+    /// A stored entry named `rom.bin` holding `data`, with every size and offset in ZIP64 records.
+    fn zip64_archive(data: &[u8]) -> Vec<u8> {
+        let crc = hash::crc32(data);
+        let mut zip = Vec::new();
+        zip.extend(0x04034B50u32.to_le_bytes());
+        zip.extend([20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        zip.extend(crc.to_le_bytes());
+        zip.extend([0xFF; 8]);
+        zip.extend([7, 0, 20, 0]);
+        zip.extend(b"rom.bin");
+        zip.extend([1, 0, 16, 0]);
+        zip.extend((data.len() as u64).to_le_bytes());
+        zip.extend((data.len() as u64).to_le_bytes());
+        zip.extend(data);
+
+        let central = zip.len();
+        zip.extend(0x02014B50u32.to_le_bytes());
+        zip.extend([45, 3, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        zip.extend(crc.to_le_bytes());
+        zip.extend([0xFF; 8]);
+        zip.extend([7, 0, 28, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        zip.extend([0xFF; 4]);
+        zip.extend(b"rom.bin");
+        zip.extend([1, 0, 24, 0]);
+        for value in [data.len(), data.len(), 0] {
+            zip.extend((value as u64).to_le_bytes());
+        }
+
+        let eocd64 = zip.len();
+        zip.extend(0x06064B50u32.to_le_bytes());
+        zip.extend(44u64.to_le_bytes());
+        zip.extend([45, 3, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        zip.extend(1u64.to_le_bytes());
+        zip.extend(1u64.to_le_bytes());
+        zip.extend(((eocd64 - central) as u64).to_le_bytes());
+        zip.extend((central as u64).to_le_bytes());
+        zip.extend(0x07064B50u32.to_le_bytes());
+        zip.extend([0; 4]);
+        zip.extend((eocd64 as u64).to_le_bytes());
+        zip.extend(1u32.to_le_bytes());
+        zip.extend(0x06054B50u32.to_le_bytes());
+        zip.extend([0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        zip.extend([0xFF; 8]);
+        zip.extend([0, 0]);
+        zip
+    }
+
+    #[test]
+    fn zip64() {
+        let data = zip64_archive(b"rom");
+        let zip = ZipArchive::new(&data).unwrap();
+        let [entry] = zip.entries() else {
+            panic!("expected one entry");
+        };
+        assert_eq!((entry.name.as_str(), entry.size), ("rom.bin", 3));
+        assert_eq!(zip.read(entry).unwrap(), b"rom");
+
+        // a locator pointing nowhere, and an entry missing its ZIP64 sizes
+        let mut bad = data.clone();
+        let locator = bad.len() - 22 - 20;
+        bad[locator + 8] ^= 0x40;
+        assert!(matches!(ZipArchive::new(&bad), Err(Error::InvalidArchive)));
+        let mut bad = data.clone();
+        let central = bad.len() - 22 - 20 - 56 - 46 - 7 - 28;
+        bad[central + 30] = 8;
+        bad[central + 46 + 7 + 2] = 8;
+        assert!(matches!(ZipArchive::new(&bad), Err(Error::InvalidArchive)));
+    }
+}
@@ -0,0 +1,490 @@
+//! Runs the command line tool end to end, checking what it writes and the exit codes it documents.
+
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+};
+
+use patch_rs::prelude::*;
+
+/// A scratch directory for one test, removed when it's dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("patch-rs-cli-{name}-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn file(&self, name: &str, data: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The command line tool, without the user's config.
+fn command() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_patch-rs"));
+    command
+        .env("HOME", env::temp_dir().join("patch-rs-cli-no-home"))
+        .env_remove("XDG_CONFIG_HOME");
+    command
+}
+
+fn run(args: &[&dyn AsRef<OsStr>]) -> Output {
+    command()
+        .args(args.iter().map(|arg| arg.as_ref()))
+        .output()
+        .unwrap()
+}
+
+fn code(output: &Output) -> i32 {
+    output.status.code().unwrap()
+}
+
+#[test]
+fn usage_errors() {
+    let dir = Scratch::new("usage");
+    let rom = dir.file("game.bin", b"abc");
+    let patch = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let dest = dir.0.join("out.bin");
+
+    let output = run(&[&"apply", &rom, &patch, &dest, &"--truncate"]);
+    assert_eq!(code(&output), 2, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only be used with IPS patches"));
+    assert!(!Path::new(&dest).exists());
+
+    // clap's own errors use the same code
+    assert_eq!(code(&run(&[&"apply", &rom])), 2);
+}
+
+#[test]
+fn minify_ups() {
+    let dir = Scratch::new("minify");
+    // an empty record at offset 0 before the one at offset 1
+    let mut data = b"UPS1\x84\x84\x80\x00\x80\x01\x00".to_vec();
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(&patch_rs::hash::crc32(&data).to_le_bytes());
+    let patch = dir.file("hack.ups", &data);
+
+    let output = run(&[&"minify", &patch]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    let minified = fs::read(&patch).unwrap();
+    assert_eq!(minified.len(), data.len() - 2);
+    assert_eq!(UpsPatch::load(&minified).unwrap().records().len(), 1);
+}
+
+#[test]
+fn config() {
+    let dir = Scratch::new("config");
+    let original = dir.file("original.bin", b"abc");
+    let modified = dir.file("modified.bin", b"abd");
+    let patch = dir.0.join("hack.ups");
+    fs::create_dir_all(dir.0.join("patch-rs")).unwrap();
+    let create = |config: &str, flag: &str| {
+        dir.file("patch-rs/config.toml", config);
+        let mut command = command();
+        command.env("XDG_CONFIG_HOME", &dir.0).arg("create");
+        command.args([&original, &modified]).arg("-o").arg(&patch);
+        command.args((!flag.is_empty()).then_some(flag));
+        command.output().unwrap()
+    };
+
+    for (config, flag) in [
+        ("progress = false\n", ""),
+        ("progress = true\n", "--no-progress"),
+        ("progress = false\n", "--progress"),
+    ] {
+        let output = create(config, flag);
+        assert_eq!(code(&output), 0, "{config} {flag}: {output:?}");
+    }
+
+    let output = create("progress = \"yes\"\n", "");
+    assert_eq!(code(&output), 1, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be true or false"));
+}
+
+#[test]
+fn match_patches() {
+    let dir = Scratch::new("match");
+    fs::create_dir_all(dir.0.join("roms")).unwrap();
+    fs::create_dir_all(dir.0.join("patches")).unwrap();
+    dir.file("roms/game.bin", b"abc");
+    dir.file("roms/other.bin", b"xyz");
+    let patch = BpsPatch::create(b"abc", b"abd").export(None).unwrap();
+    dir.file("patches/hack.bps", patch);
+    dir.file("patches/readme.txt", "not a patch");
+    let out = dir.0.join("out");
+
+    let output = run(&[
+        &"match",
+        &dir.0.join("roms"),
+        &dir.0.join("patches"),
+        &"--dest-dir",
+        &out,
+    ]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ignored 1 file(s)"));
+    let written: Vec<_> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(written.len(), 1);
+    assert_eq!(fs::read(&written[0]).unwrap(), b"abd");
+
+    // a patch for a ROM that isn't there fails
+    let patch = UpsPatch::create(b"def", b"deg").export(None).unwrap();
+    dir.file("patches/missing.ups", patch);
+    let output = run(&[
+        &"match",
+        &dir.0.join("roms"),
+        &dir.0.join("patches"),
+        &"--dest-dir",
+        &out,
+    ]);
+    assert_eq!(code(&output), 1, "{output:?}");
+}
+
+#[test]
+fn apply_exit_codes() {
+    let dir = Scratch::new("apply");
+    let rom = dir.file("game.bin", b"abc");
+    let other = dir.file("other.bin", b"xyz");
+    let patch = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let corrupt = dir.file("corrupt.ups", "not a patch");
+    let dest = dir.0.join("out.bin");
+
+    let output = run(&[&"apply", &rom, &patch, &dest]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(fs::read(&dest).unwrap(), b"abd");
+
+    let cases: [(&[&dyn AsRef<OsStr>], i32); 4] = [
+        (&[&"apply", &rom, &corrupt, &dest], 3),
+        (&[&"apply", &other, &patch, &dest], 4),
+        (
+            &[
+                &"apply",
+                &rom,
+                &patch,
+                &dest,
+                &"--expect-crc32",
+                &"00000000",
+            ],
+            5,
+        ),
+        (&[&"apply", &dir.0.join("missing.bin"), &patch, &dest], 6),
+    ];
+    for (args, expected) in cases {
+        fs::remove_file(&dest).ok();
+        let output = run(args);
+        assert_eq!(code(&output), expected, "{output:?}");
+        assert!(!dest.exists(), "{output:?}");
+    }
+}
+
+#[test]
+fn apply_batch() {
+    let dir = Scratch::new("batch");
+    fs::create_dir_all(dir.0.join("roms")).unwrap();
+    dir.file("roms/a.bin", b"abc");
+    dir.file("roms/b.bin", b"abc");
+    let patch = dir.file(
+        "hack.bps",
+        BpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let out = dir.0.join("out");
+
+    let output = run(&[
+        &"apply",
+        &dir.0.join("roms"),
+        &patch,
+        &"--dest-dir",
+        &out,
+        &"--dest-pattern",
+        &"{rom_stem} ({patch_stem}).{rom_ext}",
+        &"-j",
+        &"2",
+    ]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Patched 2 of 2 ROMs."));
+    assert_eq!(fs::read(out.join("a (hack).bin")).unwrap(), b"abd");
+    assert_eq!(fs::read(out.join("b (hack).bin")).unwrap(), b"abd");
+
+    // several ROMs need somewhere to go
+    let output = run(&[&"apply", &dir.0.join("roms"), &patch]);
+    assert_eq!(code(&output), 2, "{output:?}");
+
+    // and nothing is written if two would land on the same name
+    let clash = dir.0.join("clash");
+    let output = run(&[
+        &"apply",
+        &dir.0.join("roms"),
+        &patch,
+        &"--dest-dir",
+        &clash,
+        &"--dest-pattern",
+        &"{patch_stem}.{rom_ext}",
+    ]);
+    assert_eq!(code(&output), 1, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would both be written"));
+    assert!(!clash.join("hack.bin").exists());
+
+    let output = run(&[
+        &"apply",
+        &dir.0.join("roms"),
+        &patch,
+        &"--dest-pattern",
+        &"{rom}",
+    ]);
+    assert_eq!(code(&output), 2, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown placeholder '{rom}'"));
+}
+
+#[test]
+fn apply_options() {
+    let dir = Scratch::new("options");
+    let rom = dir.file("game.bin", b"abc");
+    let ups = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let ips = dir.file(
+        "hack.ips",
+        IpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+
+    let dest = dir.0.join("sized.bin");
+    let output = run(&[&"apply", &rom, &ips, &dest, &"--output-size", &"8"]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(fs::read(&dest).unwrap(), b"abd\0\0\0\0\0");
+
+    if cfg!(unix) {
+        let dest = dir.0.join("exec.bin");
+        let output = run(&[&"apply", &rom, &ups, &dest, &"--exec", &"touch {out}.done"]);
+        assert_eq!(code(&output), 0, "{output:?}");
+        assert!(dir.0.join("exec.bin.done").exists());
+    }
+
+    let output = run(&[&"apply", &rom, &ups, &"--in-place", &"--backup"]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(fs::read(&rom).unwrap(), b"abd");
+    assert_eq!(fs::read(dir.0.join("game.bin.bak")).unwrap(), b"abc");
+}
+
+#[test]
+fn create() {
+    let dir = Scratch::new("create");
+    let original = dir.file("original.bin", b"abc");
+    let modified = dir.file("modified.bin", b"abd");
+
+    let output = run(&[&"create", &original, &modified, &"-f", &"auto"]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Chose IPS"));
+    let patch = fs::read(dir.0.join("modified.ips")).unwrap();
+    assert_eq!(
+        IpsPatch::load(&patch).unwrap().apply(b"abc").unwrap(),
+        b"abd"
+    );
+
+    let output = run(&[&"create", &original, &modified, &"--title", &"Hack"]);
+    assert_eq!(code(&output), 2, "{output:?}");
+    assert!(!dir.0.join("modified.ups").exists());
+}
+
+#[test]
+fn auto() {
+    let dir = Scratch::new("auto");
+    let rom = dir.file("game.bin", b"abc");
+    dir.file(
+        "game.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+
+    let output = run(&[&"auto", &rom]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(fs::read(dir.0.join("game.patched.bin")).unwrap(), b"abd");
+}
+
+#[test]
+fn inspect() {
+    let dir = Scratch::new("inspect");
+    let rom = dir.file("game.bin", b"abc");
+    let modified = dir.file("modified.bin", b"abd");
+    let ups = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let ips = dir.file(
+        "hack.ips",
+        IpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let other = dir.file(
+        "other.ips",
+        IpsPatch::create(b"abc", b"abe").export(None).unwrap(),
+    );
+    let stdout = |output: &Output| String::from_utf8_lossy(&output.stdout).into_owned();
+
+    let output = run(&[&"map", &ups, &"--json"]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(
+        stdout(&output).trim(),
+        r#"[{"offset":2,"length":1,"kind":"xor"}]"#
+    );
+
+    let output = run(&[&"info", &ups]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(stdout(&output).contains("records  1"));
+
+    let output = run(&[&"diff", &rom, &modified]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(stdout(&output).contains("1 of 1 lines differ"));
+
+    assert_eq!(code(&run(&[&"compare", &ups, &ips, &rom])), 0);
+    assert_eq!(code(&run(&[&"compare", &ips, &other, &rom])), 1);
+    let corrupt = dir.file("corrupt.ups", "not a patch");
+    assert_eq!(code(&run(&[&"compare", &ups, &corrupt])), 3);
+}
+
+#[test]
+fn logging() {
+    let dir = Scratch::new("logging");
+    let rom = dir.file("game.bin", b"abc");
+    let patch = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let dest = dir.0.join("out.bin");
+
+    let output = run(&[&"-v", &"apply", &rom, &patch, &dest]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains("event=apply") && log.contains("output_crc=0xab40d461"),
+        "{log}"
+    );
+
+    let output = run(&[&"-q", &"apply", &rom, &patch, &dest]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(
+        output.stdout.is_empty() && output.stderr.is_empty(),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn response_files_and_completions() {
+    let dir = Scratch::new("response");
+    let rom = dir.file("game.bin", b"abc");
+    let patch = dir.file(
+        "hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+    let dest = dir.0.join("out.bin");
+    let args = dir.file(
+        "args.txt",
+        format!(
+            "apply\n{}\n{}\n\"{}\"\n",
+            rom.display(),
+            patch.display(),
+            dest.display()
+        ),
+    );
+
+    let mut response = OsString::from("@");
+    response.push(&args);
+    let output = run(&[&response]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert_eq!(fs::read(&dest).unwrap(), b"abd");
+
+    let output = run(&[&"completions", &"bash"]);
+    assert_eq!(code(&output), 0, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("patch-rs"));
+    assert_eq!(code(&run(&[&"completions", &"tcsh"])), 2);
+}
+
+#[cfg(feature = "serve")]
+#[test]
+fn serve() {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpStream,
+        process::Stdio,
+    };
+
+    let dir = Scratch::new("serve");
+    fs::create_dir_all(dir.0.join("patches")).unwrap();
+    fs::create_dir_all(dir.0.join("roms")).unwrap();
+    dir.file("roms/game.bin", b"abc");
+    dir.file(
+        "patches/hack.ups",
+        UpsPatch::create(b"abc", b"abd").export(None).unwrap(),
+    );
+
+    // without a patch directory or with no workers, the server doesn't start
+    let output = run(&[&"serve"]);
+    assert_eq!(code(&output), 1, "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No patch directory given"));
+    let output = run(&[
+        &"serve",
+        &"--patches",
+        &dir.0.join("patches"),
+        &"--workers",
+        &"0",
+    ]);
+    assert_eq!(code(&output), 2, "{output:?}");
+
+    let mut server = command()
+        .arg("serve")
+        .arg("--patches")
+        .arg(dir.0.join("patches"))
+        .arg("--roms")
+        .arg(dir.0.join("roms"))
+        .args(["--bind", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the server logs each request, so its output stays open until it's killed
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let addr = line
+        .split_once("http://")
+        .and_then(|(_, rest)| rest.split_once(' '))
+        .map(|(addr, _)| addr.to_owned())
+        .unwrap_or_else(|| panic!("{line}"));
+    let get = |target: &str| {
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    };
+
+    let patches = get("/patches");
+    let rom = get("/apply?patch=hack.ups&rom=game.bin");
+    let missing = get("/apply?patch=missing.ups&rom=game.bin");
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(patches.starts_with(b"HTTP/1.1 200 OK") && patches.ends_with(b"\r\n\r\nhack.ups\n"));
+    assert!(rom.starts_with(b"HTTP/1.1 200 OK") && rom.ends_with(b"\r\n\r\nabd"));
+    assert!(missing.starts_with(b"HTTP/1.1 404 Not Found"));
+}